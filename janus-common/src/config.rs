@@ -1,11 +1,15 @@
 //! Configuration types for Janus server
 
+use schemars::schema::RootSchema;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::path::Path;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 /// Main server configuration
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default, JsonSchema)]
 pub struct JanusConfig {
     /// Global server settings
     #[serde(default)]
@@ -29,7 +33,7 @@ pub struct JanusConfig {
 }
 
 /// Server listening configuration
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct ServerConfig {
     /// Address to bind to
     #[serde(default = "default_bind_address")]
@@ -46,6 +50,146 @@ pub struct ServerConfig {
     /// Enable access logging
     #[serde(default = "default_true")]
     pub access_log: bool,
+
+    /// Fraction of requests (0.0-1.0) written to the access log
+    #[serde(default = "default_access_log_sample_rate")]
+    pub access_log_sample_rate: f64,
+
+    /// Log every 4xx and 5xx response, whatever the sample rate
+    #[serde(default = "default_true")]
+    pub always_log_errors: bool,
+
+    /// Built-in health endpoint for load balancers and probes
+    #[serde(default)]
+    pub health_endpoint: HealthEndpointConfig,
+
+    /// Built-in status page, for a quick look without the TUI
+    #[serde(default)]
+    pub status_page: StatusPageConfig,
+
+    /// Maintenance mode, switched on and off through the management API
+    #[serde(default)]
+    pub maintenance: MaintenanceConfig,
+
+    /// Server error rate (0.0-1.0) above which a warning is logged
+    #[serde(default = "default_error_rate_alert_threshold")]
+    pub error_rate_alert_threshold: f64,
+
+    /// Suppression of repeated upstream and static file errors
+    #[serde(default)]
+    pub error_log: ErrorLogConfig,
+
+    /// TCP options for the HTTP listener and its connections
+    #[serde(default)]
+    pub socket_options: SocketOptions,
+
+    /// Truncate client addresses in per-client statistics (last IPv4
+    /// octet, last 80 bits of IPv6)
+    #[serde(default)]
+    pub anonymize_client_ips: bool,
+
+    /// Upstream receiving requests that no static mount or route matched,
+    /// instead of the built-in 404 page
+    #[serde(default)]
+    pub default_upstream: Option<String>,
+
+    /// Whether static mounts or routes are tried first; the default
+    /// upstream always comes last
+    #[serde(default)]
+    pub match_order: MatchOrder,
+
+    /// Request timeout in seconds for the default upstream
+    #[serde(default = "default_timeout")]
+    pub default_upstream_timeout_secs: u64,
+
+    /// Redirect requests that did not arrive over TLS to the same URL
+    /// with the `https` scheme
+    #[serde(default)]
+    pub force_https: bool,
+
+    /// Status code for HTTPS redirects (301, 302, 307 or 308)
+    #[serde(default = "default_https_redirect_code")]
+    pub https_redirect_code: u16,
+
+    /// Limit on how fast each client address may send requests; requests
+    /// over it are answered 429
+    #[serde(default)]
+    pub rate_limit: Option<RateLimitConfig>,
+
+    /// Auth subrequest made for every request before it is routed, ahead
+    /// of any route's own `auth_request`
+    #[serde(default)]
+    pub auth_request: Option<AuthRequestConfig>,
+
+    /// Gzip compression of responses for clients that accept it
+    #[serde(default)]
+    pub compression: CompressionConfig,
+
+    /// Most bytes of request bodies spooled to disk at once, across every
+    /// route; requests that would go over are answered 503
+    #[serde(default = "default_body_spool_max_bytes")]
+    pub body_spool_max_bytes: u64,
+
+    /// Longest request URI accepted, in bytes; longer ones are answered 414
+    #[serde(default = "default_max_uri_bytes")]
+    pub max_uri_bytes: usize,
+
+    /// Most header fields accepted in a request; requests with more are
+    /// answered 431
+    #[serde(default = "default_max_request_headers")]
+    pub max_request_headers: usize,
+
+    /// Largest request line and header block accepted, in bytes (at least
+    /// 8192); larger ones are answered 431. Checked as the head is read, so
+    /// one arriving in a single large read may get a little over
+    #[serde(default = "default_max_request_header_bytes")]
+    pub max_request_header_bytes: usize,
+
+    /// Seconds a client connection may sit without a request in progress
+    /// before it is closed (0 = never)
+    #[serde(default = "default_idle_connection_timeout")]
+    pub idle_connection_timeout_secs: u64,
+
+    /// How changes to the config file are noticed (read when the server
+    /// starts)
+    #[serde(default)]
+    pub config_watch_mode: WatchMode,
+
+    /// Milliseconds between checks of the config file when it is polled
+    #[serde(default = "default_poll_interval")]
+    pub poll_interval_ms: u64,
+
+    /// Serve HTTPS with certificates obtained and renewed from an ACME CA
+    /// such as Let's Encrypt (needs a build with the `acme` feature)
+    #[serde(default)]
+    pub acme: Option<AcmeConfig>,
+}
+
+impl ServerConfig {
+    /// Listener settings that differ from the address the server is
+    /// actually listening on, so apply only after a restart
+    pub fn pending_restart(&self, listening: SocketAddr) -> Vec<String> {
+        let mut pending = Vec::new();
+        // Host names are resolved when binding, so only addresses compare
+        if self
+            .bind_address
+            .parse::<IpAddr>()
+            .is_ok_and(|ip| ip != listening.ip())
+        {
+            pending.push("server.bind_address".to_string());
+        }
+        // Port 0 lets the system choose
+        if self.port != 0 && self.port != listening.port() {
+            pending.push("server.port".to_string());
+        }
+        pending
+    }
+
+    /// Catch-all route forwarding to the default upstream, if one is set
+    pub fn default_route(&self) -> Option<RouteConfig> {
+        let upstream = self.default_upstream.clone()?;
+        Some(RouteConfig::new("/*", upstream).timeout(self.default_upstream_timeout_secs))
+    }
 }
 
 impl Default for ServerConfig {
@@ -55,12 +199,273 @@ impl Default for ServerConfig {
             port: default_port(),
             workers: 0,
             access_log: true,
+            access_log_sample_rate: default_access_log_sample_rate(),
+            always_log_errors: true,
+            health_endpoint: HealthEndpointConfig::default(),
+            status_page: StatusPageConfig::default(),
+            maintenance: MaintenanceConfig::default(),
+            error_rate_alert_threshold: default_error_rate_alert_threshold(),
+            error_log: ErrorLogConfig::default(),
+            socket_options: SocketOptions::default(),
+            anonymize_client_ips: false,
+            default_upstream: None,
+            match_order: MatchOrder::default(),
+            default_upstream_timeout_secs: default_timeout(),
+            force_https: false,
+            https_redirect_code: default_https_redirect_code(),
+            rate_limit: None,
+            auth_request: None,
+            compression: CompressionConfig::default(),
+            body_spool_max_bytes: default_body_spool_max_bytes(),
+            max_uri_bytes: default_max_uri_bytes(),
+            max_request_headers: default_max_request_headers(),
+            max_request_header_bytes: default_max_request_header_bytes(),
+            idle_connection_timeout_secs: default_idle_connection_timeout(),
+            config_watch_mode: WatchMode::default(),
+            poll_interval_ms: default_poll_interval(),
+            acme: None,
+        }
+    }
+}
+
+/// Certificates from an ACME CA for an HTTPS listener
+///
+/// The CA checks each domain with an HTTP-01 challenge, which the HTTP
+/// listener answers, so it must be reachable on port 80 under every domain.
+/// Read when the server starts; changes take effect on restart.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct AcmeConfig {
+    /// Contact address registered with the CA
+    pub contact_email: String,
+
+    /// Domains the certificate covers
+    pub domains: Vec<String>,
+
+    /// Directory keeping the ACME account key and the current certificate
+    pub cache_dir: String,
+
+    /// Port of the HTTPS listener
+    #[serde(default = "default_https_port")]
+    pub https_port: u16,
+
+    /// ACME directory of the CA (Let's Encrypt by default)
+    #[serde(default = "default_acme_directory_url")]
+    pub directory_url: String,
+
+    /// Days before expiry that the certificate is renewed
+    #[serde(default = "default_renew_before_days")]
+    pub renew_before_days: u64,
+}
+
+/// TCP tuning for the HTTP listener
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct SocketOptions {
+    /// Disable Nagle's algorithm on accepted connections
+    #[serde(default = "default_true")]
+    pub tcp_nodelay: bool,
+
+    /// Maximum queue of pending connections
+    #[serde(default = "default_tcp_backlog")]
+    pub tcp_backlog: u32,
+
+    /// Kernel receive buffer size in bytes (system default if unset)
+    #[serde(default)]
+    pub recv_buffer_size: Option<u32>,
+
+    /// Kernel send buffer size in bytes (system default if unset)
+    #[serde(default)]
+    pub send_buffer_size: Option<u32>,
+
+    /// Allow rebinding the address while old connections linger
+    #[serde(default = "default_true")]
+    pub reuse_address: bool,
+}
+
+impl Default for SocketOptions {
+    fn default() -> Self {
+        Self {
+            tcp_nodelay: true,
+            tcp_backlog: default_tcp_backlog(),
+            recv_buffer_size: None,
+            send_buffer_size: None,
+            reuse_address: true,
+        }
+    }
+}
+
+/// Limits on logging the same error over and over
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ErrorLogConfig {
+    /// Occurrences of an error logged individually
+    #[serde(default = "default_error_log_burst")]
+    pub burst: u32,
+
+    /// Seconds between summaries once an error is being suppressed
+    #[serde(default = "default_error_log_summary_interval")]
+    pub summary_interval_secs: u64,
+}
+
+impl Default for ErrorLogConfig {
+    fn default() -> Self {
+        Self {
+            burst: default_error_log_burst(),
+            summary_interval_secs: default_error_log_summary_interval(),
+        }
+    }
+}
+
+/// Token bucket per client address
+///
+/// Each address may send `burst` requests at once, and after that
+/// `requests_per_second` on average. Counts start over when the
+/// configuration changes.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct RateLimitConfig {
+    /// Requests each client address may send per second
+    pub requests_per_second: u32,
+
+    /// Requests a client address may send at once (defaults to
+    /// `requests_per_second`)
+    #[serde(default)]
+    pub burst: Option<u32>,
+}
+
+impl RateLimitConfig {
+    /// Requests a client address may send at once
+    pub fn burst(&self) -> u32 {
+        self.burst.unwrap_or(self.requests_per_second)
+    }
+}
+
+/// Gzip compression of responses
+///
+/// Responses that already have a `Content-Encoding`, partial responses and
+/// those marked `Cache-Control: no-transform` are sent as they are.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct CompressionConfig {
+    /// Compress responses for clients sending `Accept-Encoding: gzip`
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Smallest response compressed, in bytes; responses of unknown length
+    /// are always compressed
+    #[serde(default = "default_compression_min_bytes")]
+    pub min_bytes: u64,
+
+    /// Media types compressed; `text/*` covers every text type
+    #[serde(default = "default_compression_content_types")]
+    pub content_types: Vec<String>,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            min_bytes: default_compression_min_bytes(),
+            content_types: default_compression_content_types(),
+        }
+    }
+}
+
+/// Janus's own health endpoint, answered before any route matching
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct HealthEndpointConfig {
+    /// Enable the health endpoint
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+
+    /// Request path
+    #[serde(default = "default_health_endpoint_path")]
+    pub path: String,
+
+    /// Serve on the management port instead of the main listener
+    #[serde(default)]
+    pub on_management_port: bool,
+
+    /// Include health probes in the access log
+    #[serde(default)]
+    pub access_log: bool,
+
+    /// Upstreams that must have an available backend to report healthy
+    #[serde(default)]
+    pub critical_upstreams: Vec<String>,
+
+    /// Report unhealthy while the config file fails to reload
+    #[serde(default)]
+    pub fail_on_config_drift: bool,
+}
+
+impl Default for HealthEndpointConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            path: default_health_endpoint_path(),
+            on_management_port: false,
+            access_log: false,
+            critical_upstreams: Vec::new(),
+            fail_on_config_drift: false,
+        }
+    }
+}
+
+/// Janus's own status page, answered before any route matching with the
+/// status and statistics the TUI shows, as HTML or with `?format=json`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct StatusPageConfig {
+    /// Enable the status page
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Request path
+    #[serde(default = "default_status_page_path")]
+    pub path: String,
+
+    /// Token required as `?token=` or an `Authorization: Bearer` header
+    /// (unset = no token needed)
+    #[serde(default)]
+    pub token: Option<String>,
+}
+
+impl Default for StatusPageConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: default_status_page_path(),
+            token: None,
+        }
+    }
+}
+
+/// How requests are answered while maintenance mode is on
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct MaintenanceConfig {
+    /// Seconds clients are told to wait in `Retry-After`
+    #[serde(default = "default_maintenance_retry_after")]
+    pub retry_after_secs: u64,
+
+    /// HTML file served as the maintenance page, unless the switch came
+    /// with its own message (unset = built-in page)
+    #[serde(default)]
+    pub page: Option<String>,
+
+    /// File the switches are saved to, so they survive a restart (unset =
+    /// every switch is off after a restart)
+    #[serde(default)]
+    pub state_file: Option<String>,
+}
+
+impl Default for MaintenanceConfig {
+    fn default() -> Self {
+        Self {
+            retry_after_secs: default_maintenance_retry_after(),
+            page: None,
+            state_file: None,
         }
     }
 }
 
 /// Management API configuration
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct ManagementConfig {
     /// Enable management API
     #[serde(default = "default_true")]
@@ -73,6 +478,41 @@ pub struct ManagementConfig {
     /// Management port
     #[serde(default = "default_management_port")]
     pub port: u16,
+
+    /// Access tokens; when empty, every connection has admin access
+    #[serde(default)]
+    pub tokens: Vec<ManagementToken>,
+
+    /// PEM certificate chain to serve the API over TLS with
+    #[serde(default)]
+    pub tls_cert: Option<String>,
+
+    /// PEM private key for `tls_cert`
+    #[serde(default)]
+    pub tls_key: Option<String>,
+
+    /// Reject connections that present no client certificate
+    #[serde(default)]
+    pub require_client_cert: bool,
+
+    /// PEM CA certificates that client certificates must be signed by
+    #[serde(default)]
+    pub client_ca_cert: Option<String>,
+
+    /// Send clients an event for every proxied request, at most 100 a
+    /// second per client
+    #[serde(default)]
+    pub live_requests: bool,
+
+    /// File every management change and config file reload is appended
+    /// to, one JSON object per line
+    #[serde(default)]
+    pub audit_log: Option<String>,
+
+    /// Send clients the server statistics every this many milliseconds
+    /// without waiting for GetStats (read when a client connects)
+    #[serde(default)]
+    pub push_stats_interval_ms: Option<u64>,
 }
 
 impl Default for ManagementConfig {
@@ -81,12 +521,73 @@ impl Default for ManagementConfig {
             enabled: true,
             address: default_management_address(),
             port: default_management_port(),
+            tokens: Vec::new(),
+            tls_cert: None,
+            tls_key: None,
+            require_client_cert: false,
+            client_ca_cert: None,
+            live_requests: false,
+            audit_log: None,
+            push_stats_interval_ms: None,
+        }
+    }
+}
+
+impl ManagementConfig {
+    /// Whether the API is served over TLS
+    pub fn tls_enabled(&self) -> bool {
+        self.tls_cert.is_some()
+    }
+
+    /// Role granted to a session holding the given token
+    pub fn role_for(&self, token: Option<&str>) -> Option<ManagementRole> {
+        if self.tokens.is_empty() {
+            return Some(ManagementRole::Admin);
         }
+        let token = token?;
+        self.tokens
+            .iter()
+            .find(|t| t.token == token)
+            .map(|t| t.role)
     }
+
+    /// Name of the token a session holds, if it has one
+    pub fn token_name(&self, token: Option<&str>) -> Option<&str> {
+        let token = token?;
+        self.tokens
+            .iter()
+            .find(|t| t.token == token)
+            .and_then(|t| t.name.as_deref())
+    }
+}
+
+/// Management access token
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ManagementToken {
+    /// Secret presented by the client
+    pub token: String,
+
+    /// Operations the token allows
+    pub role: ManagementRole,
+
+    /// Who holds the token, recorded in the audit log in place of the
+    /// secret
+    #[serde(default)]
+    pub name: Option<String>,
+}
+
+/// Access level of a management session
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ManagementRole {
+    /// Status, configuration and statistics only
+    Read,
+    /// Every operation
+    Admin,
 }
 
 /// Upstream server configuration for reverse proxy
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct UpstreamConfig {
     /// List of backend servers
     pub servers: Vec<BackendServer>,
@@ -98,10 +599,101 @@ pub struct UpstreamConfig {
     /// Health check configuration
     #[serde(default)]
     pub health_check: Option<HealthCheckConfig>,
+
+    /// Seconds a draining upstream keeps rejecting requests before removal
+    #[serde(default = "default_drain_timeout")]
+    pub drain_timeout_secs: u64,
+
+    /// Headers set on every request sent to this upstream, replacing the
+    /// client's, or added after them for a value starting with `+` (route
+    /// headers are applied afterwards)
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+
+    /// Largest response header block accepted from a backend, in bytes
+    /// (at least 8192)
+    #[serde(default = "default_max_response_header_bytes")]
+    pub max_response_header_bytes: usize,
+
+    /// Seconds allowed for each attempt at a backend, within the route's
+    /// upstream timeout
+    #[serde(default)]
+    pub timeout_per_try: Option<u64>,
+
+    /// Seconds clients are told to wait before retrying (`Retry-After`)
+    /// when the upstream has no available backends
+    #[serde(default)]
+    pub retry_after_secs: Option<u64>,
+
+    /// Further attempts at a backend after a connection failure or a
+    /// response status in `retry_codes`, within the route's upstream
+    /// timeout (streamed bodies and upgrades are never retried)
+    #[serde(default)]
+    pub retries: u32,
+
+    /// Response statuses that trigger a retry
+    #[serde(default = "default_retry_codes")]
+    pub retry_codes: Vec<u16>,
+
+    /// Connections open to this upstream's backends at once, across every
+    /// route; further requests wait up to `queue_timeout_secs`, then get a
+    /// 503. A request holds one connection, retries included
+    #[serde(default)]
+    pub max_connections: Option<u32>,
+
+    /// Seconds a request waits for a connection under `max_connections`
+    /// (0 = no waiting)
+    #[serde(default = "default_queue_timeout")]
+    pub queue_timeout_secs: u64,
+}
+
+impl UpstreamConfig {
+    /// Upstream balancing round-robin over the given backend addresses,
+    /// with every other setting at its default
+    pub fn new<I, S>(addresses: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self {
+            servers: addresses.into_iter().map(BackendServer::new).collect(),
+            load_balancing: LoadBalancing::default(),
+            health_check: None,
+            drain_timeout_secs: default_drain_timeout(),
+            headers: HashMap::new(),
+            max_response_header_bytes: default_max_response_header_bytes(),
+            timeout_per_try: None,
+            retry_after_secs: None,
+            retries: 0,
+            retry_codes: default_retry_codes(),
+            max_connections: None,
+            queue_timeout_secs: default_queue_timeout(),
+        }
+    }
+
+    /// Upstream with a single backend
+    pub fn single(address: impl Into<String>) -> Self {
+        Self::new([address])
+    }
+
+    /// Time allowed for one attempt at a backend on behalf of a route
+    pub fn attempt_timeout(&self, route: &RouteConfig) -> Duration {
+        let total = route.upstream_timeout();
+        match self.timeout_per_try {
+            Some(secs) => total.min(Duration::from_secs(secs)),
+            None => total,
+        }
+    }
 }
 
+/// Smallest response header limit the HTTP client supports
+pub const MIN_RESPONSE_HEADER_BYTES: usize = 8192;
+
+/// Smallest request header limit the HTTP server supports
+pub const MIN_REQUEST_HEADER_BYTES: usize = 8192;
+
 /// Backend server definition
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct BackendServer {
     /// Server address (host:port or URL)
     pub address: String,
@@ -113,6 +705,29 @@ pub struct BackendServer {
     /// Whether this server is a backup
     #[serde(default)]
     pub backup: bool,
+
+    /// Send this server no new requests, letting those it has finish,
+    /// so it can be removed without dropping any
+    #[serde(default)]
+    pub drain: bool,
+
+    /// Requests sent to this server at once; requests beyond it go to
+    /// another server, or get a 503 when every server is full
+    #[serde(default)]
+    pub max_concurrent_requests: Option<u32>,
+}
+
+impl BackendServer {
+    /// Primary backend of default weight
+    pub fn new(address: impl Into<String>) -> Self {
+        Self {
+            address: address.into(),
+            weight: default_weight(),
+            backup: false,
+            drain: false,
+            max_concurrent_requests: None,
+        }
+    }
 }
 
 /// Load balancing strategies for distributing requests across backend servers
@@ -127,7 +742,7 @@ pub struct BackendServer {
 /// ]
 /// load_balancing = "round_robin"  # or "least_connections", "random", "ip_hash"
 /// ```
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum LoadBalancing {
     /// Round-robin distribution - requests are distributed sequentially to each server
@@ -143,7 +758,7 @@ pub enum LoadBalancing {
 }
 
 /// Health check configuration
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct HealthCheckConfig {
     /// Interval between health checks in seconds
     #[serde(default = "default_health_interval")]
@@ -156,18 +771,76 @@ pub struct HealthCheckConfig {
     /// Path to check
     #[serde(default = "default_health_path")]
     pub path: String,
+
+    /// Headers sent with each check, such as an API key (`${VAR}` in a
+    /// value is replaced by that environment variable)
+    #[serde(default)]
+    pub custom_headers: HashMap<String, String>,
+}
+
+impl HealthCheckConfig {
+    /// Headers to send with a check, with environment variables filled in
+    pub fn headers(&self) -> Result<Vec<(String, String)>, String> {
+        let mut headers = self
+            .custom_headers
+            .iter()
+            .map(|(name, value)| Ok((name.clone(), interpolate_env(value)?)))
+            .collect::<Result<Vec<_>, String>>()?;
+        headers.sort();
+        Ok(headers)
+    }
+}
+
+/// Replace each `${VAR}` in a value with that environment variable
+fn interpolate_env(value: &str) -> Result<String, String> {
+    let mut out = String::new();
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        let Some(len) = rest[start + 2..].find('}') else {
+            break;
+        };
+        let name = &rest[start + 2..start + 2 + len];
+        let var = std::env::var(name)
+            .map_err(|_| format!("environment variable '{}' is not set", name))?;
+        out.push_str(&rest[..start]);
+        out.push_str(&var);
+        rest = &rest[start + 3 + len..];
+    }
+    out.push_str(rest);
+    Ok(out)
 }
 
 /// Route configuration
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct RouteConfig {
     /// Route path pattern (supports wildcards)
     pub path: String,
 
+    /// Match requests against this route; a disabled route keeps its
+    /// settings but is skipped
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+
     /// HTTP methods to match (empty = all)
     #[serde(default)]
     pub methods: Vec<String>,
 
+    /// What to do when the path matches but the method does not
+    #[serde(default)]
+    pub on_method_mismatch: MethodMismatch,
+
+    /// Request headers that must all be present and match (values may use
+    /// `*` as a wildcard)
+    #[serde(default)]
+    pub match_headers: HashMap<String, String>,
+
+    /// Query parameters that must all be present and match (values may use
+    /// `*` as a wildcard), compared after decoding `+` and percent-escapes
+    /// in names and values. Such routes are tried before routes with the
+    /// same path that do not match on the query
+    #[serde(default)]
+    pub query_match: HashMap<String, String>,
+
     /// Upstream name to proxy to
     pub upstream: String,
 
@@ -175,41 +848,444 @@ pub struct RouteConfig {
     #[serde(default)]
     pub rewrite: Option<String>,
 
-    /// Additional headers to add
+    /// Headers set on requests sent upstream, replacing those of the client
+    /// and upstream, or added after them for a value starting with `+`
     #[serde(default)]
     pub headers: HashMap<String, String>,
 
-    /// Request timeout in seconds
+    /// Request timeout in seconds (default for the timeouts below)
     #[serde(default = "default_timeout")]
     pub timeout: u64,
-}
-
-/// Static file serving configuration
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct StaticFileConfig {
-    /// URL path prefix
-    pub path: String,
 
-    /// Root directory for static files
-    pub root: String,
+    /// Seconds from sending the request upstream to receiving the last
+    /// byte of its response
+    #[serde(default)]
+    pub upstream_timeout_secs: Option<u64>,
 
-    /// Index file name
-    #[serde(default = "default_index")]
-    pub index: String,
+    /// Total seconds for the request, including the client body upload
+    #[serde(default)]
+    pub client_timeout_secs: Option<u64>,
 
-    /// Enable directory listing
+    /// Seconds a WebSocket session may stay open after the upgrade, in
+    /// total rather than per message (unset = no limit; `timeout` does not
+    /// apply to upgraded connections)
     #[serde(default)]
-    pub directory_listing: bool,
-}
+    pub websocket_timeout: Option<u64>,
 
-// Default value functions
-fn default_bind_address() -> String {
-    "0.0.0.0".to_string()
-}
+    /// Rewrite `Location` and `Content-Location` response headers that point
+    /// at the backend to the scheme and host the client used
+    #[serde(default = "default_true")]
+    pub proxy_redirect: bool,
 
-fn default_port() -> u16 {
-    8080
-}
+    /// Remove the route's prefix before forwarding, so `/api/*` sends
+    /// `/api/users` upstream as `/users` (ignored when `rewrite` is set)
+    #[serde(default)]
+    pub strip_prefix: bool,
+
+    /// Send the request body upstream as it arrives instead of reading it
+    /// all first, for large uploads
+    #[serde(default)]
+    pub stream_request_body: bool,
+
+    /// Keep large request bodies in a temporary file rather than memory
+    /// while they are proxied
+    #[serde(default)]
+    pub body_spool: Option<BodySpoolConfig>,
+
+    /// Ask an auth service whether to proxy each request
+    #[serde(default)]
+    pub auth_request: Option<AuthRequestConfig>,
+
+    /// Headers set on upstream responses, replacing any of the same name
+    #[serde(default)]
+    pub add_response_headers: HashMap<String, String>,
+
+    /// Headers removed from upstream responses, such as `Server`
+    #[serde(default)]
+    pub remove_response_headers: Vec<String>,
+
+    /// Keep successful responses and answer repeats of the request from
+    /// memory
+    #[serde(default)]
+    pub cache: Option<CacheConfig>,
+}
+
+impl RouteConfig {
+    /// Route proxying a path pattern to an upstream, with every other
+    /// setting at its default
+    pub fn new(path: impl Into<String>, upstream: impl Into<String>) -> Self {
+        Self {
+            path: path.into(),
+            enabled: true,
+            methods: vec![],
+            on_method_mismatch: MethodMismatch::default(),
+            match_headers: HashMap::new(),
+            query_match: HashMap::new(),
+            upstream: upstream.into(),
+            rewrite: None,
+            headers: HashMap::new(),
+            timeout: default_timeout(),
+            upstream_timeout_secs: None,
+            client_timeout_secs: None,
+            websocket_timeout: None,
+            proxy_redirect: true,
+            strip_prefix: false,
+            stream_request_body: false,
+            body_spool: None,
+            auth_request: None,
+            add_response_headers: HashMap::new(),
+            remove_response_headers: vec![],
+            cache: None,
+        }
+    }
+
+    /// Set the request timeout in seconds
+    pub fn timeout(mut self, secs: u64) -> Self {
+        self.timeout = secs;
+        self
+    }
+
+    /// Match only these methods
+    pub fn methods<I, S>(mut self, methods: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.methods = methods.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Set a header on requests sent upstream, or add it after any others
+    /// for a value starting with `+`
+    ///
+    /// # Panics
+    ///
+    /// If the name is not a valid header name.
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.insert(header_name(name), value.into());
+        self
+    }
+
+    /// Match only requests carrying this header (the value may use `*`)
+    ///
+    /// # Panics
+    ///
+    /// If the name is not a valid header name.
+    pub fn match_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.match_headers.insert(header_name(name), value.into());
+        self
+    }
+
+    /// Match only requests with this query parameter (the value may use
+    /// `*`)
+    pub fn match_query(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.query_match.insert(name.into(), value.into());
+        self
+    }
+
+    /// Rewrite the path sent upstream
+    pub fn rewrite(mut self, path: impl Into<String>) -> Self {
+        self.rewrite = Some(path.into());
+        self
+    }
+
+    /// Remove the route's prefix before forwarding
+    pub fn strip_prefix(mut self, strip: bool) -> Self {
+        self.strip_prefix = strip;
+        self
+    }
+
+    /// Ask an auth service whether to proxy each request
+    pub fn auth_request(mut self, auth: AuthRequestConfig) -> Self {
+        self.auth_request = Some(auth);
+        self
+    }
+
+    /// Set a header on upstream responses
+    ///
+    /// # Panics
+    ///
+    /// If the name is not a valid header name.
+    pub fn add_response_header(
+        mut self,
+        name: impl Into<String>,
+        value: impl Into<String>,
+    ) -> Self {
+        self.add_response_headers
+            .insert(header_name(name), value.into());
+        self
+    }
+
+    /// Remove a header from upstream responses
+    ///
+    /// # Panics
+    ///
+    /// If the name is not a valid header name.
+    pub fn remove_response_header(mut self, name: impl Into<String>) -> Self {
+        self.remove_response_headers.push(header_name(name));
+        self
+    }
+
+    /// Literal prefix of the route path, without its trailing wildcard
+    pub fn prefix(&self) -> &str {
+        self.path.trim_end_matches("/*").trim_end_matches('*')
+    }
+}
+
+impl RouteConfig {
+    /// Time allowed for the upstream exchange
+    pub fn upstream_timeout(&self) -> Duration {
+        Duration::from_secs(self.upstream_timeout_secs.unwrap_or(self.timeout))
+    }
+
+    /// Time allowed for the whole request
+    pub fn client_timeout(&self) -> Duration {
+        Duration::from_secs(self.client_timeout_secs.unwrap_or(self.timeout))
+    }
+}
+
+/// Spooling of a route's request bodies to disk
+///
+/// Bodies up to `memory_limit_bytes` stay in memory; larger ones are
+/// written to a temporary file, which is sent upstream and deleted once the
+/// request is done, however it ends.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct BodySpoolConfig {
+    /// Largest body kept in memory, in bytes
+    #[serde(default = "default_spool_memory_limit")]
+    pub memory_limit_bytes: u64,
+
+    /// Directory for spool files (the system temporary directory if unset)
+    #[serde(default)]
+    pub directory: Option<String>,
+}
+
+impl Default for BodySpoolConfig {
+    fn default() -> Self {
+        Self {
+            memory_limit_bytes: default_spool_memory_limit(),
+            directory: None,
+        }
+    }
+}
+
+/// Caching of a route's responses in memory
+///
+/// Only `GET` and `HEAD` requests without an `Authorization` header are
+/// cached, and only `200` responses the upstream does not mark
+/// `Cache-Control: no-store` or `private`. Cache hits are still checked
+/// by the route's `auth_request`, and carry `X-Cache: HIT`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct CacheConfig {
+    /// Seconds a response is served from the cache
+    #[serde(default = "default_cache_ttl")]
+    pub ttl_secs: u64,
+
+    /// Most responses kept for the route; the oldest make way for new ones
+    #[serde(default = "default_cache_max_entries")]
+    pub max_entries: usize,
+
+    /// Request headers whose values are cached separately, such as
+    /// `Accept-Language`
+    #[serde(default)]
+    pub vary_on: Vec<String>,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            ttl_secs: default_cache_ttl(),
+            max_entries: default_cache_max_entries(),
+            vary_on: vec![],
+        }
+    }
+}
+
+/// Subrequest made before proxying, in the manner of nginx's
+/// `auth_request`: a 2xx answer lets the request through, 401 or 403 turns
+/// it away with that status, and anything else is an error
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct AuthRequestConfig {
+    /// Upstream running the auth service
+    pub upstream: String,
+
+    /// Path requested from the auth service
+    pub path: String,
+
+    /// Request headers passed on to the auth service
+    #[serde(default = "default_auth_forward_headers")]
+    pub forward_headers: Vec<String>,
+
+    /// Headers of an allowing answer added to the proxied request, such as
+    /// `X-User-Id`
+    #[serde(default)]
+    pub copy_headers: Vec<String>,
+
+    /// Return the auth service's body with a 401 or 403 instead of the
+    /// built-in error page
+    #[serde(default)]
+    pub forward_body: bool,
+
+    /// Seconds to wait for the auth service
+    #[serde(default = "default_auth_timeout")]
+    pub timeout_secs: u64,
+}
+
+/// Order requests are matched against static mounts and routes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchOrder {
+    /// Static mounts, then routes
+    #[default]
+    StaticFirst,
+    /// Routes, then static mounts
+    RoutesFirst,
+}
+
+/// How the config file is watched for changes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum WatchMode {
+    /// File system events (inotify, kqueue), which NFS and other network
+    /// file systems do not deliver
+    Inotify,
+    /// Check the file's modification time every `poll_interval_ms`
+    Poll,
+    /// File system events, or polling where they are unavailable
+    #[default]
+    Auto,
+}
+
+/// Handling of requests whose method a route does not accept
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum MethodMismatch {
+    /// Try the remaining routes
+    #[default]
+    Continue,
+    /// Answer 405 Method Not Allowed with the route's methods in `Allow`
+    #[serde(rename = "reject_405")]
+    Reject405,
+}
+
+/// Static file serving configuration
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct StaticFileConfig {
+    /// URL path prefix
+    pub path: String,
+
+    /// Serve files from this mount; a disabled mount keeps its settings
+    /// but is skipped
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+
+    /// Root directory for static files
+    pub root: String,
+
+    /// Index file names tried in order when a directory is requested (a
+    /// single name is also accepted)
+    #[serde(default = "default_index", deserialize_with = "one_or_many")]
+    pub index: Vec<String>,
+
+    /// Enable directory listing
+    #[serde(default)]
+    pub directory_listing: bool,
+
+    /// Maximum entries shown in a directory listing before it is truncated
+    #[serde(default = "default_listing_limit")]
+    pub listing_limit: usize,
+
+    /// HTML file directory listings are rendered into instead of the
+    /// built-in page: `{entries}` becomes one `<li>` link per entry and
+    /// `{path}` the directory's URL path
+    #[serde(default)]
+    pub directory_listing_template: Option<String>,
+
+    /// Headers added to files and listings served from this directory,
+    /// replacing any of the same name (including `Content-Type`)
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+
+    /// Let requests for paths the mount does not have go on to the
+    /// remaining mounts and routes; when off, they are answered 404 here
+    #[serde(default = "default_true")]
+    pub fallthrough: bool,
+}
+
+impl StaticFileConfig {
+    /// Mount serving a directory under a URL path prefix, with every other
+    /// setting at its default
+    pub fn new(path: impl Into<String>, root: impl Into<String>) -> Self {
+        Self {
+            path: path.into(),
+            enabled: true,
+            root: root.into(),
+            index: default_index(),
+            directory_listing: false,
+            listing_limit: default_listing_limit(),
+            directory_listing_template: None,
+            headers: HashMap::new(),
+            fallthrough: true,
+        }
+    }
+
+    /// Enable or disable directory listing
+    pub fn directory_listing(mut self, enabled: bool) -> Self {
+        self.directory_listing = enabled;
+        self
+    }
+
+    /// Add a header to files and listings served from this directory
+    ///
+    /// # Panics
+    ///
+    /// If the name is not a valid header name.
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.insert(header_name(name), value.into());
+        self
+    }
+}
+
+// Default value functions
+fn default_bind_address() -> String {
+    "0.0.0.0".to_string()
+}
+
+fn default_port() -> u16 {
+    8080
+}
+
+fn default_access_log_sample_rate() -> f64 {
+    1.0
+}
+
+fn default_error_rate_alert_threshold() -> f64 {
+    0.05
+}
+
+fn default_tcp_backlog() -> u32 {
+    1024
+}
+
+fn default_error_log_burst() -> u32 {
+    5
+}
+
+fn default_error_log_summary_interval() -> u64 {
+    60
+}
+
+fn default_health_endpoint_path() -> String {
+    "/_janus/health".to_string()
+}
+
+fn default_status_page_path() -> String {
+    "/_janus/status".to_string()
+}
+
+fn default_maintenance_retry_after() -> u64 {
+    300
+}
 
 fn default_management_address() -> String {
     "127.0.0.1".to_string()
@@ -219,6 +1295,18 @@ fn default_management_port() -> u16 {
     9090
 }
 
+fn default_https_port() -> u16 {
+    443
+}
+
+fn default_acme_directory_url() -> String {
+    "https://acme-v02.api.letsencrypt.org/directory".to_string()
+}
+
+fn default_renew_before_days() -> u64 {
+    30
+}
+
 fn default_true() -> bool {
     true
 }
@@ -243,15 +1331,122 @@ fn default_timeout() -> u64 {
     60
 }
 
-fn default_index() -> String {
-    "index.html".to_string()
+fn default_auth_forward_headers() -> Vec<String> {
+    vec!["Authorization".to_string(), "Cookie".to_string()]
+}
+
+fn default_auth_timeout() -> u64 {
+    5
+}
+
+fn default_compression_min_bytes() -> u64 {
+    1024
+}
+
+fn default_compression_content_types() -> Vec<String> {
+    [
+        "text/*",
+        "application/javascript",
+        "application/json",
+        "application/xml",
+        "image/svg+xml",
+    ]
+    .map(String::from)
+    .to_vec()
+}
+
+fn default_https_redirect_code() -> u16 {
+    301
+}
+
+fn default_drain_timeout() -> u64 {
+    30
+}
+
+fn default_max_response_header_bytes() -> usize {
+    64 * 1024
+}
+
+fn default_retry_codes() -> Vec<u16> {
+    vec![500, 502, 503, 504]
+}
+
+fn default_cache_ttl() -> u64 {
+    60
+}
+
+fn default_cache_max_entries() -> usize {
+    1000
+}
+
+fn default_queue_timeout() -> u64 {
+    5
+}
+
+fn default_spool_memory_limit() -> u64 {
+    1024 * 1024
+}
+
+fn default_body_spool_max_bytes() -> u64 {
+    1024 * 1024 * 1024
+}
+
+fn default_max_uri_bytes() -> usize {
+    8 * 1024
+}
+
+fn default_max_request_headers() -> usize {
+    100
+}
+
+fn default_max_request_header_bytes() -> usize {
+    64 * 1024
+}
+
+fn default_idle_connection_timeout() -> u64 {
+    60
+}
+
+fn default_poll_interval() -> u64 {
+    1000
+}
+
+fn default_index() -> Vec<String> {
+    vec!["index.html".to_string()]
+}
+
+/// Accept either a single string or a list of strings
+fn one_or_many<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(name) => vec![name],
+        OneOrMany::Many(names) => names,
+    })
+}
+
+fn default_listing_limit() -> usize {
+    5000
 }
 
 impl JanusConfig {
+    /// Start building a configuration in code
+    pub fn builder() -> JanusConfigBuilder {
+        JanusConfigBuilder::default()
+    }
+
     /// Load configuration from a TOML file
     pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
-        let content = std::fs::read_to_string(path.as_ref())
-            .map_err(|e| ConfigError::IoError(e.to_string()))?;
+        let content =
+            std::fs::read_to_string(path.as_ref()).map_err(|source| ConfigError::IoError {
+                path: path.as_ref().to_path_buf(),
+                source,
+            })?;
         Self::from_toml(&content)
     }
 
@@ -260,25 +1455,757 @@ impl JanusConfig {
         toml::from_str(content).map_err(|e| ConfigError::ParseError(e.to_string()))
     }
 
+    /// Load configuration from a file, applying the overrides of a profile
+    pub fn load_with_profile<P: AsRef<Path>>(path: P, profile: &str) -> Result<Self, ConfigError> {
+        JanusConfigFile::load(path)?.resolve(Some(profile))
+    }
+
     /// Save configuration to a TOML file
     pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), ConfigError> {
-        let content =
-            toml::to_string_pretty(self).map_err(|e| ConfigError::SerializeError(e.to_string()))?;
-        std::fs::write(path.as_ref(), content).map_err(|e| ConfigError::IoError(e.to_string()))?;
-        Ok(())
+        write_atomically(path.as_ref(), &self.to_toml()?)
     }
 
     /// Convert to TOML string
     pub fn to_toml(&self) -> Result<String, ConfigError> {
         toml::to_string_pretty(self).map_err(|e| ConfigError::SerializeError(e.to_string()))
     }
+
+    /// JSON with the keys of every object sorted, so equal configurations
+    /// give the same text whatever order their maps iterate in
+    pub fn canonical_json(&self) -> String {
+        fn sorted(value: serde_json::Value) -> serde_json::Value {
+            match value {
+                serde_json::Value::Object(map) => {
+                    let mut entries: Vec<_> = map.into_iter().collect();
+                    entries.sort_by(|a, b| a.0.cmp(&b.0));
+                    entries
+                        .into_iter()
+                        .map(|(key, value)| (key, sorted(value)))
+                        .collect()
+                }
+                serde_json::Value::Array(items) => items.into_iter().map(sorted).collect(),
+                other => other,
+            }
+        }
+
+        // Every map in the configuration is keyed by strings
+        let value = serde_json::to_value(self).expect("configuration serializes to JSON");
+        sorted(value).to_string()
+    }
+
+    /// Short hash of the canonical JSON, for checking that two copies of
+    /// the configuration agree
+    ///
+    /// FNV-1a rather than the standard library's hasher, whose output may
+    /// change between Rust versions.
+    pub fn config_hash(&self) -> String {
+        let hash = self
+            .canonical_json()
+            .bytes()
+            .fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+                (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+            });
+        format!("{:016x}", hash)
+    }
+
+    /// JSON Schema of the configuration, with doc comments as descriptions
+    pub fn json_schema() -> RootSchema {
+        schemars::schema_for!(JanusConfig)
+    }
+}
+
+/// Builder for a [`JanusConfig`], validated when built
+///
+/// ```
+/// use janus_common::{JanusConfig, RouteConfig, UpstreamConfig};
+///
+/// let config = JanusConfig::builder()
+///     .port(8080)
+///     .upstream("api", UpstreamConfig::single("127.0.0.1:3000"))
+///     .route(RouteConfig::new("/api/*", "api").timeout(30).methods(["GET"]))
+///     .build()
+///     .unwrap();
+/// assert_eq!(config.routes[0].timeout, 30);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct JanusConfigBuilder {
+    config: JanusConfig,
+}
+
+impl JanusConfigBuilder {
+    /// Address to bind to
+    pub fn bind_address(mut self, address: impl Into<String>) -> Self {
+        self.config.server.bind_address = address.into();
+        self
+    }
+
+    /// Port to listen on
+    pub fn port(mut self, port: u16) -> Self {
+        self.config.server.port = port;
+        self
+    }
+
+    /// Management API settings
+    pub fn management(mut self, management: ManagementConfig) -> Self {
+        self.config.management = management;
+        self
+    }
+
+    /// Add or replace an upstream
+    pub fn upstream(mut self, name: impl Into<String>, upstream: UpstreamConfig) -> Self {
+        self.config.upstreams.insert(name.into(), upstream);
+        self
+    }
+
+    /// Upstream receiving requests nothing else matched
+    pub fn default_upstream(mut self, name: impl Into<String>) -> Self {
+        self.config.server.default_upstream = Some(name.into());
+        self
+    }
+
+    /// Add a route, after those already added
+    pub fn route(mut self, route: RouteConfig) -> Self {
+        self.config.routes.push(route);
+        self
+    }
+
+    /// Add a static directory
+    pub fn static_dir(mut self, static_dir: StaticFileConfig) -> Self {
+        self.config.static_files.push(static_dir);
+        self
+    }
+
+    /// Validate and return the configuration
+    pub fn build(self) -> Result<JanusConfig, ConfigError> {
+        validate_config(&self.config)?;
+        Ok(self.config)
+    }
+}
+
+/// Write a file next to its destination and rename it into place, so
+/// readers never see a partially written config
+fn write_atomically(path: &Path, content: &str) -> Result<(), ConfigError> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    let temp = PathBuf::from(temp);
+    std::fs::write(&temp, content).map_err(|source| ConfigError::IoError {
+        path: temp.clone(),
+        source,
+    })?;
+    std::fs::rename(&temp, path).map_err(|source| ConfigError::IoError {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+/// A config file with a base configuration and named profiles that
+/// override parts of it, e.g. `[profiles.prod.server]`
+///
+/// Profiles are kept as raw TOML tables, so a profile only overrides the
+/// settings it names. Tables are merged key by key; other values, including
+/// the `routes` and `static_files` arrays, are replaced whole.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct JanusConfigFile {
+    /// Settings shared by every profile
+    pub base: toml::Table,
+
+    /// Overrides by profile name
+    pub profiles: HashMap<String, toml::Table>,
+}
+
+impl JanusConfigFile {
+    /// Load a config file and its profiles
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
+        let content =
+            std::fs::read_to_string(path.as_ref()).map_err(|source| ConfigError::IoError {
+                path: path.as_ref().to_path_buf(),
+                source,
+            })?;
+        Self::from_toml(&content)
+    }
+
+    /// Parse a config file and its profiles from a TOML string
+    pub fn from_toml(content: &str) -> Result<Self, ConfigError> {
+        let mut base: toml::Table =
+            toml::from_str(content).map_err(|e| ConfigError::ParseError(e.to_string()))?;
+        let profiles = match base.remove("profiles") {
+            Some(profiles) => profiles
+                .try_into()
+                .map_err(|e: toml::de::Error| ConfigError::ParseError(e.to_string()))?,
+            None => HashMap::new(),
+        };
+        Ok(Self { base, profiles })
+    }
+
+    /// Configuration for a profile, or the base configuration for `None`
+    pub fn resolve(&self, profile: Option<&str>) -> Result<JanusConfig, ConfigError> {
+        let mut table = self.base.clone();
+        if let Some(name) = profile {
+            let overrides = self
+                .profiles
+                .get(name)
+                .ok_or_else(|| ConfigError::UnknownProfile(name.to_string()))?;
+            merge_tables(&mut table, overrides);
+        }
+        table
+            .try_into()
+            .map_err(|e: toml::de::Error| ConfigError::ParseError(e.to_string()))
+    }
+
+    /// Store a configuration as the base or under a profile, where only the
+    /// settings that differ from the base are kept
+    ///
+    /// Settings a profile removes (such as an unset `default_upstream`)
+    /// cannot be expressed as an override and fall back to the base value.
+    pub fn set(&mut self, profile: Option<&str>, config: &JanusConfig) -> Result<(), ConfigError> {
+        let table = toml::Table::try_from(config)
+            .map_err(|e| ConfigError::SerializeError(e.to_string()))?;
+        match profile {
+            Some(name) => {
+                let overrides = table_diff(&self.base, &table);
+                self.profiles.insert(name.to_string(), overrides);
+            }
+            None => self.base = table,
+        }
+        Ok(())
+    }
+
+    /// Convert to a TOML string, with the profiles after the base settings
+    pub fn to_toml(&self) -> Result<String, ConfigError> {
+        let mut content = self.resolve(None)?.to_toml()?;
+        if !self.profiles.is_empty() {
+            let profiles: toml::Table = [(
+                "profiles".to_string(),
+                toml::Value::try_from(&self.profiles)
+                    .map_err(|e| ConfigError::SerializeError(e.to_string()))?,
+            )]
+            .into_iter()
+            .collect();
+            content.push('\n');
+            content.push_str(
+                &toml::to_string_pretty(&profiles)
+                    .map_err(|e| ConfigError::SerializeError(e.to_string()))?,
+            );
+        }
+        Ok(content)
+    }
+
+    /// Save the config file and its profiles
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), ConfigError> {
+        write_atomically(path.as_ref(), &self.to_toml()?)
+    }
+}
+
+/// Apply overrides on top of a table, merging nested tables
+fn merge_tables(base: &mut toml::Table, overrides: &toml::Table) {
+    for (key, value) in overrides {
+        match (base.get_mut(key), value) {
+            (Some(toml::Value::Table(existing)), toml::Value::Table(value)) => {
+                merge_tables(existing, value)
+            }
+            _ => {
+                base.insert(key.clone(), value.clone());
+            }
+        }
+    }
+}
+
+/// Entries of `target` that differ from `base`, the inverse of
+/// [`merge_tables`]
+fn table_diff(base: &toml::Table, target: &toml::Table) -> toml::Table {
+    let mut diff = toml::Table::new();
+    for (key, value) in target {
+        match (base.get(key), value) {
+            (Some(old), _) if old == value => {}
+            (Some(toml::Value::Table(old)), toml::Value::Table(new)) => {
+                diff.insert(key.clone(), toml::Value::Table(table_diff(old, new)));
+            }
+            _ => {
+                diff.insert(key.clone(), value.clone());
+            }
+        }
+    }
+    diff
+}
+
+impl std::str::FromStr for JanusConfig {
+    type Err = ConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::from_toml(s)
+    }
+}
+
+impl TryFrom<&str> for JanusConfig {
+    type Error = ConfigError;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        Self::from_toml(s)
+    }
+}
+
+/// A single problem found when validating a configuration
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ValidationIssue {
+    /// Path of the offending setting, e.g. `routes[0].upstream`
+    pub field: String,
+
+    /// What is wrong with it
+    pub message: String,
+}
+
+impl std::fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+/// Check a configuration for errors that would break the running server,
+/// reporting every problem found
+pub fn validate_config(config: &JanusConfig) -> Result<(), ConfigError> {
+    let mut issues = Vec::new();
+    let mut invalid = |field: String, message: String| {
+        issues.push(ValidationIssue { field, message });
+    };
+
+    // Validate port numbers
+    if config.server.port == 0 {
+        invalid(
+            "server.port".to_string(),
+            "Server port cannot be 0".to_string(),
+        );
+    }
+
+    if config.management.enabled && config.management.port == 0 {
+        invalid(
+            "management.port".to_string(),
+            "Management port cannot be 0".to_string(),
+        );
+    }
+
+    let management = &config.management;
+    if management.tls_cert.is_some() != management.tls_key.is_some() {
+        invalid(
+            "management.tls_key".to_string(),
+            "Management TLS needs both tls_cert and tls_key".to_string(),
+        );
+    }
+    if management.client_ca_cert.is_some() && !management.tls_enabled() {
+        invalid(
+            "management.client_ca_cert".to_string(),
+            "Client certificates need management TLS (tls_cert and tls_key)".to_string(),
+        );
+    }
+    if management.push_stats_interval_ms == Some(0) {
+        invalid(
+            "management.push_stats_interval_ms".to_string(),
+            "Stats push interval cannot be 0".to_string(),
+        );
+    }
+    if management.require_client_cert && management.client_ca_cert.is_none() {
+        invalid(
+            "management.require_client_cert".to_string(),
+            "Requiring client certificates needs client_ca_cert".to_string(),
+        );
+    }
+    let health = &config.server.health_endpoint;
+    if management.tls_enabled() && health.enabled && health.on_management_port {
+        invalid(
+            "server.health_endpoint.on_management_port".to_string(),
+            "Health probes cannot be served on a TLS management port".to_string(),
+        );
+    }
+    let status_page = &config.server.status_page;
+    if status_page.enabled && !status_page.path.starts_with('/') {
+        invalid(
+            "server.status_page.path".to_string(),
+            "Status page path must start with '/'".to_string(),
+        );
+    }
+
+    if let Some(ref acme) = config.server.acme {
+        if acme.domains.is_empty() {
+            invalid(
+                "server.acme.domains".to_string(),
+                "ACME needs at least one domain".to_string(),
+            );
+        }
+        if acme.contact_email.trim().is_empty() {
+            invalid(
+                "server.acme.contact_email".to_string(),
+                "ACME needs a contact email".to_string(),
+            );
+        }
+        if acme.https_port != 0 && acme.https_port == config.server.port {
+            invalid(
+                "server.acme.https_port".to_string(),
+                format!("HTTPS port {} is already the HTTP port", acme.https_port),
+            );
+        }
+    }
+
+    if !(0.0..=1.0).contains(&config.server.access_log_sample_rate) {
+        invalid(
+            "server.access_log_sample_rate".to_string(),
+            format!(
+                "Access log sample rate must be between 0.0 and 1.0, not {}",
+                config.server.access_log_sample_rate
+            ),
+        );
+    }
+
+    if ![301, 302, 307, 308].contains(&config.server.https_redirect_code) {
+        invalid(
+            "server.https_redirect_code".to_string(),
+            format!(
+                "HTTPS redirect code must be 301, 302, 307 or 308, not {}",
+                config.server.https_redirect_code
+            ),
+        );
+    }
+
+    if let Some(ref limit) = config.server.rate_limit {
+        if limit.requests_per_second == 0 {
+            invalid(
+                "server.rate_limit.requests_per_second".to_string(),
+                "Rate limit must allow at least 1 request per second".to_string(),
+            );
+        }
+        if limit.burst == Some(0) {
+            invalid(
+                "server.rate_limit.burst".to_string(),
+                "Rate limit burst must allow at least 1 request".to_string(),
+            );
+        }
+    }
+
+    if let Some(ref auth) = config.server.auth_request {
+        if !config.upstreams.contains_key(&auth.upstream) {
+            invalid(
+                "server.auth_request.upstream".to_string(),
+                format!(
+                    "Auth requests go to non-existent upstream '{}'",
+                    auth.upstream
+                ),
+            );
+        }
+        if !auth.path.starts_with('/') {
+            invalid(
+                "server.auth_request.path".to_string(),
+                "Auth request path must start with '/'".to_string(),
+            );
+        }
+    }
+
+    if config.server.poll_interval_ms == 0 {
+        invalid(
+            "server.poll_interval_ms".to_string(),
+            "Poll interval must be at least 1 millisecond".to_string(),
+        );
+    }
+
+    if config.server.max_uri_bytes == 0 {
+        invalid(
+            "server.max_uri_bytes".to_string(),
+            "URI limit must be at least 1 byte".to_string(),
+        );
+    }
+
+    if config.server.max_request_headers == 0 {
+        invalid(
+            "server.max_request_headers".to_string(),
+            "Request header limit must allow at least 1 header".to_string(),
+        );
+    }
+
+    if config.server.max_request_header_bytes < MIN_REQUEST_HEADER_BYTES {
+        invalid(
+            "server.max_request_header_bytes".to_string(),
+            format!(
+                "Request header limit must be at least {} bytes",
+                MIN_REQUEST_HEADER_BYTES
+            ),
+        );
+    }
+
+    if let Some(ref upstream) = config.server.default_upstream {
+        if !config.upstreams.contains_key(upstream) {
+            invalid(
+                "server.default_upstream".to_string(),
+                format!("Default upstream '{}' does not exist", upstream),
+            );
+        }
+    }
+
+    for (i, route) in config.routes.iter().enumerate() {
+        // Validate routes reference existing upstreams
+        if !config.upstreams.contains_key(&route.upstream) {
+            invalid(
+                format!("routes[{}].upstream", i),
+                format!(
+                    "Route '{}' references non-existent upstream '{}'",
+                    route.path, route.upstream
+                ),
+            );
+        }
+
+        if route.strip_prefix && route.prefix().trim_matches('/').is_empty() {
+            invalid(
+                format!("routes[{}].strip_prefix", i),
+                format!("Route '{}' has no prefix to strip", route.path),
+            );
+        }
+
+        // Validate header names used for matching
+        let mut names: Vec<_> = route
+            .match_headers
+            .keys()
+            .filter(|name| !is_valid_header_name(name))
+            .collect();
+        names.sort();
+        for name in names {
+            invalid(
+                format!("routes[{}].match_headers", i),
+                format!(
+                    "Route '{}' matches on invalid header name '{}'",
+                    route.path, name
+                ),
+            );
+        }
+
+        if route.query_match.contains_key("") {
+            invalid(
+                format!("routes[{}].query_match", i),
+                format!(
+                    "Route '{}' matches on a query parameter with no name",
+                    route.path
+                ),
+            );
+        }
+
+        let mut names: Vec<_> = route
+            .add_response_headers
+            .keys()
+            .filter(|name| !is_valid_header_name(name))
+            .collect();
+        names.sort();
+        for name in names {
+            invalid(
+                format!("routes[{}].add_response_headers", i),
+                format!(
+                    "Route '{}' adds invalid response header name '{}'",
+                    route.path, name
+                ),
+            );
+        }
+        for name in route
+            .remove_response_headers
+            .iter()
+            .filter(|name| !is_valid_header_name(name))
+        {
+            invalid(
+                format!("routes[{}].remove_response_headers", i),
+                format!(
+                    "Route '{}' removes invalid response header name '{}'",
+                    route.path, name
+                ),
+            );
+        }
+
+        if route.body_spool.is_some() && route.stream_request_body {
+            invalid(
+                format!("routes[{}].body_spool", i),
+                format!(
+                    "Route '{}' cannot spool a request body it streams",
+                    route.path
+                ),
+            );
+        }
+
+        if let Some(ref cache) = route.cache {
+            if cache.ttl_secs == 0 || cache.max_entries == 0 {
+                invalid(
+                    format!("routes[{}].cache", i),
+                    format!(
+                        "Route '{}' cache needs a TTL and room for at least 1 entry",
+                        route.path
+                    ),
+                );
+            }
+            for name in cache
+                .vary_on
+                .iter()
+                .filter(|name| !is_valid_header_name(name))
+            {
+                invalid(
+                    format!("routes[{}].cache.vary_on", i),
+                    format!(
+                        "Route '{}' cache varies on invalid header name '{}'",
+                        route.path, name
+                    ),
+                );
+            }
+        }
+
+        if let Some(ref auth) = route.auth_request {
+            if !config.upstreams.contains_key(&auth.upstream) {
+                invalid(
+                    format!("routes[{}].auth_request.upstream", i),
+                    format!(
+                        "Route '{}' sends auth requests to non-existent upstream '{}'",
+                        route.path, auth.upstream
+                    ),
+                );
+            }
+            if !auth.path.starts_with('/') {
+                invalid(
+                    format!("routes[{}].auth_request.path", i),
+                    format!(
+                        "Route '{}' auth request path must start with '/'",
+                        route.path
+                    ),
+                );
+            }
+            let names = auth.forward_headers.iter().chain(&auth.copy_headers);
+            for name in names.filter(|name| !is_valid_header_name(name)) {
+                invalid(
+                    format!("routes[{}].auth_request", i),
+                    format!(
+                        "Route '{}' auth request names invalid header '{}'",
+                        route.path, name
+                    ),
+                );
+            }
+        }
+    }
+
+    for (i, static_config) in config.static_files.iter().enumerate() {
+        let mut names: Vec<_> = static_config
+            .headers
+            .keys()
+            .filter(|name| !is_valid_header_name(name))
+            .collect();
+        names.sort();
+        for name in names {
+            invalid(
+                format!("static_files[{}].headers", i),
+                format!(
+                    "Static directory '{}' sets invalid header name '{}'",
+                    static_config.path, name
+                ),
+            );
+        }
+    }
+
+    // Validate upstreams have at least one server
+    let mut upstreams: Vec<_> = config.upstreams.iter().collect();
+    upstreams.sort_by_key(|(name, _)| name.as_str());
+    for (name, upstream) in upstreams {
+        if let Some(ref check) = upstream.health_check {
+            let mut names: Vec<_> = check
+                .custom_headers
+                .keys()
+                .filter(|name| !is_valid_header_name(name))
+                .collect();
+            names.sort();
+            for header in names {
+                invalid(
+                    format!("upstreams.{}.health_check.custom_headers", name),
+                    format!(
+                        "Upstream '{}' health check sends invalid header name '{}'",
+                        name, header
+                    ),
+                );
+            }
+        }
+        if upstream.servers.is_empty() {
+            invalid(
+                format!("upstreams.{}.servers", name),
+                format!("Upstream '{}' has no servers configured", name),
+            );
+        }
+        for server in &upstream.servers {
+            if server.max_concurrent_requests == Some(0) {
+                invalid(
+                    format!("upstreams.{}.servers", name),
+                    format!(
+                        "Upstream '{}' server '{}' must allow at least 1 concurrent request",
+                        name, server.address
+                    ),
+                );
+            }
+        }
+        if upstream.max_connections == Some(0) {
+            invalid(
+                format!("upstreams.{}.max_connections", name),
+                format!("Upstream '{}' must allow at least 1 connection", name),
+            );
+        }
+        if upstream.timeout_per_try == Some(0) {
+            invalid(
+                format!("upstreams.{}.timeout_per_try", name),
+                format!(
+                    "Upstream '{}' per-try timeout must be at least 1 second",
+                    name
+                ),
+            );
+        }
+        if upstream.max_response_header_bytes < MIN_RESPONSE_HEADER_BYTES {
+            invalid(
+                format!("upstreams.{}.max_response_header_bytes", name),
+                format!(
+                    "Upstream '{}' response header limit must be at least {} bytes",
+                    name, MIN_RESPONSE_HEADER_BYTES
+                ),
+            );
+        }
+        for code in &upstream.retry_codes {
+            if !(100..=599).contains(code) {
+                invalid(
+                    format!("upstreams.{}.retry_codes", name),
+                    format!(
+                        "Upstream '{}' retries on {}, which is not an HTTP status code",
+                        name, code
+                    ),
+                );
+            }
+        }
+    }
+
+    if issues.is_empty() {
+        Ok(())
+    } else {
+        Err(ConfigError::ValidationError(issues))
+    }
+}
+
+/// Whether a string is a valid HTTP header name (an RFC 9110 token)
+fn is_valid_header_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+/// A header name given to a builder, which must be valid
+fn header_name(name: impl Into<String>) -> String {
+    let name = name.into();
+    assert!(
+        is_valid_header_name(&name),
+        "invalid header name '{}'",
+        name
+    );
+    name
 }
 
 /// Configuration error types
 #[derive(Debug, thiserror::Error)]
 pub enum ConfigError {
-    #[error("IO error: {0}")]
-    IoError(String),
+    #[error("IO error reading {}: {source}", path.display())]
+    IoError {
+        path: PathBuf,
+        source: std::io::Error,
+    },
 
     #[error("Parse error: {0}")]
     ParseError(String),
@@ -286,14 +2213,74 @@ pub enum ConfigError {
     #[error("Serialize error: {0}")]
     SerializeError(String),
 
-    #[error("Validation error: {0}")]
-    ValidationError(String),
+    #[error("Unknown profile '{0}'")]
+    UnknownProfile(String),
+
+    #[error("Validation error: {}", join_issues(.0))]
+    ValidationError(Vec<ValidationIssue>),
+}
+
+fn join_issues(issues: &[ValidationIssue]) -> String {
+    issues
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join("; ")
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_config_hash_ignores_map_order() {
+        let upstream = |name: &String| {
+            format!(
+                "[upstreams.{}]\nservers = [{{ address = \"127.0.0.1:3000\" }}]\n",
+                name
+            )
+        };
+        let names: Vec<_> = (0..16).map(|i| format!("upstream-{}", i)).collect();
+        let forward =
+            JanusConfig::from_toml(&names.iter().map(upstream).collect::<String>()).unwrap();
+        let mut backward =
+            JanusConfig::from_toml(&names.iter().rev().map(upstream).collect::<String>()).unwrap();
+        assert_eq!(forward.canonical_json(), backward.canonical_json());
+        assert_eq!(forward.config_hash(), backward.config_hash());
+        assert_eq!(forward.config_hash().len(), 16);
+
+        // The same after the trip to a management client
+        let received: JanusConfig =
+            serde_json::from_str(&serde_json::to_string(&forward).unwrap()).unwrap();
+        assert_eq!(received.config_hash(), forward.config_hash());
+
+        backward.upstreams.get_mut("upstream-3").unwrap().servers[0].weight = 2;
+        assert_ne!(forward.config_hash(), backward.config_hash());
+    }
+
+    #[test]
+    fn test_pending_restart_compares_listener() {
+        let listening: SocketAddr = "127.0.0.1:8080".parse().unwrap();
+        let mut server = ServerConfig {
+            bind_address: "127.0.0.1".to_string(),
+            port: 8080,
+            ..Default::default()
+        };
+        assert!(server.pending_restart(listening).is_empty());
+
+        server.port = 8081;
+        server.bind_address = "0.0.0.0".to_string();
+        assert_eq!(
+            server.pending_restart(listening),
+            ["server.bind_address", "server.port"]
+        );
+
+        // Neither a system-chosen port nor a host name can be compared
+        server.port = 0;
+        server.bind_address = "localhost".to_string();
+        assert!(server.pending_restart(listening).is_empty());
+    }
+
     #[test]
     fn test_default_config() {
         let config = JanusConfig::default();
@@ -338,4 +2325,386 @@ index = "index.html"
         assert_eq!(config.routes.len(), 1);
         assert_eq!(config.static_files.len(), 1);
     }
+
+    #[test]
+    fn test_index_accepts_one_or_many() {
+        let config: JanusConfig = r#"
+[[static_files]]
+path = "/"
+root = "/srv"
+index = "home.html"
+
+[[static_files]]
+path = "/docs/"
+root = "/srv/docs"
+index = ["index.html", "README.html"]
+
+[[static_files]]
+path = "/assets/"
+root = "/srv/assets"
+"#
+        .parse()
+        .unwrap();
+        assert_eq!(config.static_files[0].index, ["home.html"]);
+        assert_eq!(config.static_files[1].index, ["index.html", "README.html"]);
+        assert_eq!(config.static_files[2].index, ["index.html"]);
+    }
+
+    #[test]
+    fn test_load_error_names_path() {
+        let path = std::env::temp_dir()
+            .join("janus-missing")
+            .join("janus.toml");
+        let err = JanusConfig::load(&path).unwrap_err();
+
+        assert!(matches!(err, ConfigError::IoError { .. }));
+        assert!(err.to_string().contains(&path.display().to_string()));
+    }
+
+    const PROFILES: &str = r#"
+[server]
+port = 8080
+access_log = true
+
+[upstreams.api]
+servers = [{ address = "localhost:8001" }]
+
+[profiles.prod.server]
+port = 80
+
+[profiles.prod.upstreams.api]
+servers = [{ address = "api.internal:80" }]
+"#;
+
+    #[test]
+    fn test_profile_overrides_base() {
+        let file = JanusConfigFile::from_toml(PROFILES).unwrap();
+
+        let base = file.resolve(None).unwrap();
+        assert_eq!(base.server.port, 8080);
+        assert_eq!(base, JanusConfig::from_toml(PROFILES).unwrap());
+
+        let prod = file.resolve(Some("prod")).unwrap();
+        assert_eq!(prod.server.port, 80);
+        assert!(prod.server.access_log);
+        assert_eq!(prod.upstreams["api"].servers[0].address, "api.internal:80");
+
+        assert!(matches!(
+            file.resolve(Some("staging")),
+            Err(ConfigError::UnknownProfile(name)) if name == "staging"
+        ));
+    }
+
+    #[test]
+    fn test_set_profile_keeps_only_overrides() {
+        let mut file = JanusConfigFile::from_toml(PROFILES).unwrap();
+        let mut prod = file.resolve(Some("prod")).unwrap();
+        prod.server.workers = 4;
+        file.set(Some("prod"), &prod).unwrap();
+
+        let reloaded = JanusConfigFile::from_toml(&file.to_toml().unwrap()).unwrap();
+        assert_eq!(reloaded.resolve(Some("prod")).unwrap(), prod);
+        assert_eq!(reloaded.resolve(None).unwrap().server.workers, 0);
+
+        let overrides = &reloaded.profiles["prod"]["server"];
+        assert_eq!(overrides.get("port"), Some(&toml::Value::Integer(80)));
+        assert!(overrides.get("access_log").is_none());
+    }
+
+    #[test]
+    fn test_parse_and_try_from() {
+        let toml = "[server]\nport = 3000\n";
+        let parsed: JanusConfig = toml.parse().unwrap();
+        assert_eq!(parsed.server.port, 3000);
+        assert_eq!(JanusConfig::try_from(toml).unwrap(), parsed);
+
+        assert!(matches!(
+            "[server".parse::<JanusConfig>(),
+            Err(ConfigError::ParseError(_))
+        ));
+    }
+
+    #[test]
+    fn test_route_timeouts_default_to_timeout() {
+        let toml = r#"
+[[routes]]
+path = "/api/*"
+upstream = "backend"
+timeout = 20
+upstream_timeout_secs = 5
+"#;
+
+        let config = JanusConfig::from_toml(toml).unwrap();
+        let route = &config.routes[0];
+        assert_eq!(route.upstream_timeout(), Duration::from_secs(5));
+        assert_eq!(route.client_timeout(), Duration::from_secs(20));
+    }
+
+    #[test]
+    fn test_builders_match_parsed_defaults() {
+        let toml = r#"
+[upstreams.api]
+servers = [{ address = "127.0.0.1:3000" }]
+
+[[routes]]
+path = "/api/*"
+upstream = "api"
+methods = ["GET"]
+timeout = 30
+headers = { X-Env = "prod" }
+
+[[static_files]]
+path = "/assets/"
+root = "/srv/assets"
+"#;
+        let parsed = JanusConfig::from_toml(toml).unwrap();
+
+        let built = JanusConfig::builder()
+            .upstream("api", UpstreamConfig::single("127.0.0.1:3000"))
+            .route(
+                RouteConfig::new("/api/*", "api")
+                    .timeout(30)
+                    .methods(["GET"])
+                    .header("X-Env", "prod"),
+            )
+            .static_dir(StaticFileConfig::new("/assets/", "/srv/assets"))
+            .build()
+            .unwrap();
+        assert_eq!(built, parsed);
+    }
+
+    #[test]
+    fn test_builder_validates() {
+        let err = JanusConfig::builder()
+            .route(RouteConfig::new("/api/*", "missing"))
+            .build()
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Validation error: Route '/api/*' references non-existent upstream 'missing'"
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid header name 'X Env'")]
+    fn test_builder_rejects_invalid_header_name() {
+        let _ = RouteConfig::new("/*", "api").header("X Env", "prod");
+    }
+
+    #[test]
+    fn test_health_check_headers_from_environment() {
+        std::env::set_var("JANUS_TEST_HEALTH_KEY", "s3cret");
+        let check: HealthCheckConfig = toml::from_str(
+            r#"
+[custom_headers]
+X-Api-Key = "${JANUS_TEST_HEALTH_KEY}"
+Authorization = "Bearer ${JANUS_TEST_HEALTH_KEY}!"
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            check.headers(),
+            Ok(vec![
+                ("Authorization".to_string(), "Bearer s3cret!".to_string()),
+                ("X-Api-Key".to_string(), "s3cret".to_string()),
+            ])
+        );
+
+        let check = HealthCheckConfig {
+            custom_headers: HashMap::from([(
+                "X-Api-Key".to_string(),
+                "${JANUS_TEST_UNSET}".to_string(),
+            )]),
+            ..check
+        };
+        assert_eq!(
+            check.headers(),
+            Err("environment variable 'JANUS_TEST_UNSET' is not set".to_string())
+        );
+    }
+
+    #[test]
+    fn test_management_token_roles() {
+        let toml = r#"
+[[management.tokens]]
+token = "dashboard"
+role = "read"
+
+[[management.tokens]]
+token = "ops"
+role = "admin"
+"#;
+
+        let config = JanusConfig::from_toml(toml).unwrap();
+        let management = &config.management;
+        assert_eq!(
+            management.role_for(Some("dashboard")),
+            Some(ManagementRole::Read)
+        );
+        assert_eq!(
+            management.role_for(Some("ops")),
+            Some(ManagementRole::Admin)
+        );
+        assert_eq!(management.role_for(Some("wrong")), None);
+        assert_eq!(management.role_for(None), None);
+
+        // Without tokens, access is open
+        let open = ManagementConfig::default();
+        assert_eq!(open.role_for(None), Some(ManagementRole::Admin));
+    }
+
+    #[test]
+    fn test_validate_config() {
+        assert!(validate_config(&JanusConfig::default()).is_ok());
+
+        let mut config = JanusConfig::default();
+        config
+            .routes
+            .push(RouteConfig::new("/api/*", "missing").timeout(30));
+        let err = validate_config(&config).unwrap_err();
+        assert!(matches!(err, ConfigError::ValidationError(_)));
+        assert_eq!(
+            err.to_string(),
+            "Validation error: Route '/api/*' references non-existent upstream 'missing'"
+        );
+
+        config.routes[0].upstream = "backend".to_string();
+        config.upstreams.insert(
+            "backend".to_string(),
+            UpstreamConfig::single("localhost:3000"),
+        );
+        config.routes[0]
+            .match_headers
+            .insert("X Tenant".to_string(), "acme".to_string());
+        assert_eq!(
+            validate_config(&config).unwrap_err().to_string(),
+            "Validation error: Route '/api/*' matches on invalid header name 'X Tenant'"
+        );
+    }
+
+    #[test]
+    fn test_validate_acme() {
+        let mut config = JanusConfig::default();
+        config.server.acme = Some(AcmeConfig {
+            contact_email: " ".to_string(),
+            domains: vec![],
+            cache_dir: "/var/lib/janus/acme".to_string(),
+            https_port: config.server.port,
+            directory_url: default_acme_directory_url(),
+            renew_before_days: default_renew_before_days(),
+        });
+        let Err(ConfigError::ValidationError(issues)) = validate_config(&config) else {
+            panic!("expected validation errors");
+        };
+        let fields: Vec<_> = issues.iter().map(|i| i.field.as_str()).collect();
+        assert_eq!(
+            fields,
+            [
+                "server.acme.domains",
+                "server.acme.contact_email",
+                "server.acme.https_port"
+            ]
+        );
+
+        let acme = config.server.acme.as_mut().unwrap();
+        acme.contact_email = "admin@example.com".to_string();
+        acme.domains = vec!["example.com".to_string()];
+        acme.https_port = default_https_port();
+        assert!(validate_config(&config).is_ok());
+    }
+
+    #[test]
+    fn test_validate_config_reports_every_issue() {
+        let mut config = JanusConfig::default();
+        config.server.port = 0;
+        config.upstreams.insert(
+            "empty".to_string(),
+            UpstreamConfig {
+                timeout_per_try: Some(0),
+                retry_codes: vec![503, 999],
+                max_connections: Some(0),
+                ..UpstreamConfig::new(Vec::<String>::new())
+            },
+        );
+        config.server.default_upstream = Some("missing".to_string());
+        config.server.https_redirect_code = 303;
+        config.server.access_log_sample_rate = 1.5;
+        config.server.max_request_header_bytes = 4096;
+        config.server.rate_limit = Some(RateLimitConfig {
+            requests_per_second: 0,
+            burst: Some(0),
+        });
+        config.server.auth_request = Some(AuthRequestConfig {
+            upstream: "missing".to_string(),
+            path: "check".to_string(),
+            forward_headers: default_auth_forward_headers(),
+            copy_headers: vec![],
+            forward_body: false,
+            timeout_secs: 5,
+        });
+        config.management.require_client_cert = true;
+        config.static_files.push(StaticFileConfig {
+            headers: HashMap::from([("Cache Control".to_string(), "no-store".to_string())]),
+            ..StaticFileConfig::new("/docs/", "/srv/docs")
+        });
+        config.routes.push(RouteConfig {
+            strip_prefix: true,
+            auth_request: Some(AuthRequestConfig {
+                upstream: "missing".to_string(),
+                path: "check".to_string(),
+                forward_headers: default_auth_forward_headers(),
+                copy_headers: vec![],
+                forward_body: false,
+                timeout_secs: 5,
+            }),
+            remove_response_headers: vec!["X-Powered By".to_string()],
+            ..RouteConfig::new("/*", "empty").timeout(30)
+        });
+
+        config.routes.push(RouteConfig {
+            stream_request_body: true,
+            body_spool: Some(BodySpoolConfig::default()),
+            cache: Some(CacheConfig {
+                ttl_secs: 0,
+                vary_on: vec!["Accept Language".to_string()],
+                ..Default::default()
+            }),
+            ..RouteConfig::new("/uploads/*", "empty")
+        });
+        let Err(ConfigError::ValidationError(issues)) = validate_config(&config) else {
+            panic!("expected validation errors");
+        };
+        let fields: Vec<_> = issues.iter().map(|i| i.field.as_str()).collect();
+        assert_eq!(
+            fields,
+            [
+                "server.port",
+                "management.require_client_cert",
+                "server.access_log_sample_rate",
+                "server.https_redirect_code",
+                "server.rate_limit.requests_per_second",
+                "server.rate_limit.burst",
+                "server.auth_request.upstream",
+                "server.auth_request.path",
+                "server.max_request_header_bytes",
+                "server.default_upstream",
+                "routes[0].strip_prefix",
+                "routes[0].remove_response_headers",
+                "routes[0].auth_request.upstream",
+                "routes[0].auth_request.path",
+                "routes[1].body_spool",
+                "routes[1].cache",
+                "routes[1].cache.vary_on",
+                "static_files[0].headers",
+                "upstreams.empty.servers",
+                "upstreams.empty.max_connections",
+                "upstreams.empty.timeout_per_try",
+                "upstreams.empty.retry_codes"
+            ]
+        );
+        assert_eq!(
+            issues[18].message,
+            "Upstream 'empty' has no servers configured"
+        );
+    }
 }
@@ -0,0 +1,174 @@
+//! Differences between two configurations
+
+use crate::config::JanusConfig;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Kind of configuration change
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DiffKind {
+    Added,
+    Removed,
+    Changed,
+}
+
+/// A single configuration change
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiffEntry {
+    pub kind: DiffKind,
+    pub description: String,
+}
+
+/// Compare routes, upstreams and static directories between two configs
+pub fn diff_configs(before: &JanusConfig, after: &JanusConfig) -> Vec<DiffEntry> {
+    let mut entries = Vec::new();
+
+    diff_keyed(
+        "Route",
+        before.routes.iter().map(|r| (r.path.as_str(), r)).collect(),
+        after.routes.iter().map(|r| (r.path.as_str(), r)).collect(),
+        &mut entries,
+    );
+    diff_keyed(
+        "Upstream",
+        before
+            .upstreams
+            .iter()
+            .map(|(name, u)| (name.as_str(), u))
+            .collect(),
+        after
+            .upstreams
+            .iter()
+            .map(|(name, u)| (name.as_str(), u))
+            .collect(),
+        &mut entries,
+    );
+    diff_keyed(
+        "Static directory",
+        before
+            .static_files
+            .iter()
+            .map(|s| (s.path.as_str(), s))
+            .collect(),
+        after
+            .static_files
+            .iter()
+            .map(|s| (s.path.as_str(), s))
+            .collect(),
+        &mut entries,
+    );
+
+    entries
+}
+
+/// Diff two collections keyed by name, listing the fields that changed
+fn diff_keyed<T: Serialize + PartialEq>(
+    label: &str,
+    before: BTreeMap<&str, &T>,
+    after: BTreeMap<&str, &T>,
+    entries: &mut Vec<DiffEntry>,
+) {
+    for (key, old) in &before {
+        match after.get(key) {
+            None => entries.push(DiffEntry {
+                kind: DiffKind::Removed,
+                description: format!("{} '{}' removed", label, key),
+            }),
+            Some(new) if old != new => entries.push(DiffEntry {
+                kind: DiffKind::Changed,
+                description: format!(
+                    "{} '{}' changed: {}",
+                    label,
+                    key,
+                    changed_fields(old, new).join(", ")
+                ),
+            }),
+            Some(_) => {}
+        }
+    }
+
+    for key in after.keys().filter(|key| !before.contains_key(*key)) {
+        entries.push(DiffEntry {
+            kind: DiffKind::Added,
+            description: format!("{} '{}' added", label, key),
+        });
+    }
+}
+
+/// Names of the top-level fields that differ between two values
+fn changed_fields<T: Serialize>(old: &T, new: &T) -> Vec<String> {
+    let (Ok(serde_json::Value::Object(old)), Ok(serde_json::Value::Object(new))) =
+        (serde_json::to_value(old), serde_json::to_value(new))
+    else {
+        return Vec::new();
+    };
+
+    let mut fields: Vec<String> = old
+        .iter()
+        .filter(|(name, value)| new.get(*name) != Some(value))
+        .map(|(name, _)| name.clone())
+        .chain(new.keys().filter(|name| !old.contains_key(*name)).cloned())
+        .collect();
+    fields.sort();
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BEFORE: &str = r#"
+[upstreams.api]
+servers = [{ address = "localhost:8001" }]
+
+[upstreams.old]
+servers = [{ address = "localhost:8002" }]
+
+[[routes]]
+path = "/api/*"
+upstream = "api"
+timeout = 30
+
+[[static_files]]
+path = "/"
+root = "/var/www"
+"#;
+
+    #[test]
+    fn test_identical_configs_have_no_diff() {
+        let config = JanusConfig::from_toml(BEFORE).unwrap();
+        assert!(diff_configs(&config, &config).is_empty());
+    }
+
+    #[test]
+    fn test_diff_reports_each_kind() {
+        let before = JanusConfig::from_toml(BEFORE).unwrap();
+        let mut after = before.clone();
+        after.upstreams.remove("old");
+        after.routes[0].timeout = 10;
+        after.routes[0].upstream = "new".to_string();
+        after.upstreams.insert(
+            "new".to_string(),
+            before.upstreams.get("api").unwrap().clone(),
+        );
+
+        let diff = diff_configs(&before, &after);
+        assert_eq!(
+            diff,
+            vec![
+                DiffEntry {
+                    kind: DiffKind::Changed,
+                    description: "Route '/api/*' changed: timeout, upstream".to_string(),
+                },
+                DiffEntry {
+                    kind: DiffKind::Removed,
+                    description: "Upstream 'old' removed".to_string(),
+                },
+                DiffEntry {
+                    kind: DiffKind::Added,
+                    description: "Upstream 'new' added".to_string(),
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,328 @@
+//! Configuration edits made over the management API
+//!
+//! The server applies edits with [`apply_edit`], and the TUI runs the same
+//! function on a copy of its cached config to warn about edits the server
+//! will refuse before sending them.
+
+use crate::config::{validate_config, ConfigError, JanusConfig, ValidationIssue};
+use crate::messages::ClientMessage;
+
+/// Why an edit cannot be made
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum EditError {
+    /// The edit does not fit the configuration, e.g. a duplicate route
+    #[error("{0}")]
+    Refused(String),
+
+    /// The edited configuration would fail validation
+    #[error("{}", .0.iter().map(ToString::to_string).collect::<Vec<_>>().join("; "))]
+    Invalid(Vec<ValidationIssue>),
+}
+
+/// Apply an editing message to a configuration, returning the message to
+/// report on success
+///
+/// Only problems the edit introduces make it invalid, so an edit is never
+/// refused over issues the configuration already had.
+pub fn apply_edit(config: &mut JanusConfig, msg: &ClientMessage) -> Result<String, EditError> {
+    let existing = validation_issues(config);
+    let success = edit(config, msg)?;
+
+    let introduced: Vec<_> = validation_issues(config)
+        .into_iter()
+        .filter(|issue| !existing.contains(issue))
+        .collect();
+    if introduced.is_empty() {
+        Ok(success)
+    } else {
+        Err(EditError::Invalid(introduced))
+    }
+}
+
+/// Check an editing message against a configuration without changing it
+pub fn check_edit(config: &JanusConfig, msg: &ClientMessage) -> Result<(), EditError> {
+    apply_edit(&mut config.clone(), msg).map(|_| ())
+}
+
+fn validation_issues(config: &JanusConfig) -> Vec<ValidationIssue> {
+    match validate_config(config) {
+        Err(ConfigError::ValidationError(issues)) => issues,
+        _ => Vec::new(),
+    }
+}
+
+fn edit(config: &mut JanusConfig, msg: &ClientMessage) -> Result<String, EditError> {
+    let refused = |message: String| Err(EditError::Refused(message));
+
+    match msg {
+        ClientMessage::AddRoute(route) => {
+            if !config.upstreams.contains_key(&route.upstream) {
+                return refused(format!("Upstream '{}' not found", route.upstream));
+            }
+            if config.routes.iter().any(|r| r.path == route.path) {
+                return refused(format!("Route '{}' already exists", route.path));
+            }
+
+            config.routes.push(route.clone());
+            Ok("Route added".to_string())
+        }
+
+        ClientMessage::RemoveRoute(path) => {
+            let initial_len = config.routes.len();
+            config.routes.retain(|r| &r.path != path);
+            if config.routes.len() == initial_len {
+                return refused(format!("Route '{}' not found", path));
+            }
+
+            Ok("Route removed".to_string())
+        }
+
+        ClientMessage::SetRouteEnabled { path, enabled } => {
+            let Some(route) = config.routes.iter_mut().find(|r| &r.path == path) else {
+                return refused(format!("Route '{}' not found", path));
+            };
+            route.enabled = *enabled;
+            Ok(format!("Route '{}' {}", path, enabled_word(*enabled)))
+        }
+
+        ClientMessage::AddUpstream {
+            name,
+            config: upstream,
+        } => {
+            if config.upstreams.contains_key(name) {
+                return refused(format!("Upstream '{}' already exists", name));
+            }
+
+            config.upstreams.insert(name.clone(), upstream.clone());
+            Ok(format!("Upstream '{}' added", name))
+        }
+
+        ClientMessage::UpdateUpstream {
+            name,
+            config: upstream,
+        } => {
+            config.upstreams.insert(name.clone(), upstream.clone());
+            Ok(format!("Upstream '{}' updated", name))
+        }
+
+        ClientMessage::RemoveUpstream(name) => {
+            if config.routes.iter().any(|r| &r.upstream == name) {
+                return refused(format!(
+                    "Cannot remove upstream '{}': still in use by routes",
+                    name
+                ));
+            }
+            if config.upstreams.remove(name).is_none() {
+                return refused(format!("Upstream '{}' not found", name));
+            }
+
+            Ok(format!("Upstream '{}' removed", name))
+        }
+
+        ClientMessage::DrainBackendServer {
+            upstream,
+            server_address,
+        } => {
+            let Some(existing) = config.upstreams.get_mut(upstream) else {
+                return refused(format!("Upstream '{}' not found", upstream));
+            };
+            let Some(server) = existing
+                .servers
+                .iter_mut()
+                .find(|s| &s.address == server_address)
+            else {
+                return refused(format!(
+                    "Server '{}' not found in upstream '{}'",
+                    server_address, upstream
+                ));
+            };
+            server.drain = true;
+            Ok(format!(
+                "Server '{}' of upstream '{}' draining",
+                server_address, upstream
+            ))
+        }
+
+        ClientMessage::UpdateServerPort(port) => {
+            let old_port = config.server.port;
+            config.server.port = *port;
+            Ok(format!(
+                "Server port changed from {} to {}. Restart server to apply.",
+                old_port, port
+            ))
+        }
+
+        ClientMessage::UpdateBindAddress(address) => {
+            let old_address = std::mem::replace(&mut config.server.bind_address, address.clone());
+            Ok(format!(
+                "Bind address changed from {} to {}. Restart server to apply.",
+                old_address, address
+            ))
+        }
+
+        ClientMessage::AddStaticDir(static_config) => {
+            if config
+                .static_files
+                .iter()
+                .any(|s| s.path == static_config.path)
+            {
+                return refused(format!(
+                    "Static directory '{}' already exists",
+                    static_config.path
+                ));
+            }
+
+            config.static_files.push(static_config.clone());
+            Ok(format!("Static directory '{}' added", static_config.path))
+        }
+
+        ClientMessage::UpdateStaticDir {
+            path,
+            config: static_config,
+        } => {
+            // Moving the mount must not collide with another one
+            if &static_config.path != path
+                && config
+                    .static_files
+                    .iter()
+                    .any(|s| s.path == static_config.path)
+            {
+                return refused(format!(
+                    "Static directory '{}' already exists",
+                    static_config.path
+                ));
+            }
+
+            let Some(existing) = config.static_files.iter_mut().find(|s| &s.path == path) else {
+                return refused(format!("Static directory '{}' not found", path));
+            };
+            *existing = static_config.clone();
+            Ok(format!("Static directory '{}' updated", path))
+        }
+
+        ClientMessage::RemoveStaticDir(path) => {
+            let initial_len = config.static_files.len();
+            config.static_files.retain(|s| &s.path != path);
+            if config.static_files.len() == initial_len {
+                return refused(format!("Static directory '{}' not found", path));
+            }
+
+            Ok(format!("Static directory '{}' removed", path))
+        }
+
+        ClientMessage::SetStaticDirEnabled { path, enabled } => {
+            let Some(existing) = config.static_files.iter_mut().find(|s| &s.path == path) else {
+                return refused(format!("Static directory '{}' not found", path));
+            };
+            existing.enabled = *enabled;
+            Ok(format!(
+                "Static directory '{}' {}",
+                path,
+                enabled_word(*enabled)
+            ))
+        }
+
+        _ => refused("Not a configuration edit".to_string()),
+    }
+}
+
+fn enabled_word(enabled: bool) -> &'static str {
+    if enabled {
+        "enabled"
+    } else {
+        "disabled"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{RouteConfig, UpstreamConfig};
+
+    fn config() -> JanusConfig {
+        let mut config = JanusConfig::default();
+        config
+            .upstreams
+            .insert("api".to_string(), UpstreamConfig::single("127.0.0.1:3000"));
+        config
+    }
+
+    fn route(path: &str, upstream: &str) -> RouteConfig {
+        RouteConfig::new(path, upstream)
+    }
+
+    #[test]
+    fn test_edit_refusals() {
+        let mut config = config();
+        assert_eq!(
+            apply_edit(
+                &mut config,
+                &ClientMessage::AddRoute(route("/api/*", "api"))
+            ),
+            Ok("Route added".to_string())
+        );
+
+        for (msg, reason) in [
+            (
+                ClientMessage::AddRoute(route("/api/*", "api")),
+                "Route '/api/*' already exists",
+            ),
+            (
+                ClientMessage::AddRoute(route("/web/*", "web")),
+                "Upstream 'web' not found",
+            ),
+            (
+                ClientMessage::AddUpstream {
+                    name: "api".to_string(),
+                    config: UpstreamConfig::single("127.0.0.1:3001"),
+                },
+                "Upstream 'api' already exists",
+            ),
+            (
+                ClientMessage::RemoveUpstream("api".to_string()),
+                "Cannot remove upstream 'api': still in use by routes",
+            ),
+            (
+                ClientMessage::RemoveStaticDir("/static/".to_string()),
+                "Static directory '/static/' not found",
+            ),
+            (
+                ClientMessage::DrainBackendServer {
+                    upstream: "api".to_string(),
+                    server_address: "127.0.0.1:3001".to_string(),
+                },
+                "Server '127.0.0.1:3001' not found in upstream 'api'",
+            ),
+            (
+                ClientMessage::SetRouteEnabled {
+                    path: "/web/*".to_string(),
+                    enabled: false,
+                },
+                "Route '/web/*' not found",
+            ),
+            (
+                ClientMessage::SetStaticDirEnabled {
+                    path: "/static/".to_string(),
+                    enabled: false,
+                },
+                "Static directory '/static/' not found",
+            ),
+        ] {
+            assert_eq!(
+                check_edit(&config, &msg),
+                Err(EditError::Refused(reason.to_string()))
+            );
+        }
+    }
+
+    #[test]
+    fn test_edit_introducing_validation_issue() {
+        let mut config = config();
+        let err = check_edit(&config, &ClientMessage::UpdateServerPort(0)).unwrap_err();
+        assert_eq!(err.to_string(), "Server port cannot be 0");
+
+        // Issues the configuration already had do not block other edits
+        config.server.port = 0;
+        assert!(check_edit(&config, &ClientMessage::AddRoute(route("/api/*", "api"))).is_ok());
+    }
+}
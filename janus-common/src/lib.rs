@@ -1,7 +1,13 @@
 //! Janus Common - Shared types and configuration for Janus web server
 
 pub mod config;
+pub mod diff;
+pub mod edit;
 pub mod messages;
+pub mod reference;
 
 pub use config::*;
+pub use diff::*;
+pub use edit::*;
 pub use messages::*;
+pub use reference::*;
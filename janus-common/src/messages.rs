@@ -1,18 +1,28 @@
 //! IPC messages between server and TUI
 
-use crate::config::JanusConfig;
+use crate::config::{JanusConfig, ManagementRole, ValidationIssue};
+use crate::diff::DiffEntry;
 use serde::{Deserialize, Serialize};
 
 /// Messages sent from TUI to server
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type", content = "data")]
 pub enum ClientMessage {
+    /// Present an access token for this session
+    Authenticate { token: String },
+
+    /// Liveness check, answered with a `Pong` carrying the same value
+    Ping(u64),
+
     /// Request current server status
     GetStatus,
 
     /// Request current configuration
     GetConfig,
 
+    /// Request the changes between a previously seen config and the current one
+    GetConfigDiff { before: JanusConfig },
+
     /// Update configuration (triggers live reload)
     UpdateConfig(JanusConfig),
 
@@ -28,6 +38,15 @@ pub enum ClientMessage {
     /// Remove a route by path
     RemoveRoute(String),
 
+    /// Enable or disable the route with this path without removing it
+    SetRouteEnabled { path: String, enabled: bool },
+
+    /// Add an upstream, refused if one already has the name
+    AddUpstream {
+        name: String,
+        config: crate::config::UpstreamConfig,
+    },
+
     /// Add or update an upstream
     UpdateUpstream {
         name: String,
@@ -37,46 +56,219 @@ pub enum ClientMessage {
     /// Remove an upstream
     RemoveUpstream(String),
 
+    /// Stop routing to an upstream and remove it after its drain timeout
+    DrainUpstream(String),
+
+    /// Stop sending new requests to one server of an upstream, keeping it
+    /// in the configuration
+    DrainBackendServer {
+        upstream: String,
+        server_address: String,
+    },
+
     /// Add a static file directory
     AddStaticDir(crate::config::StaticFileConfig),
 
+    /// Replace the static file directory mounted at `path`
+    UpdateStaticDir {
+        path: String,
+        config: crate::config::StaticFileConfig,
+    },
+
     /// Remove a static file directory by path
     RemoveStaticDir(String),
 
+    /// Enable or disable the static file directory mounted at `path`
+    /// without removing it
+    SetStaticDirEnabled { path: String, enabled: bool },
+
     /// Reload configuration from file
     ReloadConfig,
 
     /// Get server statistics
     GetStats,
 
-    /// Gracefully shutdown the server
-    Shutdown,
+    /// Get the busiest client addresses, most requests first
+    GetTopClients { limit: usize },
+
+    /// Zero the request statistics, starting a new window for rates
+    ResetStats,
+
+    /// Answer every request in `scope` with 503 while enabled, showing
+    /// `message` if given
+    SetMaintenance {
+        scope: MaintenanceScope,
+        enabled: bool,
+        message: Option<String>,
+    },
+
+    /// Get latency percentiles and traffic for one route, by its path
+    /// (`static:` and the mount path for a static mount, `default` for the
+    /// default upstream)
+    GetRouteStats { path: String },
+
+    /// Run the preflight checks against the current configuration
+    GetPreflight,
+
+    /// Get the most recent warnings and errors the server logged, newest
+    /// first
+    GetErrorLog { limit: usize },
+
+    /// Get the most recent audit log entries, newest first
+    GetAuditLog { limit: usize },
+
+    /// Gracefully shut down the server after `delay_secs`; refused unless
+    /// `confirm` is set
+    Shutdown { confirm: bool, delay_secs: u64 },
+}
+
+impl ClientMessage {
+    /// Whether the message changes server state and needs the admin role
+    pub fn requires_admin(&self) -> bool {
+        match self {
+            ClientMessage::Authenticate { .. }
+            | ClientMessage::Ping(_)
+            | ClientMessage::GetStatus
+            | ClientMessage::GetConfig
+            | ClientMessage::GetConfigDiff { .. }
+            | ClientMessage::GetStats
+            | ClientMessage::GetTopClients { .. }
+            | ClientMessage::GetRouteStats { .. }
+            | ClientMessage::GetPreflight
+            | ClientMessage::GetErrorLog { .. }
+            | ClientMessage::GetAuditLog { .. } => false,
+
+            ClientMessage::UpdateConfig(_)
+            | ClientMessage::UpdateServerPort(_)
+            | ClientMessage::UpdateBindAddress(_)
+            | ClientMessage::AddRoute(_)
+            | ClientMessage::RemoveRoute(_)
+            | ClientMessage::SetRouteEnabled { .. }
+            | ClientMessage::AddUpstream { .. }
+            | ClientMessage::UpdateUpstream { .. }
+            | ClientMessage::RemoveUpstream(_)
+            | ClientMessage::DrainUpstream(_)
+            | ClientMessage::DrainBackendServer { .. }
+            | ClientMessage::AddStaticDir(_)
+            | ClientMessage::UpdateStaticDir { .. }
+            | ClientMessage::RemoveStaticDir(_)
+            | ClientMessage::SetStaticDirEnabled { .. }
+            | ClientMessage::ReloadConfig
+            | ClientMessage::ResetStats
+            | ClientMessage::SetMaintenance { .. }
+            | ClientMessage::Shutdown { .. } => true,
+        }
+    }
+
+    /// Name of the message type, as sent in its `type` field
+    pub fn name(&self) -> &'static str {
+        match self {
+            ClientMessage::Authenticate { .. } => "Authenticate",
+            ClientMessage::Ping(_) => "Ping",
+            ClientMessage::GetStatus => "GetStatus",
+            ClientMessage::GetConfig => "GetConfig",
+            ClientMessage::GetConfigDiff { .. } => "GetConfigDiff",
+            ClientMessage::UpdateConfig(_) => "UpdateConfig",
+            ClientMessage::UpdateServerPort(_) => "UpdateServerPort",
+            ClientMessage::UpdateBindAddress(_) => "UpdateBindAddress",
+            ClientMessage::AddRoute(_) => "AddRoute",
+            ClientMessage::RemoveRoute(_) => "RemoveRoute",
+            ClientMessage::SetRouteEnabled { .. } => "SetRouteEnabled",
+            ClientMessage::AddUpstream { .. } => "AddUpstream",
+            ClientMessage::UpdateUpstream { .. } => "UpdateUpstream",
+            ClientMessage::RemoveUpstream(_) => "RemoveUpstream",
+            ClientMessage::DrainUpstream(_) => "DrainUpstream",
+            ClientMessage::DrainBackendServer { .. } => "DrainBackendServer",
+            ClientMessage::AddStaticDir(_) => "AddStaticDir",
+            ClientMessage::UpdateStaticDir { .. } => "UpdateStaticDir",
+            ClientMessage::RemoveStaticDir(_) => "RemoveStaticDir",
+            ClientMessage::SetStaticDirEnabled { .. } => "SetStaticDirEnabled",
+            ClientMessage::ReloadConfig => "ReloadConfig",
+            ClientMessage::GetStats => "GetStats",
+            ClientMessage::GetTopClients { .. } => "GetTopClients",
+            ClientMessage::GetRouteStats { .. } => "GetRouteStats",
+            ClientMessage::ResetStats => "ResetStats",
+            ClientMessage::SetMaintenance { .. } => "SetMaintenance",
+            ClientMessage::GetPreflight => "GetPreflight",
+            ClientMessage::GetErrorLog { .. } => "GetErrorLog",
+            ClientMessage::GetAuditLog { .. } => "GetAuditLog",
+            ClientMessage::Shutdown { .. } => "Shutdown",
+        }
+    }
 }
 
 /// Messages sent from server to TUI
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type", content = "data")]
+// Messages are short-lived, so boxing the configuration buys nothing
+#[allow(clippy::large_enum_variant)]
 pub enum ServerMessage {
+    /// Token accepted, with the access it grants
+    Authenticated(ManagementRole),
+
+    /// Answer to a `Ping`
+    Pong(u64),
+
     /// Server status response
     Status(ServerStatus),
 
     /// Current configuration
     Config(JanusConfig),
 
+    /// Changes since the config sent in `GetConfigDiff`
+    ConfigDiff(Vec<DiffEntry>),
+
     /// Server statistics
     Stats(ServerStats),
 
+    /// Busiest client addresses
+    TopClients(Vec<ClientStats>),
+
+    /// Traffic of the route asked for in `GetRouteStats`
+    RouteStats(RouteStatsDetail),
+
+    /// Preflight check findings
+    Preflight(PreflightReport),
+
+    /// Recent warnings and errors, newest first
+    ErrorLog(Vec<ErrorLogEntry>),
+
+    /// Recent audit log entries, newest first
+    AuditLog(Vec<AuditEntry>),
+
     /// Operation success
     Success(String),
 
     /// Operation error
     Error(String),
 
+    /// A configuration update was rejected, with every problem found
+    ConfigValidationErrors(Vec<ValidationIssue>),
+
     /// Configuration was reloaded (broadcast to all clients)
     ConfigReloaded,
 
+    /// [`config_hash`](JanusConfig::config_hash) of the configuration
+    /// just reloaded, broadcast right after `ConfigReloaded`
+    ///
+    /// A message of its own rather than a field of `ConfigReloaded`, so
+    /// clients that predate it still decode `ConfigReloaded`.
+    ConfigHash(String),
+
+    /// A request was proxied (sent to all clients while
+    /// `management.live_requests` is on)
+    RouteHit {
+        path: String,
+        upstream: String,
+        status: u16,
+    },
+
     /// Server is shutting down
     ShuttingDown,
+
+    /// The management API is closing this connection but the server keeps
+    /// running (e.g. the API moved to another port or was disabled)
+    Disconnecting(String),
 }
 
 /// Server status information
@@ -88,20 +280,197 @@ pub struct ServerStatus {
     /// Uptime in seconds
     pub uptime_secs: u64,
 
-    /// Number of active connections
+    /// Number of open client connections
     pub active_connections: u64,
 
+    /// Open client connections with no request in progress for more than
+    /// [`IDLE_CONNECTION_SECS`] seconds
+    #[serde(default)]
+    pub idle_connections: u64,
+
+    /// Client connections accepted since startup
+    pub connections_accepted: u64,
+
+    /// Client connections turned away by connection limits
+    pub connections_rejected: u64,
+
     /// Number of configured routes
     pub route_count: usize,
 
-    /// Number of configured upstreams
+    /// Number of configured upstreams (including draining ones)
     pub upstream_count: usize,
 
+    /// Number of upstreams currently draining
+    pub draining_upstreams: usize,
+
     /// Server version
     pub version: String,
 
-    /// Listening address
+    /// [`config_hash`](JanusConfig::config_hash) of the running
+    /// configuration
+    #[serde(default)]
+    pub config_hash: String,
+
+    /// Address the HTTP listener is bound to
     pub listen_address: String,
+
+    /// Listener settings saved in the config that take effect only after a
+    /// restart, such as `server.port`
+    pub pending_restart_settings: Vec<String>,
+
+    /// Config file profile in use, if any
+    pub profile: Option<String>,
+
+    /// Certificate renewal from the ACME CA, when configured
+    pub acme: Option<AcmeStatus>,
+
+    /// Optional features of the management API this server supports, such
+    /// as [`CAPABILITY_RESET_STATS`]
+    #[serde(default)]
+    pub capabilities: Vec<String>,
+
+    /// Maintenance switches that are on, everything first
+    #[serde(default)]
+    pub maintenance: Vec<MaintenanceWindow>,
+}
+
+/// Seconds without a request after which a connection counts towards
+/// [`ServerStatus::idle_connections`]
+pub const IDLE_CONNECTION_SECS: u64 = 10;
+
+/// Capability of servers that accept `ResetStats`
+pub const CAPABILITY_RESET_STATS: &str = "reset_stats";
+
+/// Capability of servers that accept `SetMaintenance`
+pub const CAPABILITY_MAINTENANCE: &str = "maintenance";
+
+/// What a maintenance switch covers
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum MaintenanceScope {
+    /// Every request that would be routed, served statically or sent to
+    /// the default upstream
+    All,
+
+    /// Requests matched by the route with this path
+    Route(String),
+}
+
+impl std::fmt::Display for MaintenanceScope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MaintenanceScope::All => write!(f, "all requests"),
+            MaintenanceScope::Route(path) => write!(f, "route {}", path),
+        }
+    }
+}
+
+/// A maintenance switch that is on
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MaintenanceWindow {
+    pub scope: MaintenanceScope,
+
+    /// Shown to clients instead of the maintenance page, if set
+    pub message: Option<String>,
+
+    /// When it was switched on (Unix seconds)
+    pub since: u64,
+}
+
+/// State of the certificate obtained from an ACME CA
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+pub struct AcmeStatus {
+    /// Domains the certificate covers
+    pub domains: Vec<String>,
+
+    /// Expiry of the certificate being served (Unix seconds), if any
+    pub expires_at: Option<u64>,
+
+    /// When a certificate was last requested (Unix seconds)
+    pub last_attempt: Option<u64>,
+
+    /// Why the last request failed, cleared once one succeeds
+    pub last_error: Option<String>,
+}
+
+/// Findings of the startup preflight checks
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+pub struct PreflightReport {
+    /// Problems that stop the server from starting
+    pub errors: Vec<String>,
+
+    /// Problems that are likely mistakes but do not stop startup
+    pub warnings: Vec<String>,
+}
+
+impl PreflightReport {
+    /// Whether startup can go ahead
+    pub fn is_ok(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+/// A warning or error the server logged
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ErrorLogEntry {
+    /// Seconds since the Unix epoch
+    pub timestamp: u64,
+
+    pub level: LogLevel,
+
+    /// Module that logged it, such as `janus_core::reload`
+    pub target: String,
+
+    /// The message, followed by any other fields as `name=value`
+    pub message: String,
+}
+
+/// Severity of an [`ErrorLogEntry`]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Warn,
+    Error,
+}
+
+/// A change requested through the management API or made by reloading
+/// the config file
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct AuditEntry {
+    /// Seconds since the Unix epoch
+    pub timestamp: u64,
+
+    pub source: AuditSource,
+
+    /// Address of the management client
+    pub peer: Option<String>,
+
+    /// Name of the client certificate, if one was presented
+    pub certificate: Option<String>,
+
+    /// Name of the token the session authenticated with
+    pub token: Option<String>,
+
+    /// Access the session had, if any
+    pub role: Option<ManagementRole>,
+
+    /// Message type, such as `AddRoute`
+    pub operation: String,
+
+    /// What the operation changes
+    pub summary: String,
+
+    pub success: bool,
+
+    /// Why the operation failed
+    pub error: Option<String>,
+}
+
+/// Where an [`AuditEntry`]'s change came from
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditSource {
+    Management,
+    File,
 }
 
 /// Server statistics
@@ -110,6 +479,27 @@ pub struct ServerStats {
     /// Total requests handled
     pub total_requests: u64,
 
+    /// Total connections accepted
+    pub total_connections: u64,
+
+    /// Requests left out of the access log by sampling
+    pub access_log_sampled_out: u64,
+
+    /// Requests answered 414 for a URI over `server.max_uri_bytes`
+    #[serde(default)]
+    pub uri_too_long: u64,
+
+    /// Requests answered 431 for headers over `server.max_request_headers`
+    /// or `server.max_request_header_bytes`
+    #[serde(default)]
+    pub headers_too_large: u64,
+
+    /// Requests proxied by a configured route
+    pub route_matches: u64,
+
+    /// Requests that fell through to the default upstream
+    pub default_matches: u64,
+
     /// Total bytes received
     pub bytes_received: u64,
 
@@ -122,8 +512,46 @@ pub struct ServerStats {
     /// Response status code counts
     pub status_codes: StatusCodeStats,
 
+    /// Fraction of requests answered with a 5xx status (0.0-1.0)
+    pub error_rate: f64,
+
+    /// Fraction of requests answered with a 4xx status (0.0-1.0)
+    pub client_error_rate: f64,
+
     /// Upstream statistics
     pub upstream_stats: std::collections::HashMap<String, UpstreamStats>,
+
+    /// Traffic per route path, static mount (`static:/assets/`) or
+    /// `default` for the default upstream
+    pub route_stats: std::collections::HashMap<String, RouteStats>,
+
+    /// Failed proxy requests by failure class (`connect`, `timeout`, ...)
+    pub proxy_errors: std::collections::HashMap<String, u64>,
+
+    /// Auth subrequests per route path, timed apart from the requests they
+    /// guard
+    pub auth_requests: std::collections::HashMap<String, AuthRequestStats>,
+
+    /// Bytes of request bodies currently spooled to disk
+    pub spooled_bytes: u64,
+
+    /// Request bodies that were too large for memory and spooled to disk
+    pub spooled_requests: u64,
+
+    /// Routes with the highest P99 latency to the response head, slowest
+    /// first, as route and milliseconds
+    #[serde(default)]
+    pub slowest_routes: Vec<(String, f64)>,
+
+    /// Routes with the most requests, busiest first, as route and request
+    /// count
+    #[serde(default)]
+    pub top_routes_by_traffic: Vec<(String, u64)>,
+
+    /// When these statistics started, at startup or the last `ResetStats`,
+    /// in seconds since the Unix epoch (unset from older servers)
+    #[serde(default)]
+    pub since: Option<u64>,
 }
 
 /// HTTP status code statistics
@@ -139,6 +567,97 @@ pub struct StatusCodeStats {
     pub server_error: u64,
 }
 
+/// Traffic from a single client address
+///
+/// Counts decay over time, so they reflect recent traffic rather than
+/// totals since startup.
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+pub struct ClientStats {
+    /// Client IP address (truncated when anonymization is enabled)
+    pub address: String,
+
+    /// Requests sent
+    pub requests: u64,
+
+    /// Response bytes sent to the client
+    pub bytes: u64,
+
+    /// Requests answered with a 4xx or 5xx status
+    pub errors: u64,
+}
+
+/// Traffic attributed to a single route
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+pub struct RouteStats {
+    /// Requests handled by the route
+    pub requests: u64,
+
+    /// Requests answered with a 5xx status
+    pub errors: u64,
+}
+
+/// Latency and traffic of a single route, since startup
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+pub struct RouteStatsDetail {
+    /// Route path, as in `GetRouteStats`
+    pub path: String,
+
+    /// Requests handled by the route
+    pub requests: u64,
+
+    /// Fraction (0.0-1.0) of requests answered with a 5xx status
+    pub error_rate: f64,
+
+    /// Median latency to the response head, in milliseconds
+    pub p50_ms: f64,
+
+    /// 95th percentile latency, in milliseconds
+    pub p95_ms: f64,
+
+    /// 99th percentile latency, in milliseconds
+    pub p99_ms: f64,
+
+    /// Slowest response head, in milliseconds
+    pub max_ms: f64,
+
+    /// Response body bytes sent
+    pub bytes_sent: u64,
+
+    /// Request body bytes received, where the length was known up front
+    pub bytes_received: u64,
+
+    /// When the route last served a request (RFC 3339, UTC)
+    pub last_request: Option<String>,
+}
+
+/// Auth subrequests made for a single route
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+pub struct AuthRequestStats {
+    /// Subrequests sent to the auth service
+    pub requests: u64,
+
+    /// Requests the auth service turned away with 401 or 403
+    pub denied: u64,
+
+    /// Subrequests that failed or got an unexpected status
+    pub errors: u64,
+
+    /// Average subrequest time in milliseconds
+    pub avg_latency_ms: f64,
+
+    /// Slowest subrequest in milliseconds
+    pub max_latency_ms: f64,
+}
+
+impl AuthRequestStats {
+    /// Count a subrequest and how long it took
+    pub fn record(&mut self, latency_ms: f64) {
+        self.requests += 1;
+        self.avg_latency_ms += (latency_ms - self.avg_latency_ms) / self.requests as f64;
+        self.max_latency_ms = self.max_latency_ms.max(latency_ms);
+    }
+}
+
 /// Per-upstream statistics
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct UpstreamStats {
@@ -148,6 +667,18 @@ pub struct UpstreamStats {
     /// Failed requests
     pub failures: u64,
 
+    /// Responses rejected as malformed (oversized or invalid headers,
+    /// unexpected protocol switches)
+    pub upstream_protocol_errors: u64,
+
+    /// Requests refused because no backend was available
+    pub no_backend_available: u64,
+
+    /// Further attempts after a connection failure or a status in
+    /// `retry_codes`
+    #[serde(default)]
+    pub retries: u64,
+
     /// Average response time in milliseconds
     pub avg_response_time_ms: f64,
 
@@ -156,4 +687,17 @@ pub struct UpstreamStats {
 
     /// Total backend servers
     pub total_servers: usize,
+
+    /// Connections open to the backends, when the upstream sets
+    /// `max_connections`
+    #[serde(default)]
+    pub active_connections: u32,
+
+    /// Most connections open at once since `max_connections` was set
+    #[serde(default)]
+    pub peak_connections: u32,
+
+    /// The upstream's `max_connections`, if set
+    #[serde(default)]
+    pub max_connections: Option<u32>,
 }
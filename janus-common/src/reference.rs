@@ -0,0 +1,269 @@
+//! Markdown reference of the configuration, generated from its JSON Schema
+
+use crate::config::JanusConfig;
+use serde_json::{Map, Value};
+
+/// One documented setting
+struct Row {
+    key: String,
+    kind: String,
+    default: String,
+    description: String,
+}
+
+/// Markdown tables of every setting, one per top-level section, giving
+/// each key's path, type, default and doc comment
+pub fn config_reference() -> String {
+    let schema = serde_json::to_value(JanusConfig::json_schema())
+        .expect("the config schema serializes to JSON");
+    let definitions = schema["definitions"]
+        .as_object()
+        .cloned()
+        .unwrap_or_default();
+
+    let mut out = String::from(
+        "# Configuration Reference\n\n\
+         Generated by `janus docs generate` from the doc comments in \
+         `janus-common/src/config.rs`. Do not edit by hand.\n",
+    );
+
+    let sections = schema["properties"]
+        .as_object()
+        .cloned()
+        .unwrap_or_default();
+    for (name, section) in &sections {
+        let target = resolve(section, &definitions);
+        let (heading, prefix, table) = if let Some(items) = target.get("items") {
+            (
+                format!("[[{}]]", name),
+                format!("{}[].", name),
+                resolve(items, &definitions),
+            )
+        } else if let Some(values) = target.get("additionalProperties") {
+            (
+                format!("[{}.<name>]", name),
+                format!("{}.<name>.", name),
+                resolve(values, &definitions),
+            )
+        } else {
+            (format!("[{}]", name), format!("{}.", name), target)
+        };
+
+        out.push_str(&format!("\n## `{}`\n\n", heading));
+        if let Some(description) = section.get("description").and_then(Value::as_str) {
+            out.push_str(&format!("{}.\n\n", description.trim_end_matches('.')));
+        }
+
+        let mut rows = Vec::new();
+        collect_rows(&prefix, table, &definitions, &mut rows);
+        out.push_str("| Key | Type | Default | Description |\n");
+        out.push_str("|-----|------|---------|-------------|\n");
+        for row in rows {
+            out.push_str(&format!(
+                "| `{}` | {} | {} | {} |\n",
+                row.key, row.kind, row.default, row.description
+            ));
+        }
+    }
+
+    out
+}
+
+/// Add a row for each property of a table, descending into nested tables
+fn collect_rows(
+    prefix: &str,
+    table: &Value,
+    definitions: &Map<String, Value>,
+    rows: &mut Vec<Row>,
+) {
+    let required: Vec<&str> = table["required"]
+        .as_array()
+        .map(|names| names.iter().filter_map(Value::as_str).collect())
+        .unwrap_or_default();
+    let Some(properties) = table["properties"].as_object() else {
+        return;
+    };
+
+    for (name, property) in properties {
+        let key = format!("{}{}", prefix, name);
+        let description = property
+            .get("description")
+            .and_then(Value::as_str)
+            .unwrap_or("")
+            .replace('|', "\\|");
+        let default = if required.contains(&name.as_str()) {
+            "required".to_string()
+        } else {
+            match property.get("default") {
+                Some(Value::Null) | None => "-".to_string(),
+                Some(value) => format!("`{}`", toml_value(value)),
+            }
+        };
+
+        let target = resolve(property, definitions);
+        if target.get("properties").is_some() {
+            rows.push(Row {
+                key: key.clone(),
+                kind: optional("table", property),
+                default: "-".to_string(),
+                description,
+            });
+            collect_rows(&format!("{}.", key), target, definitions, rows);
+            continue;
+        }
+        if let Some(items) = target.get("items") {
+            let items = resolve(items, definitions);
+            if items.get("properties").is_some() {
+                rows.push(Row {
+                    key: key.clone(),
+                    kind: "array of tables".to_string(),
+                    default,
+                    description,
+                });
+                collect_rows(&format!("{}[].", key), items, definitions, rows);
+                continue;
+            }
+        }
+
+        rows.push(Row {
+            key,
+            kind: optional(&type_name(target, definitions), property),
+            default,
+            description,
+        });
+    }
+}
+
+/// The schema a property refers to, looking through `$ref`, single-entry
+/// `allOf` and the `null` alternative of optional values
+fn resolve<'a>(schema: &'a Value, definitions: &'a Map<String, Value>) -> &'a Value {
+    if let Some(name) = schema["$ref"].as_str() {
+        let name = name.trim_start_matches("#/definitions/");
+        return definitions
+            .get(name)
+            .map_or(schema, |s| resolve(s, definitions));
+    }
+    for combinator in ["allOf", "anyOf"] {
+        if let Some(options) = schema[combinator].as_array() {
+            let mut options = options.iter().filter(|s| s["type"] != "null");
+            if let (Some(only), None) = (options.next(), options.next()) {
+                return resolve(only, definitions);
+            }
+        }
+    }
+    schema
+}
+
+/// Type of a value, mentioning when it may be left unset
+fn optional(name: &str, property: &Value) -> String {
+    let nullable = property["anyOf"]
+        .as_array()
+        .is_some_and(|options| options.iter().any(|s| s["type"] == "null"))
+        || property["type"]
+            .as_array()
+            .is_some_and(|types| types.iter().any(|t| t == "null"));
+    if nullable {
+        format!("{} (optional)", name)
+    } else {
+        name.to_string()
+    }
+}
+
+/// TOML name of a value's type, or its choices when it is an enum
+fn type_name(schema: &Value, definitions: &Map<String, Value>) -> String {
+    if let Some(variants) = schema["oneOf"].as_array() {
+        let choices: Vec<String> = variants
+            .iter()
+            .flat_map(|v| v["enum"].as_array().cloned().unwrap_or_default())
+            .map(|v| format!("`{}`", toml_value(&v)))
+            .collect();
+        return choices.join(" \\| ");
+    }
+    if let Some(items) = schema.get("items") {
+        return format!(
+            "array of {}",
+            type_name(resolve(items, definitions), definitions)
+        );
+    }
+    if let Some(values) = schema.get("additionalProperties") {
+        return format!(
+            "table of {}",
+            type_name(resolve(values, definitions), definitions)
+        );
+    }
+
+    let kind = match &schema["type"] {
+        Value::Array(types) => types.iter().find(|t| *t != "null").cloned(),
+        kind => Some(kind.clone()),
+    };
+    match kind.as_ref().and_then(Value::as_str) {
+        Some("number") => "float".to_string(),
+        Some(kind) => kind.to_string(),
+        None => "any".to_string(),
+    }
+}
+
+/// A default value written as it would appear in the TOML file
+fn toml_value(value: &Value) -> String {
+    match value {
+        Value::Array(items) => {
+            let items: Vec<String> = items.iter().map(toml_value).collect();
+            format!("[{}]", items.join(", "))
+        }
+        Value::Object(entries) => {
+            let entries: Vec<String> = entries
+                .iter()
+                .map(|(k, v)| format!("{} = {}", k, toml_value(v)))
+                .collect();
+            format!("{{{}}}", entries.join(", "))
+        }
+        Value::Number(n) if n.is_f64() => format!("{:?}", n.as_f64().unwrap_or_default()),
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reference_documents_each_section() {
+        let reference = config_reference();
+
+        for heading in [
+            "## `[server]`",
+            "## `[management]`",
+            "## `[upstreams.<name>]`",
+            "## `[[routes]]`",
+            "## `[[static_files]]`",
+        ] {
+            assert!(reference.contains(heading), "missing {}", heading);
+        }
+        assert!(reference.contains("| `server.port` | integer | `8080` | Port to listen on |"));
+        assert!(reference.contains(
+            "| `server.health_endpoint.path` | string | `\"/_janus/health\"` | Request path |"
+        ));
+        assert!(reference.contains("| `upstreams.<name>.servers[].address` | string | required |"));
+        assert!(reference.contains(
+            "| `upstreams.<name>.load_balancing` | `\"round_robin\"` \\| `\"least_connections\"`"
+        ));
+        assert!(reference.contains("| `routes[].auth_request` | table (optional) | - |"));
+        assert!(reference.contains("| `routes[].auth_request.timeout_secs` | integer | `5` |"));
+        assert!(
+            reference.contains("| `static_files[].index` | array of string | `[\"index.html\"]` |")
+        );
+    }
+
+    /// The committed reference must be regenerated whenever a setting or
+    /// its doc comment changes
+    #[test]
+    fn test_committed_reference_is_current() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/../docs/config-reference.md");
+        let committed = std::fs::read_to_string(path).unwrap_or_default();
+        assert!(
+            committed == config_reference(),
+            "{} is out of date; run `janus docs generate --output docs/config-reference.md`",
+            path
+        );
+    }
+}
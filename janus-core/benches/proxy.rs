@@ -0,0 +1,72 @@
+//! Backend selection for each load balancing strategy, and route path
+//! matching for each kind of pattern
+//!
+//! Run with `cargo bench -p janus-core --bench proxy`.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use janus_common::config::{LoadBalancing, ServerConfig, UpstreamConfig};
+use janus_core::bench::{matches_route, ProxyHandler};
+
+/// Backend counts selected from
+const SERVER_COUNTS: [usize; 3] = [1, 10, 100];
+
+fn handler(load_balancing: LoadBalancing, servers: usize) -> ProxyHandler {
+    let upstream = UpstreamConfig {
+        load_balancing,
+        ..UpstreamConfig::new((0..servers).map(|i| format!("10.0.0.{}:8080", i)))
+    };
+    let route = ServerConfig {
+        default_upstream: Some("bench".to_string()),
+        ..Default::default()
+    }
+    .default_route()
+    .unwrap();
+    ProxyHandler::new(upstream, route)
+}
+
+fn select_backend(c: &mut Criterion) {
+    let mut group = c.benchmark_group("select_backend");
+    for strategy in [
+        LoadBalancing::RoundRobin,
+        LoadBalancing::LeastConnections,
+        LoadBalancing::Random,
+        LoadBalancing::IpHash,
+    ] {
+        for servers in SERVER_COUNTS {
+            let handler = handler(strategy.clone(), servers);
+            let id = BenchmarkId::new(format!("{:?}", strategy), servers);
+            group.bench_with_input(id, &handler, |b, handler| {
+                b.iter(|| {
+                    let _ = black_box(handler.select_backend(black_box(false)));
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+fn matches_route_patterns(c: &mut Criterion) {
+    let mut group = c.benchmark_group("matches_route");
+    let segments = "/segment".repeat(16);
+    let paths = [
+        ("short", "/api/users".to_string()),
+        ("long", format!("/api{}", segments)),
+    ];
+    let patterns = [
+        ("literal", "/api/users"),
+        ("prefix", "/api*"),
+        ("wildcard", "/api/*"),
+    ];
+    for (kind, pattern) in patterns {
+        for (length, path) in &paths {
+            let id = BenchmarkId::new(kind, length);
+            group.bench_with_input(id, path, |b, path| {
+                b.iter(|| matches_route(black_box(path), black_box(pattern)))
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, select_backend, matches_route_patterns);
+criterion_main!(benches);
@@ -0,0 +1,203 @@
+//! Stats updates from concurrent requests: the lock-free counters against
+//! the locked struct they replaced, for the server-wide counters alone and
+//! for everything a request records once served
+//!
+//! Run with `cargo bench -p janus-core --bench stats`.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use hyper::StatusCode;
+use janus_common::StatusCodeStats;
+use janus_core::{AppState, JanusConfig};
+use std::future::Future;
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::runtime::Runtime;
+use tokio::sync::RwLock;
+
+/// Request tasks running at once
+const TASKS: usize = 64;
+
+/// Requests counted by each task
+const REQUESTS: usize = 1_000;
+
+/// A mix of statuses, so every status class is updated
+const STATUSES: [StatusCode; 4] = [
+    StatusCode::OK,
+    StatusCode::NOT_MODIFIED,
+    StatusCode::NOT_FOUND,
+    StatusCode::BAD_GATEWAY,
+];
+
+/// Routes requests are spread across
+const ROUTES: [&str; 4] = ["/api/*", "/auth/*", "static:/assets/", "default"];
+
+/// The previous scheme, taking the write lock once for the request count
+/// and again for the status code
+#[derive(Default)]
+struct LockedStats {
+    total_requests: u64,
+    status_codes: StatusCodeStats,
+}
+
+async fn record_locked(stats: &RwLock<LockedStats>, status: StatusCode) {
+    stats.write().await.total_requests += 1;
+
+    let mut stats = stats.write().await;
+    match status.as_u16() {
+        200..=299 => stats.status_codes.success += 1,
+        300..=399 => stats.status_codes.redirect += 1,
+        400..=499 => stats.status_codes.client_error += 1,
+        _ => stats.status_codes.server_error += 1,
+    }
+}
+
+/// The previous scheme for a served request, which also took the stats
+/// write lock to count it against its client and route
+struct LockedTraffic {
+    counters: RwLock<LockedStats>,
+    /// The same per-key counting as today, serialized behind one lock
+    per_key: RwLock<AppState>,
+}
+
+async fn serve_locked(traffic: &LockedTraffic, request: Request) {
+    record_locked(&traffic.counters, request.status).await;
+    record_traffic(&*traffic.per_key.write().await, request);
+}
+
+/// Everything counted for a served request today: the server-wide
+/// counters, then the client and route
+fn serve_sharded(state: &AppState, request: Request) {
+    state
+        .counters
+        .total_requests
+        .fetch_add(1, Ordering::Relaxed);
+    state.counters.record_status(request.status);
+    record_traffic(state, request);
+}
+
+fn record_traffic(state: &AppState, request: Request) {
+    state.traffic.record_request(
+        request.client,
+        Some(request.route),
+        request.status,
+        Duration::from_millis(3),
+        0,
+        100,
+    );
+}
+
+/// What a request records once served
+#[derive(Clone, Copy)]
+struct Request {
+    client: IpAddr,
+    route: &'static str,
+    status: StatusCode,
+}
+
+/// Run `record` for every request from many tasks at once, each task
+/// standing for a different client
+fn concurrently<F, Fut>(runtime: &Runtime, record: F)
+where
+    F: Fn(Request) -> Fut + Clone + Send + 'static,
+    Fut: Future<Output = ()> + Send,
+{
+    runtime.block_on(async {
+        let tasks: Vec<_> = (0..TASKS)
+            .map(|task| {
+                let record = record.clone();
+                let client = IpAddr::V4(Ipv4Addr::new(10, 0, 0, task as u8));
+                tokio::spawn(async move {
+                    for i in 0..REQUESTS {
+                        record(Request {
+                            client,
+                            route: ROUTES[i % ROUTES.len()],
+                            status: STATUSES[i % STATUSES.len()],
+                        })
+                        .await;
+                    }
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+    });
+}
+
+fn stats_updates(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let mut group = c.benchmark_group("stats_updates");
+
+    let locked = Arc::new(RwLock::new(LockedStats::default()));
+    group.bench_function("locked", |b| {
+        b.iter(|| {
+            let stats = locked.clone();
+            concurrently(&runtime, move |request| {
+                let stats = stats.clone();
+                async move { record_locked(&stats, request.status).await }
+            })
+        })
+    });
+
+    let state = Arc::new(AppState::new(JanusConfig::default(), None));
+    group.bench_function("atomic", |b| {
+        b.iter(|| {
+            let state = state.clone();
+            concurrently(&runtime, move |request| {
+                let state = state.clone();
+                async move {
+                    state
+                        .counters
+                        .total_requests
+                        .fetch_add(1, Ordering::Relaxed);
+                    state.counters.record_status(request.status);
+                }
+            })
+        })
+    });
+
+    group.finish();
+}
+
+fn request_bookkeeping(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let mut group = c.benchmark_group("request_bookkeeping");
+
+    let locked = Arc::new(LockedTraffic {
+        counters: RwLock::default(),
+        per_key: RwLock::new(AppState::new(JanusConfig::default(), None)),
+    });
+    group.bench_function("locked", |b| {
+        b.iter(|| {
+            let traffic = locked.clone();
+            concurrently(&runtime, move |request| {
+                let traffic = traffic.clone();
+                async move { serve_locked(&traffic, request).await }
+            })
+        })
+    });
+
+    let state = Arc::new(AppState::new(JanusConfig::default(), None));
+    group.bench_function("sharded", |b| {
+        b.iter(|| {
+            let state = state.clone();
+            concurrently(&runtime, move |request| {
+                let state = state.clone();
+                async move { serve_sharded(&state, request) }
+            })
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, stats_updates, request_bookkeeping);
+criterion_main!(benches);
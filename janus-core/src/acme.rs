@@ -0,0 +1,591 @@
+//! Certificates from an ACME CA (RFC 8555), such as Let's Encrypt
+//!
+//! [`Acme`] holds the certificate served on the HTTPS listener, answers the
+//! CA's HTTP-01 challenges on the HTTP listener, and renews the certificate
+//! ahead of its expiry. Each new certificate is swapped in for new
+//! handshakes, so open connections are left alone, and a failed renewal
+//! keeps serving the current one.
+
+use crate::wait_for_shutdown;
+use anyhow::{anyhow, bail, Context, Result};
+use arc_swap::ArcSwapOption;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full};
+use hyper::header::{CONTENT_TYPE, HOST, LOCATION, USER_AGENT};
+use hyper::{Method, Request, Response, Uri};
+use hyper_util::rt::TokioIo;
+use janus_common::{AcmeConfig, AcmeStatus};
+use ring::rand::SystemRandom;
+use ring::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::net::TcpStream;
+use tokio::sync::watch;
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+use tracing::{debug, error, info, warn};
+
+/// Path prefix of the CA's HTTP-01 challenge requests
+pub const CHALLENGE_PREFIX: &str = "/.well-known/acme-challenge/";
+
+/// Wait before trying again after failing to get a certificate
+const RETRY_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Longest wait between checks of the certificate's expiry
+const MAX_CHECK_INTERVAL: Duration = Duration::from_secs(24 * 3600);
+
+/// Time allowed for each request to the CA
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Checks of a pending authorization or order before giving up
+const POLL_ATTEMPTS: u32 = 30;
+
+/// Wait between those checks
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Files kept in the cache directory
+const ACCOUNT_KEY_FILE: &str = "account.key";
+const CERT_FILE: &str = "cert.pem";
+const KEY_FILE: &str = "key.pem";
+
+/// Certificate obtained from an ACME CA, and the challenges proving
+/// control of its domains
+pub struct Acme {
+    settings: AcmeConfig,
+    cert: ArcSwapOption<CertifiedKey>,
+    /// Key authorizations of pending HTTP-01 challenges, by token
+    challenges: Mutex<HashMap<String, String>>,
+    status: Mutex<AcmeStatus>,
+}
+
+impl fmt::Debug for Acme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Acme")
+            .field("domains", &self.settings.domains)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Acme {
+    /// Start from the certificate in the cache directory, if there is one
+    pub fn new(settings: AcmeConfig) -> Arc<Self> {
+        let acme = Self {
+            cert: ArcSwapOption::empty(),
+            challenges: Mutex::new(HashMap::new()),
+            status: Mutex::new(AcmeStatus {
+                domains: settings.domains.clone(),
+                ..Default::default()
+            }),
+            settings,
+        };
+
+        let cache = acme.cache_dir();
+        match (
+            std::fs::read_to_string(cache.join(CERT_FILE)),
+            std::fs::read_to_string(cache.join(KEY_FILE)),
+        ) {
+            (Ok(chain), Ok(key)) => {
+                if let Err(e) = acme.install(&chain, &key) {
+                    warn!("Ignoring cached certificate in {}: {}", cache.display(), e);
+                }
+            }
+            _ => debug!("No cached certificate in {}", cache.display()),
+        }
+        Arc::new(acme)
+    }
+
+    /// Acceptor for the HTTPS listener, serving whichever certificate is
+    /// current at each handshake
+    pub fn acceptor(self: &Arc<Self>) -> TlsAcceptor {
+        let config = rustls::ServerConfig::builder()
+            .with_no_client_auth()
+            .with_cert_resolver(self.clone());
+        TlsAcceptor::from(Arc::new(config))
+    }
+
+    /// Answer to an HTTP-01 challenge request, if `path` is a pending one
+    pub fn challenge_response(&self, path: &str) -> Option<String> {
+        let token = path.strip_prefix(CHALLENGE_PREFIX)?;
+        self.challenges().get(token).cloned()
+    }
+
+    /// Expiry of the current certificate and the outcome of the last request
+    pub fn status(&self) -> AcmeStatus {
+        self.lock_status().clone()
+    }
+
+    /// Keep the certificate current until shutdown is requested
+    pub async fn run(self: Arc<Self>, mut shutdown: watch::Receiver<bool>) {
+        loop {
+            let wait = match self.time_until_renewal() {
+                Some(wait) => wait.min(MAX_CHECK_INTERVAL),
+                None => {
+                    let renewed = tokio::select! {
+                        renewed = self.renew() => renewed,
+                        _ = wait_for_shutdown(&mut shutdown) => break,
+                    };
+                    match renewed {
+                        // Should the CA issue certificates shorter lived
+                        // than the renewal window, do not ask right away
+                        Ok(()) => self.time_until_renewal().unwrap_or(RETRY_INTERVAL),
+                        Err(e) => {
+                            error!(
+                                "Failed to obtain a certificate for {}: {:#}",
+                                self.settings.domains.join(", "),
+                                e
+                            );
+                            self.lock_status().last_error = Some(format!("{:#}", e));
+                            RETRY_INTERVAL
+                        }
+                    }
+                }
+            };
+
+            tokio::select! {
+                _ = tokio::time::sleep(wait) => {}
+                _ = wait_for_shutdown(&mut shutdown) => break,
+            }
+        }
+    }
+
+    /// Time left before the certificate should be renewed, or `None` if it
+    /// is due (or there is none)
+    fn time_until_renewal(&self) -> Option<Duration> {
+        let expires_at = self.lock_status().expires_at?;
+        let renew_at = expires_at.saturating_sub(self.settings.renew_before_days * 24 * 3600);
+        let wait = renew_at.checked_sub(unix_now())?;
+        (wait > 0).then(|| Duration::from_secs(wait))
+    }
+
+    /// Order a certificate for the configured domains and start serving it
+    async fn renew(&self) -> Result<()> {
+        self.lock_status().last_attempt = Some(unix_now());
+        let domains = &self.settings.domains;
+        info!("Requesting a certificate for {}", domains.join(", "));
+
+        let mut client = Client::new(&self.settings.directory_url, self.account_key()?).await?;
+        client.register(&self.settings.contact_email).await?;
+
+        let identifiers: Vec<Value> = domains
+            .iter()
+            .map(|domain| json!({ "type": "dns", "value": domain }))
+            .collect();
+        let new_order = client.directory.new_order.clone();
+        let response = client
+            .post(&new_order, Some(&json!({ "identifiers": identifiers })))
+            .await?;
+        let order = response.json()?;
+        let order_url = response.location.context("Order has no URL")?;
+
+        // Challenges are answered only while they are being validated
+        let result = self.authorize(&mut client, &order).await;
+        self.challenges().clear();
+        result?;
+
+        let key = rcgen::KeyPair::generate()?;
+        let mut params = rcgen::CertificateParams::new(domains.clone())?;
+        params.distinguished_name = rcgen::DistinguishedName::new();
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, domains[0].clone());
+        let csr = params.serialize_request(&key)?;
+
+        let finalize = json_str(&order, "finalize")?;
+        client
+            .post(finalize, Some(&json!({ "csr": b64(csr.der()) })))
+            .await?;
+        let order = client.poll(&order_url).await?;
+        let chain = client.post(json_str(&order, "certificate")?, None).await?;
+        let chain = String::from_utf8(chain.body.to_vec()).context("Certificate is not PEM")?;
+
+        let key = key.serialize_pem();
+        let expires_at = self.install(&chain, &key)?;
+        self.save(&chain, &key);
+        self.lock_status().last_error = None;
+        info!(
+            "Obtained a certificate for {}, expiring at {}",
+            domains.join(", "),
+            expires_at
+        );
+        Ok(())
+    }
+
+    /// Complete the HTTP-01 challenge of each pending authorization
+    async fn authorize(&self, client: &mut Client, order: &Value) -> Result<()> {
+        let authorizations = order["authorizations"]
+            .as_array()
+            .context("Order lists no authorizations")?;
+        for url in authorizations {
+            let url = url.as_str().context("Invalid authorization URL")?;
+            let authorization = client.post(url, None).await?.json()?;
+            if authorization["status"] == "valid" {
+                continue;
+            }
+
+            let challenge = authorization["challenges"]
+                .as_array()
+                .and_then(|challenges| challenges.iter().find(|c| c["type"] == "http-01"))
+                .with_context(|| {
+                    format!(
+                        "No HTTP-01 challenge offered for {}",
+                        authorization["identifier"]["value"]
+                    )
+                })?;
+            let token = json_str(challenge, "token")?;
+            let key_authorization = format!("{}.{}", token, client.thumbprint());
+            self.challenges()
+                .insert(token.to_string(), key_authorization);
+
+            client
+                .post(json_str(challenge, "url")?, Some(&json!({})))
+                .await?;
+            client.poll(url).await?;
+        }
+        Ok(())
+    }
+
+    /// Start serving a certificate chain, returning when it expires
+    fn install(&self, chain: &str, key: &str) -> Result<u64> {
+        let certs = CertificateDer::pem_slice_iter(chain.as_bytes())
+            .collect::<Result<Vec<_>, _>>()
+            .context("Invalid certificate chain")?;
+        let leaf = certs.first().context("No certificate in chain")?;
+        let (_, parsed) =
+            x509_parser::parse_x509_certificate(leaf).context("Invalid certificate")?;
+        let expires_at = parsed.validity().not_after.timestamp().max(0) as u64;
+
+        let key = PrivateKeyDer::from_pem_slice(key.as_bytes()).context("Invalid private key")?;
+        let key = rustls::crypto::ring::sign::any_supported_type(&key)?;
+        self.cert
+            .store(Some(Arc::new(CertifiedKey::new(certs, key))));
+        self.lock_status().expires_at = Some(expires_at);
+        Ok(expires_at)
+    }
+
+    /// Keep a certificate for the next start; failing to only costs a
+    /// request to the CA then
+    fn save(&self, chain: &str, key: &str) {
+        let cache = self.cache_dir();
+        let saved = std::fs::create_dir_all(&cache)
+            .and_then(|()| std::fs::write(cache.join(CERT_FILE), chain))
+            .and_then(|()| write_private(&cache.join(KEY_FILE), key.as_bytes()));
+        if let Err(e) = saved {
+            warn!("Failed to cache certificate in {}: {}", cache.display(), e);
+        }
+    }
+
+    /// Account key from the cache directory, created on first use
+    fn account_key(&self) -> Result<EcdsaKeyPair> {
+        let path = self.cache_dir().join(ACCOUNT_KEY_FILE);
+        let rng = SystemRandom::new();
+        let pkcs8 = match std::fs::read(&path) {
+            Ok(pkcs8) => pkcs8,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng)
+                    .map_err(|_| anyhow!("Failed to generate an account key"))?;
+                std::fs::create_dir_all(self.cache_dir())?;
+                write_private(&path, pkcs8.as_ref())
+                    .with_context(|| format!("Failed to write {}", path.display()))?;
+                pkcs8.as_ref().to_vec()
+            }
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &pkcs8, &rng)
+            .map_err(|_| anyhow!("Invalid account key in {}", path.display()))
+    }
+
+    fn cache_dir(&self) -> PathBuf {
+        PathBuf::from(&self.settings.cache_dir)
+    }
+
+    fn challenges(&self) -> std::sync::MutexGuard<'_, HashMap<String, String>> {
+        self.challenges.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn lock_status(&self) -> std::sync::MutexGuard<'_, AcmeStatus> {
+        self.status.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl ResolvesServerCert for Acme {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        self.cert.load_full()
+    }
+}
+
+/// URLs from the CA's directory
+struct Directory {
+    new_nonce: String,
+    new_account: String,
+    new_order: String,
+}
+
+/// Signed requests to the CA on behalf of an account
+struct Client {
+    directory: Directory,
+    key: EcdsaKeyPair,
+    rng: SystemRandom,
+    /// Nonce for the next request, from the last response
+    nonce: Option<String>,
+    /// Account URL, once registered
+    kid: Option<String>,
+}
+
+/// Response from the CA
+struct Reply {
+    location: Option<String>,
+    body: Bytes,
+}
+
+impl Reply {
+    fn json(&self) -> Result<Value> {
+        serde_json::from_slice(&self.body).context("Invalid JSON from the CA")
+    }
+}
+
+impl Client {
+    async fn new(directory_url: &str, key: EcdsaKeyPair) -> Result<Self> {
+        let response = request(Method::GET, directory_url, None).await?;
+        if !response.status().is_success() {
+            bail!("{} from {}", response.status(), directory_url);
+        }
+        let directory: Value =
+            serde_json::from_slice(response.body()).context("Invalid ACME directory")?;
+        let url = |name: &str| json_str(&directory, name).map(str::to_string);
+        Ok(Self {
+            directory: Directory {
+                new_nonce: url("newNonce")?,
+                new_account: url("newAccount")?,
+                new_order: url("newOrder")?,
+            },
+            key,
+            rng: SystemRandom::new(),
+            nonce: None,
+            kid: None,
+        })
+    }
+
+    /// Register the account, or find it if it already exists
+    async fn register(&mut self, contact_email: &str) -> Result<()> {
+        let payload = json!({
+            "termsOfServiceAgreed": true,
+            "contact": [format!("mailto:{}", contact_email)],
+        });
+        let new_account = self.directory.new_account.clone();
+        let response = self.post(&new_account, Some(&payload)).await?;
+        self.kid = Some(response.location.context("Account has no URL")?);
+        Ok(())
+    }
+
+    /// Send a signed request, or a POST-as-GET without a payload
+    async fn post(&mut self, url: &str, payload: Option<&Value>) -> Result<Reply> {
+        let mut retried = false;
+        loop {
+            let nonce = match self.nonce.take() {
+                Some(nonce) => nonce,
+                None => self.fetch_nonce().await?,
+            };
+            let body = self.sign(url, &nonce, payload)?;
+            let response = request(Method::POST, url, Some(body)).await?;
+            self.nonce = header(&response, "replay-nonce");
+
+            if response.status().is_success() {
+                return Ok(Reply {
+                    location: header(&response, LOCATION.as_str()),
+                    body: response.into_body(),
+                });
+            }
+            let problem: Value = serde_json::from_slice(response.body()).unwrap_or_default();
+            // Nonces can expire, so one rejection is worth another try
+            if problem["type"] == "urn:ietf:params:acme:error:badNonce" && !retried {
+                retried = true;
+                continue;
+            }
+            bail!(
+                "{} from {}: {}",
+                response.status(),
+                url,
+                problem["detail"].as_str().unwrap_or("no details")
+            );
+        }
+    }
+
+    /// Fetch an object until its status is valid
+    async fn poll(&mut self, url: &str) -> Result<Value> {
+        for _ in 0..POLL_ATTEMPTS {
+            let object = self.post(url, None).await?.json()?;
+            match object["status"].as_str() {
+                Some("valid") => return Ok(object),
+                Some("invalid") => bail!("{} is invalid: {}", url, problem_detail(&object)),
+                _ => tokio::time::sleep(POLL_INTERVAL).await,
+            }
+        }
+        bail!("{} did not become valid in time", url)
+    }
+
+    async fn fetch_nonce(&self) -> Result<String> {
+        let response = request(Method::HEAD, &self.directory.new_nonce, None).await?;
+        header(&response, "replay-nonce").context("CA sent no nonce")
+    }
+
+    /// Flattened JWS of a request, signed with the account key
+    fn sign(&self, url: &str, nonce: &str, payload: Option<&Value>) -> Result<Vec<u8>> {
+        let mut protected = json!({ "alg": "ES256", "nonce": nonce, "url": url });
+        match self.kid {
+            Some(ref kid) => protected["kid"] = json!(kid),
+            None => protected["jwk"] = serde_json::from_str(&self.jwk())?,
+        }
+        let protected = b64(protected.to_string());
+        let payload = payload.map_or_else(String::new, |p| b64(p.to_string()));
+        let signature = self
+            .key
+            .sign(&self.rng, format!("{}.{}", protected, payload).as_bytes())
+            .map_err(|_| anyhow!("Failed to sign request"))?;
+        let jws = json!({
+            "protected": protected,
+            "payload": payload,
+            "signature": b64(signature),
+        });
+        Ok(jws.to_string().into_bytes())
+    }
+
+    /// Public account key as a JWK, with its members in the order the
+    /// thumbprint needs
+    fn jwk(&self) -> String {
+        // Uncompressed point: 0x04, then the x and y coordinates
+        let point = self.key.public_key().as_ref();
+        format!(
+            r#"{{"crv":"P-256","kty":"EC","x":"{}","y":"{}"}}"#,
+            b64(&point[1..33]),
+            b64(&point[33..])
+        )
+    }
+
+    /// Account key thumbprint (RFC 7638), the second half of key
+    /// authorizations
+    fn thumbprint(&self) -> String {
+        b64(ring::digest::digest(
+            &ring::digest::SHA256,
+            self.jwk().as_bytes(),
+        ))
+    }
+}
+
+/// Make a request to the CA over HTTPS (or plain HTTP, for test CAs)
+async fn request(method: Method, url: &str, body: Option<Vec<u8>>) -> Result<Response<Bytes>> {
+    tokio::time::timeout(REQUEST_TIMEOUT, send(method, url, body))
+        .await
+        .with_context(|| format!("{} timed out", url))?
+}
+
+async fn send(method: Method, url: &str, body: Option<Vec<u8>>) -> Result<Response<Bytes>> {
+    let uri: Uri = url
+        .parse()
+        .with_context(|| format!("Invalid URL {}", url))?;
+    let host = uri.host().with_context(|| format!("No host in {}", url))?;
+    let https = uri.scheme_str() != Some("http");
+    let port = uri.port_u16().unwrap_or(if https { 443 } else { 80 });
+
+    let mut builder = Request::builder()
+        .method(method)
+        .uri(uri.path_and_query().map_or("/", |p| p.as_str()))
+        .header(HOST, uri.authority().map_or(host, |a| a.as_str()))
+        .header(USER_AGENT, concat!("janus/", env!("CARGO_PKG_VERSION")));
+    if body.is_some() {
+        builder = builder.header(CONTENT_TYPE, "application/jose+json");
+    }
+    let request = builder.body(Full::new(Bytes::from(body.unwrap_or_default())))?;
+
+    let stream = TcpStream::connect((host, port))
+        .await
+        .with_context(|| format!("Failed to connect to {}", url))?;
+    if !https {
+        return exchange(TokioIo::new(stream), request).await;
+    }
+    let roots = rustls::RootCertStore {
+        roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+    };
+    let config = rustls::ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    let name = ServerName::try_from(host.to_string())?;
+    let stream = TlsConnector::from(Arc::new(config))
+        .connect(name, stream)
+        .await
+        .with_context(|| format!("TLS handshake with {} failed", host))?;
+    exchange(TokioIo::new(stream), request).await
+}
+
+/// Send one request on a new connection and read the response in full
+async fn exchange<I>(io: I, request: Request<Full<Bytes>>) -> Result<Response<Bytes>>
+where
+    I: hyper::rt::Read + hyper::rt::Write + Unpin + Send + 'static,
+{
+    let (mut sender, conn) = hyper::client::conn::http1::handshake(io).await?;
+    tokio::spawn(async move {
+        if let Err(e) = conn.await {
+            debug!("ACME connection error: {}", e);
+        }
+    });
+    let response = sender.send_request(request).await?;
+    let (parts, body) = response.into_parts();
+    let body = body.collect().await?.to_bytes();
+    Ok(Response::from_parts(parts, body))
+}
+
+fn header<B>(response: &Response<B>, name: &str) -> Option<String> {
+    response
+        .headers()
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string)
+}
+
+fn json_str<'a>(value: &'a Value, name: &str) -> Result<&'a str> {
+    value[name]
+        .as_str()
+        .with_context(|| format!("CA response has no '{}'", name))
+}
+
+/// Why an authorization or order became invalid, as reported by the CA
+fn problem_detail(object: &Value) -> String {
+    let challenge_errors = object["challenges"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|c| &c["error"]);
+    std::iter::once(&object["error"])
+        .chain(challenge_errors)
+        .find_map(|error| error["detail"].as_str())
+        .unwrap_or("no details")
+        .to_string()
+}
+
+fn b64(data: impl AsRef<[u8]>) -> String {
+    URL_SAFE_NO_PAD.encode(data)
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// Write a file only the owner can read
+fn write_private(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)?.write_all(contents)
+}
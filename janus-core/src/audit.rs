@@ -0,0 +1,186 @@
+//! Audit trail of management changes and config file reloads
+//!
+//! Every mutating management message and every reload by the config file
+//! watcher is appended to `management.audit_log` as one JSON object per
+//! line, whether it succeeded or not. The file is only ever appended to;
+//! rotating it is left to the operator.
+
+use janus_common::{AuditEntry, AuditSource, ClientMessage, ServerMessage};
+use std::io::SeekFrom;
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::Mutex;
+use tracing::error;
+
+/// Most of the end of the file read to find recent entries
+const TAIL_BYTES: u64 = 1024 * 1024;
+
+/// Writer for the audit log file
+#[derive(Debug, Default)]
+pub struct AuditLog {
+    /// Held while appending, so concurrent entries do not interleave
+    write_lock: Mutex<()>,
+}
+
+impl AuditLog {
+    /// Append an entry to the file at `path`, logging any failure
+    ///
+    /// The change has already happened by now, so a write failure does not
+    /// undo it.
+    pub async fn record(&self, path: &str, entry: &AuditEntry) {
+        let mut line = match serde_json::to_string(entry) {
+            Ok(line) => line,
+            Err(e) => {
+                error!("Failed to encode audit log entry: {}", e);
+                return;
+            }
+        };
+        line.push('\n');
+
+        let _guard = self.write_lock.lock().await;
+        let written = async {
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .await?;
+            file.write_all(line.as_bytes()).await?;
+            file.flush().await
+        };
+        if let Err(e) = written.await {
+            error!("Failed to write audit log {}: {}", path, e);
+        }
+    }
+
+    /// Up to `limit` entries from the end of the file at `path`, newest
+    /// first; a file not written yet has none
+    pub async fn recent(&self, path: &str, limit: usize) -> std::io::Result<Vec<AuditEntry>> {
+        let mut file = match File::open(path).await {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let start = file.metadata().await?.len().saturating_sub(TAIL_BYTES);
+        file.seek(SeekFrom::Start(start)).await?;
+        let mut tail = Vec::new();
+        file.read_to_end(&mut tail).await?;
+
+        let tail = String::from_utf8_lossy(&tail);
+        let mut lines = tail.lines();
+        if start > 0 {
+            // Most likely cut short by the seek
+            lines.next();
+        }
+        Ok(lines
+            .rev()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .take(limit)
+            .collect())
+    }
+}
+
+/// Entry for an operation, stamped with the current time and not yet
+/// marked as succeeded
+pub fn entry(source: AuditSource, operation: &str, summary: String) -> AuditEntry {
+    AuditEntry {
+        timestamp: std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs()),
+        source,
+        peer: None,
+        certificate: None,
+        token: None,
+        role: None,
+        operation: operation.to_string(),
+        summary,
+        success: false,
+        error: None,
+    }
+}
+
+/// Record how a management operation turned out from its response
+pub fn finish(entry: &mut AuditEntry, response: &ServerMessage) {
+    match response {
+        ServerMessage::Error(e) => entry.error = Some(e.clone()),
+        ServerMessage::ConfigValidationErrors(issues) => {
+            let issues: Vec<_> = issues
+                .iter()
+                .map(|issue| format!("{}: {}", issue.field, issue.message))
+                .collect();
+            entry.error = Some(issues.join("; "));
+        }
+        _ => entry.success = true,
+    }
+}
+
+/// What a mutating message changes, in a line
+pub fn summarize(msg: &ClientMessage) -> String {
+    match msg {
+        ClientMessage::UpdateConfig(config) => format!(
+            "{} routes, {} upstreams, {} static directories",
+            config.routes.len(),
+            config.upstreams.len(),
+            config.static_files.len()
+        ),
+        ClientMessage::UpdateServerPort(port) => format!("server.port = {}", port),
+        ClientMessage::UpdateBindAddress(address) => {
+            format!("server.bind_address = {}", address)
+        }
+        ClientMessage::AddRoute(route) => format!("{} -> {}", route.path, route.upstream),
+        ClientMessage::AddUpstream { name, config }
+        | ClientMessage::UpdateUpstream { name, config } => {
+            let servers: Vec<_> = config.servers.iter().map(|s| s.address.as_str()).collect();
+            format!("{} ({})", name, servers.join(", "))
+        }
+        ClientMessage::AddStaticDir(config) | ClientMessage::UpdateStaticDir { config, .. } => {
+            format!("{} -> {}", config.path, config.root)
+        }
+        ClientMessage::RemoveRoute(name)
+        | ClientMessage::RemoveUpstream(name)
+        | ClientMessage::DrainUpstream(name)
+        | ClientMessage::RemoveStaticDir(name) => name.clone(),
+        ClientMessage::DrainBackendServer {
+            upstream,
+            server_address,
+        } => format!("{} ({})", upstream, server_address),
+        ClientMessage::SetRouteEnabled { path, enabled }
+        | ClientMessage::SetStaticDirEnabled { path, enabled } => {
+            format!("{} {}", path, if *enabled { "on" } else { "off" })
+        }
+        ClientMessage::ReloadConfig => "from file".to_string(),
+        ClientMessage::ResetStats => "all request statistics".to_string(),
+        ClientMessage::SetMaintenance { scope, enabled, .. } => {
+            format!("{} {}", scope, if *enabled { "on" } else { "off" })
+        }
+        ClientMessage::Shutdown { delay_secs, .. } => format!("in {}s", delay_secs),
+        _ => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_recent_entries_newest_first() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.jsonl");
+        let path = path.to_str().unwrap();
+        let log = AuditLog::default();
+        assert_eq!(log.recent(path, 10).await.unwrap(), vec![]);
+
+        for i in 0..3 {
+            let mut entry = entry(AuditSource::Management, "RemoveRoute", format!("/{}", i));
+            finish(&mut entry, &ServerMessage::Success("removed".to_string()));
+            log.record(path, &entry).await;
+        }
+        let entries = log.recent(path, 2).await.unwrap();
+        let summaries: Vec<_> = entries.iter().map(|e| e.summary.as_str()).collect();
+        assert_eq!(summaries, ["/2", "/1"]);
+        assert!(entries[0].success);
+
+        // One line per entry, so the file can be followed with standard tools
+        let text = std::fs::read_to_string(path).unwrap();
+        assert_eq!(text.lines().count(), 3);
+    }
+}
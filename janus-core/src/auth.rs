@@ -0,0 +1,112 @@
+//! Auth subrequests made before proxying a route's requests
+
+use crate::proxy::ProxyHandler;
+use crate::server::{error_response, full_body};
+use crate::AppState;
+use bytes::Bytes;
+use http_body_util::combinators::BoxBody;
+use hyper::header::{HeaderName, HeaderValue, CONTENT_TYPE, WWW_AUTHENTICATE};
+use hyper::{HeaderMap, Request, Response, StatusCode};
+use janus_common::AuthRequestConfig;
+use std::convert::Infallible;
+use std::time::Instant;
+use tracing::{debug, warn};
+
+/// Ask a route's auth service whether to proxy a request
+///
+/// An allowed request gets the configured headers of the auth service's
+/// answer; otherwise the response to send the client is returned.
+pub async fn authorize<B>(
+    state: &AppState,
+    handler: &ProxyHandler,
+    auth: &AuthRequestConfig,
+    route: &str,
+    req: &mut Request<B>,
+) -> Result<(), Response<BoxBody<Bytes, Infallible>>> {
+    let mut headers = HeaderMap::new();
+    for name in &auth.forward_headers {
+        let Ok(name) = HeaderName::from_bytes(name.as_bytes()) else {
+            continue;
+        };
+        for value in req.headers().get_all(&name) {
+            headers.append(name.clone(), value.clone());
+        }
+    }
+    let original_uri = req.uri().path_and_query().map_or("/", |p| p.as_str());
+    if let Ok(uri) = HeaderValue::from_str(original_uri) {
+        headers.insert("x-original-uri", uri);
+    }
+    if let Ok(method) = HeaderValue::from_str(req.method().as_str()) {
+        headers.insert("x-original-method", method);
+    }
+
+    let started = Instant::now();
+    let result = handler.subrequest(&auth.path, headers).await;
+    let latency_ms = started.elapsed().as_secs_f64() * 1000.0;
+
+    let mut stats = state.stats.write().await;
+    let route_stats = stats.auth_requests.entry(route.to_string()).or_default();
+    route_stats.record(latency_ms);
+
+    let response = match result {
+        Ok(response) => response,
+        Err(e) => {
+            route_stats.errors += 1;
+            return Err(error_response(e.status(), "Authorization Unavailable"));
+        }
+    };
+
+    let status = response.status();
+    if status.is_success() {
+        drop(stats);
+        // Replace anything the client sent under these names, so only the
+        // auth service can set them
+        for name in &auth.copy_headers {
+            let Ok(name) = HeaderName::from_bytes(name.as_bytes()) else {
+                continue;
+            };
+            req.headers_mut().remove(&name);
+            for value in response.headers().get_all(&name) {
+                req.headers_mut().append(name.clone(), value.clone());
+            }
+        }
+        return Ok(());
+    }
+
+    if status != StatusCode::UNAUTHORIZED && status != StatusCode::FORBIDDEN {
+        route_stats.errors += 1;
+        drop(stats);
+        warn!(
+            "Auth service for route '{}' answered {}, expected 2xx, 401 or 403",
+            route, status
+        );
+        return Err(error_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Internal Server Error",
+        ));
+    }
+
+    route_stats.denied += 1;
+    drop(stats);
+    debug!("Auth service for route '{}' denied with {}", route, status);
+
+    let (parts, body) = response.into_parts();
+    let mut denied = if auth.forward_body {
+        let mut denied = Response::new(full_body(body.to_vec()));
+        *denied.status_mut() = status;
+        if let Some(content_type) = parts.headers.get(CONTENT_TYPE) {
+            denied
+                .headers_mut()
+                .insert(CONTENT_TYPE, content_type.clone());
+        }
+        denied
+    } else {
+        error_response(status, status.canonical_reason().unwrap_or("Denied"))
+    };
+    for challenge in parts.headers.get_all(WWW_AUTHENTICATE) {
+        denied
+            .headers_mut()
+            .append(WWW_AUTHENTICATE, challenge.clone());
+    }
+    Err(denied)
+}
@@ -0,0 +1,262 @@
+//! Responses kept in memory for routes with `cache` set
+//!
+//! Entries are keyed by route, so each route's `max_entries` bounds its own
+//! share. The whole cache is emptied when the configuration changes, as a
+//! route's upstream or headers may have changed with it.
+
+use bytes::Bytes;
+use http_body_util::{combinators::BoxBody, BodyExt, Full};
+use hyper::header::{HeaderName, HeaderValue, AGE, AUTHORIZATION, CACHE_CONTROL};
+use hyper::{HeaderMap, Method, Request, Response, StatusCode};
+use janus_common::config::CacheConfig;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Header telling clients whether a response came from the cache
+pub const CACHE_HEADER: &str = "x-cache";
+
+/// What a request is cached under within its route
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    method: Method,
+    uri: String,
+    /// Values of the route's `vary_on` headers, in order
+    vary: Vec<Option<HeaderValue>>,
+}
+
+impl CacheKey {
+    /// Key for a request, or None if it must not be cached
+    pub fn for_request<B>(req: &Request<B>, cache: &CacheConfig) -> Option<Self> {
+        // Answers to authorized requests are for that client alone
+        if !matches!(*req.method(), Method::GET | Method::HEAD)
+            || req.headers().contains_key(AUTHORIZATION)
+        {
+            return None;
+        }
+        let vary = cache
+            .vary_on
+            .iter()
+            .map(|name| req.headers().get(name.as_str()).cloned())
+            .collect();
+        Some(Self {
+            method: req.method().clone(),
+            uri: req.uri().to_string(),
+            vary,
+        })
+    }
+}
+
+/// A response as the upstream sent it
+struct CachedResponse {
+    headers: HeaderMap,
+    body: Bytes,
+    stored: Instant,
+}
+
+/// Cached responses of every route
+#[derive(Default)]
+pub struct ResponseCache {
+    routes: Mutex<HashMap<String, HashMap<CacheKey, CachedResponse>>>,
+}
+
+impl ResponseCache {
+    /// The route's cached response for a request, if it is fresh
+    pub fn get(
+        &self,
+        route: &str,
+        key: &CacheKey,
+        cache: &CacheConfig,
+    ) -> Option<Response<BoxBody<Bytes, Infallible>>> {
+        let mut routes = self.routes.lock().unwrap_or_else(|e| e.into_inner());
+        let entries = routes.get_mut(route)?;
+        let entry = entries.get(key)?;
+        let age = entry.stored.elapsed();
+        if age >= Duration::from_secs(cache.ttl_secs) {
+            entries.remove(key);
+            return None;
+        }
+
+        let mut response = Response::new(Full::new(entry.body.clone()).boxed());
+        *response.headers_mut() = entry.headers.clone();
+        let headers = response.headers_mut();
+        headers.insert(AGE, age.as_secs().into());
+        headers.insert(
+            HeaderName::from_static(CACHE_HEADER),
+            HeaderValue::from_static("HIT"),
+        );
+        Some(response)
+    }
+
+    /// Keep a copy of a response the route may cache, returning it to send
+    /// on to the client
+    pub async fn store(
+        &self,
+        route: &str,
+        key: CacheKey,
+        cache: &CacheConfig,
+        response: Response<BoxBody<Bytes, Infallible>>,
+    ) -> Response<BoxBody<Bytes, Infallible>> {
+        let (mut parts, body) = response.into_parts();
+        // Proxied bodies are already read in full, so this does not wait
+        let body = match body.collect().await {
+            Ok(collected) => collected.to_bytes(),
+            Err(never) => match never {},
+        };
+
+        if parts.status == StatusCode::OK && cacheable(&parts.headers) {
+            let mut routes = self.routes.lock().unwrap_or_else(|e| e.into_inner());
+            let entries = routes.entry(route.to_string()).or_default();
+            make_room(entries, cache);
+            entries.insert(
+                key,
+                CachedResponse {
+                    headers: parts.headers.clone(),
+                    body: body.clone(),
+                    stored: Instant::now(),
+                },
+            );
+        }
+
+        parts.headers.insert(
+            HeaderName::from_static(CACHE_HEADER),
+            HeaderValue::from_static("MISS"),
+        );
+        Response::from_parts(parts, Full::new(body).boxed())
+    }
+
+    /// Forget every cached response
+    pub fn clear(&self) {
+        self.routes
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clear();
+    }
+}
+
+/// Whether the upstream allows a shared cache to keep its response
+fn cacheable(headers: &HeaderMap) -> bool {
+    !headers
+        .get_all(CACHE_CONTROL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|directive| {
+            let directive = directive.trim();
+            directive.eq_ignore_ascii_case("no-store") || directive.eq_ignore_ascii_case("private")
+        })
+}
+
+/// Drop expired entries, then the oldest, until there is room for one more
+fn make_room(entries: &mut HashMap<CacheKey, CachedResponse>, cache: &CacheConfig) {
+    if entries.len() < cache.max_entries {
+        return;
+    }
+    let ttl = Duration::from_secs(cache.ttl_secs);
+    entries.retain(|_, entry| entry.stored.elapsed() < ttl);
+    while entries.len() >= cache.max_entries {
+        let Some(oldest) = entries
+            .iter()
+            .min_by_key(|(_, entry)| entry.stored)
+            .map(|(key, _)| key.clone())
+        else {
+            break;
+        };
+        entries.remove(&oldest);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn upstream_response(cache_control: Option<&str>) -> Response<BoxBody<Bytes, Infallible>> {
+        let mut response = Response::new(Full::new(Bytes::from_static(b"body")).boxed());
+        if let Some(value) = cache_control {
+            response
+                .headers_mut()
+                .insert(CACHE_CONTROL, HeaderValue::from_str(value).unwrap());
+        }
+        response
+    }
+
+    fn key(uri: &str) -> CacheKey {
+        let req = Request::get(uri).body(()).unwrap();
+        CacheKey::for_request(&req, &CacheConfig::default()).unwrap()
+    }
+
+    #[test]
+    fn test_only_anonymous_reads_are_cached() {
+        let cache = CacheConfig::default();
+        let post = Request::post("/").body(()).unwrap();
+        assert_eq!(CacheKey::for_request(&post, &cache), None);
+        let authorized = Request::get("/")
+            .header(AUTHORIZATION, "Bearer token")
+            .body(())
+            .unwrap();
+        assert_eq!(CacheKey::for_request(&authorized, &cache), None);
+    }
+
+    #[test]
+    fn test_vary_on_headers_split_keys() {
+        let cache = CacheConfig {
+            vary_on: vec!["Accept-Language".to_string()],
+            ..Default::default()
+        };
+        let request = |language: &str| {
+            let req = Request::get("/products")
+                .header("accept-language", language)
+                .body(())
+                .unwrap();
+            CacheKey::for_request(&req, &cache).unwrap()
+        };
+        assert_eq!(request("en"), request("en"));
+        assert_ne!(request("en"), request("de"));
+    }
+
+    #[tokio::test]
+    async fn test_no_store_and_private_are_not_kept() {
+        let cache = CacheConfig::default();
+        let store = ResponseCache::default();
+        for (uri, cache_control) in [
+            ("/a", Some("no-store")),
+            ("/b", Some("max-age=60, Private")),
+            ("/c", Some("max-age=60")),
+            ("/d", None),
+        ] {
+            let response = store
+                .store("/*", key(uri), &cache, upstream_response(cache_control))
+                .await;
+            assert_eq!(response.headers()[CACHE_HEADER], "MISS");
+        }
+        for (uri, kept) in [("/a", false), ("/b", false), ("/c", true), ("/d", true)] {
+            assert_eq!(
+                store.get("/*", &key(uri), &cache).is_some(),
+                kept,
+                "{}",
+                uri
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_oldest_entry_makes_room() {
+        let cache = CacheConfig {
+            max_entries: 2,
+            ..Default::default()
+        };
+        let store = ResponseCache::default();
+        for uri in ["/1", "/2", "/3"] {
+            store
+                .store("/*", key(uri), &cache, upstream_response(None))
+                .await;
+            // Entries are ordered by when they were stored
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        assert!(store.get("/*", &key("/1"), &cache).is_none());
+        let hit = store.get("/*", &key("/3"), &cache).unwrap();
+        assert_eq!(hit.headers()[CACHE_HEADER], "HIT");
+        assert_eq!(hit.headers()[AGE], "0");
+    }
+}
@@ -0,0 +1,182 @@
+//! Recent warnings and errors, kept for management clients
+//!
+//! [`ErrorLog::layer`] captures WARN and ERROR events from `tracing` into a
+//! bounded buffer, so operators can see why something failed without
+//! reading the server's stderr.
+
+use janus_common::{ErrorLogEntry, LogLevel};
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::fmt::{self, Write};
+use std::sync::{Arc, Mutex};
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+
+/// Entries kept before the oldest are dropped
+pub const ERROR_LOG_CAPACITY: usize = 200;
+
+/// Longest message kept, in bytes; the rest is cut off
+const MAX_MESSAGE_BYTES: usize = 1024;
+
+thread_local! {
+    /// Set while this thread is adding an entry, so an event logged from
+    /// inside the buffer lock is dropped instead of deadlocking
+    static RECORDING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Bounded buffer of the most recent warnings and errors
+#[derive(Debug, Default)]
+pub struct ErrorLog {
+    entries: Mutex<VecDeque<ErrorLogEntry>>,
+}
+
+impl ErrorLog {
+    /// Empty log, shared with its layer and the server
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    /// Layer recording WARN and ERROR events into this log
+    pub fn layer(self: &Arc<Self>) -> ErrorLogLayer {
+        ErrorLogLayer {
+            log: Arc::clone(self),
+        }
+    }
+
+    /// Up to `limit` entries, newest first
+    pub fn recent(&self, limit: usize) -> Vec<ErrorLogEntry> {
+        let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.iter().rev().take(limit).cloned().collect()
+    }
+
+    fn push(&self, entry: ErrorLogEntry) {
+        if RECORDING.with(|recording| recording.replace(true)) {
+            return;
+        }
+        {
+            let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+            if entries.len() == ERROR_LOG_CAPACITY {
+                entries.pop_front();
+            }
+            entries.push_back(entry);
+        }
+        RECORDING.with(|recording| recording.set(false));
+    }
+}
+
+/// `tracing` layer feeding an [`ErrorLog`]
+pub struct ErrorLogLayer {
+    log: Arc<ErrorLog>,
+}
+
+impl<S: Subscriber> Layer<S> for ErrorLogLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        let level = match *metadata.level() {
+            Level::ERROR => LogLevel::Error,
+            Level::WARN => LogLevel::Warn,
+            _ => return,
+        };
+
+        let mut visitor = MessageVisitor::default();
+        event.record(&mut visitor);
+
+        self.log.push(ErrorLogEntry {
+            timestamp: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+            level,
+            target: metadata.target().to_string(),
+            message: visitor.message,
+        });
+    }
+}
+
+/// Collects an event's message, then its other fields as `name=value`
+#[derive(Default)]
+struct MessageVisitor {
+    message: String,
+    fields: String,
+}
+
+impl Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            let mut message = Bounded(&mut self.message);
+            let _ = write!(message, "{:?}", value);
+            // Fields recorded before the message go after it
+            let fields = std::mem::take(&mut self.fields);
+            let _ = message.write_str(&fields);
+        } else if self.message.is_empty() {
+            let _ = write!(Bounded(&mut self.fields), " {}={:?}", field.name(), value);
+        } else {
+            let _ = write!(Bounded(&mut self.message), " {}={:?}", field.name(), value);
+        }
+    }
+}
+
+/// Writer that stops growing a string at [`MAX_MESSAGE_BYTES`]
+struct Bounded<'a>(&'a mut String);
+
+impl Write for Bounded<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let room = MAX_MESSAGE_BYTES.saturating_sub(self.0.len());
+        let mut end = room.min(s.len());
+        while !s.is_char_boundary(end) {
+            end -= 1;
+        }
+        self.0.push_str(&s[..end]);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[test]
+    fn test_records_warnings_and_errors_only() {
+        let log = ErrorLog::new();
+        let subscriber = tracing_subscriber::registry().with(log.layer());
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!("ignored");
+            tracing::warn!(upstream = "api", "Backend {} refused", "10.0.0.1:80");
+            tracing::error!("Config reload failed");
+            tracing::warn!("{}", "x".repeat(4 * MAX_MESSAGE_BYTES));
+        });
+
+        let entries = log.recent(10);
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].message.len(), MAX_MESSAGE_BYTES);
+        assert_eq!(entries[1].level, LogLevel::Error);
+        assert_eq!(entries[1].message, "Config reload failed");
+        assert_eq!(entries[2].level, LogLevel::Warn);
+        assert_eq!(
+            entries[2].message,
+            "Backend 10.0.0.1:80 refused upstream=\"api\""
+        );
+        assert_eq!(entries[2].target, module_path!());
+        assert_eq!(log.recent(1), entries[..1]);
+    }
+
+    #[test]
+    fn test_keeps_only_the_newest_entries() {
+        let log = ErrorLog::new();
+        let subscriber = tracing_subscriber::registry().with(log.layer());
+        tracing::subscriber::with_default(subscriber, || {
+            for i in 0..ERROR_LOG_CAPACITY + 5 {
+                tracing::warn!("warning {}", i);
+            }
+        });
+
+        let entries = log.recent(usize::MAX);
+        assert_eq!(entries.len(), ERROR_LOG_CAPACITY);
+        assert_eq!(
+            entries[0].message,
+            format!("warning {}", ERROR_LOG_CAPACITY + 4)
+        );
+        assert_eq!(entries[ERROR_LOG_CAPACITY - 1].message, "warning 5");
+    }
+}
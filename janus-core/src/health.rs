@@ -0,0 +1,91 @@
+//! Health endpoint for Janus itself
+
+use crate::server::full_body;
+use crate::AppState;
+use bytes::Bytes;
+use http_body_util::combinators::BoxBody;
+use hyper::{Response, StatusCode};
+use janus_common::JanusConfig;
+use std::convert::Infallible;
+use std::sync::atomic::Ordering;
+use tokio::net::TcpStream;
+
+/// Build the health response from the current state
+pub async fn health_response(
+    state: &AppState,
+    config: &JanusConfig,
+) -> Response<BoxBody<Bytes, Infallible>> {
+    let settings = &config.server.health_endpoint;
+    let draining = state.draining_upstreams.lock().await;
+
+    // An upstream is available if neither it nor every one of its servers
+    // is draining; backups count, as they take over once no primary is left
+    let is_available = |name: &str| {
+        config.upstreams.get(name).is_some_and(|u| {
+            u.servers.iter().any(|server| !server.drain) && !draining.contains(name)
+        })
+    };
+    let healthy_upstreams = config
+        .upstreams
+        .keys()
+        .filter(|name| is_available(name))
+        .count();
+
+    let mut problems: Vec<String> = settings
+        .critical_upstreams
+        .iter()
+        .filter(|name| !is_available(name))
+        .map(|name| format!("critical upstream '{}' has no available backends", name))
+        .collect();
+    drop(draining);
+
+    let config_drift = state.config_drift.load(Ordering::Relaxed);
+    if settings.fail_on_config_drift && config_drift {
+        problems.push("config file failed to reload".to_string());
+    }
+
+    let status = if problems.is_empty() {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    let body = serde_json::json!({
+        "status": if problems.is_empty() { "ok" } else { "unavailable" },
+        "version": env!("CARGO_PKG_VERSION"),
+        "uptime_secs": state.start_time.elapsed().as_secs(),
+        "healthy_upstreams": healthy_upstreams,
+        "total_upstreams": config.upstreams.len(),
+        "config_drift": config_drift,
+        "config_hash": **state.config_hash.load(),
+        "problems": problems,
+    });
+
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .header("Cache-Control", "no-store")
+        .body(full_body(body.to_string().into_bytes()))
+        .unwrap()
+}
+
+/// Check whether a connection to the management port is a plain HTTP
+/// request for the health endpoint rather than a WebSocket handshake
+pub async fn is_health_request(stream: &TcpStream, health_path: &str) -> bool {
+    let mut buf = [0u8; 512];
+    let Ok(n) = stream.peek(&mut buf).await else {
+        return false;
+    };
+
+    let head = String::from_utf8_lossy(&buf[..n]);
+    let Some(target) = head
+        .lines()
+        .next()
+        .and_then(|line| line.strip_prefix("GET "))
+        .and_then(|rest| rest.split(' ').next())
+    else {
+        return false;
+    };
+
+    target.split('?').next() == Some(health_path)
+}
@@ -0,0 +1,805 @@
+//! Janus Core - Embeddable web server and reverse proxy
+//!
+//! The [`Janus`] handle runs the HTTP server, management API and config
+//! watcher in-process, so Janus can be driven from code as well as from the
+//! `janus` binary.
+//!
+//! ```no_run
+//! use janus_core::{Janus, JanusConfig};
+//!
+//! # async fn run() -> anyhow::Result<()> {
+//! let janus = Janus::new(JanusConfig::default());
+//! let handle = janus.start().await?;
+//!
+//! // ... later
+//! janus.shutdown().await;
+//! handle.await??;
+//! # Ok(())
+//! # }
+//! ```
+
+#[cfg(feature = "acme")]
+mod acme;
+mod audit;
+mod auth;
+mod cache;
+mod error_log;
+mod health;
+mod maintenance;
+mod management;
+mod middleware;
+mod preflight;
+mod proxy;
+mod reload;
+mod server;
+mod spool;
+mod stats;
+mod status_page;
+mod tls;
+
+use anyhow::{Context, Result};
+use arc_swap::ArcSwap;
+use janus_common::{ConfigError, JanusConfigFile, ServerMessage, ServerStatus};
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+use tokio::sync::{broadcast, watch, Mutex, MutexGuard, RwLock};
+use tokio::task::JoinHandle;
+use tracing::{error, info};
+
+pub use error_log::{ErrorLog, ErrorLogLayer};
+pub use janus_common::{JanusConfig, PreflightReport, ServerStats};
+pub use preflight::{preflight, PreflightOptions};
+
+/// Internals measured by the benchmarks in `benches/`; not a stable API
+#[doc(hidden)]
+pub mod bench {
+    pub use crate::proxy::ProxyHandler;
+    pub use crate::server::matches_route;
+}
+
+/// Routes listed in [`ServerStats::slowest_routes`] and
+/// [`ServerStats::top_routes_by_traffic`]
+const TOP_ROUTES: usize = 10;
+
+/// Shared application state
+pub struct AppState {
+    /// Running configuration; requests take a snapshot when they start
+    /// and keep using it even if the configuration changes meanwhile
+    pub config: ArcSwap<JanusConfig>,
+
+    /// [`config_hash`](JanusConfig::config_hash) of the running
+    /// configuration, computed when it is applied
+    pub config_hash: ArcSwap<String>,
+
+    pub stats: Arc<RwLock<stats::Stats>>,
+
+    /// Request counters, updated without locking
+    pub counters: stats::Counters,
+
+    /// Per-route, per-client and per-upstream counts, updated without a
+    /// server-wide lock
+    pub traffic: Arc<stats::Traffic>,
+
+    /// Proxy handlers for the current configuration, replaced along with it
+    pub proxies: ArcSwap<proxy::ProxyTable>,
+    pub start_time: std::time::Instant,
+
+    /// When statistics started counting: at startup, or the last
+    /// `ResetStats`
+    pub stats_since: std::sync::Mutex<(std::time::Instant, std::time::SystemTime)>,
+
+    /// Config file backing this instance (None for configs built in code)
+    pub config_path: Option<PathBuf>,
+
+    /// Profile of the config file in use, whose overrides changes are
+    /// saved to
+    pub profile: Option<String>,
+
+    /// Set while the config file fails to reload, so the file and the
+    /// running configuration disagree
+    pub config_drift: AtomicBool,
+
+    /// Request bodies spooled to disk by routes with `body_spool`
+    pub spool: Arc<spool::SpoolUsage>,
+
+    /// Upstreams rejecting new requests until they are removed
+    pub draining_upstreams: Arc<Mutex<HashSet<String>>>,
+
+    /// Messages broadcast to every management client
+    pub events: broadcast::Sender<ServerMessage>,
+
+    /// Proxied requests for the live feed, kept apart from `events` so a
+    /// busy feed cannot crowd out other notices
+    pub route_hits: broadcast::Sender<ServerMessage>,
+
+    /// Shutdown signal observed by every server task
+    pub shutdown: watch::Sender<bool>,
+
+    /// Source of IDs for requests that arrive without one
+    pub request_ids: AtomicU64,
+
+    /// Set while the server error rate is above the alert threshold
+    pub error_rate_alert: AtomicBool,
+
+    /// Notified whenever the configuration changes
+    pub config_changed: watch::Sender<()>,
+
+    /// Held for the duration of a [`ConfigTransaction`]
+    config_lock: Mutex<()>,
+
+    /// Address the management API is listening on, while it is running
+    pub management_addr: std::sync::RwLock<Option<SocketAddr>>,
+
+    /// Address the HTTP server is listening on, once started
+    pub listen_addr: OnceLock<SocketAddr>,
+
+    /// Client connections on every listener, for closing idle ones
+    pub(crate) connections: server::Connections,
+
+    /// Recent warnings and errors, filled by an [`ErrorLogLayer`] when one
+    /// is installed
+    pub error_log: Arc<ErrorLog>,
+
+    /// Appends to `management.audit_log`
+    pub audit: audit::AuditLog,
+
+    /// Configuration running before the last change that was applied
+    pub previous_config: std::sync::Mutex<Option<JanusConfig>>,
+
+    /// Directory listing templates of the current configuration, read
+    /// when it is applied
+    pub(crate) listing_templates: ArcSwap<HashMap<String, Arc<server::ListingTemplate>>>,
+
+    /// Middleware run on every request before routing, built from the
+    /// current configuration
+    pub(crate) middleware: ArcSwap<middleware::Chain>,
+
+    /// Responses of routes with `cache` set
+    pub(crate) response_cache: cache::ResponseCache,
+
+    /// Maintenance switches that are on, set through the management API
+    pub(crate) maintenance: maintenance::Maintenance,
+
+    /// Certificate for the HTTPS listener, once started with ACME enabled
+    #[cfg(feature = "acme")]
+    pub(crate) acme: OnceLock<Arc<acme::Acme>>,
+
+    /// Address the HTTPS server is listening on, once started
+    pub https_addr: OnceLock<SocketAddr>,
+}
+
+impl AppState {
+    pub fn new(config: JanusConfig, config_path: Option<PathBuf>) -> Self {
+        let stats = Arc::new(RwLock::new(stats::Stats::default()));
+        let traffic = Arc::new(stats::Traffic::default());
+        let draining_upstreams = Arc::new(Mutex::new(HashSet::new()));
+        let spool = Arc::new(spool::SpoolUsage::default());
+        let config = Arc::new(config);
+        let proxies = proxy::ProxyTable::build(
+            config.clone(),
+            &Default::default(),
+            &stats,
+            &traffic,
+            &draining_upstreams,
+            &spool,
+        );
+        let listing_templates = server::load_listing_templates(&config);
+        let middleware = middleware::Chain::build(&config);
+
+        Self {
+            config_hash: ArcSwap::from_pointee(config.config_hash()),
+            config: ArcSwap::new(config),
+            stats,
+            counters: stats::Counters::default(),
+            traffic,
+            proxies: ArcSwap::from_pointee(proxies),
+            start_time: std::time::Instant::now(),
+            stats_since: std::sync::Mutex::new((
+                std::time::Instant::now(),
+                std::time::SystemTime::now(),
+            )),
+            config_path,
+            profile: None,
+            config_drift: AtomicBool::new(false),
+            spool,
+            draining_upstreams,
+            events: broadcast::channel(64).0,
+            route_hits: broadcast::channel(1024).0,
+            shutdown: watch::channel(false).0,
+            // Seeded from the clock so IDs differ across restarts
+            request_ids: AtomicU64::new(
+                std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .map_or(0, |d| d.as_nanos() as u64),
+            ),
+            error_rate_alert: AtomicBool::new(false),
+            config_changed: watch::channel(()).0,
+            config_lock: Mutex::new(()),
+            management_addr: std::sync::RwLock::new(None),
+            listen_addr: OnceLock::new(),
+            connections: server::Connections::default(),
+            error_log: ErrorLog::new(),
+            audit: audit::AuditLog::default(),
+            previous_config: std::sync::Mutex::new(None),
+            listing_templates: ArcSwap::from_pointee(listing_templates),
+            middleware: ArcSwap::from_pointee(middleware),
+            response_cache: cache::ResponseCache::default(),
+            maintenance: maintenance::Maintenance::default(),
+            #[cfg(feature = "acme")]
+            acme: OnceLock::new(),
+            https_addr: OnceLock::new(),
+        }
+    }
+
+    /// Tell tasks that depend on configuration to re-read it
+    pub fn notify_config_changed(&self) {
+        self.config_changed.send_replace(());
+    }
+
+    /// Start changing the configuration, waiting for any change already in
+    /// progress to finish first
+    pub async fn transaction(&self) -> ConfigTransaction<'_> {
+        let guard = self.config_lock.lock().await;
+        let config = JanusConfig::clone(&self.config.load());
+        ConfigTransaction {
+            state: self,
+            _guard: guard,
+            config,
+        }
+    }
+
+    /// Allocate an ID for a request that did not carry one
+    pub fn next_request_id(&self) -> String {
+        format!("{:016x}", self.request_ids.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// Persist configuration to the backing file, if there is one
+    ///
+    /// The file's profiles are kept, and with a profile in use only its
+    /// overrides of the base settings are written.
+    pub fn save_config(&self, config: &JanusConfig) -> Result<(), ConfigError> {
+        let Some(ref path) = self.config_path else {
+            return Ok(());
+        };
+        // A missing or unreadable file is replaced outright
+        let mut file = JanusConfigFile::load(path).unwrap_or_default();
+        file.set(self.profile.as_deref(), config)?;
+        file.save(path)
+    }
+
+    /// Zero every request statistic and start counting again from now
+    pub async fn reset_stats(&self) {
+        // Held so requests finishing meanwhile are not half counted
+        let mut stats = self.stats.write().await;
+        stats.reset();
+        self.counters.reset();
+        self.traffic.reset();
+        *self.stats_since.lock().unwrap_or_else(|e| e.into_inner()) =
+            (std::time::Instant::now(), std::time::SystemTime::now());
+    }
+
+    /// Build a status snapshot for reporting
+    pub async fn status_snapshot(&self) -> ServerStatus {
+        let config = self.config.load_full();
+        let counters = &self.counters;
+        let listening = self.listen_addr.get().copied();
+        #[cfg(feature = "acme")]
+        let acme = self.acme.get().map(|acme| acme.status());
+        #[cfg(not(feature = "acme"))]
+        let acme = None;
+
+        ServerStatus {
+            running: true,
+            uptime_secs: self.start_time.elapsed().as_secs(),
+            active_connections: counters.active_connections.load(Ordering::Relaxed),
+            idle_connections: self
+                .connections
+                .idle(std::time::Duration::from_secs(
+                    janus_common::IDLE_CONNECTION_SECS,
+                ))
+                .len() as u64,
+            connections_accepted: counters.total_connections.load(Ordering::Relaxed),
+            connections_rejected: counters.rejected_connections.load(Ordering::Relaxed),
+            route_count: config.routes.len(),
+            upstream_count: config.upstreams.len(),
+            draining_upstreams: self.draining_upstreams.lock().await.len(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            config_hash: String::clone(&self.config_hash.load()),
+            listen_address: match listening {
+                Some(addr) => addr.to_string(),
+                None => format!("{}:{}", config.server.bind_address, config.server.port),
+            },
+            pending_restart_settings: listening
+                .map(|addr| config.server.pending_restart(addr))
+                .unwrap_or_default(),
+            profile: self.profile.clone(),
+            acme,
+            capabilities: vec![
+                janus_common::CAPABILITY_RESET_STATS.to_string(),
+                janus_common::CAPABILITY_MAINTENANCE.to_string(),
+            ],
+            maintenance: self.maintenance.active(),
+        }
+    }
+
+    /// Build a statistics snapshot for reporting
+    pub async fn stats_snapshot(&self) -> ServerStats {
+        let stats = self.stats.read().await;
+        let counters = &self.counters;
+        let total_requests = counters.total_requests.load(Ordering::Relaxed);
+        let (since, since_time) = *self.stats_since.lock().unwrap_or_else(|e| e.into_inner());
+        let window = since.elapsed().as_secs_f64();
+
+        // Servers count as healthy unless they or their upstream are
+        // draining, which are what take them out of rotation
+        let config = self.config.load();
+        let draining = self.draining_upstreams.lock().await;
+        let proxies = self.proxies.load();
+        let upstream_protocol_errors = self.traffic.upstream_protocol_errors.counts();
+        let no_backend_available = self.traffic.no_backend_available.counts();
+        let forwarded = self.traffic.upstreams();
+        let names = config
+            .upstreams
+            .keys()
+            .chain(upstream_protocol_errors.keys())
+            .chain(no_backend_available.keys())
+            .chain(forwarded.keys());
+        let mut upstream_stats = HashMap::new();
+        for name in names {
+            let traffic = forwarded.get(name).cloned().unwrap_or_default();
+            let total_servers = config.upstreams.get(name).map_or(0, |u| u.servers.len());
+            let count = |counts: &HashMap<String, u64>| counts.get(name).copied().unwrap_or(0);
+            let connections = proxies.connection_limit(name);
+            let upstream = janus_common::UpstreamStats {
+                requests: traffic.requests,
+                failures: traffic.failures,
+                retries: traffic.retries,
+                avg_response_time_ms: traffic.avg_response_time_ms,
+                upstream_protocol_errors: count(&upstream_protocol_errors),
+                no_backend_available: count(&no_backend_available),
+                healthy_servers: match config.upstreams.get(name) {
+                    Some(upstream) if !draining.contains(name) => {
+                        upstream.servers.iter().filter(|s| !s.drain).count()
+                    }
+                    _ => 0,
+                },
+                total_servers,
+                active_connections: connections.map_or(0, |limit| limit.in_use()),
+                peak_connections: connections.map_or(0, |limit| limit.peak()),
+                max_connections: connections.map(|limit| limit.max()),
+            };
+            upstream_stats.insert(name.clone(), upstream);
+        }
+        drop(draining);
+
+        ServerStats {
+            total_requests,
+            total_connections: counters.total_connections.load(Ordering::Relaxed),
+            access_log_sampled_out: counters.access_log_sampled_out.load(Ordering::Relaxed),
+            uri_too_long: counters.uri_too_long.load(Ordering::Relaxed),
+            headers_too_large: counters.headers_too_large.load(Ordering::Relaxed),
+            route_matches: counters.route_matches.load(Ordering::Relaxed),
+            default_matches: counters.default_matches.load(Ordering::Relaxed),
+            bytes_received: counters.bytes_received.load(Ordering::Relaxed),
+            bytes_sent: counters.bytes_sent.load(Ordering::Relaxed),
+            requests_per_second: if window > 0.0 {
+                total_requests as f64 / window
+            } else {
+                0.0
+            },
+            status_codes: counters.status_codes(),
+            error_rate: counters.error_rate(),
+            client_error_rate: counters.client_error_rate(),
+            upstream_stats,
+            route_stats: self.traffic.routes(),
+            proxy_errors: self.traffic.proxy_errors.counts(),
+            auth_requests: stats.auth_requests.clone(),
+            spooled_bytes: self.spool.bytes(),
+            spooled_requests: self.spool.spills(),
+            slowest_routes: self.traffic.slowest_routes(TOP_ROUTES),
+            top_routes_by_traffic: self.traffic.busiest_routes(TOP_ROUTES),
+            since: since_time
+                .duration_since(std::time::UNIX_EPOCH)
+                .ok()
+                .map(|d| d.as_secs()),
+        }
+    }
+}
+
+/// An in-progress change to the configuration
+///
+/// Only one transaction runs at a time, so checks made against `config`
+/// still hold when it is committed, and saves land in the order the changes
+/// were made. Dropping a transaction discards it.
+pub struct ConfigTransaction<'a> {
+    state: &'a AppState,
+    _guard: MutexGuard<'a, ()>,
+
+    /// Configuration being changed, starting from the running one
+    pub config: JanusConfig,
+}
+
+impl ConfigTransaction<'_> {
+    /// Save the changed configuration, then make it the running one
+    pub async fn commit(self) -> Result<(), ConfigError> {
+        self.state.save_config(&self.config)?;
+        self.apply().await;
+        Ok(())
+    }
+
+    /// Make the changed configuration the running one without saving it,
+    /// for changes read from the config file itself
+    pub async fn apply(self) {
+        let proxies = self.state.build_proxies(Arc::new(self.config));
+        let previous = self.state.swap_config(proxies);
+        self.state.keep_previous_config(&previous);
+    }
+
+    /// Like [`apply`](Self::apply), but first select a backend once through
+    /// every new handler, leaving the running configuration untouched if
+    /// any fails
+    pub async fn apply_checked(self) -> Result<(), String> {
+        let state = self.state;
+        let proxies = state.build_proxies(Arc::new(self.config));
+
+        // Probe the new handlers before any request can reach them
+        let draining = state.draining_upstreams.lock().await.clone();
+        proxies.probe(&draining)?;
+
+        let previous = state.swap_config(proxies);
+        state.keep_previous_config(&previous);
+        Ok(())
+    }
+}
+
+impl AppState {
+    /// Build handlers for a configuration without putting them in service
+    fn build_proxies(&self, config: Arc<JanusConfig>) -> proxy::ProxyTable {
+        proxy::ProxyTable::build(
+            config,
+            &self.proxies.load(),
+            &self.stats,
+            &self.traffic,
+            &self.draining_upstreams,
+            &self.spool,
+        )
+    }
+
+    /// Make the configuration of a built table the running one, along with
+    /// everything else built from it, returning the one it replaced
+    fn swap_config(&self, proxies: proxy::ProxyTable) -> Arc<JanusConfig> {
+        // Requests take their configuration from the table, so routes and
+        // handlers always come from the same version
+        let config = proxies.config().clone();
+        self.config_hash.store(Arc::new(config.config_hash()));
+        self.proxies.store(Arc::new(proxies));
+        self.listing_templates
+            .store(Arc::new(server::load_listing_templates(&config)));
+        self.middleware
+            .store(Arc::new(middleware::Chain::build(&config)));
+        self.response_cache.clear();
+        let previous = self.config.swap(config);
+
+        self.notify_config_changed();
+        previous
+    }
+
+    fn keep_previous_config(&self, previous: &JanusConfig) {
+        let mut kept = self
+            .previous_config
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        *kept = Some(previous.clone());
+    }
+}
+
+/// Wait until shutdown has been requested (or the state has been dropped)
+pub(crate) async fn wait_for_shutdown(rx: &mut watch::Receiver<bool>) {
+    let _ = rx.wait_for(|&stop| stop).await;
+}
+
+/// Handle to an embedded Janus server
+pub struct Janus {
+    state: Arc<AppState>,
+}
+
+impl Janus {
+    /// Create a server from a configuration built in code
+    pub fn new(config: JanusConfig) -> Self {
+        Self::from_state(AppState::new(config, None))
+    }
+
+    /// Create a server backed by a config file, which is watched for changes
+    /// and updated when the configuration is changed at runtime
+    pub fn with_config_file(config: JanusConfig, path: impl Into<PathBuf>) -> Self {
+        Self::from_state(AppState::new(config, Some(path.into())))
+    }
+
+    /// Create a server from a profile of a config file; changes made at
+    /// runtime are saved as overrides in that profile
+    pub fn with_config_profile(
+        config: JanusConfig,
+        path: impl Into<PathBuf>,
+        profile: impl Into<String>,
+    ) -> Self {
+        let mut state = AppState::new(config, Some(path.into()));
+        state.profile = Some(profile.into());
+        Self::from_state(state)
+    }
+
+    /// Report warnings and errors from this log to management clients,
+    /// with its [`ErrorLog::layer`] installed in the `tracing` subscriber
+    ///
+    /// # Panics
+    ///
+    /// If called after [`Janus::start`] or [`Janus::state`].
+    pub fn with_error_log(mut self, error_log: Arc<ErrorLog>) -> Self {
+        Arc::get_mut(&mut self.state)
+            .expect("the error log is set before the server state is shared")
+            .error_log = error_log;
+        self
+    }
+
+    fn from_state(state: AppState) -> Self {
+        Self {
+            state: Arc::new(state),
+        }
+    }
+
+    /// Bind the listeners and start serving in the background
+    ///
+    /// The returned handle completes once the server has stopped, either
+    /// after [`Janus::shutdown`] or because the HTTP server failed.
+    pub async fn start(&self) -> Result<JoinHandle<Result<()>>> {
+        let config = self.state.config.load_full();
+
+        // Restored before listening, so no request slips through a switch
+        // that was on when the server last stopped
+        if let Some(ref path) = config.server.maintenance.state_file {
+            self.state
+                .maintenance
+                .load(path)
+                .await
+                .with_context(|| format!("Failed to restore maintenance state from {}", path))?;
+        }
+
+        let listener = server::bind_listener(
+            &config.server.bind_address,
+            config.server.port,
+            &config.server.socket_options,
+        )
+        .await?;
+        let _ = self.state.listen_addr.set(listener.local_addr()?);
+        let tls_server = self.start_https(&config).await?;
+
+        let mgmt_listener = if config.management.enabled {
+            let listener = management::Listener::bind(&config.management).await?;
+            *self.state.management_addr.write().unwrap() = Some(listener.local_addr()?);
+            Some(listener)
+        } else {
+            None
+        };
+
+        // Start file watcher for live reloading
+        let reload_state = self.state.clone();
+        let reload_handle = tokio::spawn(async move {
+            if let Err(e) = reload::watch_config(reload_state).await {
+                error!("Config watcher error: {}", e);
+            }
+        });
+
+        // Start management WebSocket server, which follows config changes
+        // even when it starts out disabled
+        let mgmt_state = self.state.clone();
+        let mgmt_handle = tokio::spawn(async move {
+            if let Err(e) = management::supervise(mgmt_state, mgmt_listener).await {
+                error!("Management server error: {}", e);
+            }
+        });
+
+        // Start HTTP server
+        let server_state = self.state.clone();
+        let server_handle = tokio::spawn(server::run_server(server_state, listener));
+
+        let state = self.state.clone();
+        Ok(tokio::spawn(async move {
+            let result = match server_handle.await {
+                Ok(result) => result,
+                Err(e) => Err(e.into()),
+            };
+
+            // Bring the remaining tasks down with the HTTP server
+            state.shutdown.send_replace(true);
+            let _ = reload_handle.await;
+            let _ = mgmt_handle.await;
+            for handle in tls_server {
+                let _ = handle.await;
+            }
+
+            result
+        }))
+    }
+
+    /// Bind the HTTPS listener and keep its certificate current, when
+    /// ACME is configured
+    #[cfg(feature = "acme")]
+    async fn start_https(&self, config: &JanusConfig) -> Result<Vec<JoinHandle<()>>> {
+        let Some(ref settings) = config.server.acme else {
+            return Ok(Vec::new());
+        };
+        let listener = server::bind_listener(
+            &config.server.bind_address,
+            settings.https_port,
+            &config.server.socket_options,
+        )
+        .await?;
+        let _ = self.state.https_addr.set(listener.local_addr()?);
+
+        let acme = acme::Acme::new(settings.clone());
+        let _ = self.state.acme.set(acme.clone());
+        let renewal = tokio::spawn(acme.clone().run(self.state.shutdown.subscribe()));
+
+        let state = self.state.clone();
+        let server = tokio::spawn(async move {
+            if let Err(e) = server::run_tls_server(state, listener, acme.acceptor()).await {
+                error!("HTTPS server error: {}", e);
+            }
+        });
+        Ok(vec![renewal, server])
+    }
+
+    #[cfg(not(feature = "acme"))]
+    async fn start_https(&self, config: &JanusConfig) -> Result<Vec<JoinHandle<()>>> {
+        if config.server.acme.is_some() {
+            anyhow::bail!("server.acme is set, but Janus was built without the 'acme' feature");
+        }
+        Ok(Vec::new())
+    }
+
+    /// Address the HTTPS server is listening on, once started with ACME
+    /// enabled
+    pub fn https_addr(&self) -> Option<SocketAddr> {
+        self.state.https_addr.get().copied()
+    }
+
+    /// Address the HTTP server is listening on, once started
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.state.listen_addr.get().copied()
+    }
+
+    /// Address the management API is listening on, while it is running
+    pub fn management_addr(&self) -> Option<SocketAddr> {
+        *self.state.management_addr.read().unwrap()
+    }
+
+    /// Current configuration
+    pub async fn config(&self) -> JanusConfig {
+        JanusConfig::clone(&self.state.config.load())
+    }
+
+    /// Validate and apply a new configuration
+    pub async fn update_config(&self, new_config: JanusConfig) -> Result<()> {
+        janus_common::validate_config(&new_config)?;
+
+        let mut tx = self.state.transaction().await;
+        tx.config = new_config;
+        tx.commit().await?;
+        info!("Configuration updated");
+
+        Ok(())
+    }
+
+    /// Reload the configuration from its file, as the file watcher does
+    /// when the file changes
+    pub async fn reload(&self) -> Result<()> {
+        reload::reload_audited(&self.state).await
+    }
+
+    /// Current server statistics
+    pub async fn stats(&self) -> ServerStats {
+        self.state.stats_snapshot().await
+    }
+
+    /// Shared state, for embedding alongside other services
+    pub fn state(&self) -> Arc<AppState> {
+        self.state.clone()
+    }
+
+    /// Stop accepting requests and wait for in-flight connections to finish
+    pub async fn shutdown(&self) {
+        self.state.shutdown.send_replace(true);
+        self.state.shutdown.closed().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use janus_common::config::{RouteConfig, UpstreamConfig};
+
+    #[tokio::test]
+    async fn test_start_and_shutdown() {
+        let mut config = JanusConfig::default();
+        config.server.bind_address = "127.0.0.1".to_string();
+        config.server.port = 0;
+        config.management.port = 0;
+
+        let janus = Janus::new(config);
+        let handle = janus.start().await.unwrap();
+        assert!(janus.local_addr().unwrap().port() > 0);
+        assert!(janus.management_addr().unwrap().port() > 0);
+
+        janus.shutdown().await;
+        handle.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_apply_checked_rolls_back() {
+        let mut config = JanusConfig::default();
+        config
+            .upstreams
+            .insert("api".to_string(), UpstreamConfig::single("127.0.0.1:3000"));
+        config.routes.push(RouteConfig::new("/api/*", "api"));
+        let state = AppState::new(config.clone(), None);
+
+        // An upstream without servers gets past the transaction unvalidated
+        let mut tx = state.transaction().await;
+        tx.config.upstreams.get_mut("api").unwrap().servers.clear();
+        assert_eq!(
+            tx.apply_checked().await,
+            Err("Route '/api/*': no backend available: no servers configured".to_string())
+        );
+        assert_eq!(**state.config.load(), config);
+        assert!(state
+            .proxies
+            .load()
+            .route(0)
+            .unwrap()
+            .select_backend(false)
+            .is_ok());
+        assert_eq!(*state.previous_config.lock().unwrap(), None);
+
+        // Draining upstreams are refused on purpose, so they pass
+        state
+            .draining_upstreams
+            .lock()
+            .await
+            .insert("api".to_string());
+        let mut tx = state.transaction().await;
+        tx.config.upstreams.get_mut("api").unwrap().servers.clear();
+        let changed = tx.config.clone();
+        assert_eq!(tx.apply_checked().await, Ok(()));
+        assert_eq!(**state.config.load(), changed);
+        assert_eq!(*state.previous_config.lock().unwrap(), Some(config));
+    }
+
+    #[tokio::test]
+    async fn test_rejected_config_is_never_served() {
+        let mut config = JanusConfig::default();
+        config
+            .upstreams
+            .insert("api".to_string(), UpstreamConfig::single("127.0.0.1:3000"));
+        config.routes.push(RouteConfig::new("/api/*", "api"));
+        let state = AppState::new(config, None);
+        let serving = state.proxies.load_full();
+        let changes = state.config_changed.subscribe();
+
+        // A route to a working upstream alongside one that cannot be served
+        let mut tx = state.transaction().await;
+        tx.config
+            .upstreams
+            .insert("new".to_string(), UpstreamConfig::single("127.0.0.1:3001"));
+        tx.config
+            .routes
+            .insert(0, RouteConfig::new("/new/*", "new"));
+        tx.config.upstreams.get_mut("api").unwrap().servers.clear();
+        assert!(tx.apply_checked().await.is_err());
+
+        // Requests take their handlers from the table, which never changed
+        assert!(Arc::ptr_eq(&state.proxies.load_full(), &serving));
+        assert!(!changes.has_changed().unwrap());
+    }
+}
@@ -0,0 +1,196 @@
+//! Maintenance mode, answering requests with 503 while a switch is on
+//!
+//! Switches are runtime state rather than configuration, so flipping one
+//! never rewrites the config file. With `server.maintenance.state_file`
+//! set they are saved there on every change and restored at startup.
+
+use crate::server::{error_page, escape_html, full_body};
+use arc_swap::ArcSwap;
+use bytes::Bytes;
+use http_body_util::combinators::BoxBody;
+use hyper::header::{HeaderValue, CACHE_CONTROL, CONTENT_TYPE, RETRY_AFTER};
+use hyper::{Response, StatusCode};
+use janus_common::config::MaintenanceConfig;
+use janus_common::{MaintenanceScope, MaintenanceWindow};
+use std::convert::Infallible;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tracing::{error, warn};
+
+/// Maintenance switches that are on
+#[derive(Debug, Default)]
+pub struct Maintenance {
+    /// Read on every request, so kept lock-free
+    windows: ArcSwap<Vec<MaintenanceWindow>>,
+
+    /// Held while saving, so the file always ends up with the latest state
+    save_lock: Mutex<()>,
+}
+
+impl Maintenance {
+    /// Switch maintenance on or off for a scope; switching on a scope that
+    /// is already on replaces its message
+    pub fn set(&self, scope: MaintenanceScope, enabled: bool, message: Option<String>) {
+        self.windows.rcu(|windows| {
+            let mut windows = Vec::clone(windows);
+            let existing = windows.iter().position(|w| w.scope == scope);
+            match (enabled, existing) {
+                (true, Some(i)) => windows[i].message = message.clone(),
+                (true, None) => {
+                    windows.push(MaintenanceWindow {
+                        scope: scope.clone(),
+                        message: message.clone(),
+                        since: std::time::SystemTime::now()
+                            .duration_since(std::time::UNIX_EPOCH)
+                            .map_or(0, |d| d.as_secs()),
+                    });
+                    windows.sort_by(|a, b| a.scope.cmp(&b.scope));
+                }
+                (false, Some(i)) => {
+                    windows.remove(i);
+                }
+                (false, None) => {}
+            }
+            windows
+        });
+    }
+
+    /// The switch for every request, if it is on
+    pub fn all(&self) -> Option<MaintenanceWindow> {
+        self.find(|scope| *scope == MaintenanceScope::All)
+    }
+
+    /// The switch for the route with this path, if it is on
+    pub fn route(&self, path: &str) -> Option<MaintenanceWindow> {
+        self.find(|scope| matches!(scope, MaintenanceScope::Route(route) if route == path))
+    }
+
+    fn find(&self, covers: impl Fn(&MaintenanceScope) -> bool) -> Option<MaintenanceWindow> {
+        let windows = self.windows.load();
+        windows.iter().find(|w| covers(&w.scope)).cloned()
+    }
+
+    /// Every switch that is on, everything first
+    pub fn active(&self) -> Vec<MaintenanceWindow> {
+        Vec::clone(&self.windows.load())
+    }
+
+    /// Restore the switches saved at `path`; a file not written yet has
+    /// none
+    pub async fn load(&self, path: &str) -> anyhow::Result<()> {
+        let text = match tokio::fs::read_to_string(path).await {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        let windows: Vec<MaintenanceWindow> = serde_json::from_str(&text)?;
+        self.windows.store(Arc::new(windows));
+        Ok(())
+    }
+
+    /// Save the switches to `path`, logging any failure
+    ///
+    /// The switch has already been flipped by now, so a write failure
+    /// does not undo it.
+    pub async fn save(&self, path: &str) {
+        let _guard = self.save_lock.lock().await;
+        let text = match serde_json::to_string_pretty(&self.active()) {
+            Ok(text) => text,
+            Err(e) => {
+                error!("Failed to encode maintenance state: {}", e);
+                return;
+            }
+        };
+        if let Err(e) = tokio::fs::write(path, text).await {
+            error!("Failed to save maintenance state to {}: {}", path, e);
+        }
+    }
+}
+
+/// Answer a request covered by a maintenance switch
+pub async fn maintenance_response(
+    window: &MaintenanceWindow,
+    config: &MaintenanceConfig,
+) -> Response<BoxBody<Bytes, Infallible>> {
+    let page = match (&window.message, &config.page) {
+        (None, Some(path)) => match tokio::fs::read(path).await {
+            Ok(page) => Some(page),
+            Err(e) => {
+                warn!("Failed to read maintenance page {}: {}", path, e);
+                None
+            }
+        },
+        _ => None,
+    };
+
+    let mut response = match page {
+        Some(page) => {
+            let mut response = Response::new(full_body(page));
+            *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+            response
+                .headers_mut()
+                .insert(CONTENT_TYPE, HeaderValue::from_static("text/html"));
+            response
+        }
+        None => {
+            let message = window
+                .message
+                .as_deref()
+                .unwrap_or("Down for maintenance, please try again later.");
+            let detail = format!("\n    <p>{}</p>", escape_html(message));
+            error_page(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Service Unavailable",
+                &detail,
+            )
+        }
+    };
+    let headers = response.headers_mut();
+    headers.insert(RETRY_AFTER, config.retry_after_secs.into());
+    headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_switching_on_again_keeps_since() {
+        let maintenance = Maintenance::default();
+        let route = MaintenanceScope::Route("/api/*".to_string());
+        maintenance.set(route.clone(), true, None);
+        maintenance.set(MaintenanceScope::All, true, Some("Deploying".to_string()));
+        let since = maintenance.route("/api/*").unwrap().since;
+
+        maintenance.set(route.clone(), true, Some("Back at noon".to_string()));
+        let window = maintenance.route("/api/*").unwrap();
+        assert_eq!(window.message.as_deref(), Some("Back at noon"));
+        assert_eq!(window.since, since);
+
+        // Everything first, whatever order they were switched on in
+        let scopes: Vec<_> = maintenance.active().into_iter().map(|w| w.scope).collect();
+        assert_eq!(scopes, [MaintenanceScope::All, route.clone()]);
+
+        maintenance.set(route.clone(), false, None);
+        assert!(maintenance.route("/api/*").is_none());
+        assert!(maintenance.all().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_saved_switches_are_restored() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("maintenance.json");
+        let path = path.to_str().unwrap();
+
+        let fresh = Maintenance::default();
+        fresh.load(path).await.unwrap();
+        assert!(fresh.active().is_empty());
+
+        let maintenance = Maintenance::default();
+        maintenance.set(MaintenanceScope::All, true, Some("Deploying".to_string()));
+        maintenance.save(path).await;
+        fresh.load(path).await.unwrap();
+        assert_eq!(fresh.active(), maintenance.active());
+    }
+}
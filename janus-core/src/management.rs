@@ -0,0 +1,755 @@
+//! Management WebSocket server for TUI connections
+
+use crate::preflight::{preflight, PreflightOptions};
+use crate::{audit, health, tls, wait_for_shutdown, AppState, ConfigTransaction};
+use anyhow::Result;
+use futures::{SinkExt, StreamExt};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper_util::rt::TokioIo;
+use janus_common::{
+    apply_edit, AuditSource, ClientMessage, ConfigError, EditError, JanusConfig, MaintenanceScope,
+    ManagementConfig, ManagementRole, RouteStatsDetail, ServerMessage, ServerStats,
+};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{broadcast, mpsc, watch};
+use tokio::time::MissedTickBehavior;
+use tokio_rustls::TlsAcceptor;
+use tokio_tungstenite::{accept_async, tungstenite::Message, WebSocketStream};
+use tracing::{debug, error, info, warn};
+
+/// A bound management listener, with TLS if configured
+pub struct Listener {
+    tcp: TcpListener,
+    tls: Option<TlsAcceptor>,
+}
+
+impl Listener {
+    /// Bind the management address and load any TLS certificates
+    pub async fn bind(settings: &ManagementConfig) -> Result<Self> {
+        let tls = tls::acceptor(settings)?;
+        let tcp = TcpListener::bind((settings.address.as_str(), settings.port)).await?;
+        Ok(Self { tcp, tls })
+    }
+
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.tcp.local_addr()
+    }
+}
+
+/// Run the management API until shutdown, rebinding, stopping or starting
+/// it when its address, port or `enabled` setting changes
+///
+/// TLS settings are read whenever the listener is (re)started.
+pub async fn supervise(state: Arc<AppState>, listener: Option<Listener>) -> Result<()> {
+    let mut shutdown = state.shutdown.subscribe();
+    let mut changes = state.config_changed.subscribe();
+    let mut settings = state.config.load().management.clone();
+    let mut current = listener
+        .map(|listener| Instance::start(&state, listener))
+        .transpose()?;
+
+    loop {
+        tokio::select! {
+            _ = wait_for_shutdown(&mut shutdown) => break,
+            changed = changes.changed() => if changed.is_err() {
+                break;
+            },
+        }
+
+        let new = state.config.load().management.clone();
+        if (new.enabled, &new.address, new.port)
+            == (settings.enabled, &settings.address, settings.port)
+        {
+            continue;
+        }
+        settings = new;
+
+        if !settings.enabled {
+            if let Some(instance) = current.take() {
+                info!("Management API disabled");
+                *state.management_addr.write().unwrap() = None;
+                let notice = ServerMessage::Disconnecting("Management API disabled".to_string());
+                instance.stop(notice).await;
+            }
+            continue;
+        }
+
+        // Bind the new listener before closing the old one, so the API
+        // stays reachable if the new address cannot be used
+        let listener = match Listener::bind(&settings).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                error!(
+                    "Failed to bind management API on {}:{}: {}",
+                    settings.address, settings.port, e
+                );
+                continue;
+            }
+        };
+        let instance = Instance::start(&state, listener)?;
+        if let Some(old) = current.replace(instance) {
+            let notice = ServerMessage::Disconnecting(format!(
+                "Management API moved to {}:{}",
+                settings.address, settings.port
+            ));
+            old.stop(notice).await;
+        }
+    }
+
+    if let Some(instance) = current {
+        instance.stop(ServerMessage::ShuttingDown).await;
+    }
+    Ok(())
+}
+
+/// A management listener and the connections it accepted
+struct Instance {
+    stop: watch::Sender<Option<ServerMessage>>,
+    handle: tokio::task::JoinHandle<()>,
+}
+
+impl Instance {
+    fn start(state: &Arc<AppState>, listener: Listener) -> Result<Self> {
+        *state.management_addr.write().unwrap() = Some(listener.local_addr()?);
+        let (stop, stop_rx) = watch::channel(None);
+        let state = state.clone();
+        let handle = tokio::spawn(async move {
+            if let Err(e) = run_management_server(state, listener, stop_rx).await {
+                error!("Management server error: {}", e);
+            }
+        });
+        Ok(Self { stop, handle })
+    }
+
+    /// Close every connection with the given notice and stop listening
+    async fn stop(self, notice: ServerMessage) {
+        self.stop.send_replace(Some(notice));
+        self.stop.closed().await;
+        let _ = self.handle.await;
+    }
+}
+
+/// Wait until a management instance is told to stop
+async fn wait_for_stop(rx: &mut watch::Receiver<Option<ServerMessage>>) {
+    let _ = rx.wait_for(Option::is_some).await;
+}
+
+/// Run the management WebSocket server until told to stop
+async fn run_management_server(
+    state: Arc<AppState>,
+    listener: Listener,
+    mut stop: watch::Receiver<Option<ServerMessage>>,
+) -> Result<()> {
+    let scheme = if listener.tls.is_some() { "wss" } else { "ws" };
+    info!(
+        "Management server listening on {}://{}",
+        scheme,
+        listener.local_addr()?
+    );
+
+    loop {
+        let (stream, peer_addr) = tokio::select! {
+            accepted = listener.tcp.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(_) => break,
+            },
+            _ = wait_for_stop(&mut stop) => break,
+        };
+        let state = state.clone();
+        let stop = stop.clone();
+        let tls = listener.tls.clone();
+
+        tokio::spawn(async move {
+            let Some(tls) = tls else {
+                let health = state.config.load().server.health_endpoint.clone();
+                if health.enabled
+                    && health.on_management_port
+                    && health::is_health_request(&stream, &health.path).await
+                {
+                    serve_health(stream, state).await;
+                    return;
+                }
+                let peer = Peer {
+                    addr: peer_addr,
+                    certificate: None,
+                };
+                serve_websocket(stream, peer, state, stop).await;
+                return;
+            };
+
+            match tls.accept(stream).await {
+                Ok(stream) => {
+                    let peer = Peer {
+                        addr: peer_addr,
+                        certificate: tls::peer_name(stream.get_ref().1),
+                    };
+                    serve_websocket(stream, peer, state, stop).await;
+                }
+                Err(e) => warn!("TLS handshake with {} failed: {}", peer_addr, e),
+            }
+        });
+    }
+
+    Ok(())
+}
+
+/// Client end of a management connection
+struct Peer {
+    addr: SocketAddr,
+
+    /// Name of the client certificate, if one was presented
+    certificate: Option<String>,
+}
+
+impl std::fmt::Display for Peer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.addr)?;
+        if let Some(ref name) = self.certificate {
+            write!(f, " (client certificate '{}')", name)?;
+        }
+        Ok(())
+    }
+}
+
+/// Complete the WebSocket handshake and run the session
+async fn serve_websocket<S>(
+    stream: S,
+    peer: Peer,
+    state: Arc<AppState>,
+    stop: watch::Receiver<Option<ServerMessage>>,
+) where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    match accept_async(stream).await {
+        Ok(ws_stream) => {
+            info!("New management connection from {}", peer);
+            if let Err(e) = handle_connection(ws_stream, &peer, state, stop).await {
+                error!("Connection error: {}", e);
+            }
+            info!("Management connection from {} closed", peer);
+        }
+        Err(e) => {
+            error!("WebSocket handshake failed: {}", e);
+        }
+    }
+}
+
+/// Answer a plain HTTP health probe on the management port
+async fn serve_health(stream: TcpStream, state: Arc<AppState>) {
+    let service = service_fn(move |_req| {
+        let state = state.clone();
+        async move {
+            let config = state.config.load_full();
+            Ok::<_, Infallible>(health::health_response(&state, &config).await)
+        }
+    });
+
+    if let Err(e) = http1::Builder::new()
+        .serve_connection(TokioIo::new(stream), service)
+        .await
+    {
+        debug!("Health probe connection error: {:?}", e);
+    }
+}
+
+/// Handle a single WebSocket connection
+async fn handle_connection<S>(
+    ws_stream: WebSocketStream<S>,
+    peer: &Peer,
+    state: Arc<AppState>,
+    mut stop: watch::Receiver<Option<ServerMessage>>,
+) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (mut write, mut read) = ws_stream.split();
+
+    // Turned away before any message is read
+    if peer.certificate.is_none() && state.config.load().management.require_client_cert {
+        warn!(
+            "Rejected management connection from {}: no client certificate",
+            peer
+        );
+        let error = ServerMessage::Error("Client certificate required".to_string());
+        write
+            .send(Message::Text(serde_json::to_string(&error)?))
+            .await?;
+        write.send(Message::Close(None)).await?;
+        return Ok(());
+    }
+
+    let mut events = state.events.subscribe();
+    let mut route_hits = state.route_hits.subscribe();
+    let mut live_budget = LiveBudget::new(Instant::now());
+    let mut token: Option<String> = None;
+
+    // Stops once this connection drops the receiver, however it ends
+    let (stats_tx, mut pushed_stats) = mpsc::channel(1);
+    if let Some(ms) = state.config.load().management.push_stats_interval_ms {
+        let interval = Duration::from_millis(ms);
+        tokio::spawn(push_stats(state.clone(), interval, stats_tx));
+    }
+
+    loop {
+        let msg = tokio::select! {
+            msg = read.next() => match msg {
+                Some(msg) => msg,
+                None => break,
+            },
+            event = events.recv() => {
+                match event {
+                    Ok(event) => {
+                        // Events are only for sessions that may read
+                        let config = state.config.load_full();
+                        if config.management.role_for(token.as_deref()).is_none() {
+                            continue;
+                        }
+                        drop(config);
+                        let event_text = serde_json::to_string(&event)?;
+                        write.send(Message::Text(event_text)).await?;
+                    }
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!("Management client lagged, dropped {} events", skipped);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => {}
+                }
+                continue;
+            }
+            hit = route_hits.recv() => {
+                // Hits beyond the budget, or missed while lagging, are dropped
+                if let Ok(hit) = hit {
+                    let config = state.config.load_full();
+                    let readable = config.management.role_for(token.as_deref()).is_some();
+                    drop(config);
+                    if readable && live_budget.allow(Instant::now()) {
+                        write.send(Message::Text(serde_json::to_string(&hit)?)).await?;
+                    }
+                }
+                continue;
+            }
+            Some(stats) = pushed_stats.recv() => {
+                let config = state.config.load_full();
+                let readable = config.management.role_for(token.as_deref()).is_some();
+                drop(config);
+                if readable {
+                    let stats = serde_json::to_string(&ServerMessage::Stats(stats))?;
+                    write.send(Message::Text(stats)).await?;
+                }
+                continue;
+            }
+            _ = wait_for_stop(&mut stop) => {
+                let notice = stop.borrow().clone().unwrap_or(ServerMessage::ShuttingDown);
+                let notice = serde_json::to_string(&notice)?;
+                write.send(Message::Text(notice)).await?;
+                write.send(Message::Close(None)).await?;
+                break;
+            }
+        };
+
+        match msg {
+            Ok(Message::Text(text)) => match serde_json::from_str::<ClientMessage>(&text) {
+                Ok(client_msg) => {
+                    let response =
+                        handle_session_message(client_msg, peer, &state, &mut token).await;
+                    let response_text = serde_json::to_string(&response)?;
+                    write.send(Message::Text(response_text)).await?;
+                }
+                Err(e) => {
+                    warn!("Invalid message format: {}", e);
+                    let error = ServerMessage::Error(format!("Invalid message: {}", e));
+                    let response_text = serde_json::to_string(&error)?;
+                    write.send(Message::Text(response_text)).await?;
+                }
+            },
+            Ok(Message::Close(_)) => {
+                debug!("Client initiated close");
+                break;
+            }
+            Ok(Message::Ping(data)) => {
+                write.send(Message::Pong(data)).await?;
+            }
+            Ok(_) => {
+                // Ignore other message types
+            }
+            Err(e) => {
+                error!("WebSocket error: {}", e);
+                break;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Hand a connection the statistics every `interval` until it closes
+///
+/// A snapshot is skipped while the connection has yet to send the last
+/// one, so a slow client is not sent stale statistics.
+async fn push_stats(state: Arc<AppState>, interval: Duration, tx: mpsc::Sender<ServerStats>) {
+    let mut ticks = tokio::time::interval(interval);
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        tokio::select! {
+            _ = ticks.tick() => {}
+            _ = tx.closed() => return,
+        }
+        match tx.try_reserve() {
+            Ok(permit) => permit.send(state.stats_snapshot().await),
+            Err(mpsc::error::TrySendError::Full(())) => {}
+            Err(mpsc::error::TrySendError::Closed(())) => return,
+        }
+    }
+}
+
+/// Most route hits sent to one management client per second
+const LIVE_REQUESTS_PER_SEC: u32 = 100;
+
+/// Route hits sent to a client in the current one-second window
+struct LiveBudget {
+    window_start: Instant,
+    sent: u32,
+}
+
+impl LiveBudget {
+    fn new(now: Instant) -> Self {
+        Self {
+            window_start: now,
+            sent: 0,
+        }
+    }
+
+    /// Whether another hit may be sent now, counting it if so
+    fn allow(&mut self, now: Instant) -> bool {
+        if now.duration_since(self.window_start) >= Duration::from_secs(1) {
+            *self = Self::new(now);
+        }
+        if self.sent < LIVE_REQUESTS_PER_SEC {
+            self.sent += 1;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Check the session's access before handling a message, recording
+/// changes in the audit log
+async fn handle_session_message(
+    msg: ClientMessage,
+    peer: &Peer,
+    state: &Arc<AppState>,
+    token: &mut Option<String>,
+) -> ServerMessage {
+    if let ClientMessage::Authenticate { token: presented } = msg {
+        let role = state.config.load().management.role_for(Some(&presented));
+        return match role {
+            Some(role) => {
+                *token = Some(presented);
+                ServerMessage::Authenticated(role)
+            }
+            None => {
+                warn!("Rejected management token");
+                ServerMessage::Error("invalid token".to_string())
+            }
+        };
+    }
+
+    // Resolved per message so token changes apply as soon as config reloads
+    let config = state.config.load_full();
+    let role = config.management.role_for(token.as_deref());
+
+    // Attempts without access are recorded too. The path is read before
+    // the change, so a change to the audit log itself is recorded.
+    let audit = match config.management.audit_log {
+        Some(ref path) if msg.requires_admin() => {
+            let mut entry =
+                audit::entry(AuditSource::Management, msg.name(), audit::summarize(&msg));
+            entry.peer = Some(peer.addr.to_string());
+            entry.certificate = peer.certificate.clone();
+            entry.token = config
+                .management
+                .token_name(token.as_deref())
+                .map(str::to_string);
+            entry.role = role;
+            Some((path.clone(), entry))
+        }
+        _ => None,
+    };
+    drop(config);
+
+    let response = match role {
+        None => ServerMessage::Error("authentication required".to_string()),
+        Some(ManagementRole::Read) if msg.requires_admin() => {
+            ServerMessage::Error("insufficient permissions".to_string())
+        }
+        Some(_) => handle_message(msg, state).await,
+    };
+
+    if let Some((path, mut entry)) = audit {
+        audit::finish(&mut entry, &response);
+        state.audit.record(&path, &entry).await;
+    }
+    response
+}
+
+/// Handle a client message and return a response
+async fn handle_message(msg: ClientMessage, state: &Arc<AppState>) -> ServerMessage {
+    match msg {
+        ClientMessage::Authenticate { .. } => unreachable!("authentication is handled per session"),
+
+        ClientMessage::Ping(id) => ServerMessage::Pong(id),
+
+        ClientMessage::GetStatus => ServerMessage::Status(state.status_snapshot().await),
+
+        ClientMessage::GetConfig => ServerMessage::Config(JanusConfig::clone(&state.config.load())),
+
+        ClientMessage::GetConfigDiff { before } => {
+            let config = state.config.load_full();
+            ServerMessage::ConfigDiff(janus_common::diff_configs(&before, &config))
+        }
+
+        ClientMessage::UpdateConfig(new_config) => {
+            if let Err(e) = janus_common::validate_config(&new_config) {
+                return match e {
+                    ConfigError::ValidationError(issues) => {
+                        ServerMessage::ConfigValidationErrors(issues)
+                    }
+                    e => ServerMessage::Error(e.to_string()),
+                };
+            }
+
+            let mut tx = state.transaction().await;
+            tx.config = new_config;
+            commit(tx, "Configuration updated".to_string()).await
+        }
+
+        ClientMessage::AddRoute(_)
+        | ClientMessage::RemoveRoute(_)
+        | ClientMessage::SetRouteEnabled { .. }
+        | ClientMessage::AddUpstream { .. }
+        | ClientMessage::UpdateUpstream { .. }
+        | ClientMessage::DrainBackendServer { .. }
+        | ClientMessage::UpdateServerPort(_)
+        | ClientMessage::UpdateBindAddress(_)
+        | ClientMessage::AddStaticDir(_)
+        | ClientMessage::UpdateStaticDir { .. }
+        | ClientMessage::RemoveStaticDir(_)
+        | ClientMessage::SetStaticDirEnabled { .. } => {
+            let mut tx = state.transaction().await;
+            match apply_edit(&mut tx.config, &msg) {
+                Ok(success) => commit(tx, success).await,
+                Err(e) => edit_error(e),
+            }
+        }
+
+        ClientMessage::RemoveUpstream(ref name) => {
+            let mut tx = state.transaction().await;
+            let response = match apply_edit(&mut tx.config, &msg) {
+                Ok(success) => commit(tx, success).await,
+                Err(e) => return edit_error(e),
+            };
+            if matches!(response, ServerMessage::Success(_)) {
+                state.draining_upstreams.lock().await.remove(name);
+            }
+            response
+        }
+
+        ClientMessage::DrainUpstream(name) => {
+            let config = state.config.load_full();
+            let Some(upstream) = config.upstreams.get(&name) else {
+                return ServerMessage::Error(format!("Upstream '{}' not found", name));
+            };
+            let drain_timeout = upstream.drain_timeout_secs;
+            drop(config);
+
+            if !state.draining_upstreams.lock().await.insert(name.clone()) {
+                return ServerMessage::Error(format!("Upstream '{}' is already draining", name));
+            }
+
+            let drain_state = state.clone();
+            let drain_name = name.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(std::time::Duration::from_secs(drain_timeout)).await;
+                finish_drain(&drain_state, &drain_name).await;
+            });
+
+            ServerMessage::Success(format!(
+                "Upstream '{}' draining, removal in {}s",
+                name, drain_timeout
+            ))
+        }
+
+        ClientMessage::ReloadConfig => match crate::reload::reload_config(state).await {
+            Ok(()) => ServerMessage::Success("Configuration reloaded from file".to_string()),
+            Err(e) => ServerMessage::Error(format!("Failed to reload config: {}", e)),
+        },
+
+        ClientMessage::GetStats => ServerMessage::Stats(state.stats_snapshot().await),
+
+        ClientMessage::ResetStats => {
+            state.reset_stats().await;
+            ServerMessage::Success("Statistics reset".to_string())
+        }
+
+        ClientMessage::SetMaintenance {
+            scope,
+            enabled,
+            message,
+        } => {
+            let config = state.config.load_full();
+            // Switches for removed routes can still be turned off
+            if let MaintenanceScope::Route(ref path) = scope {
+                if enabled && !config.routes.iter().any(|r| r.path == *path) {
+                    return ServerMessage::Error(format!("Route '{}' not found", path));
+                }
+            }
+            let success = format!(
+                "Maintenance {} for {}",
+                if enabled { "on" } else { "off" },
+                scope
+            );
+            state.maintenance.set(scope, enabled, message);
+            if let Some(ref path) = config.server.maintenance.state_file {
+                state.maintenance.save(path).await;
+            }
+            ServerMessage::Success(success)
+        }
+
+        ClientMessage::GetTopClients { limit } => {
+            ServerMessage::TopClients(state.traffic.top_clients(limit))
+        }
+
+        ClientMessage::GetRouteStats { path } => {
+            if let Some(detail) = state.traffic.route_detail(&path) {
+                return ServerMessage::RouteStats(detail);
+            }
+            // Routes that have not served anything yet have empty stats
+            let config = state.config.load();
+            let known = (path == "default" && config.server.default_upstream.is_some())
+                || config.routes.iter().any(|r| r.path == path)
+                || config
+                    .static_files
+                    .iter()
+                    .any(|s| path.strip_prefix("static:") == Some(s.path.as_str()));
+            if known {
+                ServerMessage::RouteStats(RouteStatsDetail {
+                    path,
+                    ..Default::default()
+                })
+            } else {
+                ServerMessage::Error(format!("Route '{}' not found", path))
+            }
+        }
+
+        ClientMessage::GetErrorLog { limit } => {
+            ServerMessage::ErrorLog(state.error_log.recent(limit))
+        }
+
+        ClientMessage::GetAuditLog { limit } => {
+            let Some(path) = state.config.load().management.audit_log.clone() else {
+                return ServerMessage::AuditLog(Vec::new());
+            };
+            match state.audit.recent(&path, limit).await {
+                Ok(entries) => ServerMessage::AuditLog(entries),
+                Err(e) => ServerMessage::Error(format!("Failed to read audit log: {}", e)),
+            }
+        }
+
+        ClientMessage::GetPreflight => {
+            let config = JanusConfig::clone(&state.config.load());
+            // The running server already holds its listeners
+            let options = PreflightOptions {
+                resolve_upstreams: true,
+                check_bind: false,
+                probe_health_checks: true,
+            };
+            ServerMessage::Preflight(preflight(&config, options).await)
+        }
+
+        ClientMessage::Shutdown { confirm: false, .. } => {
+            ServerMessage::Error("Shutdown must be confirmed".to_string())
+        }
+
+        ClientMessage::Shutdown { delay_secs, .. } => {
+            info!("Shutdown requested in {}s via management API", delay_secs);
+            let state = Arc::clone(state);
+            let mut shutdown = state.shutdown.subscribe();
+            tokio::spawn(async move {
+                tokio::select! {
+                    _ = tokio::time::sleep(Duration::from_secs(delay_secs)) => {
+                        state.shutdown.send_replace(true);
+                    }
+                    _ = wait_for_shutdown(&mut shutdown) => {}
+                }
+            });
+            ServerMessage::Success(format!("Shutting down in {}s", delay_secs))
+        }
+    }
+}
+
+/// Response to an edit the configuration does not allow
+fn edit_error(e: EditError) -> ServerMessage {
+    match e {
+        EditError::Refused(reason) => ServerMessage::Error(reason),
+        EditError::Invalid(issues) => ServerMessage::ConfigValidationErrors(issues),
+    }
+}
+
+/// Save a configuration change, answering with `success` once it is running
+async fn commit(tx: ConfigTransaction<'_>, success: String) -> ServerMessage {
+    match tx.commit().await {
+        Ok(()) => ServerMessage::Success(success),
+        Err(e) => ServerMessage::Error(format!("Failed to save config: {}", e)),
+    }
+}
+
+/// Remove an upstream whose drain timeout has elapsed
+async fn finish_drain(state: &Arc<AppState>, name: &str) {
+    // Explicit removals also run in a transaction, so the draining set
+    // cannot change underneath this one
+    let mut tx = state.transaction().await;
+    if !state.draining_upstreams.lock().await.contains(name) {
+        // Already removed explicitly
+        return;
+    }
+
+    if tx.config.routes.iter().any(|r| r.upstream == name) {
+        warn!(
+            "Drained upstream '{}' is still in use by routes, leaving it draining",
+            name
+        );
+        return;
+    }
+
+    tx.config.upstreams.remove(name);
+    if let Err(e) = tx.commit().await {
+        error!("Failed to save config after draining '{}': {}", name, e);
+        return;
+    }
+    state.draining_upstreams.lock().await.remove(name);
+    info!("Upstream '{}' drained and removed", name);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_live_budget_per_second() {
+        let start = Instant::now();
+        let mut budget = LiveBudget::new(start);
+
+        let sent = (0..150).filter(|_| budget.allow(start)).count();
+        assert_eq!(sent, LIVE_REQUESTS_PER_SEC as usize);
+        assert!(!budget.allow(start + Duration::from_millis(999)));
+
+        // A new window starts a second later
+        assert!(budget.allow(start + Duration::from_secs(1)));
+    }
+}
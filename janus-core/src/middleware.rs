@@ -0,0 +1,816 @@
+//! Steps every request passes through before it is routed
+//!
+//! Each [`Middleware`] may change the request or answer it, which ends the
+//! chain, and may change the response to a request it let through. The
+//! chain is built from the configuration whenever one is applied, so steps
+//! whose settings are off cost nothing per request. Health probes and ACME
+//! challenges are answered before the chain runs, as they are not counted
+//! as traffic.
+
+use crate::server::{error_response, https_redirect};
+use crate::{auth, AppState};
+use bytes::Bytes;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use futures::future::BoxFuture;
+use http_body_util::combinators::BoxBody;
+use http_body_util::BodyExt;
+use hyper::body::{Body, Frame, SizeHint};
+use hyper::header::{
+    HeaderValue, CACHE_CONTROL, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE,
+    ETAG, RETRY_AFTER, VARY,
+};
+use hyper::{HeaderMap, Method, Request, Response, StatusCode};
+use janus_common::config::{AuthRequestConfig, CompressionConfig, RateLimitConfig};
+use janus_common::JanusConfig;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::io::Write;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::task::{Context, Poll};
+use std::time::Instant;
+use tracing::warn;
+
+/// Response a middleware answers a request with
+pub type MiddlewareResponse = Response<BoxBody<Bytes, Infallible>>;
+
+/// Header carrying the request ID
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Connection a request arrived on, attached to its extensions
+#[derive(Debug, Clone, Copy)]
+pub struct Connection {
+    pub is_tls: bool,
+    pub remote_addr: SocketAddr,
+}
+
+/// ID a request is known by in logs and error pages, attached to its
+/// extensions
+#[derive(Debug, Clone)]
+pub struct RequestId(pub String);
+
+/// A step run on every request before routing
+///
+/// Steps see the request's head; its body is left for whatever serves the
+/// request. `process` returns a boxed future rather than being an
+/// `async fn`, so steps can be kept together as trait objects.
+pub trait Middleware: Send + Sync {
+    /// Process a request, answering it to stop the chain
+    fn process<'a>(
+        &'a self,
+        req: &'a mut Request<()>,
+        state: &'a AppState,
+    ) -> BoxFuture<'a, Option<MiddlewareResponse>>;
+
+    /// Whether [`Middleware::respond`] changes anything, so the chain
+    /// keeps the request headers for it
+    fn rewrites_responses(&self) -> bool {
+        false
+    }
+
+    /// Change the response to a request the chain let through, given the
+    /// request's method and headers
+    fn respond(
+        &self,
+        _method: &Method,
+        _request: &HeaderMap,
+        response: MiddlewareResponse,
+    ) -> MiddlewareResponse {
+        response
+    }
+}
+
+/// Middleware run in order on every request
+#[derive(Default)]
+pub struct Chain {
+    steps: Vec<Box<dyn Middleware>>,
+}
+
+impl Chain {
+    /// Chain for a configuration
+    pub fn build(config: &JanusConfig) -> Self {
+        let server = &config.server;
+        let mut chain = Self::default();
+        chain.push(RequestIdMiddleware);
+        if server.force_https {
+            chain.push(HttpsRedirectMiddleware {
+                code: server.https_redirect_code,
+            });
+        }
+        chain.push(ForwardedProtoMiddleware);
+        if let Some(ref limit) = server.rate_limit {
+            chain.push(RateLimitMiddleware::new(limit));
+        }
+        // After the rate limit, so clients over it do not reach the auth
+        // service
+        if let Some(ref auth) = server.auth_request {
+            chain.push(AuthMiddleware { auth: auth.clone() });
+        }
+        if server.compression.enabled {
+            chain.push(CompressionMiddleware {
+                config: server.compression.clone(),
+            });
+        }
+        chain
+    }
+
+    fn push(&mut self, middleware: impl Middleware + 'static) {
+        self.steps.push(Box::new(middleware));
+    }
+
+    /// Run every step in turn, stopping at the first to answer
+    pub async fn run(&self, req: &mut Request<()>, state: &AppState) -> Option<MiddlewareResponse> {
+        for step in &self.steps {
+            if let Some(response) = step.process(req, state).await {
+                return Some(response);
+            }
+        }
+        None
+    }
+
+    /// Whether any step changes responses
+    pub fn rewrites_responses(&self) -> bool {
+        self.steps.iter().any(|step| step.rewrites_responses())
+    }
+
+    /// Let every step change the response, the last step first
+    pub fn respond(
+        &self,
+        method: &Method,
+        request: &HeaderMap,
+        response: MiddlewareResponse,
+    ) -> MiddlewareResponse {
+        self.steps.iter().rev().fold(response, |response, step| {
+            step.respond(method, request, response)
+        })
+    }
+}
+
+/// Whether a request came in over TLS
+fn is_tls<B>(req: &Request<B>) -> bool {
+    req.extensions()
+        .get::<Connection>()
+        .is_some_and(|connection| connection.is_tls)
+}
+
+/// Takes the client's request ID, or assigns one
+struct RequestIdMiddleware;
+
+impl Middleware for RequestIdMiddleware {
+    fn process<'a>(
+        &'a self,
+        req: &'a mut Request<()>,
+        state: &'a AppState,
+    ) -> BoxFuture<'a, Option<MiddlewareResponse>> {
+        let id = req
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string)
+            .unwrap_or_else(|| state.next_request_id());
+        req.extensions_mut().insert(RequestId(id));
+        Box::pin(std::future::ready(None))
+    }
+}
+
+/// Redirects plain HTTP requests to HTTPS (`server.force_https`)
+struct HttpsRedirectMiddleware {
+    code: u16,
+}
+
+impl Middleware for HttpsRedirectMiddleware {
+    fn process<'a>(
+        &'a self,
+        req: &'a mut Request<()>,
+        _state: &'a AppState,
+    ) -> BoxFuture<'a, Option<MiddlewareResponse>> {
+        let response = (!is_tls(req)).then(|| https_redirect(req, self.code));
+        Box::pin(std::future::ready(response))
+    }
+}
+
+/// Tells upstreams which scheme the client used, replacing any value the
+/// client sent, which upstreams cannot trust
+struct ForwardedProtoMiddleware;
+
+impl Middleware for ForwardedProtoMiddleware {
+    fn process<'a>(
+        &'a self,
+        req: &'a mut Request<()>,
+        _state: &'a AppState,
+    ) -> BoxFuture<'a, Option<MiddlewareResponse>> {
+        let proto = if is_tls(req) { "https" } else { "http" };
+        req.headers_mut()
+            .insert("x-forwarded-proto", HeaderValue::from_static(proto));
+        Box::pin(std::future::ready(None))
+    }
+}
+
+/// Answers 429 to clients sending requests faster than
+/// `server.rate_limit` allows
+struct RateLimitMiddleware {
+    /// Tokens added to each bucket per second
+    rate: f64,
+    /// Tokens a bucket holds when full
+    burst: f64,
+    buckets: Mutex<Buckets>,
+}
+
+/// Token bucket of every client address seen
+struct Buckets {
+    by_client: HashMap<IpAddr, Bucket>,
+    /// Number of buckets at which full ones are next dropped
+    prune_at: usize,
+}
+
+#[derive(Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Fewest buckets kept before full ones are dropped
+const MIN_PRUNE_AT: usize = 1024;
+
+impl RateLimitMiddleware {
+    fn new(config: &RateLimitConfig) -> Self {
+        Self {
+            rate: f64::from(config.requests_per_second),
+            burst: f64::from(config.burst()),
+            buckets: Mutex::new(Buckets {
+                by_client: HashMap::new(),
+                prune_at: MIN_PRUNE_AT,
+            }),
+        }
+    }
+
+    /// Tokens a bucket holds at `now`
+    fn refilled(&self, bucket: Bucket, now: Instant) -> f64 {
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        (bucket.tokens + elapsed * self.rate).min(self.burst)
+    }
+
+    /// Take a token for a request from `ip`, or return the seconds until
+    /// one is available
+    fn take(&self, ip: IpAddr, now: Instant) -> Result<(), u64> {
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+
+        // A full bucket is the same as none, so those can go once there
+        // are many; the threshold doubles to keep this rare
+        if buckets.by_client.len() >= buckets.prune_at {
+            buckets
+                .by_client
+                .retain(|_, bucket| self.refilled(*bucket, now) < self.burst);
+            buckets.prune_at = (buckets.by_client.len() * 2).max(MIN_PRUNE_AT);
+        }
+
+        let bucket = buckets.by_client.entry(ip).or_insert(Bucket {
+            tokens: self.burst,
+            updated: now,
+        });
+        let tokens = self.refilled(*bucket, now);
+        *bucket = Bucket {
+            tokens: if tokens >= 1.0 { tokens - 1.0 } else { tokens },
+            updated: now,
+        };
+        if tokens >= 1.0 {
+            Ok(())
+        } else {
+            Err(((1.0 - tokens) / self.rate).ceil().max(1.0) as u64)
+        }
+    }
+}
+
+impl Middleware for RateLimitMiddleware {
+    fn process<'a>(
+        &'a self,
+        req: &'a mut Request<()>,
+        _state: &'a AppState,
+    ) -> BoxFuture<'a, Option<MiddlewareResponse>> {
+        let response = req
+            .extensions()
+            .get::<Connection>()
+            .and_then(|connection| self.take(connection.remote_addr.ip(), Instant::now()).err())
+            .map(|retry_after| {
+                let mut response =
+                    error_response(StatusCode::TOO_MANY_REQUESTS, "Too Many Requests");
+                response
+                    .headers_mut()
+                    .insert(RETRY_AFTER, retry_after.into());
+                response
+            });
+        Box::pin(std::future::ready(response))
+    }
+}
+
+/// Label the server-wide auth service's statistics are kept under
+const SERVER_AUTH_LABEL: &str = "*";
+
+/// Asks the `server.auth_request` service about every request
+struct AuthMiddleware {
+    auth: AuthRequestConfig,
+}
+
+impl Middleware for AuthMiddleware {
+    fn process<'a>(
+        &'a self,
+        req: &'a mut Request<()>,
+        state: &'a AppState,
+    ) -> BoxFuture<'a, Option<MiddlewareResponse>> {
+        Box::pin(async move {
+            let Some(handler) = state.proxies.load().server_auth() else {
+                // Never let a request through that was not checked
+                warn!("Auth upstream '{}' not found", self.auth.upstream);
+                return Some(error_response(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Internal Server Error",
+                ));
+            };
+            auth::authorize(state, &handler, &self.auth, SERVER_AUTH_LABEL, req)
+                .await
+                .err()
+        })
+    }
+}
+
+/// Gzip-compresses responses for clients that accept it
+/// (`server.compression`)
+struct CompressionMiddleware {
+    config: CompressionConfig,
+}
+
+impl CompressionMiddleware {
+    /// Whether a response is one to compress
+    fn compresses(&self, response: &MiddlewareResponse) -> bool {
+        let status = response.status();
+        if status.is_informational()
+            || status == StatusCode::NO_CONTENT
+            || status == StatusCode::PARTIAL_CONTENT
+            || status == StatusCode::NOT_MODIFIED
+        {
+            return false;
+        }
+
+        let headers = response.headers();
+        if headers.contains_key(CONTENT_ENCODING)
+            || headers.contains_key(CONTENT_RANGE)
+            || has_token(headers.get_all(CACHE_CONTROL), "no-transform")
+        {
+            return false;
+        }
+
+        let length = headers
+            .get(CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok()?.parse::<u64>().ok())
+            .or_else(|| response.body().size_hint().exact());
+        if length.is_some_and(|length| length < self.config.min_bytes) {
+            return false;
+        }
+
+        let Some(media_type) = headers
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.split(';').next())
+            .map(|v| v.trim().to_ascii_lowercase())
+        else {
+            return false;
+        };
+        self.config
+            .content_types
+            .iter()
+            .any(|pattern| match pattern.strip_suffix("/*") {
+                Some(kind) => media_type
+                    .split_once('/')
+                    .is_some_and(|(media_kind, _)| media_kind.eq_ignore_ascii_case(kind)),
+                None => pattern.eq_ignore_ascii_case(&media_type),
+            })
+    }
+}
+
+impl Middleware for CompressionMiddleware {
+    fn process<'a>(
+        &'a self,
+        _req: &'a mut Request<()>,
+        _state: &'a AppState,
+    ) -> BoxFuture<'a, Option<MiddlewareResponse>> {
+        Box::pin(std::future::ready(None))
+    }
+
+    fn rewrites_responses(&self) -> bool {
+        true
+    }
+
+    fn respond(
+        &self,
+        method: &Method,
+        request: &HeaderMap,
+        response: MiddlewareResponse,
+    ) -> MiddlewareResponse {
+        if !self.compresses(&response) {
+            return response;
+        }
+        let (mut parts, body) = response.into_parts();
+        // Caches must keep the plain and compressed forms apart
+        if !has_token(parts.headers.get_all(VARY), "accept-encoding") {
+            parts
+                .headers
+                .append(VARY, HeaderValue::from_static("accept-encoding"));
+        }
+        // A HEAD response has no body to compress, so its length and
+        // validator stay as the upstream sent them
+        if *method == Method::HEAD || !accepts_gzip(request) {
+            return Response::from_parts(parts, body);
+        }
+
+        parts.headers.remove(CONTENT_LENGTH);
+        parts
+            .headers
+            .insert(CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+        // The bytes differ from the upstream's, so a strong validator no
+        // longer holds
+        if let Some(etag) = parts.headers.get(ETAG) {
+            if !etag.as_bytes().starts_with(b"W/") {
+                let mut weak = b"W/".to_vec();
+                weak.extend_from_slice(etag.as_bytes());
+                if let Ok(weak) = HeaderValue::from_bytes(&weak) {
+                    parts.headers.insert(ETAG, weak);
+                }
+            }
+        }
+        Response::from_parts(parts, GzipBody::new(body).boxed())
+    }
+}
+
+/// Whether any of a comma-separated header's values lists `token`
+fn has_token(values: hyper::header::GetAll<'_, HeaderValue>, token: &str) -> bool {
+    values
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|item| item.trim().eq_ignore_ascii_case(token))
+}
+
+/// Whether a request's `Accept-Encoding` allows gzip
+fn accepts_gzip(request: &HeaderMap) -> bool {
+    let mut wildcard = false;
+    for item in request
+        .get_all(hyper::header::ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+    {
+        let mut params = item.split(';');
+        let coding = params.next().unwrap_or("").trim();
+        // `q=0` rules the coding out
+        let refused = params.any(|param| {
+            param
+                .trim()
+                .strip_prefix("q=")
+                .and_then(|q| q.parse::<f32>().ok())
+                .is_some_and(|q| q == 0.0)
+        });
+        if coding.eq_ignore_ascii_case("gzip") {
+            return !refused;
+        }
+        if coding == "*" {
+            wildcard = !refused;
+        }
+    }
+    wildcard
+}
+
+/// Compresses a body as its frames arrive, flushing after each so streamed
+/// responses are not held back
+struct GzipBody {
+    inner: BoxBody<Bytes, Infallible>,
+    /// Taken once the inner body ends
+    encoder: Option<GzEncoder<Vec<u8>>>,
+    /// Sent after the last of the compressed data
+    trailers: Option<HeaderMap>,
+}
+
+impl GzipBody {
+    fn new(inner: BoxBody<Bytes, Infallible>) -> Self {
+        Self {
+            inner,
+            encoder: Some(GzEncoder::new(Vec::new(), Compression::default())),
+            trailers: None,
+        }
+    }
+}
+
+impl Body for GzipBody {
+    type Data = Bytes;
+    type Error = Infallible;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, Infallible>>> {
+        let this = &mut *self;
+        loop {
+            let Some(encoder) = this.encoder.as_mut() else {
+                return Poll::Ready(this.trailers.take().map(|t| Ok(Frame::trailers(t))));
+            };
+            let frame = match Pin::new(&mut this.inner).poll_frame(cx) {
+                Poll::Ready(Some(Ok(frame))) => frame,
+                Poll::Ready(Some(Err(e))) => match e {},
+                Poll::Ready(None) => {
+                    // Writing to a Vec cannot fail
+                    let rest = this
+                        .encoder
+                        .take()
+                        .and_then(|encoder| encoder.finish().ok())
+                        .unwrap_or_default();
+                    return Poll::Ready(Some(Ok(Frame::data(rest.into()))));
+                }
+                Poll::Pending => return Poll::Pending,
+            };
+            match frame.into_data() {
+                Ok(data) => {
+                    let _ = encoder.write_all(&data);
+                    let _ = encoder.flush();
+                    let compressed = std::mem::take(encoder.get_mut());
+                    if !compressed.is_empty() {
+                        return Poll::Ready(Some(Ok(Frame::data(compressed.into()))));
+                    }
+                }
+                Err(frame) => {
+                    if let Ok(trailers) = frame.into_trailers() {
+                        this.trailers = Some(trailers);
+                    }
+                }
+            }
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.encoder.is_none() && self.trailers.is_none()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        SizeHint::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::Full;
+    use std::io::Read;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    /// Counts the requests it sees, answering them if given a status
+    struct Step {
+        seen: Arc<AtomicUsize>,
+        answer: Option<StatusCode>,
+    }
+
+    impl Middleware for Step {
+        fn process<'a>(
+            &'a self,
+            _req: &'a mut Request<()>,
+            _state: &'a AppState,
+        ) -> BoxFuture<'a, Option<MiddlewareResponse>> {
+            self.seen.fetch_add(1, Ordering::Relaxed);
+            let response = self.answer.map(|status| error_response(status, "Answered"));
+            Box::pin(std::future::ready(response))
+        }
+    }
+
+    fn request(is_tls: bool) -> Request<()> {
+        let mut req = Request::get("/page")
+            .header("host", "example.com:8080")
+            .body(())
+            .unwrap();
+        req.extensions_mut().insert(Connection {
+            is_tls,
+            remote_addr: "192.0.2.1:40000".parse().unwrap(),
+        });
+        req
+    }
+
+    fn text_response(len: usize, content_type: &str) -> MiddlewareResponse {
+        let mut response = Response::new(Full::new(Bytes::from("a".repeat(len))).boxed());
+        response
+            .headers_mut()
+            .insert(CONTENT_TYPE, HeaderValue::from_str(content_type).unwrap());
+        response
+    }
+
+    fn accepting(accept_encoding: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            hyper::header::ACCEPT_ENCODING,
+            HeaderValue::from_str(accept_encoding).unwrap(),
+        );
+        headers
+    }
+
+    #[tokio::test]
+    async fn test_chain_stops_at_first_answer() {
+        let state = AppState::new(JanusConfig::default(), None);
+        let seen: Vec<_> = (0..4).map(|_| Arc::new(AtomicUsize::new(0))).collect();
+        let mut chain = Chain::default();
+        for (seen, answer) in seen.iter().zip([
+            None,
+            Some(StatusCode::FORBIDDEN),
+            Some(StatusCode::INTERNAL_SERVER_ERROR),
+            None,
+        ]) {
+            chain.push(Step {
+                seen: seen.clone(),
+                answer,
+            });
+        }
+
+        let response = chain.run(&mut request(false), &state).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let counts: Vec<_> = seen.iter().map(|s| s.load(Ordering::Relaxed)).collect();
+        assert_eq!(counts, [1, 1, 0, 0]);
+
+        // A chain nothing answers lets the request through every step
+        let mut chain = Chain::default();
+        chain.push(Step {
+            seen: seen[3].clone(),
+            answer: None,
+        });
+        assert!(chain.run(&mut request(false), &state).await.is_none());
+        assert_eq!(seen[3].load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn test_https_redirect_only_with_force_https() {
+        let mut config = JanusConfig::default();
+        let state = AppState::new(config.clone(), None);
+
+        let chain = Chain::build(&config);
+        let mut req = request(false);
+        assert!(chain.run(&mut req, &state).await.is_none());
+        assert_eq!(req.headers()["x-forwarded-proto"], "http");
+        assert!(req.extensions().get::<RequestId>().is_some());
+
+        config.server.force_https = true;
+        config.server.https_redirect_code = 308;
+        let chain = Chain::build(&config);
+        let response = chain.run(&mut request(false), &state).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+        assert_eq!(
+            response.headers()[hyper::header::LOCATION],
+            "https://example.com/page"
+        );
+
+        let mut req = request(true);
+        assert!(chain.run(&mut req, &state).await.is_none());
+        assert_eq!(req.headers()["x-forwarded-proto"], "https");
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_per_client() {
+        let mut config = JanusConfig::default();
+        config.server.rate_limit = Some(RateLimitConfig {
+            requests_per_second: 1,
+            burst: Some(2),
+        });
+        let state = AppState::new(config.clone(), None);
+        let chain = Chain::build(&config);
+
+        for _ in 0..2 {
+            assert!(chain.run(&mut request(false), &state).await.is_none());
+        }
+        let response = chain.run(&mut request(false), &state).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[RETRY_AFTER], "1");
+
+        // Other clients have buckets of their own
+        let mut other = request(false);
+        other.extensions_mut().insert(Connection {
+            is_tls: false,
+            remote_addr: "192.0.2.2:40000".parse().unwrap(),
+        });
+        assert!(chain.run(&mut other, &state).await.is_none());
+    }
+
+    #[test]
+    fn test_rate_limit_refills() {
+        let limit = RateLimitMiddleware::new(&RateLimitConfig {
+            requests_per_second: 2,
+            burst: None,
+        });
+        let ip = "192.0.2.1".parse().unwrap();
+        let start = Instant::now();
+        assert_eq!(limit.take(ip, start), Ok(()));
+        assert_eq!(limit.take(ip, start), Ok(()));
+        assert_eq!(limit.take(ip, start), Err(1));
+        assert_eq!(
+            limit.take(ip, start + std::time::Duration::from_millis(500)),
+            Ok(())
+        );
+        assert!(limit
+            .take(ip, start + std::time::Duration::from_millis(500))
+            .is_err());
+    }
+
+    #[test]
+    fn test_accepts_gzip() {
+        assert!(accepts_gzip(&accepting("gzip")));
+        assert!(accepts_gzip(&accepting("br, GZIP;q=0.5")));
+        assert!(accepts_gzip(&accepting("*")));
+        assert!(!accepts_gzip(&accepting("gzip;q=0, *")));
+        assert!(!accepts_gzip(&accepting("br, *;q=0")));
+        assert!(!accepts_gzip(&accepting("identity")));
+        assert!(!accepts_gzip(&HeaderMap::new()));
+    }
+
+    #[tokio::test]
+    async fn test_compression() {
+        let mut config = JanusConfig::default();
+        config.server.compression.enabled = true;
+        let chain = Chain::build(&config);
+        assert!(chain.rewrites_responses());
+        assert!(!Chain::build(&JanusConfig::default()).rewrites_responses());
+
+        let mut response = text_response(4096, "text/html; charset=utf-8");
+        response
+            .headers_mut()
+            .insert(CONTENT_LENGTH, HeaderValue::from_static("4096"));
+        response
+            .headers_mut()
+            .insert(ETAG, HeaderValue::from_static("\"v1\""));
+        let response = chain.respond(&Method::GET, &accepting("gzip"), response);
+        let headers = response.headers();
+        assert_eq!(headers[CONTENT_ENCODING], "gzip");
+        assert_eq!(headers[VARY], "accept-encoding");
+        assert_eq!(headers[ETAG], "W/\"v1\"");
+        assert!(!headers.contains_key(CONTENT_LENGTH));
+
+        let compressed = response.into_body().collect().await.unwrap().to_bytes();
+        assert!(compressed.len() < 4096);
+        let mut body = String::new();
+        flate2::read::GzDecoder::new(&compressed[..])
+            .read_to_string(&mut body)
+            .unwrap();
+        assert_eq!(body, "a".repeat(4096));
+
+        // Still varies for clients that do not take gzip
+        let response = chain.respond(
+            &Method::GET,
+            &HeaderMap::new(),
+            text_response(4096, "application/json"),
+        );
+        assert!(!response.headers().contains_key(CONTENT_ENCODING));
+        assert_eq!(response.headers()[VARY], "accept-encoding");
+
+        for response in [
+            text_response(100, "text/html"),
+            text_response(4096, "image/png"),
+        ] {
+            let response = chain.respond(&Method::GET, &accepting("gzip"), response);
+            assert!(!response.headers().contains_key(CONTENT_ENCODING));
+            assert!(!response.headers().contains_key(VARY));
+        }
+
+        let mut encoded = text_response(4096, "text/plain");
+        encoded
+            .headers_mut()
+            .insert(CONTENT_ENCODING, HeaderValue::from_static("br"));
+        let response = chain.respond(&Method::GET, &accepting("gzip"), encoded);
+        assert_eq!(response.headers()[CONTENT_ENCODING], "br");
+
+        // HEAD keeps the headers an uncompressed GET would have
+        let mut head = text_response(4096, "text/plain");
+        head.headers_mut()
+            .insert(CONTENT_LENGTH, HeaderValue::from_static("4096"));
+        head.headers_mut()
+            .insert(ETAG, HeaderValue::from_static("\"v1\""));
+        let response = chain.respond(&Method::HEAD, &accepting("gzip"), head);
+        let headers = response.headers();
+        assert!(!headers.contains_key(CONTENT_ENCODING));
+        assert_eq!(headers[CONTENT_LENGTH], "4096");
+        assert_eq!(headers[ETAG], "\"v1\"");
+        assert_eq!(headers[VARY], "accept-encoding");
+    }
+
+    #[tokio::test]
+    async fn test_gzip_body_streams_frames() {
+        let frames = futures::stream::iter(
+            ["first ", "second"].map(|chunk| Ok::<_, Infallible>(Frame::data(Bytes::from(chunk)))),
+        );
+        let body = http_body_util::StreamBody::new(frames).boxed();
+        let mut gzip = GzipBody::new(body);
+
+        // Each frame is flushed through on its own
+        let first = gzip.frame().await.unwrap().unwrap().into_data().unwrap();
+        let mut decoder = flate2::write::GzDecoder::new(Vec::new());
+        decoder.write_all(&first).unwrap();
+        decoder.flush().unwrap();
+        assert_eq!(decoder.get_ref(), b"first ");
+
+        let rest = gzip.collect().await.unwrap().to_bytes();
+        decoder.write_all(&rest).unwrap();
+        assert_eq!(decoder.finish().unwrap(), b"first second");
+    }
+}
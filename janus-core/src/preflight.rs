@@ -0,0 +1,267 @@
+//! Startup checks that catch configuration mistakes before traffic arrives
+
+use bytes::Bytes;
+use http_body_util::Empty;
+use hyper::Request;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use janus_common::config::HealthCheckConfig;
+use janus_common::{ConfigError, JanusConfig, PreflightReport};
+use std::path::Path;
+use std::time::Duration;
+
+/// Which of the slower or environment-dependent checks to run
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PreflightOptions {
+    /// Resolve upstream server host names
+    pub resolve_upstreams: bool,
+
+    /// Try binding the server and management addresses
+    pub check_bind: bool,
+
+    /// Request the health check path of upstreams that have one from each
+    /// of their servers
+    pub probe_health_checks: bool,
+}
+
+/// Check a configuration, collecting every finding rather than stopping at
+/// the first
+pub async fn preflight(config: &JanusConfig, options: PreflightOptions) -> PreflightReport {
+    let mut report = PreflightReport::default();
+
+    match janus_common::validate_config(config) {
+        Ok(()) => {}
+        Err(ConfigError::ValidationError(issues)) => {
+            report.errors.extend(issues.iter().map(ToString::to_string))
+        }
+        Err(e) => report.errors.push(e.to_string()),
+    }
+
+    for route in &config.routes {
+        if let Some(problem) = route_path_problem(&route.path) {
+            report
+                .errors
+                .push(format!("Route '{}' {}", route.path, problem));
+        }
+    }
+
+    for static_config in &config.static_files {
+        let root = Path::new(&static_config.root);
+        if !root.is_dir() {
+            report.warnings.push(format!(
+                "Static directory '{}': root '{}' is not a directory",
+                static_config.path, static_config.root
+            ));
+        } else if let Err(e) = std::fs::read_dir(root) {
+            report.warnings.push(format!(
+                "Static directory '{}': root '{}' is not readable: {}",
+                static_config.path, static_config.root, e
+            ));
+        }
+    }
+
+    let mut upstreams: Vec<_> = config.upstreams.iter().collect();
+    upstreams.sort_by_key(|(name, _)| name.as_str());
+    for (name, upstream) in upstreams {
+        if upstream.health_check.is_some() && only_disabled_routes(config, name) {
+            report.warnings.push(format!(
+                "Upstream '{}': every route to it is disabled, so its health checks serve no traffic",
+                name
+            ));
+        }
+        for server in &upstream.servers {
+            if let Some(problem) = address_problem(&server.address) {
+                report.errors.push(format!(
+                    "Upstream '{}': server '{}' {}",
+                    name, server.address, problem
+                ));
+            } else if options.resolve_upstreams && !resolves(&server.address).await {
+                report.warnings.push(format!(
+                    "Upstream '{}': server '{}' does not resolve",
+                    name, server.address
+                ));
+            } else if let Some(check) = upstream
+                .health_check
+                .as_ref()
+                .filter(|_| options.probe_health_checks)
+            {
+                if let Err(problem) = probe_health(&server.address, check).await {
+                    report.warnings.push(format!(
+                        "Upstream '{}': server '{}' failed its health check: {}",
+                        name, server.address, problem
+                    ));
+                }
+            }
+        }
+    }
+
+    if config.management.enabled {
+        if let Err(e) = crate::tls::acceptor(&config.management) {
+            report.errors.push(format!("Management TLS: {:#}", e));
+        }
+    }
+
+    if options.check_bind {
+        let server = &config.server;
+        if let Err(e) = std::net::TcpListener::bind((server.bind_address.as_str(), server.port)) {
+            report.errors.push(format!(
+                "Cannot bind {}:{}: {}",
+                server.bind_address, server.port, e
+            ));
+        }
+        let management = &config.management;
+        if management.enabled {
+            if let Err(e) =
+                std::net::TcpListener::bind((management.address.as_str(), management.port))
+            {
+                report.errors.push(format!(
+                    "Cannot bind management {}:{}: {}",
+                    management.address, management.port, e
+                ));
+            }
+        }
+    }
+
+    report
+}
+
+/// Whether routes point at an upstream but none of them is enabled, and it
+/// is not the default upstream either
+fn only_disabled_routes(config: &JanusConfig, upstream: &str) -> bool {
+    let mut routes = config
+        .routes
+        .iter()
+        .filter(|r| r.upstream == upstream)
+        .peekable();
+    routes.peek().is_some()
+        && routes.all(|r| !r.enabled)
+        && config.server.default_upstream.as_deref() != Some(upstream)
+}
+
+/// Why a route path is malformed, if it is
+fn route_path_problem(path: &str) -> Option<&'static str> {
+    if !path.starts_with('/') {
+        Some("must start with '/'")
+    } else if path.trim_end_matches('*').contains('*') {
+        Some("may only use '*' at the end")
+    } else {
+        None
+    }
+}
+
+/// Why an upstream server address is not a usable `host:port`, if it is not
+fn address_problem(address: &str) -> Option<&'static str> {
+    if address.contains("://") {
+        return Some("must be host:port without a scheme");
+    }
+    match address.rsplit_once(':') {
+        Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => None,
+        _ => Some("must be host:port"),
+    }
+}
+
+/// Whether a `host:port` resolves to at least one address
+async fn resolves(address: &str) -> bool {
+    let lookup = tokio::net::lookup_host(address);
+    match tokio::time::timeout(Duration::from_secs(2), lookup).await {
+        Ok(Ok(mut addrs)) => addrs.next().is_some(),
+        _ => false,
+    }
+}
+
+/// Request a server's health check path, which must answer with a 2xx
+async fn probe_health(address: &str, check: &HealthCheckConfig) -> Result<(), String> {
+    let mut request = Request::get(format!("http://{}{}", address, check.path));
+    for (name, value) in check.headers()? {
+        request = request.header(name, value);
+    }
+    let request = request
+        .body(Empty::<Bytes>::new())
+        .map_err(|e| e.to_string())?;
+
+    let client = Client::builder(TokioExecutor::new()).build_http();
+    let timeout = Duration::from_secs(check.timeout);
+    match tokio::time::timeout(timeout, client.request(request)).await {
+        Ok(Ok(response)) if response.status().is_success() => Ok(()),
+        Ok(Ok(response)) => Err(format!("answered {}", response.status())),
+        Ok(Err(e)) => Err(format!("{:#}", anyhow::Error::new(e))),
+        Err(_) => Err(format!("no answer within {}s", check.timeout)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use janus_common::config::{RouteConfig, StaticFileConfig, UpstreamConfig};
+
+    #[test]
+    fn test_route_path_problem() {
+        assert_eq!(route_path_problem("/api/*"), None);
+        assert_eq!(route_path_problem("/health"), None);
+        assert!(route_path_problem("api/*").is_some());
+        assert!(route_path_problem("/api/*/users").is_some());
+    }
+
+    #[test]
+    fn test_address_problem() {
+        assert_eq!(address_problem("localhost:3000"), None);
+        assert_eq!(address_problem("[::1]:3000"), None);
+        assert!(address_problem("http://localhost:3000").is_some());
+        assert!(address_problem("localhost").is_some());
+        assert!(address_problem(":3000").is_some());
+    }
+
+    #[tokio::test]
+    async fn test_preflight_collects_all_findings() {
+        let mut config = JanusConfig::default();
+        config.upstreams.insert(
+            "api".to_string(),
+            UpstreamConfig::single("http://localhost:3000"),
+        );
+        config.static_files.push(StaticFileConfig::new(
+            "/assets/",
+            "/nonexistent/janus-assets",
+        ));
+
+        let report = preflight(&config, PreflightOptions::default()).await;
+        assert_eq!(
+            report.errors,
+            vec![
+                "Upstream 'api': server 'http://localhost:3000' must be host:port without a scheme"
+            ]
+        );
+        assert_eq!(
+            report.warnings,
+            vec![
+                "Static directory '/assets/': root '/nonexistent/janus-assets' is not a directory"
+            ]
+        );
+        assert!(!report.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_upstream_with_only_disabled_routes() {
+        let mut upstream = UpstreamConfig::single("127.0.0.1:3000");
+        upstream.health_check = Some(HealthCheckConfig {
+            interval: 10,
+            timeout: 5,
+            path: "/health".to_string(),
+            custom_headers: Default::default(),
+        });
+        let mut config = JanusConfig::default();
+        config.upstreams.insert("api".to_string(), upstream);
+        config.routes.push(RouteConfig::new("/api/*", "api"));
+        config.routes.push(RouteConfig::new("/v1/*", "api"));
+        config.routes[0].enabled = false;
+
+        let report = preflight(&config, PreflightOptions::default()).await;
+        assert!(report.warnings.is_empty());
+
+        config.routes[1].enabled = false;
+        let report = preflight(&config, PreflightOptions::default()).await;
+        assert_eq!(
+            report.warnings,
+            vec!["Upstream 'api': every route to it is disabled, so its health checks serve no traffic"]
+        );
+    }
+}
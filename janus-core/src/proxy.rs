@@ -0,0 +1,1390 @@
+//! Reverse proxy handler
+
+use crate::middleware::Connection;
+use crate::spool::{self, SpoolError, SpoolUsage, SpooledBody, UploadBody};
+use crate::stats::{Stats, Traffic};
+use bytes::Bytes;
+use http_body_util::{combinators::BoxBody, BodyExt, Full};
+use hyper::body::Incoming;
+use hyper::header::{HeaderName, HeaderValue};
+use hyper::upgrade::OnUpgrade;
+use hyper::{HeaderMap, Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use janus_common::config::{
+    AuthRequestConfig, ErrorLogConfig, LoadBalancing, RouteConfig, UpstreamConfig,
+    MIN_RESPONSE_HEADER_BYTES,
+};
+use janus_common::JanusConfig;
+use std::collections::{HashMap, HashSet};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{Mutex, OwnedSemaphorePermit, RwLock, Semaphore};
+use tracing::{debug, error, info, warn};
+
+/// Backend server that answered a proxied request, attached to the
+/// response's extensions
+#[derive(Debug, Clone)]
+pub struct Backend(pub String);
+
+/// Request slots of an upstream's servers that set
+/// `max_concurrent_requests`, by address, with the limit they were made for
+type Limits = HashMap<String, (u32, Arc<Semaphore>)>;
+
+/// Connection slots of an upstream that sets `max_connections`
+pub struct ConnectionLimit {
+    max: u32,
+    slots: Arc<Semaphore>,
+    peak: AtomicU32,
+}
+
+impl ConnectionLimit {
+    fn new(max: u32) -> Self {
+        Self {
+            max,
+            slots: Arc::new(Semaphore::new(max as usize)),
+            peak: AtomicU32::new(0),
+        }
+    }
+
+    /// Most connections allowed at once
+    pub fn max(&self) -> u32 {
+        self.max
+    }
+
+    /// Connections open now
+    pub fn in_use(&self) -> u32 {
+        self.max
+            .saturating_sub(self.slots.available_permits() as u32)
+    }
+
+    /// Most connections open at once since the limit was set
+    pub fn peak(&self) -> u32 {
+        self.peak.load(Ordering::Relaxed)
+    }
+}
+
+/// Proxy handlers for every route of a configuration
+///
+/// The table is built when the configuration changes and shared by every
+/// request. Round-robin positions and request slots belong to the upstream,
+/// so routes to the same upstream share them, and they carry over when the
+/// table is rebuilt.
+#[derive(Default)]
+pub struct ProxyTable {
+    /// Configuration the handlers were built from
+    config: Arc<JanusConfig>,
+    /// Handler per route, in config order; `None` if the upstream is missing
+    routes: Vec<Option<Arc<ProxyHandler>>>,
+    /// Indices of the routes in the order they are tried
+    order: Vec<usize>,
+    /// Handler for the auth service of each route that has one
+    auth: Vec<Option<Arc<ProxyHandler>>>,
+    /// Handler for the auth service every request is checked by, if any
+    server_auth: Option<Arc<ProxyHandler>>,
+    default: Option<Arc<ProxyHandler>>,
+    counters: HashMap<String, Arc<AtomicUsize>>,
+    limits: HashMap<String, Arc<Limits>>,
+    connections: HashMap<String, Arc<ConnectionLimit>>,
+}
+
+impl ProxyTable {
+    /// Build handlers for a configuration, keeping the round-robin
+    /// positions of the previous table
+    pub fn build(
+        config: Arc<JanusConfig>,
+        previous: &ProxyTable,
+        stats: &Arc<RwLock<Stats>>,
+        traffic: &Arc<Traffic>,
+        draining: &Arc<Mutex<HashSet<String>>>,
+        spool: &Arc<SpoolUsage>,
+    ) -> Self {
+        let counters: HashMap<String, Arc<AtomicUsize>> = config
+            .upstreams
+            .keys()
+            .map(|name| {
+                let counter = previous.counters.get(name).cloned().unwrap_or_default();
+                (name.clone(), counter)
+            })
+            .collect();
+        let limits: HashMap<String, Arc<Limits>> = config
+            .upstreams
+            .iter()
+            .map(|(name, upstream)| {
+                let previous = previous.limits.get(name);
+                let limits = upstream
+                    .servers
+                    .iter()
+                    .filter_map(|server| {
+                        let max = server.max_concurrent_requests?;
+                        // An unchanged limit keeps counting requests in flight
+                        let slots = previous
+                            .and_then(|limits| limits.get(&server.address))
+                            .filter(|(previous_max, _)| *previous_max == max)
+                            .map(|(_, slots)| slots.clone())
+                            .unwrap_or_else(|| Arc::new(Semaphore::new(max as usize)));
+                        Some((server.address.clone(), (max, slots)))
+                    })
+                    .collect();
+                (name.clone(), Arc::new(limits))
+            })
+            .collect();
+        let connections: HashMap<String, Arc<ConnectionLimit>> = config
+            .upstreams
+            .iter()
+            .filter_map(|(name, upstream)| {
+                let max = upstream.max_connections?;
+                // An unchanged limit keeps counting connections open
+                let limit = previous
+                    .connections
+                    .get(name)
+                    .filter(|limit| limit.max == max)
+                    .cloned()
+                    .unwrap_or_else(|| Arc::new(ConnectionLimit::new(max)));
+                Some((name.clone(), limit))
+            })
+            .collect();
+
+        let handler = |route: RouteConfig| {
+            let upstream = config.upstreams.get(&route.upstream)?;
+            let counter = counters[&route.upstream].clone();
+            let slots = limits[&route.upstream].clone();
+            let connection_limit = connections.get(&route.upstream).cloned();
+            let handler = ProxyHandler::new(upstream.clone(), route)
+                .with_counter(counter)
+                .with_limits(slots)
+                .with_connection_limit(connection_limit)
+                .with_draining(draining.clone())
+                .with_spool(spool.clone(), config.server.body_spool_max_bytes)
+                .with_error_log(stats.clone(), config.server.error_log.clone())
+                .with_traffic(traffic.clone());
+            Some(Arc::new(handler))
+        };
+
+        let routes = config.routes.iter().cloned().map(handler).collect();
+        let auth = config
+            .routes
+            .iter()
+            .map(|route| handler(auth_route(&route.path, route.auth_request.as_ref()?)))
+            .collect();
+        let server_auth = config
+            .server
+            .auth_request
+            .as_ref()
+            .and_then(|auth| handler(auth_route("/*", auth)));
+        let default = config.server.default_route().and_then(handler);
+        let order = route_order(&config.routes);
+        Self {
+            config,
+            routes,
+            order,
+            auth,
+            server_auth,
+            default,
+            counters,
+            limits,
+            connections,
+        }
+    }
+
+    /// Connection limit of an upstream, if it sets one
+    pub fn connection_limit(&self, upstream: &str) -> Option<&ConnectionLimit> {
+        self.connections.get(upstream).map(Arc::as_ref)
+    }
+
+    /// Configuration the handlers were built from
+    pub fn config(&self) -> &Arc<JanusConfig> {
+        &self.config
+    }
+
+    /// Indices of the routes in the order requests are matched against them
+    pub fn route_order(&self) -> &[usize] {
+        &self.order
+    }
+
+    /// Handler for the route at `index` in the configuration
+    pub fn route(&self, index: usize) -> Option<Arc<ProxyHandler>> {
+        self.routes.get(index).cloned().flatten()
+    }
+
+    /// Handler for the auth service of the route at `index`, if it has one
+    pub fn auth(&self, index: usize) -> Option<Arc<ProxyHandler>> {
+        self.auth.get(index).cloned().flatten()
+    }
+
+    /// Handler for the auth service every request is checked by, if
+    /// `server.auth_request` is set
+    pub fn server_auth(&self) -> Option<Arc<ProxyHandler>> {
+        self.server_auth.clone()
+    }
+
+    /// Handler for requests no route matches
+    pub fn default_route(&self) -> Option<Arc<ProxyHandler>> {
+        self.default.clone()
+    }
+
+    /// Select a backend once through every handler, reporting the first
+    /// that could not serve a request
+    ///
+    /// Upstreams in `draining`, and those whose every server is draining,
+    /// are skipped, since refusing them is intended.
+    pub fn probe(&self, draining: &HashSet<String>) -> Result<(), String> {
+        for (index, route) in self.config.routes.iter().enumerate() {
+            let Some(handler) = self.route(index) else {
+                return Err(format!(
+                    "Route '{}' has no upstream '{}'",
+                    route.path, route.upstream
+                ));
+            };
+            for handler in std::iter::once(handler).chain(self.auth(index)) {
+                probe_handler(&handler, draining)
+                    .map_err(|e| format!("Route '{}': {}", route.path, e))?;
+            }
+        }
+        if let Some(handler) = &self.server_auth {
+            probe_handler(handler, draining).map_err(|e| format!("Server auth: {}", e))?;
+        }
+        if let Some(handler) = &self.default {
+            probe_handler(handler, draining).map_err(|e| format!("Default upstream: {}", e))?;
+        }
+        Ok(())
+    }
+}
+
+fn probe_handler(handler: &ProxyHandler, draining: &HashSet<String>) -> Result<(), ProxyError> {
+    let servers = &handler.upstream.servers;
+    let every_server_draining = !servers.is_empty() && servers.iter().all(|s| s.drain);
+    if draining.contains(&handler.route.upstream) || every_server_draining {
+        return Ok(());
+    }
+    handler.select_backend(false).map(|_| ())
+}
+
+/// Route sending the auth subrequests for requests to `path`
+fn auth_route(path: &str, auth: &AuthRequestConfig) -> RouteConfig {
+    RouteConfig {
+        proxy_redirect: false,
+        ..RouteConfig::new(path, auth.upstream.clone()).timeout(auth.timeout_secs)
+    }
+}
+
+/// Why a request could not be proxied
+#[derive(Debug, thiserror::Error)]
+pub enum ProxyError {
+    /// The upstream has no server to send the request to
+    #[error("no backend available: {0}")]
+    NoBackend(&'static str),
+
+    /// The backend's host name did not resolve
+    #[error("could not resolve {backend}: {cause}")]
+    Resolve { backend: String, cause: String },
+
+    /// The backend refused or dropped the connection attempt
+    #[error("could not connect to {backend}: {cause}")]
+    Connect { backend: String, cause: String },
+
+    /// The backend did not answer within the upstream timeout
+    #[error("{backend} timed out")]
+    Timeout { backend: String },
+
+    /// The backend's response broke the HTTP protocol
+    #[error("protocol error from {backend}: {cause}")]
+    Protocol { backend: String, cause: String },
+
+    /// The request failed after connecting, before a response arrived
+    #[error("request to {backend} failed: {cause}")]
+    Upstream { backend: String, cause: String },
+
+    /// The response body could not be read
+    #[error("reading the response body from {backend} failed: {cause}")]
+    Body { backend: String, cause: String },
+
+    /// The request body could not be read from the client
+    #[error("reading the request body failed: {0}")]
+    ClientBody(String),
+
+    /// Spooling the request body would go over the spool's size limit
+    #[error("request body spool is full")]
+    SpoolFull,
+
+    /// The request body could not be spooled to disk
+    #[error("spooling the request body failed: {0}")]
+    Spool(String),
+}
+
+impl From<SpoolError> for ProxyError {
+    fn from(e: SpoolError) -> Self {
+        match e {
+            SpoolError::Client(cause) => ProxyError::ClientBody(cause),
+            SpoolError::Full => ProxyError::SpoolFull,
+            SpoolError::Io(e) => ProxyError::Spool(e.to_string()),
+        }
+    }
+}
+
+impl ProxyError {
+    /// Short name of the failure class, for logs and statistics
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ProxyError::NoBackend(_) => "no_backend",
+            ProxyError::Resolve { .. } => "resolve",
+            ProxyError::Connect { .. } => "connect",
+            ProxyError::Timeout { .. } => "timeout",
+            ProxyError::Protocol { .. } => "protocol",
+            ProxyError::Upstream { .. } => "upstream",
+            ProxyError::Body { .. } => "body",
+            ProxyError::ClientBody(_) => "client_body",
+            ProxyError::SpoolFull => "spool_full",
+            ProxyError::Spool(_) => "spool",
+        }
+    }
+
+    /// Status returned to the client
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ProxyError::NoBackend(_) | ProxyError::SpoolFull => StatusCode::SERVICE_UNAVAILABLE,
+            ProxyError::Spool(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            ProxyError::Timeout { .. } => StatusCode::GATEWAY_TIMEOUT,
+            ProxyError::ClientBody(_) => StatusCode::BAD_REQUEST,
+            _ => StatusCode::BAD_GATEWAY,
+        }
+    }
+
+    /// Whether the request can be sent again to another backend: it never
+    /// reached this one
+    fn is_retriable(&self) -> bool {
+        matches!(
+            self,
+            ProxyError::Resolve { .. } | ProxyError::Connect { .. }
+        )
+    }
+
+    /// Backend the request was sent to, if one was selected
+    pub fn backend(&self) -> Option<&str> {
+        match self {
+            ProxyError::Resolve { backend, .. }
+            | ProxyError::Connect { backend, .. }
+            | ProxyError::Timeout { backend }
+            | ProxyError::Protocol { backend, .. }
+            | ProxyError::Upstream { backend, .. }
+            | ProxyError::Body { backend, .. } => Some(backend),
+            ProxyError::NoBackend(_)
+            | ProxyError::ClientBody(_)
+            | ProxyError::SpoolFull
+            | ProxyError::Spool(_) => None,
+        }
+    }
+}
+
+/// Request as it is sent upstream, kept for retries
+struct Outgoing {
+    method: Method,
+    /// Path and query sent upstream, after any rewrite
+    path: String,
+    headers: HeaderMap,
+    /// The client request's extensions, which carry the original case of
+    /// its header names
+    extensions: hyper::http::Extensions,
+    /// Host the client addressed, for rewriting redirects to the backend
+    client_host: Option<String>,
+    /// Scheme the client connected with, for the same
+    client_scheme: &'static str,
+    /// The client's side of the connection, should the backend switch
+    /// protocols
+    upgrade: Option<OnUpgrade>,
+    body: OutgoingBody,
+}
+
+impl Outgoing {
+    /// Whether the request can be sent more than once
+    fn replayable(&self) -> bool {
+        self.upgrade.is_none() && matches!(self.body, OutgoingBody::Read(_))
+    }
+}
+
+enum OutgoingBody {
+    /// Relayed as it arrives, so it can be sent only once
+    Stream(Option<UploadBody>),
+    /// Read in full before sending
+    Read(SpooledBody),
+}
+
+/// Proxy handler for forwarding requests to upstream servers
+pub struct ProxyHandler {
+    upstream: UpstreamConfig,
+    route: RouteConfig,
+    counter: Arc<AtomicUsize>,
+    limits: Arc<Limits>,
+    connection_limit: Option<Arc<ConnectionLimit>>,
+    draining: Option<Arc<Mutex<HashSet<String>>>>,
+    error_log: Option<(Arc<RwLock<Stats>>, ErrorLogConfig)>,
+    /// Server statistics failures are counted in
+    traffic: Option<Arc<Traffic>>,
+    /// Shared spool usage and its size limit, for routes spooling bodies
+    spool: Option<(Arc<SpoolUsage>, u64)>,
+}
+
+impl ProxyHandler {
+    pub fn new(upstream: UpstreamConfig, route: RouteConfig) -> Self {
+        Self {
+            upstream,
+            route,
+            counter: Arc::default(),
+            limits: Arc::default(),
+            connection_limit: None,
+            draining: None,
+            error_log: None,
+            traffic: None,
+            spool: None,
+        }
+    }
+
+    /// Rate limit repeated upstream errors using the server's stats
+    pub fn with_error_log(mut self, stats: Arc<RwLock<Stats>>, settings: ErrorLogConfig) -> Self {
+        self.error_log = Some((stats, settings));
+        self
+    }
+
+    /// Count failures in the server's statistics
+    pub fn with_traffic(mut self, traffic: Arc<Traffic>) -> Self {
+        self.traffic = Some(traffic);
+        self
+    }
+
+    /// Select no backend while the upstream is in the draining set
+    pub fn with_draining(mut self, draining: Arc<Mutex<HashSet<String>>>) -> Self {
+        self.draining = Some(draining);
+        self
+    }
+
+    /// Count spooled request bodies against a limit shared with other
+    /// handlers
+    pub fn with_spool(mut self, usage: Arc<SpoolUsage>, max_bytes: u64) -> Self {
+        self.spool = Some((usage, max_bytes));
+        self
+    }
+
+    /// Share the round-robin position with other handlers for the upstream
+    pub fn with_counter(mut self, counter: Arc<AtomicUsize>) -> Self {
+        self.counter = counter;
+        self
+    }
+
+    /// Share request slots with other handlers for the upstream
+    fn with_limits(mut self, limits: Arc<Limits>) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Share connection slots with other handlers for the upstream
+    fn with_connection_limit(mut self, limit: Option<Arc<ConnectionLimit>>) -> Self {
+        self.connection_limit = limit;
+        self
+    }
+
+    /// Route this handler forwards for
+    pub fn route(&self) -> &RouteConfig {
+        &self.route
+    }
+
+    /// Seconds clients should wait before retrying when no backend is
+    /// available, if configured
+    pub fn retry_after_secs(&self) -> Option<u64> {
+        self.upstream.retry_after_secs
+    }
+
+    /// Forward request to upstream server, retrying on other backends as
+    /// the upstream allows
+    pub async fn forward(
+        &self,
+        req: Request<Incoming>,
+        _remote_addr: SocketAddr,
+    ) -> Result<Response<BoxBody<Bytes, Infallible>>, ProxyError> {
+        let start = Instant::now();
+        let result = self.send(req).await;
+        if let Some(ref traffic) = self.traffic {
+            let failed = match result {
+                Ok(ref response) => response.status().is_server_error(),
+                // The client's doing, so not the upstream's failure
+                Err(ProxyError::ClientBody(_)) => false,
+                Err(_) => true,
+            };
+            traffic.record_upstream(&self.route.upstream, start.elapsed(), failed);
+        }
+        result
+    }
+
+    /// Send a request to a backend, retrying on others as the upstream
+    /// allows
+    async fn send(
+        &self,
+        req: Request<Incoming>,
+    ) -> Result<Response<BoxBody<Bytes, Infallible>>, ProxyError> {
+        // Held across retries, which give up one connection before opening
+        // the next
+        let _connection = self.connection_slot().await?;
+        let (mut backend, mut slot) = self.backend().await?;
+
+        let result = match self.prepare(req).await {
+            Ok(mut outgoing) => {
+                // Retries share the route's upstream timeout
+                let deadline = Instant::now() + self.route.upstream_timeout();
+                let mut retries = if outgoing.replayable() {
+                    self.upstream.retries
+                } else {
+                    0
+                };
+                loop {
+                    let result = self.exchange(&mut outgoing, backend, deadline).await;
+                    if retries == 0 || Instant::now() >= deadline {
+                        break result;
+                    }
+                    match result {
+                        Ok(ref response)
+                            if self
+                                .upstream
+                                .retry_codes
+                                .contains(&response.status().as_u16()) => {}
+                        Err(ref e) if e.is_retriable() => {}
+                        _ => break result,
+                    }
+                    retries -= 1;
+                    // Free this backend's slot before taking another
+                    slot = None;
+                    let Ok(next) = self.backend().await else {
+                        // Nowhere left to send it, so the last answer stands
+                        break result;
+                    };
+                    match result {
+                        Ok(response) => info!(
+                            "Retrying request for upstream '{}' after {} from {}",
+                            self.route.upstream,
+                            response.status().as_u16(),
+                            backend
+                        ),
+                        Err(e) => {
+                            self.count_error(&e);
+                            self.log_error(&format!("[{}] {}", e.kind(), e)).await;
+                            info!(
+                                "Retrying request for upstream '{}' after {} error",
+                                self.route.upstream,
+                                e.kind()
+                            );
+                        }
+                    }
+                    if let Some(ref traffic) = self.traffic {
+                        traffic.record_retry(&self.route.upstream);
+                    }
+                    (backend, slot) = next;
+                }
+            }
+            Err(e) => Err(e),
+        };
+        drop(slot);
+
+        match result {
+            Ok(mut response) => {
+                self.clear_errors().await;
+                response
+                    .extensions_mut()
+                    .insert(Backend(backend.to_string()));
+                Ok(response)
+            }
+            Err(e) => {
+                self.count_error(&e);
+                if let ProxyError::ClientBody(_) = e {
+                    // The client's doing, so not an upstream error
+                    debug!(
+                        "Request for upstream '{}' failed: {}",
+                        self.route.upstream, e
+                    );
+                } else {
+                    self.log_error(&format!("[{}] {}", e.kind(), e)).await;
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Send a bodiless GET for `path` with the given headers, returning the
+    /// response in full
+    pub async fn subrequest(
+        &self,
+        path: &str,
+        headers: HeaderMap,
+    ) -> Result<Response<Bytes>, ProxyError> {
+        let _connection = self.connection_slot().await?;
+        let (backend, _slot) = self.backend().await?;
+
+        let mut headers = headers;
+        apply_headers(&mut headers, &self.upstream.headers);
+        let mut builder = Request::get(format!("http://{}{}", backend, path));
+        if let Some(map) = builder.headers_mut() {
+            *map = headers;
+        }
+        let host = backend.split(':').next().unwrap_or(backend);
+        let req = builder
+            .header(hyper::header::HOST, host)
+            .body(Full::new(Bytes::new()))
+            .map_err(|e| ProxyError::Upstream {
+                backend: backend.to_string(),
+                cause: e.to_string(),
+            })?;
+
+        let client =
+            hyper_util::client::legacy::Client::builder(hyper_util::rt::TokioExecutor::new())
+                .http1_max_buf_size(
+                    self.upstream
+                        .max_response_header_bytes
+                        .max(MIN_RESPONSE_HEADER_BYTES),
+                )
+                .build_http();
+        let exchange = async {
+            let response = client
+                .request(req)
+                .await
+                .map_err(|e| request_error(backend, &e))?;
+            let (parts, body) = response.into_parts();
+            let body = body
+                .collect()
+                .await
+                .map_err(|e| ProxyError::Body {
+                    backend: backend.to_string(),
+                    cause: e.to_string(),
+                })?
+                .to_bytes();
+            Ok(Response::from_parts(parts, body))
+        };
+
+        let result = match tokio::time::timeout(
+            self.upstream.attempt_timeout(&self.route),
+            exchange,
+        )
+        .await
+        {
+            Ok(result) => result,
+            Err(_) => Err(ProxyError::Timeout {
+                backend: backend.to_string(),
+            }),
+        };
+        match result {
+            Ok(response) => {
+                self.clear_errors().await;
+                Ok(response)
+            }
+            Err(e) => {
+                self.count_error(&e);
+                self.log_error(&format!("[{}] {}", e.kind(), e)).await;
+                Err(e)
+            }
+        }
+    }
+
+    /// Select the backend for a request, unless the upstream is draining,
+    /// with one of its request slots if it limits them
+    async fn backend(&self) -> Result<(&str, Option<OwnedSemaphorePermit>), ProxyError> {
+        let draining = match self.draining {
+            Some(ref draining) => draining.lock().await.contains(&self.route.upstream),
+            None => false,
+        };
+
+        let result = self
+            .select_backend(draining)
+            .and_then(|selected| self.reserve(selected));
+        if let Err(ProxyError::NoBackend(reason)) = result {
+            self.log_error(&format!("has no available backends: {}", reason))
+                .await;
+        }
+        if let Err(ref e) = result {
+            self.count_error(e);
+        }
+        result
+    }
+
+    /// Wait up to `queue_timeout_secs` for a connection slot, if the
+    /// upstream limits its connections
+    async fn connection_slot(&self) -> Result<Option<OwnedSemaphorePermit>, ProxyError> {
+        let Some(ref limit) = self.connection_limit else {
+            return Ok(None);
+        };
+        // Polled once before timing out, so a free slot is taken even
+        // without waiting
+        let wait = Duration::from_secs(self.upstream.queue_timeout_secs);
+        match tokio::time::timeout(wait, limit.slots.clone().acquire_owned()).await {
+            Ok(Ok(permit)) => {
+                limit.peak.fetch_max(limit.in_use(), Ordering::Relaxed);
+                Ok(Some(permit))
+            }
+            _ => {
+                let reason = "every connection is in use";
+                self.log_error(&format!("has no available backends: {}", reason))
+                    .await;
+                let e = ProxyError::NoBackend(reason);
+                self.count_error(&e);
+                Err(e)
+            }
+        }
+    }
+
+    /// Work out what to send upstream, reading the body unless the route
+    /// streams it
+    async fn prepare(&self, mut req: Request<Incoming>) -> Result<Outgoing, ProxyError> {
+        // The client's side of the connection, should the backend switch
+        // protocols
+        let upgrade = is_upgrade_request(req.headers()).then(|| hyper::upgrade::on(&mut req));
+
+        // Apply path rewrite if configured
+        let path = req.uri().path();
+        let mut upstream_path = if let Some(ref rewrite) = self.route.rewrite {
+            apply_rewrite(path, &self.route.path, rewrite)
+        } else if self.route.strip_prefix {
+            strip_route_prefix(path, self.route.prefix())
+        } else {
+            path.to_string()
+        };
+        if let Some(query) = req.uri().query() {
+            upstream_path = format!("{}?{}", upstream_path, query);
+        }
+
+        // Host the client addressed, for rewriting redirects to the backend
+        let client_host = req
+            .headers()
+            .get(hyper::header::HOST)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string);
+
+        // Copy headers (except host), every value of a repeated header in
+        // the order received
+        let mut headers = req.headers().clone();
+        headers.remove(hyper::header::HOST);
+
+        // Then the upstream's configured headers, and the route's, which
+        // can replace or add to the upstream's
+        apply_headers(&mut headers, &self.upstream.headers);
+        apply_headers(&mut headers, &self.route.headers);
+        let extensions = std::mem::take(req.extensions_mut());
+        let client_scheme = match extensions.get::<Connection>() {
+            Some(connection) if connection.is_tls => "https",
+            _ => "http",
+        };
+
+        let method = req.method().clone();
+
+        // Stream the body through, or read it all before sending, into
+        // memory or a spool file
+        let body = if self.route.stream_request_body {
+            OutgoingBody::Stream(Some(req.into_body().map_err(Into::into).boxed()))
+        } else if let (Some(settings), Some((usage, max_bytes))) =
+            (&self.route.body_spool, &self.spool)
+        {
+            OutgoingBody::Read(
+                spool::read_body(req.into_body(), settings, usage, *max_bytes).await?,
+            )
+        } else {
+            let body_bytes = req
+                .collect()
+                .await
+                .map_err(|e| ProxyError::ClientBody(e.to_string()))?
+                .to_bytes();
+            OutgoingBody::Read(SpooledBody::Memory(body_bytes))
+        };
+
+        Ok(Outgoing {
+            method,
+            path: upstream_path,
+            headers,
+            extensions,
+            client_host,
+            client_scheme,
+            upgrade,
+            body,
+        })
+    }
+
+    /// Send a request to a backend and read its response, giving up at
+    /// the attempt timeout or the deadline, whichever comes first
+    async fn exchange(
+        &self,
+        outgoing: &mut Outgoing,
+        backend: &str,
+        deadline: Instant,
+    ) -> Result<Response<BoxBody<Bytes, Infallible>>, ProxyError> {
+        let upstream_url = format!("http://{}{}", backend, outgoing.path);
+        debug!("Proxying to {}", upstream_url);
+
+        // Build request to upstream
+        let mut builder = Request::builder()
+            .method(outgoing.method.clone())
+            .uri(&upstream_url);
+        if let Some(headers) = builder.headers_mut() {
+            *headers = outgoing.headers.clone();
+        }
+        if let Some(extensions) = builder.extensions_mut() {
+            *extensions = outgoing.extensions.clone();
+        }
+
+        // Set host header to upstream
+        let host = backend.split(':').next().unwrap_or(backend);
+        builder = builder.header(hyper::header::HOST, host);
+
+        let body = match outgoing.body {
+            OutgoingBody::Stream(ref mut body) => body.take().ok_or_else(|| {
+                ProxyError::ClientBody("streamed body was already sent".to_string())
+            })?,
+            OutgoingBody::Read(ref body) => body
+                .upload()
+                .await
+                .map_err(|e| ProxyError::Spool(e.to_string()))?,
+        };
+        let upstream_req = builder.body(body).map_err(|e| ProxyError::Upstream {
+            backend: backend.to_string(),
+            cause: e.to_string(),
+        })?;
+        let upgrade = outgoing.upgrade.take();
+        let upgrading = upgrade.is_some();
+
+        // Create HTTP client and send request; the read buffer bounds the
+        // response header block
+        let client =
+            hyper_util::client::legacy::Client::builder(hyper_util::rt::TokioExecutor::new())
+                .http1_max_buf_size(
+                    self.upstream
+                        .max_response_header_bytes
+                        .max(MIN_RESPONSE_HEADER_BYTES),
+                )
+                .build_http();
+
+        // The attempt timeout covers sending the request through to the
+        // last byte of the response body
+        let exchange = async {
+            let mut response = client
+                .request(upstream_req)
+                .await
+                .map_err(|e| request_error(backend, &e))?;
+            if let (Some(client_upgrade), StatusCode::SWITCHING_PROTOCOLS) =
+                (upgrade, response.status())
+            {
+                self.relay_upgrade(client_upgrade, hyper::upgrade::on(&mut response), backend);
+                let (parts, _) = response.into_parts();
+                return Ok::<_, ProxyError>((parts.status, parts.headers, Bytes::new()));
+            }
+            let (parts, body) = response.into_parts();
+            let body_bytes = body
+                .collect()
+                .await
+                .map_err(|e| ProxyError::Body {
+                    backend: backend.to_string(),
+                    cause: e.to_string(),
+                })?
+                .to_bytes();
+            Ok((parts.status, parts.headers, body_bytes))
+        };
+
+        let timeout = self
+            .upstream
+            .attempt_timeout(&self.route)
+            .min(deadline.saturating_duration_since(Instant::now()));
+        let (status, mut headers, body_bytes) = match tokio::time::timeout(timeout, exchange).await
+        {
+            Ok(result) => result?,
+            Err(_) => {
+                return Err(ProxyError::Timeout {
+                    backend: backend.to_string(),
+                })
+            }
+        };
+
+        // Other 1xx responses are skipped by the client, but a switch of
+        // protocols cannot be relayed without an upgrade
+        if status == StatusCode::SWITCHING_PROTOCOLS && !upgrading {
+            return Err(ProxyError::Protocol {
+                backend: backend.to_string(),
+                cause: "switched protocols without an upgrade request".to_string(),
+            });
+        }
+
+        if let (true, Some(client_host)) = (self.route.proxy_redirect, &outgoing.client_host) {
+            rewrite_locations(&mut headers, backend, outgoing.client_scheme, client_host);
+        }
+
+        self.edit_response_headers(&mut headers);
+
+        // A header's name comes only with its first value, so it is kept
+        // for the values that follow
+        let mut builder = Response::builder().status(status);
+        let mut current = None;
+        for (name, value) in headers {
+            if name.is_some() {
+                current = name;
+            }
+            if let Some(ref name) = current {
+                builder = builder.header(name, value);
+            }
+        }
+
+        Ok(builder
+            .body(
+                Full::new(body_bytes)
+                    .map_err(|_: Infallible| unreachable!())
+                    .boxed(),
+            )
+            .unwrap())
+    }
+
+    /// Copy bytes both ways between the upgraded client and backend
+    /// connections until either side closes, or the route's WebSocket
+    /// timeout ends the session
+    fn relay_upgrade(&self, client: OnUpgrade, upstream: OnUpgrade, backend: &str) {
+        let limit = self.route.websocket_timeout;
+        let backend = backend.to_string();
+        tokio::spawn(async move {
+            let (client, upstream) = match tokio::try_join!(client, upstream) {
+                Ok(upgraded) => upgraded,
+                Err(e) => {
+                    debug!("Upgrade with {} failed: {}", backend, e);
+                    return;
+                }
+            };
+            let mut client = TokioIo::new(client);
+            let mut upstream = TokioIo::new(upstream);
+            let relay = tokio::io::copy_bidirectional(&mut client, &mut upstream);
+            match limit {
+                Some(secs) => {
+                    if tokio::time::timeout(Duration::from_secs(secs), relay)
+                        .await
+                        .is_err()
+                    {
+                        warn!(
+                            "WebSocket session to upstream timed out after {} seconds",
+                            secs
+                        );
+                    }
+                }
+                None => {
+                    if let Err(e) = relay.await {
+                        debug!("Upgraded connection to {} closed: {}", backend, e);
+                    }
+                }
+            }
+        });
+    }
+
+    /// Remove and add the route's configured response headers
+    fn edit_response_headers(&self, headers: &mut HeaderMap) {
+        for name in &self.route.remove_response_headers {
+            match HeaderName::from_bytes(name.as_bytes()) {
+                Ok(name) => {
+                    headers.remove(name);
+                }
+                Err(_) => warn!("Skipping invalid header name '{}'", name),
+            }
+        }
+        for (name, value) in &self.route.add_response_headers {
+            match (
+                HeaderName::from_bytes(name.as_bytes()),
+                HeaderValue::from_str(value),
+            ) {
+                (Ok(name), Ok(value)) => {
+                    headers.insert(name, value);
+                }
+                _ => warn!("Skipping invalid header '{}: {}'", name, value),
+            }
+        }
+    }
+
+    /// Log an upstream error, unless it has been repeating
+    async fn log_error(&self, message: &str) {
+        let scope = format!("upstream '{}'", self.route.upstream);
+        match self.error_log {
+            Some((ref stats, ref settings)) => {
+                let line = stats
+                    .write()
+                    .await
+                    .error_log
+                    .record(&scope, message, settings);
+                if let Some(line) = line {
+                    error!("{}", line);
+                }
+            }
+            None => error!("{} {}", scope, message),
+        }
+    }
+
+    /// Count a failure by its class, and protocol errors by upstream
+    fn count_error(&self, error: &ProxyError) {
+        let Some(ref traffic) = self.traffic else {
+            return;
+        };
+        traffic.proxy_errors.increment(error.kind());
+        let per_upstream = match error {
+            ProxyError::Protocol { .. } => &traffic.upstream_protocol_errors,
+            ProxyError::NoBackend(_) => &traffic.no_backend_available,
+            _ => return,
+        };
+        per_upstream.increment(&self.route.upstream);
+    }
+
+    /// Reset error suppression once the upstream answers again
+    async fn clear_errors(&self) {
+        if let Some((ref stats, _)) = self.error_log {
+            // Only take the write lock when there are errors to clear
+            if stats.read().await.error_log.is_empty() {
+                return;
+            }
+            let scope = format!("upstream '{}'", self.route.upstream);
+            for summary in stats.write().await.error_log.clear(&scope) {
+                info!("{}", summary);
+            }
+        }
+    }
+
+    /// Take a request slot on the selected backend, or failing that the
+    /// first server after it, of the same kind, with one free
+    fn reserve<'a>(
+        &'a self,
+        selected: &'a str,
+    ) -> Result<(&'a str, Option<OwnedSemaphorePermit>), ProxyError> {
+        let servers = &self.upstream.servers;
+        let Some(start) = servers.iter().position(|s| s.address == selected) else {
+            return Ok((selected, None));
+        };
+        let backup = servers[start].backup;
+        let candidates = servers[start..]
+            .iter()
+            .chain(&servers[..start])
+            .filter(|s| s.backup == backup && !s.drain);
+        for server in candidates {
+            let Some((_, slots)) = self.limits.get(&server.address) else {
+                return Ok((&server.address, None));
+            };
+            if let Ok(permit) = slots.clone().try_acquire_owned() {
+                return Ok((&server.address, Some(permit)));
+            }
+        }
+        Err(ProxyError::NoBackend("every backend is at capacity"))
+    }
+
+    /// Select a backend server based on load balancing strategy
+    pub fn select_backend(&self, draining: bool) -> Result<&str, ProxyError> {
+        if draining {
+            return Err(ProxyError::NoBackend("upstream is draining"));
+        }
+
+        // Draining servers finish what they have but get nothing new
+        let available = self.upstream.servers.iter().filter(|s| !s.drain);
+        let servers: Vec<_> = available.clone().filter(|s| !s.backup).collect();
+
+        if servers.is_empty() {
+            // Fall back to backup servers
+            let backups: Vec<_> = available.filter(|s| s.backup).collect();
+
+            if backups.is_empty() {
+                return Err(ProxyError::NoBackend(if self.upstream.servers.is_empty() {
+                    "no servers configured"
+                } else {
+                    "every server is draining"
+                }));
+            }
+
+            return Ok(&backups[0].address);
+        }
+
+        let server = match self.upstream.load_balancing {
+            LoadBalancing::RoundRobin => {
+                let idx = self.counter.fetch_add(1, Ordering::Relaxed) % servers.len();
+                &servers[idx].address
+            }
+            LoadBalancing::Random => {
+                use std::time::{SystemTime, UNIX_EPOCH};
+                let seed = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap()
+                    .subsec_nanos() as usize;
+                let idx = seed % servers.len();
+                &servers[idx].address
+            }
+            LoadBalancing::LeastConnections | LoadBalancing::IpHash => {
+                // For simplicity, fall back to round-robin for these strategies
+                let idx = self.counter.fetch_add(1, Ordering::Relaxed) % servers.len();
+                &servers[idx].address
+            }
+        };
+
+        Ok(server)
+    }
+}
+
+/// Classify a failure to get a response from a backend
+fn request_error(backend: &str, err: &hyper_util::client::legacy::Error) -> ProxyError {
+    let backend = backend.to_string();
+    if err.is_connect() {
+        // The connector's errors are opaque, but name the failing step
+        let cause =
+            std::error::Error::source(err).map_or_else(|| err.to_string(), |e| e.to_string());
+        return if cause.starts_with("dns error") {
+            ProxyError::Resolve { backend, cause }
+        } else {
+            ProxyError::Connect { backend, cause }
+        };
+    }
+    match malformed_response(err) {
+        Some(cause) => ProxyError::Protocol { backend, cause },
+        None => ProxyError::Upstream {
+            backend,
+            cause: err.to_string(),
+        },
+    }
+}
+
+/// Describe why an upstream response could not be parsed, if that is what
+/// failed
+fn malformed_response(err: &(dyn std::error::Error + 'static)) -> Option<String> {
+    let mut source = Some(err);
+    let err = std::iter::from_fn(|| {
+        let current = source?;
+        source = current.source();
+        Some(current)
+    })
+    .find_map(|e| e.downcast_ref::<hyper::Error>())?;
+    let cause = if err.is_parse_too_large() {
+        "response headers too large"
+    } else if err.is_parse_status() {
+        "invalid status line"
+    } else if err.is_parse() {
+        "malformed response headers"
+    } else {
+        return None;
+    };
+    Some(format!("{} ({})", cause, err))
+}
+
+/// Add configured headers to a request: a value starting with `+` is sent
+/// after any the request already has, and any other replaces them
+fn apply_headers(headers: &mut HeaderMap, configured: &HashMap<String, String>) {
+    for (name, value) in configured {
+        let (value, append) = match value.strip_prefix('+') {
+            Some(value) => (value, true),
+            None => (value.as_str(), false),
+        };
+        match (
+            HeaderName::from_bytes(name.as_bytes()),
+            HeaderValue::from_str(value),
+        ) {
+            (Ok(name), Ok(value)) if append => {
+                headers.append(name, value);
+            }
+            (Ok(name), Ok(value)) => {
+                headers.insert(name, value);
+            }
+            _ => warn!("Skipping invalid header '{}: {}'", name, value),
+        }
+    }
+}
+
+/// Point `Location` and `Content-Location` headers that name the backend at
+/// the scheme and host the client used instead
+fn rewrite_locations(headers: &mut HeaderMap, backend: &str, scheme: &str, client_host: &str) {
+    for name in [hyper::header::LOCATION, hyper::header::CONTENT_LOCATION] {
+        let rewritten = headers
+            .get(&name)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|location| rewrite_location(location, backend, scheme, client_host))
+            .and_then(|location| HeaderValue::from_str(&location).ok());
+        if let Some(location) = rewritten {
+            headers.insert(name, location);
+        }
+    }
+}
+
+/// Whether a request asks to switch protocols, as a WebSocket handshake
+/// does
+fn is_upgrade_request(headers: &HeaderMap) -> bool {
+    let connection_upgrade = headers
+        .get_all(hyper::header::CONNECTION)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|token| token.trim().eq_ignore_ascii_case("upgrade"));
+    connection_upgrade && headers.contains_key(hyper::header::UPGRADE)
+}
+
+/// Rewrite an absolute URL on the backend to the client's scheme and host,
+/// keeping the path and query; relative URLs and other hosts are left alone
+fn rewrite_location(
+    location: &str,
+    backend: &str,
+    client_scheme: &str,
+    client_host: &str,
+) -> Option<String> {
+    let (scheme, rest) = location.split_once("://")?;
+    let default_port = match scheme.to_ascii_lowercase().as_str() {
+        "http" => "80",
+        "https" => "443",
+        _ => return None,
+    };
+    let end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+    let (authority, tail) = rest.split_at(end);
+
+    let with_port = |host: &str| {
+        if host.contains(':') {
+            host.to_ascii_lowercase()
+        } else {
+            format!("{}:{}", host.to_ascii_lowercase(), default_port)
+        }
+    };
+    if with_port(authority) != with_port(backend) {
+        return None;
+    }
+
+    // The backend's scheme says nothing about how the client connected
+    Some(format!("{}://{}{}", client_scheme, client_host, tail))
+}
+
+/// Remove a route prefix from a path, keeping it absolute
+fn strip_route_prefix(path: &str, prefix: &str) -> String {
+    let rest = path
+        .strip_prefix(prefix.trim_end_matches('/'))
+        .unwrap_or(path);
+    if rest.starts_with('/') {
+        rest.to_string()
+    } else {
+        format!("/{}", rest)
+    }
+}
+
+/// Apply path rewrite rule
+fn apply_rewrite(path: &str, pattern: &str, rewrite: &str) -> String {
+    // Simple rewrite: remove the matched prefix and prepend the rewrite prefix
+    let prefix = pattern.trim_end_matches("/*").trim_end_matches('*');
+    let suffix = path.strip_prefix(prefix).unwrap_or(path);
+    format!("{}{}", rewrite.trim_end_matches('/'), suffix)
+}
+
+/// Order routes are tried in: config order, except that a route matching on
+/// the query moves ahead of the first route with the same path that does not
+fn route_order(routes: &[RouteConfig]) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..routes.len()).collect();
+    order.sort_by_key(|&i| {
+        let route = &routes[i];
+        if route.query_match.is_empty() {
+            return (i, true);
+        }
+        let plain = routes[..i]
+            .iter()
+            .position(|other| other.path == route.path && other.query_match.is_empty());
+        (plain.unwrap_or(i), false)
+    });
+    order
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_query_routes_go_before_plain_routes_with_their_path() {
+        let routes = [
+            RouteConfig::new("/api/*", "v1"),
+            RouteConfig::new("/health", "app"),
+            RouteConfig::new("/api/*", "v2").match_query("version", "2"),
+            RouteConfig::new("/api/*", "v3").match_query("version", "3"),
+            RouteConfig::new("/other/*", "v2").match_query("version", "2"),
+        ];
+        assert_eq!(route_order(&routes), [2, 3, 0, 1, 4]);
+    }
+
+    #[test]
+    fn test_error_status_by_class() {
+        let backend = "app:80".to_string();
+        let cause = String::new();
+        let cases = [
+            (ProxyError::NoBackend("draining"), 503),
+            (
+                ProxyError::Resolve {
+                    backend: backend.clone(),
+                    cause: cause.clone(),
+                },
+                502,
+            ),
+            (
+                ProxyError::Connect {
+                    backend: backend.clone(),
+                    cause: cause.clone(),
+                },
+                502,
+            ),
+            (
+                ProxyError::Timeout {
+                    backend: backend.clone(),
+                },
+                504,
+            ),
+            (ProxyError::Protocol { backend, cause }, 502),
+            (ProxyError::ClientBody(String::new()), 400),
+        ];
+        for (error, status) in cases {
+            assert_eq!(error.status().as_u16(), status, "{}", error.kind());
+        }
+    }
+
+    #[test]
+    fn test_strip_route_prefix() {
+        assert_eq!(strip_route_prefix("/api/users", "/api/"), "/users");
+        assert_eq!(strip_route_prefix("/api", "/api/"), "/");
+        assert_eq!(strip_route_prefix("/api/", "/api/"), "/");
+        assert_eq!(strip_route_prefix("/v1/api/x", "/v1/api"), "/x");
+    }
+
+    #[test]
+    fn test_rewrite_location() {
+        let rewrite = |location| rewrite_location(location, "10.0.0.5:8080", "http", "example.com");
+        assert_eq!(
+            rewrite("http://10.0.0.5:8080/login?next=/a").as_deref(),
+            Some("http://example.com/login?next=/a")
+        );
+        assert_eq!(
+            rewrite("http://10.0.0.5:8080").as_deref(),
+            Some("http://example.com")
+        );
+        assert_eq!(rewrite("/login"), None);
+        assert_eq!(rewrite("http://other.example/login"), None);
+        assert_eq!(rewrite("http://10.0.0.5:9090/login"), None);
+
+        // Clients on the HTTPS listener are sent back to it
+        assert_eq!(
+            rewrite_location(
+                "http://10.0.0.5:8080/login",
+                "10.0.0.5:8080",
+                "https",
+                "example.com"
+            )
+            .as_deref(),
+            Some("https://example.com/login")
+        );
+    }
+
+    #[test]
+    fn test_rewrite_location_default_port() {
+        assert_eq!(
+            rewrite_location(
+                "http://app.internal/x",
+                "app.internal:80",
+                "http",
+                "example.com:8443"
+            )
+            .as_deref(),
+            Some("http://example.com:8443/x")
+        );
+        assert_eq!(
+            rewrite_location(
+                "https://app.internal/x",
+                "app.internal:80",
+                "http",
+                "example.com"
+            ),
+            None
+        );
+    }
+}
@@ -0,0 +1,265 @@
+//! Configuration live reload using file watcher
+
+use crate::{audit, wait_for_shutdown, AppState};
+use anyhow::Result;
+use janus_common::{AuditSource, JanusConfigFile, ServerMessage, WatchMode};
+use notify::{Config, Event, RecommendedWatcher, RecursiveMode, Watcher};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+use tracing::{debug, error, info, warn};
+
+/// Quiet time after the last file event before reloading
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// Wait for an event and then for `quiet` to pass without another,
+/// returning `None` once the watcher is gone
+async fn settled(rx: &mut mpsc::Receiver<()>, quiet: Duration) -> Option<()> {
+    rx.recv().await?;
+    let timer = tokio::time::sleep(quiet);
+    tokio::pin!(timer);
+    loop {
+        tokio::select! {
+            () = &mut timer => return Some(()),
+            event = rx.recv() => {
+                event?;
+                timer.as_mut().reset(Instant::now() + quiet);
+            }
+        }
+    }
+}
+
+/// Watch configuration file for changes and reload automatically
+pub async fn watch_config(state: Arc<AppState>) -> Result<()> {
+    let Some(config_path) = state.config_path.clone() else {
+        debug!("No config file, skipping file watcher");
+        return Ok(());
+    };
+
+    if !config_path.exists() {
+        warn!("Config file does not exist, skipping file watcher");
+        return Ok(());
+    }
+
+    let (tx, mut rx) = mpsc::channel(100);
+    let (mode, poll_interval) = {
+        let server = &state.config.load().server;
+        (
+            server.config_watch_mode,
+            Duration::from_millis(server.poll_interval_ms),
+        )
+    };
+
+    // Events stop once the watcher is dropped, at the end of this function
+    let _watcher = match mode {
+        WatchMode::Inotify => Some(watch_events(&config_path, tx)?),
+        WatchMode::Poll => {
+            tokio::spawn(poll_changes(config_path.clone(), poll_interval, tx));
+            None
+        }
+        WatchMode::Auto => match watch_events(&config_path, tx.clone()) {
+            Ok(watcher) => Some(watcher),
+            Err(e) => {
+                warn!("File events unavailable ({}), polling instead", e);
+                tokio::spawn(poll_changes(config_path.clone(), poll_interval, tx));
+                None
+            }
+        },
+    };
+
+    info!("Watching {} for changes", config_path.display());
+
+    let mut shutdown = state.shutdown.subscribe();
+
+    loop {
+        // Editors and saves write in several steps, so wait for them to stop
+        tokio::select! {
+            settled = settled(&mut rx, DEBOUNCE) => if settled.is_none() { break },
+            _ = wait_for_shutdown(&mut shutdown) => break,
+        }
+
+        let _ = reload_audited(&state).await;
+    }
+
+    Ok(())
+}
+
+/// Send on `tx` when file system events report a change to the config
+/// file's directory
+fn watch_events(config_path: &Path, tx: mpsc::Sender<()>) -> Result<RecommendedWatcher> {
+    let mut watcher = RecommendedWatcher::new(
+        move |res: Result<Event, notify::Error>| {
+            if let Ok(event) = res {
+                if event.kind.is_modify() || event.kind.is_create() {
+                    let _ = tx.blocking_send(());
+                }
+            }
+        },
+        Config::default(),
+    )?;
+
+    // Watch the config file's parent directory
+    let watch_path = config_path.parent().unwrap_or(config_path);
+    watcher.watch(watch_path, RecursiveMode::NonRecursive)?;
+    Ok(watcher)
+}
+
+/// Send on `tx` whenever the config file's modification time or size has
+/// changed since the last check, until the receiver is gone
+async fn poll_changes(config_path: PathBuf, interval: Duration, tx: mpsc::Sender<()>) {
+    let mut last = file_stamp(&config_path).await;
+    let mut ticks = tokio::time::interval(interval);
+    ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        tokio::select! {
+            _ = ticks.tick() => {}
+            () = tx.closed() => return,
+        }
+        // A file briefly missing while it is replaced is not a change
+        let Some(current) = file_stamp(&config_path).await else {
+            continue;
+        };
+        if last != Some(current) {
+            last = Some(current);
+            if tx.send(()).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// Modification time and size of a file, if it can be read
+async fn file_stamp(path: &Path) -> Option<(SystemTime, u64)> {
+    let metadata = tokio::fs::metadata(path).await.ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+/// Reload configuration from file, auditing it under the settings in force
+/// before the reload, for reloads not asked for by a management client
+///
+/// Saves made by the management API come back through the file watcher
+/// unchanged and are audited where they were made, so only reloads that
+/// failed or changed something are recorded.
+pub async fn reload_audited(state: &Arc<AppState>) -> Result<()> {
+    let before = state.config.load_full();
+    let audit_path = before.management.audit_log.clone();
+    let mut entry = audit::entry(
+        AuditSource::File,
+        "ReloadConfig",
+        state
+            .config_path
+            .as_ref()
+            .map_or_else(String::new, |path| path.display().to_string()),
+    );
+    let result = reload_config(state).await;
+    match result {
+        Ok(()) => {
+            info!("Configuration reloaded successfully");
+            entry.success = true;
+        }
+        Err(ref e) => {
+            error!("Failed to reload configuration: {}", e);
+            entry.error = Some(e.to_string());
+        }
+    }
+    let changed = entry.error.is_some() || **state.config.load() != *before;
+    if let (true, Some(path)) = (changed, audit_path) {
+        state.audit.record(&path, &entry).await;
+    }
+    result
+}
+
+/// Reload configuration from file
+pub async fn reload_config(state: &Arc<AppState>) -> Result<()> {
+    let Some(ref config_path) = state.config_path else {
+        anyhow::bail!("No configuration file to reload from");
+    };
+
+    // Read the file inside a transaction, so a change being saved from the
+    // management API lands before it is read
+    let mut tx = state.transaction().await;
+    let loaded = JanusConfigFile::load(config_path)
+        .and_then(|file| file.resolve(state.profile.as_deref()))
+        .and_then(|config| janus_common::validate_config(&config).map(|()| config));
+
+    // A file that fails to load leaves the running config out of sync with it
+    state.config_drift.store(loaded.is_err(), Ordering::Relaxed);
+    tx.config = loaded?;
+    let config_hash = tx.config.config_hash();
+
+    // Validation cannot catch everything, so try the new handlers once and
+    // keep the running config if they fail
+    if let Err(problem) = tx.apply_checked().await {
+        state.config_drift.store(true, Ordering::Relaxed);
+        let message = format!("Reloaded configuration not applied: {}", problem);
+        let _ = state.events.send(ServerMessage::Error(message.clone()));
+        anyhow::bail!(message);
+    }
+
+    // No subscribers just means no management clients are connected
+    let _ = state.events.send(ServerMessage::ConfigReloaded);
+    let _ = state.events.send(ServerMessage::ConfigHash(config_hash));
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_settles_after_last_event() {
+        let (tx, mut rx) = mpsc::channel(10);
+        let quiet = Duration::from_millis(500);
+
+        // Events just inside the quiet time keep pushing the reload back
+        let events = tokio::spawn(async move {
+            for i in 0..5 {
+                if i > 0 {
+                    tokio::time::sleep(Duration::from_millis(499)).await;
+                }
+                tx.send(()).await.unwrap();
+            }
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            tx
+        });
+
+        let started = Instant::now();
+        assert_eq!(settled(&mut rx, quiet).await, Some(()));
+        assert_eq!(started.elapsed(), Duration::from_millis(4 * 499 + 500));
+
+        drop(events.await.unwrap());
+        assert_eq!(settled(&mut rx, quiet).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_polling_notices_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("janus.toml");
+        std::fs::write(&path, "# one").unwrap();
+
+        let (tx, mut rx) = mpsc::channel(10);
+        let poller = tokio::spawn(poll_changes(path.clone(), Duration::from_millis(10), tx));
+
+        // Nothing is sent for the file as it was at the start
+        let quiet = tokio::time::timeout(Duration::from_millis(100), rx.recv()).await;
+        assert!(quiet.is_err());
+
+        // The size changes even where timestamps are coarse
+        std::fs::write(&path, "# two, longer").unwrap();
+        tokio::time::timeout(Duration::from_secs(5), rx.recv())
+            .await
+            .expect("change not noticed")
+            .unwrap();
+
+        // Polling ends along with the watcher
+        drop(rx);
+        tokio::time::timeout(Duration::from_secs(5), poller)
+            .await
+            .expect("poller still running")
+            .unwrap();
+    }
+}
@@ -0,0 +1,1792 @@
+//! HTTP Server implementation
+
+use crate::maintenance::maintenance_response;
+use crate::middleware::{Connection, RequestId, REQUEST_ID_HEADER};
+use crate::proxy::{Backend, ProxyError, ProxyHandler, ProxyTable};
+use crate::status_page::{is_status_page, status_page_response};
+use crate::{auth, cache, health, stats, wait_for_shutdown, AppState};
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use futures::stream::{self, StreamExt};
+use http_body_util::{combinators::BoxBody, BodyExt, Full, StreamBody};
+use hyper::body::{Body, Frame, Incoming, SizeHint};
+use hyper::header::{HeaderName, HeaderValue, ALLOW, CONTENT_LENGTH, LOCATION, RETRY_AFTER};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{HeaderMap, Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use janus_common::config::MIN_REQUEST_HEADER_BYTES;
+use janus_common::{JanusConfig, MatchOrder, MethodMismatch, ServerMessage, SocketOptions};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::fmt;
+use std::hash::BuildHasher;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Component, Path, PathBuf};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{ready, Poll};
+use std::time::{Duration, Instant};
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+use tokio::task::AbortHandle;
+use tokio_rustls::TlsAcceptor;
+use tracing::{debug, info, warn};
+
+/// Bind the HTTP listener with the configured socket options
+pub async fn bind_listener(
+    bind_address: &str,
+    port: u16,
+    options: &SocketOptions,
+) -> Result<TcpListener> {
+    let addr = tokio::net::lookup_host((bind_address, port))
+        .await?
+        .next()
+        .with_context(|| format!("No address found for {}", bind_address))?;
+
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    socket.set_reuse_address(options.reuse_address)?;
+    if let Some(size) = options.recv_buffer_size {
+        socket.set_recv_buffer_size(size as usize)?;
+    }
+    if let Some(size) = options.send_buffer_size {
+        socket.set_send_buffer_size(size as usize)?;
+    }
+    socket.set_nonblocking(true)?;
+    socket
+        .bind(&addr.into())
+        .with_context(|| format!("Failed to bind {}", addr))?;
+    socket.listen(options.tcp_backlog.min(i32::MAX as u32) as i32)?;
+
+    Ok(TcpListener::from_std(socket.into())?)
+}
+
+/// Run the HTTP server until shutdown is requested
+///
+/// Also closes idle connections on every listener, HTTPS included.
+pub async fn run_server(state: Arc<AppState>, listener: TcpListener) -> Result<()> {
+    info!("HTTP server listening on http://{}", listener.local_addr()?);
+    tokio::spawn(close_idle_connections(state.clone()));
+    accept_connections(state, listener, None).await?;
+    info!("HTTP server stopped accepting connections");
+    Ok(())
+}
+
+/// Run the HTTPS server until shutdown is requested
+#[cfg(feature = "acme")]
+pub async fn run_tls_server(
+    state: Arc<AppState>,
+    listener: TcpListener,
+    acceptor: TlsAcceptor,
+) -> Result<()> {
+    info!(
+        "HTTPS server listening on https://{}",
+        listener.local_addr()?
+    );
+    accept_connections(state, listener, Some(acceptor)).await?;
+    info!("HTTPS server stopped accepting connections");
+    Ok(())
+}
+
+/// Serve connections from a listener, over TLS when given an acceptor,
+/// until shutdown is requested
+async fn accept_connections(
+    state: Arc<AppState>,
+    listener: TcpListener,
+    acceptor: Option<TlsAcceptor>,
+) -> Result<()> {
+    let mut shutdown = state.shutdown.subscribe();
+
+    loop {
+        let (stream, remote_addr) = tokio::select! {
+            accepted = listener.accept() => accepted?,
+            _ = wait_for_shutdown(&mut shutdown) => break,
+        };
+        state
+            .counters
+            .total_connections
+            .fetch_add(1, Ordering::Relaxed);
+        // Read per connection so changes apply without rebinding
+        if state.config.load().server.socket_options.tcp_nodelay {
+            if let Err(e) = stream.set_nodelay(true) {
+                debug!("Failed to set TCP_NODELAY: {}", e);
+            }
+        }
+        let state = state.clone();
+        let shutdown = shutdown.clone();
+        let acceptor = acceptor.clone();
+
+        let connection = OpenConnection::new(state.clone());
+        let (id, activity) = (connection.id, connection.activity.clone());
+        // Held until the task is registered, so it cannot deregister first
+        let registry = state.clone();
+        let mut open = registry.connections.open.lock().unwrap();
+        let task_activity = activity.clone();
+        let task = tokio::spawn(async move {
+            let _connection = connection;
+            match acceptor {
+                Some(acceptor) => match acceptor.accept(stream).await {
+                    Ok(stream) => {
+                        let io = TokioIo::new(stream);
+                        serve_connection(state, io, remote_addr, true, task_activity, shutdown)
+                            .await
+                    }
+                    Err(e) => debug!("TLS handshake with {} failed: {}", remote_addr, e),
+                },
+                None => {
+                    let io = TokioIo::new(stream);
+                    serve_connection(state, io, remote_addr, false, task_activity, shutdown).await
+                }
+            }
+        });
+        open.insert(id, (task.abort_handle(), activity));
+    }
+    Ok(())
+}
+
+/// Client connections being served, so idle ones can be found and closed
+#[derive(Default)]
+pub(crate) struct Connections {
+    next_id: AtomicU64,
+    open: Mutex<HashMap<u64, (AbortHandle, Arc<Activity>)>>,
+}
+
+impl Connections {
+    /// Connections with no request in progress for at least `idle`
+    pub(crate) fn idle(&self, idle: Duration) -> Vec<AbortHandle> {
+        let open = self.open.lock().unwrap();
+        open.values()
+            .filter(|(_, activity)| activity.idle_for().is_some_and(|d| d >= idle))
+            .map(|(task, _)| task.clone())
+            .collect()
+    }
+}
+
+/// When a connection last did anything
+pub(crate) struct Activity {
+    last: Mutex<Instant>,
+
+    /// Requests in progress, counting until their response body is sent
+    busy: AtomicUsize,
+}
+
+impl Activity {
+    fn new() -> Self {
+        Self {
+            last: Mutex::new(Instant::now()),
+            busy: AtomicUsize::new(0),
+        }
+    }
+
+    fn touch(&self) {
+        *self.last.lock().unwrap() = Instant::now();
+    }
+
+    /// How long the connection has been idle, or None while it is serving
+    /// a request
+    fn idle_for(&self) -> Option<Duration> {
+        if self.busy.load(Ordering::Acquire) > 0 {
+            return None;
+        }
+        Some(self.last.lock().unwrap().elapsed())
+    }
+}
+
+/// Marks a connection busy until dropped along with the response body
+struct Busy(Arc<Activity>);
+
+impl Busy {
+    fn new(activity: Arc<Activity>) -> Self {
+        activity.busy.fetch_add(1, Ordering::AcqRel);
+        activity.touch();
+        Self(activity)
+    }
+}
+
+impl Drop for Busy {
+    fn drop(&mut self) {
+        self.0.touch();
+        self.0.busy.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Close connections idle for longer than
+/// `server.idle_connection_timeout_secs`, until shutdown is requested
+async fn close_idle_connections(state: Arc<AppState>) {
+    let mut shutdown = state.shutdown.subscribe();
+    let mut ticks = tokio::time::interval(Duration::from_secs(1));
+    ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        tokio::select! {
+            _ = ticks.tick() => {}
+            _ = wait_for_shutdown(&mut shutdown) => return,
+        }
+        // Read on every pass so changes apply to open connections
+        let timeout = state.config.load().server.idle_connection_timeout_secs;
+        if timeout == 0 {
+            continue;
+        }
+        let idle = state.connections.idle(Duration::from_secs(timeout));
+        if !idle.is_empty() {
+            debug!("Closing {} idle connections", idle.len());
+        }
+        // Dropping the task's future closes the socket
+        for task in idle {
+            task.abort();
+        }
+    }
+}
+
+/// Serve HTTP/1 requests on an accepted connection until it closes
+async fn serve_connection<I>(
+    state: Arc<AppState>,
+    io: I,
+    remote_addr: SocketAddr,
+    is_tls: bool,
+    activity: Arc<Activity>,
+    mut shutdown: watch::Receiver<bool>,
+) where
+    I: hyper::rt::Read + hyper::rt::Write + Unpin + Send + 'static,
+{
+    let (max_headers, max_header_bytes) = {
+        let config = state.config.load();
+        (
+            config.server.max_request_headers,
+            config.server.max_request_header_bytes,
+        )
+    };
+    let service_state = state.clone();
+    let service = service_fn(move |req| {
+        let state = service_state.clone();
+        let busy = Busy::new(activity.clone());
+        async move {
+            let response = serve_request(state, req, remote_addr, is_tls).await?;
+            // Still busy while the body streams out
+            Ok::<_, Infallible>(response.map(|body| {
+                body.map_frame(move |frame| {
+                    busy.0.touch();
+                    frame
+                })
+                .boxed()
+            }))
+        }
+    });
+
+    // Read per connection so changes apply without rebinding; hyper
+    // answers requests over these limits itself, before they reach the
+    // service. Header names keep the client's case, for backends that care.
+    let conn = http1::Builder::new()
+        .max_headers(max_headers)
+        .max_buf_size(max_header_bytes.max(MIN_REQUEST_HEADER_BYTES))
+        .preserve_header_case(true)
+        .serve_connection(io, service)
+        .with_upgrades();
+    tokio::pin!(conn);
+
+    let result = tokio::select! {
+        result = conn.as_mut() => result,
+        _ = wait_for_shutdown(&mut shutdown) => {
+            // Let the in-flight request finish, then close
+            conn.as_mut().graceful_shutdown();
+            conn.await
+        }
+    };
+
+    if let Err(err) = result {
+        if err.is_parse_too_large() {
+            state
+                .counters
+                .headers_too_large
+                .fetch_add(1, Ordering::Relaxed);
+        }
+        debug!("Connection error: {:?}", err);
+    }
+}
+
+/// Counts a client connection as open until dropped, when it is also
+/// removed from [`Connections`]
+struct OpenConnection {
+    state: Arc<AppState>,
+    id: u64,
+    activity: Arc<Activity>,
+}
+
+impl OpenConnection {
+    fn new(state: Arc<AppState>) -> Self {
+        state
+            .counters
+            .active_connections
+            .fetch_add(1, Ordering::Relaxed);
+        let id = state.connections.next_id.fetch_add(1, Ordering::Relaxed);
+        Self {
+            state,
+            id,
+            activity: Arc::new(Activity::new()),
+        }
+    }
+}
+
+impl Drop for OpenConnection {
+    fn drop(&mut self) {
+        self.state.connections.open.lock().unwrap().remove(&self.id);
+        self.state
+            .counters
+            .active_connections
+            .fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Handle a request, then log it and count it against the client that sent
+/// it and the route that served it
+async fn serve_request(
+    state: Arc<AppState>,
+    req: Request<Incoming>,
+    remote_addr: SocketAddr,
+    is_tls: bool,
+) -> Result<Response<BoxBody<Bytes, Infallible>>, Infallible> {
+    let (max_uri_bytes, counted, anonymize, access_log, sample_rate, always_log_errors) = {
+        let config = state.config.load();
+        (
+            config.server.max_uri_bytes,
+            !is_health_probe(&config, req.uri().path())
+                && !is_status_page(&config, req.uri().path()),
+            config.server.anonymize_client_ips,
+            config.server.access_log,
+            config.server.access_log_sample_rate,
+            config.server.always_log_errors,
+        )
+    };
+
+    // Mostly scanners, so turned away before anything else looks at them
+    if uri_len(req.uri()) > max_uri_bytes {
+        state.counters.uri_too_long.fetch_add(1, Ordering::Relaxed);
+        return Ok(error_response(StatusCode::URI_TOO_LONG, "URI Too Long"));
+    }
+
+    let method = req.method().clone();
+    let uri = req.uri().clone();
+    // Streamed bodies have no known length and count as zero bytes
+    let bytes_received = req.body().size_hint().exact().unwrap_or(0);
+
+    let started = std::time::Instant::now();
+    let response = handle_request(state.clone(), req, remote_addr, is_tls).await?;
+    let latency = started.elapsed();
+    if !counted {
+        return Ok(response);
+    }
+
+    let status = response.status();
+    let target = response.extensions().get::<Target>();
+    let error = status.is_client_error() || status.is_server_error();
+    let logged = access_log && ((error && always_log_errors) || sampled(sample_rate));
+    if access_log && !logged {
+        state
+            .counters
+            .access_log_sampled_out
+            .fetch_add(1, Ordering::Relaxed);
+    }
+    if logged {
+        info!(
+            "{} {} {} - {} {} {}",
+            remote_addr.ip(),
+            method,
+            uri.path(),
+            uri.query().unwrap_or(""),
+            status.as_u16(),
+            target.map_or_else(|| "route=-".to_string(), Target::to_string)
+        );
+    }
+
+    let ip = if anonymize {
+        stats::anonymize_ip(remote_addr.ip())
+    } else {
+        remote_addr.ip()
+    };
+    let bytes = response.body().size_hint().exact();
+
+    let route = target.map(|target| target.route.clone());
+    state.traffic.record_request(
+        ip,
+        route.as_deref(),
+        status,
+        latency,
+        bytes_received,
+        bytes.unwrap_or(0),
+    );
+    if bytes.is_some() {
+        return Ok(response);
+    }
+
+    // Streamed and compressed bodies are counted as they are sent
+    let traffic = state.traffic.clone();
+    Ok(response.map(|body| {
+        CountedBody {
+            inner: body,
+            traffic,
+            client: ip,
+            route,
+            sent: 0,
+        }
+        .boxed()
+    }))
+}
+
+/// Response body adding the bytes sent to the traffic statistics once it
+/// ends or the client goes away
+struct CountedBody {
+    inner: BoxBody<Bytes, Infallible>,
+    traffic: Arc<stats::Traffic>,
+    client: IpAddr,
+    route: Option<String>,
+    /// Bytes sent and not yet counted
+    sent: u64,
+}
+
+impl CountedBody {
+    fn count(&mut self) {
+        let sent = std::mem::take(&mut self.sent);
+        if sent > 0 {
+            self.traffic
+                .record_bytes_sent(self.client, self.route.as_deref(), sent);
+        }
+    }
+}
+
+impl Body for CountedBody {
+    type Data = Bytes;
+    type Error = Infallible;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, Infallible>>> {
+        let frame = ready!(Pin::new(&mut self.inner).poll_frame(cx));
+        match frame {
+            Some(Ok(ref frame)) => {
+                if let Some(data) = frame.data_ref() {
+                    self.sent += data.len() as u64;
+                }
+            }
+            // Counted before the client sees the end of the body
+            None => self.count(),
+            Some(Err(e)) => match e {},
+        }
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl Drop for CountedBody {
+    fn drop(&mut self) {
+        self.count();
+    }
+}
+
+/// What served a request, attached to the response's extensions
+#[derive(Debug, Clone)]
+struct Target {
+    /// Route path, `static:` and the mount path, or `default`
+    route: String,
+    upstream: Option<String>,
+    backend: Option<String>,
+}
+
+impl Target {
+    /// Attribute a response to a route, picking up the backend the proxy
+    /// selected, if any
+    fn attach(
+        response: &mut Response<BoxBody<Bytes, Infallible>>,
+        route: String,
+        upstream: Option<&str>,
+    ) {
+        let backend = response
+            .extensions_mut()
+            .remove::<Backend>()
+            .map(|Backend(addr)| addr);
+        response.extensions_mut().insert(Target {
+            route,
+            upstream: upstream.map(str::to_string),
+            backend,
+        });
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "route={}", self.route)?;
+        if let Some(ref upstream) = self.upstream {
+            write!(f, " upstream={}", upstream)?;
+        }
+        if let Some(ref backend) = self.backend {
+            write!(f, " backend={}", backend)?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether a request is for the health endpoint on the HTTP port
+fn is_health_probe(config: &JanusConfig, path: &str) -> bool {
+    let health = &config.server.health_endpoint;
+    health.enabled && !health.on_management_port && path == health.path
+}
+
+/// Length of a request target as the client sent it
+fn uri_len(uri: &hyper::Uri) -> usize {
+    let scheme = uri
+        .scheme_str()
+        .map_or(0, |scheme| scheme.len() + "://".len());
+    let authority = uri
+        .authority()
+        .map_or(0, |authority| authority.as_str().len());
+    let path = uri.path_and_query().map_or(0, |path| path.as_str().len());
+    scheme + authority + path
+}
+
+/// Whether a request falls in a sample of the given rate (0.0-1.0)
+fn sampled(rate: f64) -> bool {
+    if rate >= 1.0 {
+        return true;
+    }
+    if rate <= 0.0 {
+        return false;
+    }
+
+    // xorshift64 per thread; the first state comes from the hasher's
+    // random keys, so threads do not share a sequence
+    thread_local! {
+        static STATE: std::cell::Cell<u64> = std::cell::Cell::new(
+            std::collections::hash_map::RandomState::new().hash_one(0u8) | 1,
+        );
+    }
+    let x = STATE.with(|state| {
+        let mut x = state.get();
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        state.set(x);
+        x
+    });
+    ((x >> 11) as f64 / (1u64 << 53) as f64) < rate
+}
+
+/// Handle incoming HTTP request
+async fn handle_request(
+    state: Arc<AppState>,
+    mut req: Request<Incoming>,
+    remote_addr: SocketAddr,
+    is_tls: bool,
+) -> Result<Response<BoxBody<Bytes, Infallible>>, Infallible> {
+    let method = req.method().clone();
+    let uri = req.uri().clone();
+    let path = uri.path();
+
+    // Routes and handlers come from one snapshot, which the request keeps
+    // even if the configuration changes while it is being served
+    let proxies = state.proxies.load_full();
+    let config = proxies.config();
+
+    // Health probes bypass routing and are not counted as traffic
+    if is_health_probe(config, path) {
+        if config.server.health_endpoint.access_log {
+            info!("{} {} {} - health", remote_addr.ip(), method, path);
+        }
+        return Ok(health::health_response(&state, config).await);
+    }
+
+    // Nor is the status page, which must never be answered by a route
+    if is_status_page(config, path) {
+        return Ok(status_page_response(&state, config, &req).await);
+    }
+
+    // The ACME CA fetches challenges over plain HTTP, before any routing
+    #[cfg(feature = "acme")]
+    if let Some(acme) = state.acme.get() {
+        if path.starts_with(crate::acme::CHALLENGE_PREFIX) {
+            return Ok(match acme.challenge_response(path) {
+                Some(key_authorization) => Response::new(full_body(key_authorization.into_bytes())),
+                None => error_response(StatusCode::NOT_FOUND, "Not Found"),
+            });
+        }
+    }
+
+    state
+        .counters
+        .total_requests
+        .fetch_add(1, Ordering::Relaxed);
+
+    // Maintenance answers before anything else gets to
+    if let Some(window) = state.maintenance.all() {
+        let response = maintenance_response(&window, &config.server.maintenance).await;
+        update_status_stats(&state, response.status()).await;
+        return Ok(response);
+    }
+
+    req.extensions_mut().insert(Connection {
+        is_tls,
+        remote_addr,
+    });
+    let middleware = state.middleware.load_full();
+    let (parts, body) = req.into_parts();
+    let mut head = Request::from_parts(parts, ());
+    if let Some(response) = middleware.run(&mut head, &state).await {
+        update_status_stats(&state, response.status()).await;
+        return Ok(response);
+    }
+    // Response steps see the request as the chain left it
+    let request_head = middleware
+        .rewrites_responses()
+        .then(|| (head.method().clone(), head.headers().clone()));
+    let (parts, ()) = head.into_parts();
+    let req = Request::from_parts(parts, body);
+
+    let response = route_request(state, &proxies, req, remote_addr).await;
+    Ok(match request_head {
+        Some((method, headers)) => middleware.respond(&method, &headers, response),
+        None => response,
+    })
+}
+
+/// Answer a request the middleware let through from a static mount, a
+/// route or the default upstream
+async fn route_request(
+    state: Arc<AppState>,
+    proxies: &ProxyTable,
+    mut req: Request<Incoming>,
+    remote_addr: SocketAddr,
+) -> Response<BoxBody<Bytes, Infallible>> {
+    let method = req.method().clone();
+    let uri = req.uri().clone();
+    let path = uri.path();
+    let config = proxies.config();
+
+    let routes_first = config.server.match_order == MatchOrder::RoutesFirst;
+    if !routes_first {
+        if let Some(response) = serve_static(&state, config, &req).await {
+            return response;
+        }
+    }
+
+    // Try to match proxy routes
+    for &i in proxies.route_order() {
+        let route = &config.routes[i];
+        if route.enabled && matches_route(path, &route.path) {
+            // Check method if specified
+            if !route.methods.is_empty() {
+                let method_str = method.as_str().to_uppercase();
+                if !route.methods.iter().any(|m| m.to_uppercase() == method_str) {
+                    if route.on_method_mismatch == MethodMismatch::Reject405
+                        && matches_headers(req.headers(), &route.match_headers)
+                        && matches_query(uri.query(), &route.query_match)
+                    {
+                        let request_id = request_id(&state, &req);
+                        debug!(
+                            "Rejecting {} {} for route '{}' [{}]",
+                            method, path, route.path, request_id
+                        );
+                        let allow = route
+                            .methods
+                            .iter()
+                            .map(|m| m.to_uppercase())
+                            .collect::<Vec<_>>()
+                            .join(", ");
+                        let route_path = route.path.clone();
+
+                        let mut response =
+                            error_response(StatusCode::METHOD_NOT_ALLOWED, "Method Not Allowed");
+                        let headers = response.headers_mut();
+                        if let Ok(allow) = HeaderValue::from_str(&allow) {
+                            headers.insert(ALLOW, allow);
+                        }
+                        if let Ok(id) = HeaderValue::from_str(&request_id) {
+                            headers.insert(REQUEST_ID_HEADER, id);
+                        }
+                        Target::attach(&mut response, route_path, None);
+                        update_status_stats(&state, StatusCode::METHOD_NOT_ALLOWED).await;
+                        return response;
+                    }
+                    continue;
+                }
+            }
+
+            // Check request headers and query parameters if specified
+            if !matches_headers(req.headers(), &route.match_headers)
+                || !matches_query(uri.query(), &route.query_match)
+            {
+                continue;
+            }
+
+            // Find the route's handler, which exists if its upstream does
+            let proxy = proxies.route(i);
+            if let Some(proxy) = proxy {
+                let client_timeout = route.client_timeout();
+                let label = format!("route '{}'", route.path);
+                let (route_path, upstream_name) = (route.path.clone(), route.upstream.clone());
+
+                state.counters.route_matches.fetch_add(1, Ordering::Relaxed);
+
+                if let Some(window) = state.maintenance.route(&route.path) {
+                    let mut response =
+                        maintenance_response(&window, &config.server.maintenance).await;
+                    Target::attach(&mut response, route_path, Some(&upstream_name));
+                    update_status_stats(&state, response.status()).await;
+                    return response;
+                }
+
+                if let Some(ref auth) = route.auth_request {
+                    let denied = match proxies.auth(i) {
+                        Some(handler) => {
+                            auth::authorize(&state, &handler, auth, &route.path, &mut req)
+                                .await
+                                .err()
+                        }
+                        None => {
+                            // Never proxy a request that was not checked
+                            warn!(
+                                "Auth upstream '{}' not found for route '{}'",
+                                auth.upstream, route.path
+                            );
+                            Some(error_response(
+                                StatusCode::INTERNAL_SERVER_ERROR,
+                                "Internal Server Error",
+                            ))
+                        }
+                    };
+                    if let Some(mut response) = denied {
+                        Target::attach(&mut response, route_path, Some(&auth.upstream));
+                        update_status_stats(&state, response.status()).await;
+                        return response;
+                    }
+                }
+
+                // Checked after auth, so cached responses are only served
+                // to clients allowed to see them
+                let cached = route.cache.as_ref().and_then(|cache| {
+                    let key = cache::CacheKey::for_request(&req, cache)?;
+                    Some((cache, key))
+                });
+                if let Some((cache, ref key)) = cached {
+                    if let Some(mut response) = state.response_cache.get(&route.path, key, cache) {
+                        Target::attach(&mut response, route_path, Some(&upstream_name));
+                        update_status_stats(&state, response.status()).await;
+                        return response;
+                    }
+                }
+
+                let mut response =
+                    forward_request(&state, &proxy, req, remote_addr, client_timeout, &label).await;
+                if let Some((cache, key)) = cached {
+                    response = state
+                        .response_cache
+                        .store(&route.path, key, cache, response)
+                        .await;
+                }
+                Target::attach(&mut response, route_path, Some(&upstream_name));
+                return response;
+            } else {
+                warn!(
+                    "Upstream '{}' not found for route '{}'",
+                    route.upstream, route.path
+                );
+            }
+        }
+    }
+
+    if routes_first {
+        if let Some(response) = serve_static(&state, config, &req).await {
+            return response;
+        }
+    }
+
+    // Fall through to the default upstream, if there is one
+    if let Some(ref upstream) = config.server.default_upstream {
+        let proxy = proxies.default_route();
+        if let Some(proxy) = proxy {
+            let route = proxy.route();
+            let label = format!("default upstream '{}'", upstream);
+
+            state
+                .counters
+                .default_matches
+                .fetch_add(1, Ordering::Relaxed);
+            let mut response = forward_request(
+                &state,
+                &proxy,
+                req,
+                remote_addr,
+                route.client_timeout(),
+                &label,
+            )
+            .await;
+            Target::attach(&mut response, "default".to_string(), Some(&route.upstream));
+            return response;
+        }
+        warn!("Default upstream '{}' not found", upstream);
+    }
+
+    // No route matched - return 404
+    let response = error_response(StatusCode::NOT_FOUND, "Not Found");
+    update_status_stats(&state, StatusCode::NOT_FOUND).await;
+    response
+}
+
+/// Serve a request from the first static mount that has what it asks for,
+/// or answer 404 from a mount that does not let it fall through
+///
+/// `None` lets matching go on to the routes.
+async fn serve_static(
+    state: &Arc<AppState>,
+    config: &JanusConfig,
+    req: &Request<Incoming>,
+) -> Option<Response<BoxBody<Bytes, Infallible>>> {
+    let method = req.method();
+    let uri = req.uri();
+    let path = uri.path();
+
+    for static_config in &config.static_files {
+        if static_config.enabled && path.starts_with(&static_config.path) {
+            let mount = format!("static:{}", static_config.path);
+            let file_path = path.strip_prefix(&static_config.path).unwrap_or(path);
+            let Some(file_path) = static_file_path(file_path) else {
+                // Never passed on, as it names something outside the root
+                let mut response = error_response(StatusCode::NOT_FOUND, "Not Found");
+                Target::attach(&mut response, mount, None);
+                update_status_stats(state, StatusCode::NOT_FOUND).await;
+                return Some(response);
+            };
+            let mut full_path = Path::new(&static_config.root).join(file_path);
+
+            let is_dir = full_path.is_dir();
+            let index = if is_dir {
+                static_config
+                    .index
+                    .iter()
+                    .map(|name| full_path.join(name))
+                    .find(|candidate| candidate.is_file())
+            } else {
+                None
+            };
+
+            // Mounts only serve reads, but other methods for paths they do
+            // not have still fall through to the routes
+            let found = index.is_some()
+                || (is_dir && static_config.directory_listing)
+                || full_path.is_file();
+            if found && method != Method::GET && method != Method::HEAD {
+                let mut response = static_method_response(method);
+                Target::attach(&mut response, mount, None);
+                update_status_stats(state, response.status()).await;
+                return Some(response);
+            }
+            let head = method == Method::HEAD;
+
+            if is_dir {
+                // Redirect to the slashed form first, so relative links in
+                // the index page or listing resolve inside the directory
+                if (index.is_some() || static_config.directory_listing) && !path.ends_with('/') {
+                    let location = match uri.query() {
+                        Some(query) => format!("{}/?{}", path, query),
+                        None => format!("{}/", path),
+                    };
+                    let mut response =
+                        error_response(StatusCode::MOVED_PERMANENTLY, "Moved Permanently");
+                    if let Ok(location) = HeaderValue::from_str(&location) {
+                        response.headers_mut().insert(LOCATION, location);
+                    }
+                    Target::attach(&mut response, mount, None);
+                    update_status_stats(state, StatusCode::MOVED_PERMANENTLY).await;
+                    return Some(response);
+                }
+
+                if let Some(index) = index {
+                    full_path = index;
+                }
+            }
+
+            if full_path.is_file() {
+                // HEAD takes the length from metadata without reading the
+                // file; GET takes it from what was read, in case the file
+                // changed in between
+                let contents = if head {
+                    tokio::fs::metadata(&full_path)
+                        .await
+                        .map(|metadata| (metadata.len(), Vec::new()))
+                } else {
+                    tokio::fs::read(&full_path)
+                        .await
+                        .map(|contents| (contents.len() as u64, contents))
+                };
+                match contents {
+                    Ok((length, contents)) => {
+                        let content_type = guess_content_type(&full_path);
+                        let mut response = Response::builder()
+                            .status(StatusCode::OK)
+                            .header("Content-Type", content_type)
+                            .header(CONTENT_LENGTH, length)
+                            .body(full_body(contents))
+                            .unwrap();
+                        add_static_headers(&mut response, &static_config.headers);
+                        Target::attach(&mut response, mount, None);
+
+                        // Only take the write lock when there are errors to clear
+                        if !state.stats.read().await.error_log.is_empty() {
+                            let scope = format!("static directory '{}'", static_config.path);
+                            for summary in state.stats.write().await.error_log.clear(&scope) {
+                                info!("{}", summary);
+                            }
+                        }
+                        update_status_stats(state, StatusCode::OK).await;
+                        return Some(response);
+                    }
+                    Err(e) => {
+                        let scope = format!("static directory '{}'", static_config.path);
+                        let message = format!("failed to read {:?}: {}", full_path, e);
+                        let line = state.stats.write().await.error_log.record(
+                            &scope,
+                            &message,
+                            &config.server.error_log,
+                        );
+                        if let Some(line) = line {
+                            warn!("{}", line);
+                        }
+                    }
+                }
+            } else if static_config.directory_listing && is_dir {
+                let listing = if head {
+                    full_body(Vec::new())
+                } else {
+                    let template = static_config
+                        .directory_listing_template
+                        .as_ref()
+                        .and_then(|file| state.listing_templates.load().get(file).cloned());
+                    directory_listing_body(
+                        full_path,
+                        path.to_string(),
+                        static_config.listing_limit,
+                        template,
+                    )
+                };
+                let mut response = Response::builder()
+                    .status(StatusCode::OK)
+                    .header("Content-Type", "text/html")
+                    .body(listing)
+                    .unwrap();
+                add_static_headers(&mut response, &static_config.headers);
+                Target::attach(&mut response, mount, None);
+
+                update_status_stats(state, StatusCode::OK).await;
+                return Some(response);
+            }
+
+            if !static_config.fallthrough {
+                let mut response = error_response(StatusCode::NOT_FOUND, "Not Found");
+                Target::attach(&mut response, mount, None);
+                update_status_stats(state, StatusCode::NOT_FOUND).await;
+                return Some(response);
+            }
+        }
+    }
+    None
+}
+
+/// Set a static directory's configured headers on a response, replacing
+/// the detected ones
+fn add_static_headers<B>(response: &mut Response<B>, headers: &HashMap<String, String>) {
+    for (name, value) in headers {
+        match (
+            HeaderName::from_bytes(name.as_bytes()),
+            HeaderValue::from_str(value),
+        ) {
+            (Ok(name), Ok(value)) => {
+                response.headers_mut().insert(name, value);
+            }
+            _ => warn!("Skipping invalid header '{}: {}'", name, value),
+        }
+    }
+}
+
+/// Answer a method other than GET or HEAD on a static mount: the allowed
+/// methods for OPTIONS, 405 for anything else
+fn static_method_response(method: &Method) -> Response<BoxBody<Bytes, Infallible>> {
+    if method == Method::OPTIONS {
+        return Response::builder()
+            .status(StatusCode::NO_CONTENT)
+            .header(ALLOW, "GET, HEAD, OPTIONS")
+            .body(full_body(Vec::new()))
+            .unwrap();
+    }
+    let mut response = error_response(StatusCode::METHOD_NOT_ALLOWED, "Method Not Allowed");
+    response
+        .headers_mut()
+        .insert(ALLOW, HeaderValue::from_static("GET, HEAD"));
+    response
+}
+
+/// Redirect a request to the same host, path and query over HTTPS
+pub(crate) fn https_redirect<B>(
+    req: &Request<B>,
+    code: u16,
+) -> Response<BoxBody<Bytes, Infallible>> {
+    // The default HTTPS port applies, so any port the client used is dropped
+    let host = req
+        .headers()
+        .get(hyper::header::HOST)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|host| host.parse::<hyper::http::uri::Authority>().ok())
+        .or_else(|| req.uri().authority().cloned());
+    let Some(host) = host else {
+        return error_response(StatusCode::BAD_REQUEST, "Bad Request");
+    };
+
+    let path = req.uri().path_and_query().map_or("/", |path| path.as_str());
+    let location = format!("https://{}{}", host.host(), path);
+
+    let status = StatusCode::from_u16(code).unwrap_or(StatusCode::MOVED_PERMANENTLY);
+    let mut response = error_response(status, status.canonical_reason().unwrap_or("Redirect"));
+    if let Ok(location) = HeaderValue::from_str(&location) {
+        response.headers_mut().insert(LOCATION, location);
+    }
+    response
+}
+
+/// Forward a request upstream and record the response status
+///
+/// The client timeout bounds the whole exchange, including reading the
+/// request body from the client.
+async fn forward_request(
+    state: &Arc<AppState>,
+    proxy: &ProxyHandler,
+    req: Request<Incoming>,
+    remote_addr: SocketAddr,
+    client_timeout: Duration,
+    label: &str,
+) -> Response<BoxBody<Bytes, Infallible>> {
+    let request_id = request_id(state, &req);
+    // Only paid for while the feed is on and someone follows it
+    let live_path = (state.route_hits.receiver_count() > 0
+        && state.config.load().management.live_requests)
+        .then(|| req.uri().path().to_string());
+    let response = match tokio::time::timeout(client_timeout, proxy.forward(req, remote_addr)).await
+    {
+        Ok(Ok(response)) => response,
+        Ok(Err(e)) => {
+            // The handler has logged the cause; the client only gets an ID
+            // to quote
+            debug!(
+                "Request to {} failed [{}] [{}]: {}",
+                label,
+                e.kind(),
+                request_id,
+                e
+            );
+            let status = e.status();
+            let mut response = error_response_with_id(
+                status,
+                status.canonical_reason().unwrap_or("Error"),
+                &request_id,
+            );
+            if let Some(backend) = e.backend() {
+                response
+                    .extensions_mut()
+                    .insert(Backend(backend.to_string()));
+            }
+            if let (ProxyError::NoBackend(_), Some(secs)) = (&e, proxy.retry_after_secs()) {
+                response.headers_mut().insert(RETRY_AFTER, secs.into());
+            }
+            response
+        }
+        Err(_) => {
+            warn!(
+                "Request to {} exceeded client timeout of {}s",
+                label,
+                client_timeout.as_secs()
+            );
+            error_response(StatusCode::GATEWAY_TIMEOUT, "Gateway Timeout")
+        }
+    };
+
+    update_status_stats(state, response.status()).await;
+    if let Some(path) = live_path {
+        let _ = state.route_hits.send(ServerMessage::RouteHit {
+            path,
+            upstream: proxy.route().upstream.clone(),
+            status: response.status().as_u16(),
+        });
+    }
+    response
+}
+
+/// Check if path matches route pattern
+///
+/// `/api/*` matches `/api` and everything below it, but not `/apiv2`;
+/// a bare trailing `*` (as in `/api*`) is a plain prefix match.
+pub fn matches_route(path: &str, pattern: &str) -> bool {
+    if let Some(prefix) = pattern.strip_suffix("/*") {
+        path.strip_prefix(prefix)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    } else if pattern.ends_with('*') {
+        let prefix = pattern.trim_end_matches('*');
+        path.starts_with(prefix)
+    } else {
+        path == pattern
+    }
+}
+
+/// The ID the middleware gave a request, or a newly assigned one
+fn request_id<B>(state: &AppState, req: &Request<B>) -> String {
+    match req.extensions().get::<RequestId>() {
+        Some(RequestId(id)) => id.clone(),
+        None => state.next_request_id(),
+    }
+}
+
+/// Check that every required header is present with a matching value
+fn matches_headers(headers: &HeaderMap, required: &HashMap<String, String>) -> bool {
+    required.iter().all(|(name, pattern)| {
+        headers
+            .get_all(name.as_str())
+            .iter()
+            .any(|value| value.to_str().is_ok_and(|value| glob_match(pattern, value)))
+    })
+}
+
+/// Whether a query string has every required parameter, with a value
+/// matching its pattern
+fn matches_query(query: Option<&str>, required: &HashMap<String, String>) -> bool {
+    required.iter().all(|(name, pattern)| {
+        query.unwrap_or("").split('&').any(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            // Form encoding writes spaces as `+`, and `%2B` for a plus
+            let decode = |part: &str| percent_decode(&part.replace('+', " "));
+            decode(key).is_some_and(|key| key == *name)
+                && decode(value).is_some_and(|value| glob_match(pattern, &value))
+        })
+    })
+}
+
+/// Match a value against a pattern where `*` stands for any run of characters
+fn glob_match(pattern: &str, value: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let Some(mut rest) = parts.next().and_then(|first| value.strip_prefix(first)) else {
+        return false;
+    };
+
+    let mut parts: Vec<&str> = parts.collect();
+    let Some(last) = parts.pop() else {
+        // No wildcard, so the value must match exactly
+        return rest.is_empty();
+    };
+    for part in parts {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+/// Update status code statistics
+async fn update_status_stats(state: &Arc<AppState>, status: StatusCode) {
+    state.counters.record_status(status);
+    let error_rate = state.counters.error_rate();
+
+    // Log only when the rate crosses the threshold, not on every request
+    let threshold = state.config.load().server.error_rate_alert_threshold;
+    let alerting = error_rate > threshold;
+    if state.error_rate_alert.swap(alerting, Ordering::Relaxed) != alerting {
+        if alerting {
+            warn!(
+                "Server error rate {:.1}% exceeds alert threshold {:.1}%",
+                error_rate * 100.0,
+                threshold * 100.0
+            );
+        } else {
+            info!(
+                "Server error rate {:.1}% is back below alert threshold {:.1}%",
+                error_rate * 100.0,
+                threshold * 100.0
+            );
+        }
+    }
+}
+
+/// Guess content type from file extension
+fn guess_content_type(path: &std::path::Path) -> &'static str {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("html") | Some("htm") => "text/html",
+        Some("css") => "text/css",
+        Some("js") => "application/javascript",
+        Some("json") => "application/json",
+        Some("png") => "image/png",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("svg") => "image/svg+xml",
+        Some("ico") => "image/x-icon",
+        Some("txt") => "text/plain",
+        Some("xml") => "application/xml",
+        Some("pdf") => "application/pdf",
+        Some("woff") => "font/woff",
+        Some("woff2") => "font/woff2",
+        Some("ttf") => "font/ttf",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Entries rendered into each chunk of a directory listing
+const LISTING_CHUNK: usize = 256;
+
+/// Directory entries collected for a listing
+struct Listing {
+    /// Names and whether each is a directory
+    entries: Vec<(String, bool)>,
+    /// Set when the directory held more than the limit
+    truncated: bool,
+}
+
+/// Operator-supplied page a directory listing is rendered into, split
+/// around its `{entries}` placeholder
+#[derive(Debug)]
+pub(crate) struct ListingTemplate {
+    head: String,
+    tail: String,
+}
+
+impl ListingTemplate {
+    /// Page up to the entries, with the parent link when there is one
+    fn header(&self, url_path: &str) -> String {
+        let mut html = self.head.replace("{path}", &escape_html(url_path));
+        if url_path != "/" {
+            html.push_str("<li><a href=\"..\">..</a></li>\n");
+        }
+        html
+    }
+
+    /// Page after the entries, noting when entries were left out
+    fn footer(&self, url_path: &str, truncated_at: Option<usize>) -> String {
+        let mut html = truncated_at
+            .map(|limit| {
+                format!(
+                    "<li class=\"truncated\">Listing truncated to the first {} entries</li>\n",
+                    limit
+                )
+            })
+            .unwrap_or_default();
+        html.push_str(&self.tail.replace("{path}", &escape_html(url_path)));
+        html
+    }
+}
+
+/// Read the listing templates static directories name, keyed by file
+///
+/// Directories whose template is missing or has no `{entries}` keep the
+/// built-in page.
+pub(crate) fn load_listing_templates(
+    config: &JanusConfig,
+) -> HashMap<String, Arc<ListingTemplate>> {
+    let mut templates = HashMap::new();
+    let files = config
+        .static_files
+        .iter()
+        .filter_map(|s| s.directory_listing_template.as_ref());
+    for file in files {
+        if templates.contains_key(file) {
+            continue;
+        }
+        match std::fs::read_to_string(file) {
+            Ok(template) => match template.split_once("{entries}") {
+                Some((head, tail)) => {
+                    let template = ListingTemplate {
+                        head: head.to_string(),
+                        tail: tail.to_string(),
+                    };
+                    templates.insert(file.clone(), Arc::new(template));
+                }
+                None => warn!(
+                    "Directory listing template {} has no {{entries}} placeholder; using the built-in listing",
+                    file
+                ),
+            },
+            Err(e) => warn!(
+                "Failed to read directory listing template {}: {}; using the built-in listing",
+                file, e
+            ),
+        }
+    }
+    templates
+}
+
+/// Stream a directory listing as HTML, in the given template or the
+/// built-in page
+///
+/// The directory is only scanned once the body is polled, so a client that
+/// disconnects stops the scan along with the rest of the response.
+fn directory_listing_body(
+    dir: PathBuf,
+    url_path: String,
+    limit: usize,
+    template: Option<Arc<ListingTemplate>>,
+) -> BoxBody<Bytes, Infallible> {
+    let chunks = stream::once(read_listing(dir, limit)).flat_map(move |listing| {
+        let truncated_at = listing.truncated.then_some(limit);
+        let (header, footer) = match template {
+            Some(ref template) => (
+                template.header(&url_path),
+                template.footer(&url_path, truncated_at),
+            ),
+            None => (listing_header(&url_path), listing_footer(truncated_at)),
+        };
+        stream::iter([header])
+            .chain(
+                stream::iter(listing.entries)
+                    .chunks(LISTING_CHUNK)
+                    .map(|rows| listing_rows(&rows)),
+            )
+            .chain(stream::iter([footer]))
+    });
+
+    let frames = chunks.map(|html| Ok::<_, Infallible>(Frame::data(Bytes::from(html))));
+    BodyExt::boxed(StreamBody::new(frames))
+}
+
+/// Read up to `limit` entries of a directory, directories first
+async fn read_listing(dir: PathBuf, limit: usize) -> Listing {
+    let mut entries = Vec::new();
+    let mut truncated = false;
+
+    if let Ok(mut read_dir) = tokio::fs::read_dir(&dir).await {
+        while let Ok(Some(entry)) = read_dir.next_entry().await {
+            if entries.len() == limit {
+                truncated = true;
+                break;
+            }
+            if let Ok(name) = entry.file_name().into_string() {
+                // Usually answered from the directory entry without a stat
+                let is_dir = entry.file_type().await.map(|t| t.is_dir()).unwrap_or(false);
+                entries.push((name, is_dir));
+            }
+        }
+    }
+
+    entries.sort_by(|a, b| match (a.1, b.1) {
+        (true, false) => std::cmp::Ordering::Less,
+        (false, true) => std::cmp::Ordering::Greater,
+        _ => a.0.cmp(&b.0),
+    });
+
+    Listing { entries, truncated }
+}
+
+/// Opening of a directory listing page, up to and including the parent link
+fn listing_header(url_path: &str) -> String {
+    let mut html = format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+    <title>Index of {path}</title>
+    <style>
+        body {{ font-family: monospace; padding: 20px; }}
+        a {{ text-decoration: none; }}
+        a:hover {{ text-decoration: underline; }}
+        .dir {{ color: blue; }}
+        .file {{ color: black; }}
+    </style>
+</head>
+<body>
+    <h1>Index of {path}</h1>
+    <hr>
+    <ul>
+"#,
+        path = escape_html(url_path)
+    );
+
+    // Parent directory link
+    if url_path != "/" {
+        html.push_str("        <li><a href=\"..\">..</a></li>\n");
+    }
+
+    html
+}
+
+/// List items for a batch of directory entries
+fn listing_rows(entries: &[(String, bool)]) -> String {
+    let mut html = String::new();
+    for (name, is_dir) in entries {
+        let class = if *is_dir { "dir" } else { "file" };
+        let suffix = if *is_dir { "/" } else { "" };
+        html.push_str(&format!(
+            "        <li><a class=\"{}\" href=\"{}{}\">{}{}</a></li>\n",
+            class,
+            encode_path_segment(name),
+            suffix,
+            escape_html(name),
+            suffix
+        ));
+    }
+    html
+}
+
+/// Percent-encode a file name for a relative link, leaving only unreserved
+/// characters, so no name can end the attribute or read as a scheme
+fn encode_path_segment(name: &str) -> String {
+    let mut encoded = String::with_capacity(name.len());
+    for byte in name.bytes() {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'.' | b'_' | b'~') {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+/// Percent-decode a request path below a static mount into a path relative
+/// to its root, or `None` if it would leave the root or is not UTF-8
+fn static_file_path(path: &str) -> Option<PathBuf> {
+    let decoded = percent_decode(path)?;
+    let relative = Path::new(decoded.trim_start_matches('/'));
+    relative
+        .components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+        .then(|| relative.to_path_buf())
+}
+
+/// Decode `%XX` escapes, leaving malformed ones as they are, or `None` if
+/// the result is not UTF-8
+fn percent_decode(text: &str) -> Option<String> {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .filter(|hex| bytes[i] == b'%' && hex.iter().all(u8::is_ascii_hexdigit));
+        match hex {
+            Some(hex) => {
+                let hex = std::str::from_utf8(hex).ok()?;
+                decoded.push(u8::from_str_radix(hex, 16).ok()?);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8(decoded).ok()
+}
+
+/// Close of a directory listing page, noting when entries were left out
+fn listing_footer(truncated_at: Option<usize>) -> String {
+    let notice = truncated_at
+        .map(|limit| {
+            format!(
+                "    <p class=\"truncated\">Listing truncated to the first {} entries</p>\n",
+                limit
+            )
+        })
+        .unwrap_or_default();
+
+    format!(
+        r#"    </ul>
+{}    <hr>
+    <p>Janus Server</p>
+</body>
+</html>"#,
+        notice
+    )
+}
+
+/// Create a full body response
+pub(crate) fn full_body(data: Vec<u8>) -> BoxBody<Bytes, Infallible> {
+    Full::new(Bytes::from(data))
+        .map_err(|_| unreachable!())
+        .boxed()
+}
+
+/// Create an error response
+pub(crate) fn error_response(
+    status: StatusCode,
+    message: &str,
+) -> Response<BoxBody<Bytes, Infallible>> {
+    error_page(status, message, "")
+}
+
+/// Create an error response naming the request, so it can be found in the
+/// logs
+fn error_response_with_id(
+    status: StatusCode,
+    message: &str,
+    request_id: &str,
+) -> Response<BoxBody<Bytes, Infallible>> {
+    let detail = format!("\n    <p>Request ID: {}</p>", escape_html(request_id));
+    let mut response = error_page(status, message, &detail);
+    if let Ok(id) = HeaderValue::from_str(request_id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, id);
+    }
+    response
+}
+
+/// Escape text for inclusion in an HTML page
+pub(crate) fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Janus's own page for an error, with `detail` as extra HTML
+pub(crate) fn error_page(
+    status: StatusCode,
+    message: &str,
+    detail: &str,
+) -> Response<BoxBody<Bytes, Infallible>> {
+    let body = format!(
+        r#"<!DOCTYPE html>
+<html>
+<head><title>{} {}</title></head>
+<body>
+    <h1>{} {}</h1>{}
+    <hr>
+    <p>Janus Server</p>
+</body>
+</html>"#,
+        status.as_u16(),
+        message,
+        status.as_u16(),
+        message,
+        detail
+    );
+
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "text/html")
+        .body(full_body(body.into_bytes()))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sampling_follows_rate() {
+        assert!((0..1000).all(|_| sampled(1.0)));
+        assert!(!(0..1000).any(|_| sampled(0.0)));
+
+        let hits = (0..100_000).filter(|_| sampled(0.25)).count();
+        assert!(
+            (23_000..27_000).contains(&hits),
+            "{} of 100000 sampled",
+            hits
+        );
+    }
+
+    #[tokio::test]
+    async fn test_bind_listener_applies_socket_options() {
+        let options = SocketOptions {
+            recv_buffer_size: Some(64 * 1024),
+            ..SocketOptions::default()
+        };
+        let listener = bind_listener("127.0.0.1", 0, &options).await.unwrap();
+
+        let socket = socket2::SockRef::from(&listener);
+        assert!(socket.reuse_address().unwrap());
+        // The kernel may round the buffer size up
+        assert!(socket.recv_buffer_size().unwrap() >= 64 * 1024);
+        assert!(listener.local_addr().unwrap().port() > 0);
+    }
+
+    #[test]
+    fn test_connection_is_not_idle_while_busy() {
+        let activity = Arc::new(Activity::new());
+        assert!(activity.idle_for().is_some());
+
+        let busy = Busy::new(activity.clone());
+        assert_eq!(activity.idle_for(), None);
+        drop(busy);
+        assert!(activity
+            .idle_for()
+            .is_some_and(|idle| idle < Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn test_matches_route_exact() {
+        assert!(matches_route("/api", "/api"));
+        assert!(matches_route("/", "/"));
+        assert!(matches_route("/a/b/c", "/a/b/c"));
+        assert!(!matches_route("/api/users", "/api"));
+        assert!(!matches_route("/apiv2", "/api"));
+        assert!(!matches_route("/", "/api"));
+        assert!(!matches_route("", "/"));
+        assert!(matches_route("", ""));
+    }
+
+    #[test]
+    fn test_matches_route_segment_wildcard() {
+        assert!(matches_route("/api", "/api/*"));
+        assert!(matches_route("/api/", "/api/*"));
+        assert!(matches_route("/api/users", "/api/*"));
+        assert!(matches_route("/api/users/42/posts", "/api/*"));
+        assert!(!matches_route("/apiv2", "/api/*"));
+        assert!(!matches_route("/apiv2/users", "/api/*"));
+        assert!(!matches_route("/ap", "/api/*"));
+        assert!(matches_route("/v1/api/x", "/v1/api/*"));
+        assert!(!matches_route("/v1/apix", "/v1/api/*"));
+    }
+
+    #[test]
+    fn test_matches_route_root_and_bare_wildcards() {
+        assert!(matches_route("/", "/*"));
+        assert!(matches_route("/anything/at/all", "/*"));
+        assert!(matches_route("", "/*"));
+        assert!(matches_route("/anything", "*"));
+        assert!(matches_route("", "*"));
+    }
+
+    #[test]
+    fn test_matches_route_prefix_wildcard() {
+        // Without a slash before the star the match is a plain prefix
+        assert!(matches_route("/apiv2", "/api*"));
+        assert!(matches_route("/api/users", "/api*"));
+        assert!(!matches_route("/ap", "/api*"));
+    }
+
+    #[test]
+    fn test_matches_route_trailing_slash() {
+        assert!(matches_route("/api/", "/api/"));
+        assert!(!matches_route("/api", "/api/"));
+        assert!(!matches_route("/api/", "/api"));
+    }
+
+    #[test]
+    fn test_matches_route_without_leading_slash() {
+        // Request paths always start with '/', so these never match
+        assert!(!matches_route("/api", "api"));
+        assert!(!matches_route("/api/users", "api/*"));
+    }
+
+    #[test]
+    fn test_matches_route_query_string_is_not_stripped() {
+        // Callers pass the URI path; a query string is matched literally
+        assert!(!matches_route("/api?x=1", "/api"));
+        assert!(!matches_route("/api?x=1", "/api/*"));
+        assert!(matches_route("/api/users?x=1", "/api/*"));
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("acme", "acme"));
+        assert!(!glob_match("acme", "acme-eu"));
+        assert!(glob_match("acme-*", "acme-eu"));
+        assert!(glob_match("*-eu", "acme-eu"));
+        assert!(glob_match("a*m*e", "acme"));
+        assert!(glob_match("*", ""));
+        assert!(!glob_match("acme-*-eu", "acme-eu"));
+    }
+
+    #[test]
+    fn test_listing_rows_are_escaped() {
+        let rows = listing_rows(&[
+            ("<script>\"x\".txt".to_string(), false),
+            ("a b".to_string(), true),
+        ]);
+        assert!(
+            rows.contains(r#"href="%3Cscript%3E%22x%22.txt">&lt;script&gt;&quot;x&quot;.txt</a>"#)
+        );
+        assert!(rows.contains(r#"href="a%20b/">a b/</a>"#));
+        assert!(listing_header("/<b>/").contains("Index of /&lt;b&gt;/"));
+    }
+
+    #[test]
+    fn test_static_file_path() {
+        let path = |p| static_file_path(p).map(|p| p.to_string_lossy().into_owned());
+        assert_eq!(path("/docs/a%20b.txt").as_deref(), Some("docs/a b.txt"));
+        assert_eq!(path("/100%").as_deref(), Some("100%"));
+        assert_eq!(path("/./a").as_deref(), Some("./a"));
+        assert_eq!(path("/../secret"), None);
+        assert_eq!(path("/docs/%2e%2e/%2E%2E/secret"), None);
+        assert_eq!(path("/%ff"), None);
+    }
+
+    #[test]
+    fn test_matches_headers_requires_all() {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-tenant", "acme".parse().unwrap());
+        let required = HashMap::from([
+            ("X-Tenant".to_string(), "acme".to_string()),
+            ("X-Region".to_string(), "*".to_string()),
+        ]);
+        assert!(!matches_headers(&headers, &required));
+
+        headers.insert("x-region", "eu".parse().unwrap());
+        assert!(matches_headers(&headers, &required));
+    }
+
+    #[test]
+    fn test_matches_query_requires_all() {
+        let required = HashMap::from([
+            ("version".to_string(), "2".to_string()),
+            ("debug".to_string(), "*".to_string()),
+        ]);
+        assert!(matches_query(Some("version=2&debug"), &required));
+        assert!(matches_query(Some("a=b&debug=on&version=2"), &required));
+        assert!(!matches_query(Some("version=2"), &required));
+        assert!(!matches_query(Some("version=20&debug=1"), &required));
+        assert!(!matches_query(None, &required));
+        assert!(matches_query(None, &HashMap::new()));
+
+        // Names and values are compared decoded
+        let required = HashMap::from([
+            ("user name".to_string(), "Jane Doe".to_string()),
+            ("tag".to_string(), "c++*".to_string()),
+        ]);
+        assert!(matches_query(
+            Some("user+name=Jane%20Doe&tag=c%2B%2B"),
+            &required
+        ));
+        assert!(matches_query(
+            Some("user%20name=Jane+Doe&tag=c%2b%2b17"),
+            &required
+        ));
+        assert!(!matches_query(
+            Some("user+name=Jane+Doe&tag=c++"),
+            &required
+        ));
+        assert!(!matches_query(
+            Some("user+name=Jane%FF&tag=c%2B%2B"),
+            &required
+        ));
+    }
+
+    #[test]
+    fn test_https_redirect_keeps_path_and_query() {
+        let req = Request::get("/a/b?c=1")
+            .header("Host", "example.com:8080")
+            .body(())
+            .unwrap();
+        let response = https_redirect(&req, 308);
+        assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+        assert_eq!(response.headers()[LOCATION], "https://example.com/a/b?c=1");
+
+        let req = Request::get("/").body(()).unwrap();
+        let response = https_redirect(&req, 301);
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+}
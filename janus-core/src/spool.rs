@@ -0,0 +1,216 @@
+//! Request bodies spooled to disk
+//!
+//! Routes with `body_spool` read the request body before proxying it, as
+//! routes without streaming do, but keep bodies over the memory limit in a
+//! temporary file. The file is deleted once the request and every body sent
+//! upstream from it are dropped, so it goes away whether the request
+//! succeeds, fails or the client disconnects.
+
+use bytes::Bytes;
+use futures::stream;
+use http_body_util::{combinators::BoxBody, BodyExt, Full, StreamBody};
+use hyper::body::{Frame, Incoming};
+use janus_common::config::BodySpoolConfig;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tracing::warn;
+
+/// Request body sent upstream, whatever it is read from
+pub type UploadBody = BoxBody<Bytes, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Size of the chunks a spool file is sent upstream in
+const READ_CHUNK_BYTES: usize = 64 * 1024;
+
+/// Spool usage across every route
+#[derive(Debug, Default)]
+pub struct SpoolUsage {
+    /// Bytes currently on disk
+    bytes: AtomicU64,
+    /// Bodies written to disk so far
+    spills: AtomicU64,
+    /// Source of unique file names
+    files: AtomicU64,
+}
+
+impl SpoolUsage {
+    /// Bytes of request bodies currently on disk
+    pub fn bytes(&self) -> u64 {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    /// Request bodies spooled to disk so far
+    pub fn spills(&self) -> u64 {
+        self.spills.load(Ordering::Relaxed)
+    }
+
+    /// Count `len` more bytes on disk, unless that would go over `max`
+    fn reserve(&self, len: u64, max: u64) -> bool {
+        self.bytes
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bytes| {
+                bytes.checked_add(len).filter(|total| *total <= max)
+            })
+            .is_ok()
+    }
+}
+
+/// Why a request body could not be spooled
+#[derive(Debug, thiserror::Error)]
+pub enum SpoolError {
+    /// The client's body could not be read
+    #[error("{0}")]
+    Client(String),
+
+    /// The body would take the spool over its size limit
+    #[error("spool is full")]
+    Full,
+
+    /// The spool file could not be written
+    #[error("spool file error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+/// Request body read in full, so it can be sent more than once
+pub enum SpooledBody {
+    Memory(Bytes),
+    File(Arc<SpoolFile>),
+}
+
+impl SpooledBody {
+    /// Body to send upstream, from the start
+    pub async fn upload(&self) -> std::io::Result<UploadBody> {
+        match self {
+            SpooledBody::Memory(bytes) => Ok(Full::new(bytes.clone())
+                .map_err(|never| match never {})
+                .boxed()),
+            SpooledBody::File(spool) => spool.upload().await,
+        }
+    }
+}
+
+/// Spool file of one request body, deleted when dropped
+pub struct SpoolFile {
+    path: PathBuf,
+    file: File,
+    len: u64,
+    usage: Arc<SpoolUsage>,
+}
+
+impl SpoolFile {
+    async fn create(settings: &BodySpoolConfig, usage: &Arc<SpoolUsage>) -> std::io::Result<Self> {
+        let directory = settings
+            .directory
+            .as_ref()
+            .map_or_else(std::env::temp_dir, PathBuf::from);
+        let path = directory.join(format!(
+            "janus-spool-{}-{}",
+            std::process::id(),
+            usage.files.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = tokio::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .await?;
+        Ok(Self {
+            path,
+            file,
+            len: 0,
+            usage: usage.clone(),
+        })
+    }
+
+    async fn write(&mut self, data: &[u8], max_bytes: u64) -> Result<(), SpoolError> {
+        let len = data.len() as u64;
+        if !self.usage.reserve(len, max_bytes) {
+            return Err(SpoolError::Full);
+        }
+        self.len += len;
+        self.file.write_all(data).await?;
+        Ok(())
+    }
+
+    /// Stream the file from its start, keeping it until the stream is
+    /// dropped
+    async fn upload(self: &Arc<Self>) -> std::io::Result<UploadBody> {
+        let file = File::open(&self.path).await?;
+        let chunks = stream::unfold((file, self.clone()), |(mut file, spool)| async move {
+            let mut buf = vec![0; READ_CHUNK_BYTES];
+            match file.read(&mut buf).await {
+                Ok(0) => None,
+                Ok(n) => {
+                    buf.truncate(n);
+                    Some((Ok(Frame::data(Bytes::from(buf))), (file, spool)))
+                }
+                Err(e) => Some((Err(e.into()), (file, spool))),
+            }
+        });
+        Ok(BodyExt::boxed(StreamBody::new(chunks)))
+    }
+}
+
+impl Drop for SpoolFile {
+    fn drop(&mut self) {
+        self.usage.bytes.fetch_sub(self.len, Ordering::Relaxed);
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            warn!("Failed to remove spool file {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// Read a request body, keeping it in memory up to the route's limit and
+/// in a spool file beyond it
+pub async fn read_body(
+    mut body: Incoming,
+    settings: &BodySpoolConfig,
+    usage: &Arc<SpoolUsage>,
+    max_bytes: u64,
+) -> Result<SpooledBody, SpoolError> {
+    let mut buffered = Vec::new();
+    let mut spool: Option<SpoolFile> = None;
+
+    while let Some(frame) = body.frame().await {
+        let frame = frame.map_err(|e| SpoolError::Client(e.to_string()))?;
+        let Ok(data) = frame.into_data() else {
+            continue;
+        };
+        if let Some(ref mut spool) = spool {
+            spool.write(&data, max_bytes).await?;
+        } else if (buffered.len() + data.len()) as u64 > settings.memory_limit_bytes {
+            let mut file = SpoolFile::create(settings, usage).await?;
+            usage.spills.fetch_add(1, Ordering::Relaxed);
+            file.write(&buffered, max_bytes).await?;
+            file.write(&data, max_bytes).await?;
+            buffered = Vec::new();
+            spool = Some(file);
+        } else {
+            buffered.extend_from_slice(&data);
+        }
+    }
+
+    match spool {
+        Some(mut spool) => {
+            spool.file.flush().await?;
+            Ok(SpooledBody::File(Arc::new(spool)))
+        }
+        None => Ok(SpooledBody::Memory(Bytes::from(buffered))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reserve_stops_at_limit() {
+        let usage = SpoolUsage::default();
+        assert!(usage.reserve(60, 100));
+        assert!(!usage.reserve(41, 100));
+        assert!(usage.reserve(40, 100));
+        assert_eq!(usage.bytes(), 100);
+        assert!(!usage.reserve(u64::MAX, u64::MAX));
+    }
+}
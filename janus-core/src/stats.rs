@@ -0,0 +1,1048 @@
+//! Server statistics tracking
+
+use hyper::StatusCode;
+use janus_common::config::ErrorLogConfig;
+use janus_common::{
+    AuthRequestStats, ClientStats, RouteStats, RouteStatsDetail, StatusCodeStats, UpstreamStats,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Server-wide counters, updated on every request without taking a lock
+#[derive(Debug, Default)]
+pub struct Counters {
+    /// Total requests handled
+    pub total_requests: AtomicU64,
+
+    /// Total connections accepted
+    pub total_connections: AtomicU64,
+
+    /// Connections currently open
+    pub active_connections: AtomicU64,
+
+    /// Connections turned away by connection limits
+    pub rejected_connections: AtomicU64,
+
+    /// Requests left out of the access log by sampling
+    pub access_log_sampled_out: AtomicU64,
+
+    /// Requests turned away for an overlong URI
+    pub uri_too_long: AtomicU64,
+
+    /// Requests turned away for too many or too large headers
+    pub headers_too_large: AtomicU64,
+
+    /// Requests proxied by a configured route
+    pub route_matches: AtomicU64,
+
+    /// Requests that fell through to the default upstream
+    pub default_matches: AtomicU64,
+
+    /// Total bytes received
+    pub bytes_received: AtomicU64,
+
+    /// Total bytes sent
+    pub bytes_sent: AtomicU64,
+
+    /// Responses by status class, each on its own cache line since every
+    /// request updates one of them
+    success: PaddedCounter,
+    redirect: PaddedCounter,
+    client_error: PaddedCounter,
+    server_error: PaddedCounter,
+}
+
+/// A counter that does not share a cache line with its neighbours
+#[derive(Debug, Default)]
+#[repr(align(64))]
+struct PaddedCounter(AtomicU64);
+
+impl Counters {
+    /// Count a response by its status class
+    pub fn record_status(&self, status: StatusCode) {
+        let counter = match status.as_u16() {
+            200..=299 => &self.success,
+            300..=399 => &self.redirect,
+            400..=499 => &self.client_error,
+            500.. => &self.server_error,
+            _ => return,
+        };
+        counter.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Response status code counts
+    pub fn status_codes(&self) -> StatusCodeStats {
+        StatusCodeStats {
+            success: self.success.0.load(Ordering::Relaxed),
+            redirect: self.redirect.0.load(Ordering::Relaxed),
+            client_error: self.client_error.0.load(Ordering::Relaxed),
+            server_error: self.server_error.0.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Fraction of requests answered with a 5xx status
+    pub fn error_rate(&self) -> f64 {
+        self.server_error.0.load(Ordering::Relaxed) as f64 / self.requests().max(1) as f64
+    }
+
+    /// Fraction of requests answered with a 4xx status
+    pub fn client_error_rate(&self) -> f64 {
+        self.client_error.0.load(Ordering::Relaxed) as f64 / self.requests().max(1) as f64
+    }
+
+    fn requests(&self) -> u64 {
+        self.total_requests.load(Ordering::Relaxed)
+    }
+
+    /// Zero the request counters; connection counts are left alone, as
+    /// they describe the listener rather than traffic
+    pub fn reset(&self) {
+        for counter in [
+            &self.total_requests,
+            &self.access_log_sampled_out,
+            &self.uri_too_long,
+            &self.headers_too_large,
+            &self.route_matches,
+            &self.default_matches,
+            &self.bytes_received,
+            &self.bytes_sent,
+            &self.success.0,
+            &self.redirect.0,
+            &self.client_error.0,
+            &self.server_error.0,
+        ] {
+            counter.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Statistics updated only on rarer events, such as auth subrequests and
+/// logged errors
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Stats {
+    /// Auth subrequests per route
+    pub auth_requests: HashMap<String, AuthRequestStats>,
+
+    /// Repeated errors per upstream or static directory, for log suppression
+    #[serde(skip)]
+    pub error_log: ErrorLog,
+}
+
+impl Stats {
+    /// Forget all traffic, keeping the error log's suppression state
+    pub fn reset(&mut self) {
+        *self = Self {
+            error_log: std::mem::take(&mut self.error_log),
+            ..Self::default()
+        };
+    }
+}
+
+/// Shards the per-key statistics are split across, so requests for
+/// different routes or clients rarely wait on each other
+const SHARDS: usize = 16;
+
+/// Statistics kept per route, client and upstream, updated on every
+/// request without a server-wide lock
+#[derive(Debug, Default)]
+pub struct Traffic {
+    /// Requests, errors, latency and bytes per route, static mount or
+    /// default upstream
+    routes: Keyed<RouteTraffic>,
+
+    /// Requests, failures, retries and latency per upstream
+    upstreams: Keyed<UpstreamTraffic>,
+
+    /// Failed proxy requests per failure class
+    pub proxy_errors: Keyed<AtomicU64>,
+
+    /// Malformed responses per upstream
+    pub upstream_protocol_errors: Keyed<AtomicU64>,
+
+    /// Requests refused for lack of an available backend, per upstream
+    pub no_backend_available: Keyed<AtomicU64>,
+
+    /// Busiest client addresses
+    clients: ClientShards,
+}
+
+impl Traffic {
+    /// Count a request against the client that sent it and, if known, the
+    /// route that served it, with the time taken to produce its response
+    /// head and the body bytes each way
+    pub fn record_request(
+        &self,
+        client: IpAddr,
+        route: Option<&str>,
+        status: StatusCode,
+        latency: Duration,
+        bytes_received: u64,
+        bytes_sent: u64,
+    ) {
+        let error = status.is_client_error() || status.is_server_error();
+        self.clients.record(client, bytes_sent, error);
+        if let Some(route) = route {
+            self.record_route(route, status, latency, bytes_received, bytes_sent);
+        }
+    }
+
+    /// Count response bytes of a request already recorded, for bodies
+    /// whose length was only known once they were sent
+    pub fn record_bytes_sent(&self, client: IpAddr, route: Option<&str>, bytes: u64) {
+        self.clients.add_bytes(client, bytes);
+        if let Some(route) = route {
+            self.routes.update(route, |traffic| {
+                traffic.bytes_sent.fetch_add(bytes, Ordering::Relaxed);
+            });
+        }
+    }
+
+    /// Count a request against the route that served it
+    pub fn record_route(
+        &self,
+        route: &str,
+        status: StatusCode,
+        latency: Duration,
+        bytes_received: u64,
+        bytes_sent: u64,
+    ) {
+        self.routes.update(route, |traffic| {
+            traffic.requests.fetch_add(1, Ordering::Relaxed);
+            if status.is_server_error() {
+                traffic.errors.fetch_add(1, Ordering::Relaxed);
+            }
+            traffic.latency.record(latency);
+            let nanos = u64::try_from(latency.as_nanos()).unwrap_or(u64::MAX);
+            traffic.max_latency.fetch_max(nanos, Ordering::Relaxed);
+            traffic
+                .bytes_received
+                .fetch_add(bytes_received, Ordering::Relaxed);
+            traffic.bytes_sent.fetch_add(bytes_sent, Ordering::Relaxed);
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since| since.as_millis() as u64);
+            traffic.last_request.fetch_max(now, Ordering::Relaxed);
+        });
+    }
+
+    /// Count a request forwarded to an upstream, with the time taken to
+    /// get its response head, retries included
+    pub fn record_upstream(&self, upstream: &str, latency: Duration, failed: bool) {
+        self.upstreams.update(upstream, |traffic| {
+            traffic.requests.fetch_add(1, Ordering::Relaxed);
+            if failed {
+                traffic.failures.fetch_add(1, Ordering::Relaxed);
+            }
+            let nanos = u64::try_from(latency.as_nanos()).unwrap_or(u64::MAX);
+            traffic.latency.fetch_add(nanos, Ordering::Relaxed);
+        });
+    }
+
+    /// Count a further attempt at a request to an upstream
+    pub fn record_retry(&self, upstream: &str) {
+        self.upstreams.update(upstream, |traffic| {
+            traffic.retries.fetch_add(1, Ordering::Relaxed);
+        });
+    }
+
+    /// Requests, failures, retries and average latency per upstream, with
+    /// the other statistics left for the caller
+    pub fn upstreams(&self) -> HashMap<String, UpstreamStats> {
+        let mut upstreams = HashMap::new();
+        self.upstreams.for_each(|upstream, traffic| {
+            let requests = traffic.requests.load(Ordering::Relaxed);
+            let latency = Duration::from_nanos(traffic.latency.load(Ordering::Relaxed));
+            let stats = UpstreamStats {
+                requests,
+                failures: traffic.failures.load(Ordering::Relaxed),
+                retries: traffic.retries.load(Ordering::Relaxed),
+                avg_response_time_ms: latency.as_secs_f64() * 1000.0 / requests.max(1) as f64,
+                ..Default::default()
+            };
+            upstreams.insert(upstream.to_string(), stats);
+        });
+        upstreams
+    }
+
+    /// Forget all traffic
+    pub fn reset(&self) {
+        self.routes.clear();
+        self.upstreams.clear();
+        self.proxy_errors.clear();
+        self.upstream_protocol_errors.clear();
+        self.no_backend_available.clear();
+        self.clients.reset();
+    }
+
+    /// Requests and errors per route
+    pub fn routes(&self) -> HashMap<String, RouteStats> {
+        let mut routes = HashMap::new();
+        self.routes.for_each(|route, traffic| {
+            let stats = RouteStats {
+                requests: traffic.requests.load(Ordering::Relaxed),
+                errors: traffic.errors.load(Ordering::Relaxed),
+            };
+            routes.insert(route.to_string(), stats);
+        });
+        routes
+    }
+
+    /// Everything known about one route's traffic, or `None` if it has
+    /// served no requests
+    pub fn route_detail(&self, route: &str) -> Option<RouteStatsDetail> {
+        self.routes.get(route, |traffic| {
+            let requests = traffic.requests.load(Ordering::Relaxed);
+            let errors = traffic.errors.load(Ordering::Relaxed);
+            let percentile = |fraction| traffic.latency.percentile(fraction).unwrap_or(0.0);
+            let max_latency = Duration::from_nanos(traffic.max_latency.load(Ordering::Relaxed));
+            let last_request = traffic.last_request.load(Ordering::Relaxed);
+            RouteStatsDetail {
+                path: route.to_string(),
+                requests,
+                error_rate: errors as f64 / requests.max(1) as f64,
+                p50_ms: percentile(0.5),
+                p95_ms: percentile(0.95),
+                p99_ms: percentile(0.99),
+                max_ms: max_latency.as_secs_f64() * 1000.0,
+                bytes_sent: traffic.bytes_sent.load(Ordering::Relaxed),
+                bytes_received: traffic.bytes_received.load(Ordering::Relaxed),
+                last_request: (last_request > 0).then(|| {
+                    let at = UNIX_EPOCH + Duration::from_millis(last_request);
+                    chrono::DateTime::<chrono::Utc>::from(at)
+                        .to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+                }),
+            }
+        })
+    }
+
+    /// Up to `limit` routes with the highest P99 latency, slowest first, as
+    /// route and milliseconds
+    pub fn slowest_routes(&self, limit: usize) -> Vec<(String, f64)> {
+        let mut routes = Vec::new();
+        self.routes.for_each(|route, traffic| {
+            if let Some(p99) = traffic.latency.percentile(0.99) {
+                routes.push((route.to_string(), p99));
+            }
+        });
+        routes.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        routes.truncate(limit);
+        routes
+    }
+
+    /// Up to `limit` routes with the most requests, busiest first, as route
+    /// and request count
+    pub fn busiest_routes(&self, limit: usize) -> Vec<(String, u64)> {
+        let mut routes: Vec<_> = self
+            .routes()
+            .into_iter()
+            .map(|(route, stats)| (route, stats.requests))
+            .collect();
+        routes.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        routes.truncate(limit);
+        routes
+    }
+
+    /// The clients with the most requests, busiest first
+    pub fn top_clients(&self, limit: usize) -> Vec<ClientStats> {
+        self.clients.top(limit)
+    }
+}
+
+/// Values per key, split across shards that are locked separately
+///
+/// Updates take a shard's read lock, so values synchronize themselves;
+/// only the first update for a key takes the write lock to insert it.
+#[derive(Debug)]
+pub struct Keyed<V> {
+    hasher: RandomState,
+    shards: Vec<RwLock<HashMap<String, V>>>,
+}
+
+impl<V> Default for Keyed<V> {
+    fn default() -> Self {
+        Self {
+            hasher: RandomState::new(),
+            shards: (0..SHARDS).map(|_| RwLock::default()).collect(),
+        }
+    }
+}
+
+impl<V: Default> Keyed<V> {
+    fn shard(&self, key: &str) -> &RwLock<HashMap<String, V>> {
+        &self.shards[self.hasher.hash_one(key) as usize % SHARDS]
+    }
+
+    /// Update the value for a key, starting from its default
+    pub fn update(&self, key: &str, update: impl FnOnce(&V)) {
+        let shard = self.shard(key);
+        let values = shard.read().unwrap_or_else(|e| e.into_inner());
+        if let Some(value) = values.get(key) {
+            return update(value);
+        }
+        drop(values);
+
+        let mut values = shard.write().unwrap_or_else(|e| e.into_inner());
+        update(values.entry(key.to_string()).or_default());
+    }
+
+    /// Read the value for a key, if it has one
+    pub fn get<T>(&self, key: &str, read: impl FnOnce(&V) -> T) -> Option<T> {
+        let values = self.shard(key).read().unwrap_or_else(|e| e.into_inner());
+        values.get(key).map(read)
+    }
+
+    /// Visit every key and its value
+    pub fn for_each(&self, mut visit: impl FnMut(&str, &V)) {
+        for shard in &self.shards {
+            let values = shard.read().unwrap_or_else(|e| e.into_inner());
+            for (key, value) in values.iter() {
+                visit(key, value);
+            }
+        }
+    }
+
+    fn clear(&self) {
+        for shard in &self.shards {
+            shard.write().unwrap_or_else(|e| e.into_inner()).clear();
+        }
+    }
+}
+
+impl Keyed<AtomicU64> {
+    /// Add one to a key's count
+    pub fn increment(&self, key: &str) {
+        self.update(key, |count| {
+            count.fetch_add(1, Ordering::Relaxed);
+        });
+    }
+
+    /// Every key's count
+    pub fn counts(&self) -> HashMap<String, u64> {
+        let mut counts = HashMap::new();
+        self.for_each(|key, count| {
+            counts.insert(key.to_string(), count.load(Ordering::Relaxed));
+        });
+        counts
+    }
+}
+
+/// Counts, latency, bytes and recency of one route's requests
+#[derive(Debug, Default)]
+struct RouteTraffic {
+    requests: AtomicU64,
+    /// Requests answered with a 5xx status
+    errors: AtomicU64,
+    latency: LatencyHistogram,
+    /// Longest latency, in nanoseconds
+    max_latency: AtomicU64,
+    /// Request body bytes, where the length was known up front
+    bytes_received: AtomicU64,
+    /// Response body bytes
+    bytes_sent: AtomicU64,
+    /// Milliseconds since the Unix epoch, or 0 before the first request
+    last_request: AtomicU64,
+}
+
+/// Counts and latency of the requests forwarded to one upstream
+#[derive(Debug, Default)]
+struct UpstreamTraffic {
+    requests: AtomicU64,
+    /// Requests that failed or got a 5xx status
+    failures: AtomicU64,
+    /// Further attempts after a retriable failure
+    retries: AtomicU64,
+    /// Total time to response heads, in nanoseconds
+    latency: AtomicU64,
+}
+
+/// Buckets per doubling of latency, so each bucket is about 19% wider than
+/// the one before
+const LATENCY_BUCKETS_PER_DOUBLING: f64 = 4.0;
+
+/// Upper bound of the first latency bucket, in milliseconds
+const LATENCY_MIN_MS: f64 = 0.1;
+
+/// Latency buckets, reaching past 20 minutes
+const LATENCY_BUCKETS: usize = 96;
+
+/// Latencies counted in logarithmic buckets, so percentiles take constant
+/// memory and are accurate to within a bucket's width
+#[derive(Debug)]
+pub struct LatencyHistogram {
+    counts: [AtomicU64; LATENCY_BUCKETS],
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self {
+            counts: std::array::from_fn(|_| AtomicU64::new(0)),
+        }
+    }
+}
+
+impl LatencyHistogram {
+    /// Count one latency
+    pub fn record(&self, latency: Duration) {
+        let ms = latency.as_secs_f64() * 1000.0;
+        let bucket = if ms <= LATENCY_MIN_MS {
+            0
+        } else {
+            ((ms / LATENCY_MIN_MS).log2() * LATENCY_BUCKETS_PER_DOUBLING).ceil() as usize
+        };
+        self.counts[bucket.min(LATENCY_BUCKETS - 1)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Latency in milliseconds that a fraction (0.0-1.0) of requests came
+    /// in under, rounded up to its bucket's bound; `None` with no requests
+    pub fn percentile(&self, fraction: f64) -> Option<f64> {
+        // Read once, so the total agrees with the buckets while they change
+        let counts = self.counts.each_ref().map(|c| c.load(Ordering::Relaxed));
+        let total: u64 = counts.iter().sum();
+        if total == 0 {
+            return None;
+        }
+        let rank = ((total as f64 * fraction).ceil() as u64).clamp(1, total);
+        let mut seen = 0;
+        let bucket = counts.iter().position(|&count| {
+            seen += count;
+            seen >= rank
+        })?;
+        Some(LATENCY_MIN_MS * (bucket as f64 / LATENCY_BUCKETS_PER_DOUBLING).exp2())
+    }
+}
+
+/// Rate limiter for identical error log lines
+///
+/// The first few occurrences of an error are logged as they happen; after
+/// that only a periodic count is logged until the error's scope (an upstream
+/// or static directory) succeeds again.
+#[derive(Debug, Clone, Default)]
+pub struct ErrorLog {
+    errors: HashMap<String, RepeatedError>,
+}
+
+#[derive(Debug, Clone)]
+struct RepeatedError {
+    scope: String,
+    logged: u32,
+    suppressed: u64,
+    since: Instant,
+}
+
+impl ErrorLog {
+    /// Whether no errors are being tracked, so there is nothing to clear
+    pub fn is_empty(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    /// Record an error, returning the line to log, if any
+    pub fn record(
+        &mut self,
+        scope: &str,
+        message: &str,
+        settings: &ErrorLogConfig,
+    ) -> Option<String> {
+        let key = format!("{} {}", scope, message);
+        let now = Instant::now();
+        let error = self
+            .errors
+            .entry(key.clone())
+            .or_insert_with(|| RepeatedError {
+                scope: scope.to_string(),
+                logged: 0,
+                suppressed: 0,
+                since: now,
+            });
+
+        if error.logged < settings.burst {
+            error.logged += 1;
+            error.since = now;
+            return Some(key);
+        }
+
+        error.suppressed += 1;
+        let elapsed = now.duration_since(error.since);
+        if elapsed < Duration::from_secs(settings.summary_interval_secs) {
+            return None;
+        }
+
+        let line = format!(
+            "{}: {} occurrences in last {}s",
+            key,
+            format_count(error.suppressed),
+            elapsed.as_secs()
+        );
+        error.suppressed = 0;
+        error.since = now;
+        Some(line)
+    }
+
+    /// Reset the errors of a scope that has succeeded, returning summaries
+    /// of occurrences that were not yet logged
+    pub fn clear(&mut self, scope: &str) -> Vec<String> {
+        let mut summaries = Vec::new();
+        self.errors.retain(|key, error| {
+            if error.scope != scope {
+                return true;
+            }
+            if error.suppressed > 0 {
+                summaries.push(format!(
+                    "{}: {} more occurrences before recovering",
+                    key,
+                    format_count(error.suppressed)
+                ));
+            }
+            false
+        });
+        summaries
+    }
+}
+
+/// Client addresses tracked at once
+const CLIENT_CAPACITY: usize = 1024;
+
+/// How often client counts are halved, so rankings follow recent traffic
+const CLIENT_DECAY_INTERVAL: Duration = Duration::from_secs(300);
+
+/// Per-client request counts in fixed memory
+///
+/// When full, a new client replaces the one with the fewest requests and
+/// inherits its count (the "space-saving" scheme), so heavy hitters are kept
+/// while the counts of newcomers may be overestimated.
+#[derive(Debug, Clone)]
+pub struct ClientTracker {
+    clients: HashMap<IpAddr, ClientCounts>,
+    capacity: usize,
+    last_decay: Instant,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct ClientCounts {
+    requests: u64,
+    bytes: u64,
+    errors: u64,
+}
+
+impl Default for ClientTracker {
+    fn default() -> Self {
+        Self::with_capacity(CLIENT_CAPACITY)
+    }
+}
+
+impl ClientTracker {
+    fn with_capacity(capacity: usize) -> Self {
+        Self {
+            clients: HashMap::with_capacity(capacity),
+            capacity,
+            last_decay: Instant::now(),
+        }
+    }
+
+    /// Count a request from a client
+    pub fn record(&mut self, ip: IpAddr, bytes: u64, error: bool) {
+        self.record_at(ip, bytes, error, Instant::now());
+    }
+
+    fn record_at(&mut self, ip: IpAddr, bytes: u64, error: bool, now: Instant) {
+        if now.duration_since(self.last_decay) >= CLIENT_DECAY_INTERVAL {
+            self.decay();
+            self.last_decay = now;
+        }
+
+        if !self.clients.contains_key(&ip) && self.clients.len() >= self.capacity {
+            let evicted = self
+                .clients
+                .iter()
+                .min_by_key(|(_, counts)| counts.requests)
+                .map(|(&ip, &counts)| (ip, counts));
+            if let Some((evicted, counts)) = evicted {
+                self.clients.remove(&evicted);
+                self.clients.insert(ip, counts);
+            }
+        }
+
+        let counts = self.clients.entry(ip).or_default();
+        counts.requests += 1;
+        counts.bytes += bytes;
+        if error {
+            counts.errors += 1;
+        }
+    }
+
+    /// Count bytes sent to a client after its request, unless it has been
+    /// replaced since
+    pub fn add_bytes(&mut self, ip: IpAddr, bytes: u64) {
+        if let Some(counts) = self.clients.get_mut(&ip) {
+            counts.bytes += bytes;
+        }
+    }
+
+    /// Halve every count, forgetting clients that have gone quiet
+    fn decay(&mut self) {
+        self.clients.retain(|_, counts| {
+            counts.requests /= 2;
+            counts.bytes /= 2;
+            counts.errors /= 2;
+            counts.requests > 0
+        });
+    }
+
+    /// Up to `limit` clients with the most requests, busiest first
+    fn ranked(&self, limit: usize) -> Vec<(IpAddr, ClientCounts)> {
+        let mut clients: Vec<_> = self.clients.iter().map(|(&ip, &c)| (ip, c)).collect();
+        rank_clients(&mut clients, limit);
+        clients
+    }
+}
+
+/// Sort clients busiest first and keep the first `limit`
+fn rank_clients(clients: &mut Vec<(IpAddr, ClientCounts)>, limit: usize) {
+    clients.sort_by(|a, b| b.1.requests.cmp(&a.1.requests).then(a.0.cmp(&b.0)));
+    clients.truncate(limit);
+}
+
+fn client_stats(clients: Vec<(IpAddr, ClientCounts)>) -> Vec<ClientStats> {
+    clients
+        .into_iter()
+        .map(|(ip, counts)| ClientStats {
+            address: ip.to_string(),
+            requests: counts.requests,
+            bytes: counts.bytes,
+            errors: counts.errors,
+        })
+        .collect()
+}
+
+/// Client trackers for separate parts of the address space, each with its
+/// own lock and a share of the capacity
+#[derive(Debug)]
+struct ClientShards {
+    hasher: RandomState,
+    shards: Vec<Mutex<ClientTracker>>,
+}
+
+impl Default for ClientShards {
+    fn default() -> Self {
+        Self {
+            hasher: RandomState::new(),
+            shards: (0..SHARDS)
+                .map(|_| Mutex::new(ClientTracker::with_capacity(CLIENT_CAPACITY / SHARDS)))
+                .collect(),
+        }
+    }
+}
+
+impl ClientShards {
+    fn shard(&self, ip: &IpAddr) -> &Mutex<ClientTracker> {
+        &self.shards[self.hasher.hash_one(ip) as usize % SHARDS]
+    }
+
+    fn record(&self, ip: IpAddr, bytes: u64, error: bool) {
+        let mut shard = self.shard(&ip).lock().unwrap_or_else(|e| e.into_inner());
+        shard.record(ip, bytes, error);
+    }
+
+    fn add_bytes(&self, ip: IpAddr, bytes: u64) {
+        let mut shard = self.shard(&ip).lock().unwrap_or_else(|e| e.into_inner());
+        shard.add_bytes(ip, bytes);
+    }
+
+    fn top(&self, limit: usize) -> Vec<ClientStats> {
+        let mut clients = Vec::new();
+        for shard in &self.shards {
+            let shard = shard.lock().unwrap_or_else(|e| e.into_inner());
+            clients.extend(shard.ranked(limit));
+        }
+        rank_clients(&mut clients, limit);
+        client_stats(clients)
+    }
+
+    fn reset(&self) {
+        for shard in &self.shards {
+            let mut shard = shard.lock().unwrap_or_else(|e| e.into_inner());
+            *shard = ClientTracker::with_capacity(shard.capacity);
+        }
+    }
+}
+
+/// Truncate an address for privacy: the last octet of IPv4, or the last
+/// 80 bits of IPv6
+pub fn anonymize_ip(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+            IpAddr::V4(Ipv4Addr::new(a, b, c, 0))
+        }
+        IpAddr::V6(ip) => {
+            let segments = ip.segments();
+            IpAddr::V6(Ipv6Addr::new(
+                segments[0],
+                segments[1],
+                segments[2],
+                0,
+                0,
+                0,
+                0,
+                0,
+            ))
+        }
+    }
+}
+
+/// Format a count with thousands separators
+fn format_count(count: u64) -> String {
+    let digits = count.to_string();
+    let mut formatted = String::with_capacity(digits.len() + digits.len() / 3);
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            formatted.push(',');
+        }
+        formatted.push(digit);
+    }
+    formatted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(burst: u32, summary_interval_secs: u64) -> ErrorLogConfig {
+        ErrorLogConfig {
+            burst,
+            summary_interval_secs,
+        }
+    }
+
+    #[test]
+    fn test_repeated_errors_are_suppressed_after_burst() {
+        let mut log = ErrorLog::default();
+        let settings = settings(2, 60);
+
+        let lines: Vec<_> = (0..5)
+            .filter_map(|_| log.record("upstream 'api'", "connection refused", &settings))
+            .collect();
+        assert_eq!(lines, vec!["upstream 'api' connection refused"; 2]);
+
+        // A different error is tracked separately
+        assert!(log
+            .record("upstream 'api'", "request timed out", &settings)
+            .is_some());
+
+        assert_eq!(
+            log.clear("upstream 'api'"),
+            vec!["upstream 'api' connection refused: 3 more occurrences before recovering"]
+        );
+        assert!(log
+            .record("upstream 'api'", "connection refused", &settings)
+            .is_some());
+    }
+
+    #[test]
+    fn test_summary_logged_each_interval() {
+        let mut log = ErrorLog::default();
+        let settings = settings(1, 0);
+
+        assert!(log.record("upstream 'api'", "refused", &settings).is_some());
+        assert_eq!(
+            log.record("upstream 'api'", "refused", &settings).unwrap(),
+            "upstream 'api' refused: 1 occurrences in last 0s"
+        );
+    }
+
+    #[test]
+    fn test_counters_by_status_class() {
+        let counters = Counters::default();
+        counters.total_requests.fetch_add(4, Ordering::Relaxed);
+        for status in [
+            StatusCode::OK,
+            StatusCode::NOT_MODIFIED,
+            StatusCode::NOT_FOUND,
+            StatusCode::BAD_GATEWAY,
+        ] {
+            counters.record_status(status);
+        }
+
+        let codes = counters.status_codes();
+        assert_eq!(
+            (
+                codes.success,
+                codes.redirect,
+                codes.client_error,
+                codes.server_error
+            ),
+            (1, 1, 1, 1)
+        );
+        assert_eq!(counters.error_rate(), 0.25);
+        assert_eq!(counters.client_error_rate(), 0.25);
+    }
+
+    #[test]
+    fn test_record_route_counts_server_errors() {
+        let stats = Traffic::default();
+        let latency = Duration::from_millis(5);
+        stats.record_route("/api/*", StatusCode::OK, latency, 0, 0);
+        stats.record_route("/api/*", StatusCode::NOT_FOUND, latency, 0, 0);
+        stats.record_route("/api/*", StatusCode::BAD_GATEWAY, latency, 0, 0);
+        stats.record_route("static:/assets/", StatusCode::OK, latency, 0, 0);
+
+        let routes = stats.routes();
+        assert_eq!(
+            routes["/api/*"],
+            RouteStats {
+                requests: 3,
+                errors: 1
+            }
+        );
+        assert_eq!(routes["static:/assets/"].requests, 1);
+    }
+
+    #[test]
+    fn test_route_detail() {
+        let stats = Traffic::default();
+        assert!(stats.route_detail("/api/*").is_none());
+
+        for ms in 1..=100 {
+            let status = if ms % 4 == 0 {
+                StatusCode::BAD_GATEWAY
+            } else {
+                StatusCode::OK
+            };
+            stats.record_route("/api/*", status, Duration::from_millis(ms), 10, 100);
+        }
+        let detail = stats.route_detail("/api/*").unwrap();
+        assert_eq!(detail.path, "/api/*");
+        assert_eq!(detail.requests, 100);
+        assert_eq!(detail.error_rate, 0.25);
+        assert!(detail.p50_ms <= detail.p95_ms && detail.p95_ms <= detail.p99_ms);
+        assert_eq!(detail.max_ms, 100.0);
+        assert_eq!((detail.bytes_received, detail.bytes_sent), (1000, 10_000));
+        assert!(detail.last_request.unwrap().ends_with('Z'));
+    }
+
+    #[test]
+    fn test_latency_percentiles_within_a_bucket() {
+        let latency = LatencyHistogram::default();
+        assert_eq!(latency.percentile(0.99), None);
+
+        for ms in 1..=100 {
+            latency.record(Duration::from_millis(ms));
+        }
+        let p50 = latency.percentile(0.5).unwrap();
+        let p99 = latency.percentile(0.99).unwrap();
+        assert!((50.0..50.0 * 1.19).contains(&p50), "p50 = {}", p50);
+        assert!((99.0..99.0 * 1.19).contains(&p99), "p99 = {}", p99);
+
+        // Beyond the last bucket still counts, at its bound
+        latency.record(Duration::from_secs(24 * 3600));
+        assert!(latency.percentile(1.0).unwrap() > 20.0 * 60.0 * 1000.0);
+    }
+
+    #[test]
+    fn test_slowest_routes_by_p99() {
+        let stats = Traffic::default();
+        for (route, ms) in [("/fast", 2), ("/slow", 800), ("/medium", 40)] {
+            stats.record_route(route, StatusCode::OK, Duration::from_millis(ms), 0, 0);
+        }
+        let slowest: Vec<_> = stats
+            .slowest_routes(2)
+            .into_iter()
+            .map(|(route, _)| route)
+            .collect();
+        assert_eq!(slowest, ["/slow", "/medium"]);
+    }
+
+    #[test]
+    fn test_busiest_routes_by_requests() {
+        let stats = Traffic::default();
+        for (route, requests) in [("/a", 3), ("/b", 5), ("/c", 3)] {
+            for _ in 0..requests {
+                stats.record_route(route, StatusCode::OK, Duration::ZERO, 0, 0);
+            }
+        }
+        // Ties are broken by route, so the order is stable between refreshes
+        assert_eq!(
+            stats.busiest_routes(2),
+            [("/b".to_string(), 5), ("/a".to_string(), 3)]
+        );
+    }
+
+    #[test]
+    fn test_client_tracker_keeps_heavy_hitters() {
+        let mut tracker = ClientTracker::with_capacity(2);
+        let heavy: IpAddr = "10.0.0.1".parse().unwrap();
+        for _ in 0..10 {
+            tracker.record(heavy, 100, false);
+        }
+        tracker.record("10.0.0.2".parse().unwrap(), 0, true);
+        // Evicts 10.0.0.2, inheriting its count
+        tracker.record("10.0.0.3".parse().unwrap(), 0, false);
+
+        let top = client_stats(tracker.ranked(10));
+        assert_eq!(top.len(), 2);
+        assert_eq!(top[0].address, "10.0.0.1");
+        assert_eq!((top[0].requests, top[0].bytes), (10, 1000));
+        assert_eq!(top[1].address, "10.0.0.3");
+        assert_eq!((top[1].requests, top[1].errors), (2, 1));
+        assert_eq!(client_stats(tracker.ranked(1)).len(), 1);
+    }
+
+    #[test]
+    fn test_client_tracker_decays() {
+        let mut tracker = ClientTracker::default();
+        let start = tracker.last_decay;
+        let busy: IpAddr = "10.0.0.1".parse().unwrap();
+        for _ in 0..4 {
+            tracker.record_at(busy, 0, false, start);
+        }
+        tracker.record_at("10.0.0.2".parse().unwrap(), 0, false, start);
+
+        let later: IpAddr = "10.0.0.3".parse().unwrap();
+        tracker.record_at(later, 0, false, start + CLIENT_DECAY_INTERVAL);
+
+        let top = client_stats(tracker.ranked(10));
+        assert_eq!(top.len(), 2);
+        assert_eq!(top[0].requests, 2);
+        assert_eq!(top[1].address, "10.0.0.3");
+    }
+
+    #[test]
+    fn test_top_clients_across_shards() {
+        let traffic = Traffic::default();
+        for i in 0..100u8 {
+            let ip = IpAddr::V4(Ipv4Addr::new(10, 0, 0, i));
+            for _ in 0..=u32::from(i) % 7 {
+                traffic.record_request(ip, None, StatusCode::OK, Duration::ZERO, 0, 10);
+            }
+        }
+        let top = traffic.top_clients(3);
+        assert_eq!(top.len(), 3);
+        assert!(top.iter().all(|client| client.requests == 7));
+        // Ties are broken by address, wherever the clients are kept
+        assert_eq!(top[0].address, "10.0.0.6");
+        assert_eq!(top[1].address, "10.0.0.13");
+
+        traffic.reset();
+        assert!(traffic.top_clients(3).is_empty());
+    }
+
+    #[test]
+    fn test_anonymize_ip() {
+        let anonymize = |ip: &str| anonymize_ip(ip.parse().unwrap()).to_string();
+        assert_eq!(anonymize("192.168.1.77"), "192.168.1.0");
+        assert_eq!(anonymize("2001:db8:abcd:12:1:2:3:4"), "2001:db8:abcd::");
+    }
+
+    #[test]
+    fn test_format_count() {
+        assert_eq!(format_count(0), "0");
+        assert_eq!(format_count(999), "999");
+        assert_eq!(format_count(4812), "4,812");
+        assert_eq!(format_count(1234567), "1,234,567");
+    }
+}
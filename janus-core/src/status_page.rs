@@ -0,0 +1,253 @@
+//! Built-in status page, showing what the TUI's Status and Stats tabs do
+//!
+//! Rendered from the same [`ServerStatus`] and [`ServerStats`] the
+//! management API sends, so the two cannot disagree.
+
+use crate::server::{error_response, escape_html, full_body};
+use crate::AppState;
+use bytes::Bytes;
+use http_body_util::combinators::BoxBody;
+use hyper::header::{HeaderValue, AUTHORIZATION, CACHE_CONTROL, WWW_AUTHENTICATE};
+use hyper::{Request, Response, StatusCode};
+use janus_common::config::StatusPageConfig;
+use janus_common::{JanusConfig, ServerStats, ServerStatus};
+use std::convert::Infallible;
+use std::fmt::Write;
+
+/// Whether a request is for the status page
+pub fn is_status_page(config: &JanusConfig, path: &str) -> bool {
+    let page = &config.server.status_page;
+    page.enabled && path == page.path
+}
+
+/// Answer a request for the status page
+pub async fn status_page_response<B>(
+    state: &AppState,
+    config: &JanusConfig,
+    req: &Request<B>,
+) -> Response<BoxBody<Bytes, Infallible>> {
+    let mut response = if authorized(&config.server.status_page, req) {
+        let status = state.status_snapshot().await;
+        let stats = state.stats_snapshot().await;
+        if query_param(req, "format") == Some("json") {
+            let body = serde_json::json!({ "status": status, "stats": stats });
+            Response::builder()
+                .header("Content-Type", "application/json")
+                .body(full_body(body.to_string().into_bytes()))
+                .unwrap()
+        } else {
+            Response::builder()
+                .header("Content-Type", "text/html; charset=utf-8")
+                .body(full_body(render(&status, &stats).into_bytes()))
+                .unwrap()
+        }
+    } else {
+        let mut response = error_response(StatusCode::UNAUTHORIZED, "Unauthorized");
+        response
+            .headers_mut()
+            .insert(WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+        response
+    };
+    response
+        .headers_mut()
+        .insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    response
+}
+
+/// Whether the request carries the page's token, if it has one
+fn authorized<B>(page: &StatusPageConfig, req: &Request<B>) -> bool {
+    let Some(ref token) = page.token else {
+        return true;
+    };
+    let bearer = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    bearer == Some(token.as_str()) || query_param(req, "token") == Some(token.as_str())
+}
+
+/// Value of a query parameter, as sent
+fn query_param<'a, B>(req: &'a Request<B>, name: &str) -> Option<&'a str> {
+    req.uri()
+        .query()?
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+/// The page itself
+fn render(status: &ServerStatus, stats: &ServerStats) -> String {
+    let mut rows = vec![
+        ("Version", status.version.clone()),
+        ("Uptime", format_duration(status.uptime_secs)),
+        ("Listening on", status.listen_address.clone()),
+        (
+            "Connections",
+            format!(
+                "{} active ({} idle), {} accepted, {} rejected",
+                status.active_connections,
+                status.idle_connections,
+                status.connections_accepted,
+                status.connections_rejected
+            ),
+        ),
+        ("Routes", status.route_count.to_string()),
+        (
+            "Upstreams",
+            format!(
+                "{} ({} draining)",
+                status.upstream_count, status.draining_upstreams
+            ),
+        ),
+        (
+            "Requests",
+            format!(
+                "{} ({:.2}/s)",
+                stats.total_requests, stats.requests_per_second
+            ),
+        ),
+        (
+            "Status codes",
+            format!(
+                "2xx {} / 3xx {} / 4xx {} / 5xx {}",
+                stats.status_codes.success,
+                stats.status_codes.redirect,
+                stats.status_codes.client_error,
+                stats.status_codes.server_error
+            ),
+        ),
+        ("Error rate", format!("{:.2}%", stats.error_rate * 100.0)),
+        (
+            "Traffic",
+            format!(
+                "{} bytes received, {} bytes sent",
+                stats.bytes_received, stats.bytes_sent
+            ),
+        ),
+    ];
+    if let Some(ref profile) = status.profile {
+        rows.insert(3, ("Profile", profile.clone()));
+    }
+
+    let mut page = String::from(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>Janus status</title>
+<style>
+body { font-family: sans-serif; margin: 2em; }
+table { border-collapse: collapse; margin-bottom: 2em; }
+th, td { text-align: left; padding: 0.25em 1em 0.25em 0; }
+.down { color: #c00; font-weight: bold; }
+</style>
+</head>
+<body>
+<h1>Janus status</h1>
+<table>
+"#,
+    );
+    for (label, value) in rows {
+        let _ = writeln!(
+            page,
+            "<tr><th>{}</th><td>{}</td></tr>",
+            label,
+            escape_html(&value)
+        );
+    }
+    page.push_str(
+        "</table>\n<h2>Upstreams</h2>\n<table>\n\
+         <tr><th>Name</th><th>Healthy</th><th>Requests</th><th>Failures</th><th>Avg ms</th></tr>\n",
+    );
+    let mut upstreams: Vec<_> = stats.upstream_stats.iter().collect();
+    upstreams.sort_by_key(|(name, _)| name.as_str());
+    for (name, upstream) in upstreams {
+        let class = if upstream.healthy_servers == 0 {
+            " class=\"down\""
+        } else {
+            ""
+        };
+        let _ = writeln!(
+            page,
+            "<tr{}><td>{}</td><td>{}/{}</td><td>{}</td><td>{}</td><td>{:.1}</td></tr>",
+            class,
+            escape_html(name),
+            upstream.healthy_servers,
+            upstream.total_servers,
+            upstream.requests,
+            upstream.failures,
+            upstream.avg_response_time_ms
+        );
+    }
+    page.push_str("</table>\n</body>\n</html>\n");
+    page
+}
+
+/// Duration as days, hours, minutes and seconds, largest first
+fn format_duration(secs: u64) -> String {
+    let (days, hours, minutes, seconds) = (
+        secs / 86400,
+        secs % 86400 / 3600,
+        secs % 3600 / 60,
+        secs % 60,
+    );
+    if days > 0 {
+        format!("{}d {}h {}m {}s", days, hours, minutes, seconds)
+    } else if hours > 0 {
+        format!("{}h {}m {}s", hours, minutes, seconds)
+    } else if minutes > 0 {
+        format!("{}m {}s", minutes, seconds)
+    } else {
+        format!("{}s", seconds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use janus_common::UpstreamStats;
+
+    #[test]
+    fn test_token_from_header_or_query() {
+        let page = StatusPageConfig {
+            token: Some("secret".to_string()),
+            ..Default::default()
+        };
+        let request = |uri: &str, authorization: Option<&str>| {
+            let mut builder = Request::get(uri);
+            if let Some(value) = authorization {
+                builder = builder.header(AUTHORIZATION, value);
+            }
+            builder.body(()).unwrap()
+        };
+        assert!(authorized(&page, &request("/s?token=secret", None)));
+        assert!(authorized(&page, &request("/s", Some("Bearer secret"))));
+        assert!(!authorized(&page, &request("/s", None)));
+        assert!(!authorized(&page, &request("/s?token=wrong", None)));
+        assert!(!authorized(
+            &page,
+            &request("/s?format=json", Some("secret"))
+        ));
+        assert!(authorized(
+            &StatusPageConfig::default(),
+            &request("/s", None)
+        ));
+    }
+
+    #[test]
+    fn test_page_escapes_upstream_names() {
+        let mut stats = ServerStats::default();
+        stats.upstream_stats.insert(
+            "<api>".to_string(),
+            UpstreamStats {
+                total_servers: 2,
+                ..Default::default()
+            },
+        );
+        let page = render(&ServerStatus::default(), &stats);
+        assert!(page.contains("<tr class=\"down\"><td>&lt;api&gt;</td><td>0/2</td>"));
+        assert!(!page.contains("<api>"));
+    }
+}
@@ -0,0 +1,68 @@
+//! TLS for the management API
+
+use anyhow::{Context, Result};
+use janus_common::ManagementConfig;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{RootCertStore, ServerConfig, ServerConnection};
+use std::sync::Arc;
+use tokio_rustls::TlsAcceptor;
+
+/// Build the acceptor for the management listener, if TLS is configured
+pub fn acceptor(settings: &ManagementConfig) -> Result<Option<TlsAcceptor>> {
+    let (Some(cert_path), Some(key_path)) = (&settings.tls_cert, &settings.tls_key) else {
+        return Ok(None);
+    };
+
+    let certs = read_certs(cert_path)?;
+    let key = PrivateKeyDer::from_pem_file(key_path)
+        .with_context(|| format!("Failed to read private key {}", key_path))?;
+
+    let builder = ServerConfig::builder();
+    let builder = match settings.client_ca_cert {
+        Some(ref ca_path) => {
+            let mut roots = RootCertStore::empty();
+            for cert in read_certs(ca_path)? {
+                roots
+                    .add(cert)
+                    .with_context(|| format!("Invalid CA certificate in {}", ca_path))?;
+            }
+            // Connections without a certificate still complete the
+            // handshake, so they can be told why they are turned away
+            let verifier = WebPkiClientVerifier::builder(Arc::new(roots))
+                .allow_unauthenticated()
+                .build()?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    let config = builder
+        .with_single_cert(certs, key)
+        .with_context(|| format!("Invalid certificate or key in {}", cert_path))?;
+    Ok(Some(TlsAcceptor::from(Arc::new(config))))
+}
+
+/// Read every certificate in a PEM file
+fn read_certs(path: &str) -> Result<Vec<CertificateDer<'static>>> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .with_context(|| format!("Failed to read certificates {}", path))?;
+    anyhow::ensure!(!certs.is_empty(), "No certificates found in {}", path);
+    Ok(certs)
+}
+
+/// Name of the client certificate presented on a connection: its common
+/// name, or the full subject when it has none
+pub fn peer_name(conn: &ServerConnection) -> Option<String> {
+    let cert = conn.peer_certificates()?.first()?;
+    let (_, cert) = x509_parser::parse_x509_certificate(cert).ok()?;
+    let subject = cert.subject();
+    let name = subject
+        .iter_common_name()
+        .next()
+        .and_then(|cn| cn.as_str().ok())
+        .map_or_else(|| subject.to_string(), str::to_string);
+    Some(name)
+}
@@ -0,0 +1,508 @@
+//! Certificates obtained from a mock ACME CA and served over HTTPS
+//!
+//! Run with `cargo test -p janus-core --features acme --test acme`.
+
+#![cfg(feature = "acme")]
+
+mod common;
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use bytes::Bytes;
+use common::{send, test_config, upstream, TestServer, TIMEOUT};
+use http_body_util::{BodyExt, Full};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use janus_common::config::{AcmeConfig, RouteConfig};
+use janus_common::{AcmeStatus, ClientMessage, JanusConfig, ServerMessage};
+use rustls::pki_types::ServerName;
+use serde_json::{json, Value};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+
+const DOMAIN: &str = "janus.test";
+
+#[tokio::test]
+async fn test_obtains_certificate_and_serves_https() {
+    let ca = MockCa::start(true).await;
+    let cache = tempfile::tempdir().unwrap();
+    let server = TestServer::start(acme_config(&ca, cache.path())).await;
+    ca.validate_at(server.addr);
+
+    let status = wait_for_status(&server, |acme| acme.expires_at.is_some()).await;
+    assert_eq!(status.domains, [DOMAIN]);
+    assert_eq!(status.last_error, None);
+    assert!(status.expires_at.is_some());
+    assert_eq!(ca.validated(), 1);
+    assert!(cache.path().join("cert.pem").exists());
+
+    // The challenge is forgotten once validated
+    let response = server.get("/.well-known/acme-challenge/token-0").await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+
+    let https = server.janus.https_addr().unwrap();
+    let issuer = handshake(&ca, https).await.unwrap();
+    assert!(issuer.contains("Mock ACME CA"));
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_failed_renewal_keeps_cached_certificate() {
+    let cache = tempfile::tempdir().unwrap();
+    let ca = MockCa::start(true).await;
+    let server = TestServer::start(acme_config(&ca, cache.path())).await;
+    ca.validate_at(server.addr);
+    let first = wait_for_status(&server, |acme| acme.expires_at.is_some()).await;
+    server.stop().await;
+
+    // Renewing right away, from a CA that rejects every challenge
+    let failing = MockCa::start(false).await;
+    let mut config = acme_config(&failing, cache.path());
+    config.server.acme.as_mut().unwrap().renew_before_days = 365;
+    let server = TestServer::start(config).await;
+    failing.validate_at(server.addr);
+
+    let status = wait_for_status(&server, |acme| acme.last_error.is_some()).await;
+    let error = status.last_error.unwrap();
+    assert!(error.contains("wrong key authorization"), "{}", error);
+    assert_eq!(status.expires_at, first.expires_at);
+
+    let https = server.janus.https_addr().unwrap();
+    handshake(&ca, https).await.unwrap();
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_redirects_keep_clients_on_https() {
+    // Redirects every request to a login page on itself
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let backend = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let mut request = [0; 1024];
+            let _ = stream.read(&mut request).await;
+            let response = format!(
+                "HTTP/1.1 302 Found\r\nLocation: http://{}/login\r\n\
+                 Content-Length: 0\r\n\r\n",
+                backend
+            );
+            let _ = stream.write_all(response.as_bytes()).await;
+        }
+    });
+
+    let ca = MockCa::start(true).await;
+    let cache = tempfile::tempdir().unwrap();
+    let mut config = acme_config(&ca, cache.path());
+    config
+        .upstreams
+        .insert("app".to_string(), upstream(&[backend]));
+    config.routes.push(RouteConfig::new("/app/*", "app"));
+    let server = TestServer::start(config).await;
+    ca.validate_at(server.addr);
+    wait_for_status(&server, |acme| acme.expires_at.is_some()).await;
+
+    let https = server.janus.https_addr().unwrap();
+    let mut stream = connect(&ca, https).await.unwrap();
+    stream
+        .write_all(b"GET /app/ HTTP/1.1\r\nHost: janus.test\r\nConnection: close\r\n\r\n")
+        .await
+        .unwrap();
+    let mut response = String::new();
+    let _ = stream.read_to_string(&mut response).await;
+    assert!(
+        response.starts_with("HTTP/1.1 302"),
+        "unexpected response: {}",
+        response
+    );
+    assert!(
+        response
+            .to_ascii_lowercase()
+            .contains("location: https://janus.test/login\r\n"),
+        "unexpected response: {}",
+        response
+    );
+
+    // Plain HTTP clients are still sent to plain HTTP
+    let response = server.get("/app/").await;
+    assert_eq!(
+        response.header("location"),
+        Some(format!("http://{}/login", server.addr).as_str())
+    );
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_no_certificate_until_obtained() {
+    let ca = MockCa::start(false).await;
+    let cache = tempfile::tempdir().unwrap();
+    let server = TestServer::start(acme_config(&ca, cache.path())).await;
+    ca.validate_at(server.addr);
+
+    let status = wait_for_status(&server, |acme| acme.last_error.is_some()).await;
+    assert_eq!(status.expires_at, None);
+
+    let https = server.janus.https_addr().unwrap();
+    assert!(handshake(&ca, https).await.is_err());
+    // Plain HTTP is unaffected
+    assert_eq!(server.get("/").await.status, StatusCode::NOT_FOUND);
+    server.stop().await;
+}
+
+fn acme_config(ca: &MockCa, cache: &std::path::Path) -> JanusConfig {
+    let mut config = test_config();
+    config.server.acme = Some(AcmeConfig {
+        contact_email: "admin@janus.test".to_string(),
+        domains: vec![DOMAIN.to_string()],
+        cache_dir: cache.to_string_lossy().into_owned(),
+        https_port: 0,
+        directory_url: format!("http://{}/directory", ca.addr),
+        renew_before_days: 30,
+    });
+    config
+}
+
+/// Wait for the ACME status to reach a state
+async fn wait_for_status(server: &TestServer, reached: impl Fn(&AcmeStatus) -> bool) -> AcmeStatus {
+    let mut mgmt = server.management().await;
+    tokio::time::timeout(TIMEOUT, async {
+        loop {
+            let ServerMessage::Status(status) = mgmt.request(ClientMessage::GetStatus).await else {
+                panic!("expected status");
+            };
+            let acme = status.acme.expect("no ACME status");
+            if reached(&acme) {
+                return acme;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+    })
+    .await
+    .expect("ACME status not reached")
+}
+
+/// Complete a TLS handshake for the domain, trusting only the mock CA, and
+/// return the issuer of the certificate served
+async fn handshake(ca: &MockCa, addr: SocketAddr) -> std::io::Result<String> {
+    let stream = connect(ca, addr).await?;
+    let (_, session) = stream.get_ref();
+    let leaf = &session.peer_certificates().unwrap()[0];
+    let (_, parsed) = x509_parser::parse_x509_certificate(leaf).unwrap();
+    Ok(parsed.issuer().to_string())
+}
+
+/// Connect over TLS to the domain, trusting only the mock CA
+async fn connect(
+    ca: &MockCa,
+    addr: SocketAddr,
+) -> std::io::Result<tokio_rustls::client::TlsStream<TcpStream>> {
+    let mut roots = rustls::RootCertStore::empty();
+    roots.add(ca.state.cert.der().clone()).unwrap();
+    let config = rustls::ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    let stream = TcpStream::connect(addr).await?;
+    tokio_rustls::TlsConnector::from(Arc::new(config))
+        .connect(ServerName::try_from(DOMAIN).unwrap(), stream)
+        .await
+}
+
+/// ACME CA issuing certificates once it has fetched the HTTP-01 challenge
+/// from the Janus HTTP listener
+struct MockCa {
+    addr: SocketAddr,
+    state: Arc<CaState>,
+    handle: JoinHandle<()>,
+}
+
+struct CaState {
+    url: String,
+    cert: rcgen::Certificate,
+    key: rcgen::KeyPair,
+    /// Whether challenges are checked against the account key, or always
+    /// judged wrong
+    accept: bool,
+    /// Janus HTTP listener, where challenges are fetched from
+    janus: Mutex<Option<SocketAddr>>,
+    account: Mutex<Option<Value>>,
+    order: Mutex<Option<Order>>,
+    validated: Mutex<usize>,
+}
+
+#[derive(Default)]
+struct Order {
+    domains: Vec<String>,
+    /// Authorizations whose challenge was fetched, and whether it was right
+    checked: Vec<bool>,
+    valid: Vec<bool>,
+    chain: Option<String>,
+}
+
+impl MockCa {
+    async fn start(accept: bool) -> Self {
+        let key = rcgen::KeyPair::generate().unwrap();
+        let mut params = rcgen::CertificateParams::new(Vec::new()).unwrap();
+        params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, "Mock ACME CA");
+        let cert = params.self_signed(&key).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let state = Arc::new(CaState {
+            url: format!("http://{}", addr),
+            cert,
+            key,
+            accept,
+            janus: Mutex::new(None),
+            account: Mutex::new(None),
+            order: Mutex::new(None),
+            validated: Mutex::new(0),
+        });
+
+        let ca = state.clone();
+        let handle = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let ca = ca.clone();
+                tokio::spawn(async move {
+                    let service = service_fn(move |req| {
+                        let ca = ca.clone();
+                        async move { Ok::<_, Infallible>(ca.handle(req).await) }
+                    });
+                    let _ = http1::Builder::new()
+                        .serve_connection(TokioIo::new(stream), service)
+                        .await;
+                });
+            }
+        });
+
+        Self {
+            addr,
+            state,
+            handle,
+        }
+    }
+
+    /// Fetch challenges from Janus, listening at this address
+    fn validate_at(&self, janus: SocketAddr) {
+        *self.state.janus.lock().unwrap() = Some(janus);
+    }
+
+    fn validated(&self) -> usize {
+        *self.state.validated.lock().unwrap()
+    }
+}
+
+impl Drop for MockCa {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+impl CaState {
+    async fn handle(&self, req: Request<hyper::body::Incoming>) -> Response<Full<Bytes>> {
+        let path = req.uri().path().to_string();
+        let method = req.method().clone();
+        let body = req.into_body().collect().await.unwrap().to_bytes();
+
+        if path == "/directory" {
+            return reply(
+                StatusCode::OK,
+                json!({
+                    "newNonce": format!("{}/nonce", self.url),
+                    "newAccount": format!("{}/account", self.url),
+                    "newOrder": format!("{}/order", self.url),
+                }),
+                None,
+            );
+        }
+        if method == Method::HEAD {
+            return reply(StatusCode::OK, Value::Null, None);
+        }
+
+        let jws: Value = serde_json::from_slice(&body).unwrap();
+        let protected = decode(&jws["protected"]);
+        assert_eq!(protected["alg"], "ES256");
+        assert_eq!(protected["url"], format!("{}{}", self.url, path));
+        let payload = decode(&jws["payload"]);
+
+        match path.as_str() {
+            "/account" => {
+                assert_eq!(payload["contact"][0], "mailto:admin@janus.test");
+                *self.account.lock().unwrap() = Some(protected["jwk"].clone());
+                let account = format!("{}/account/1", self.url);
+                reply(
+                    StatusCode::CREATED,
+                    json!({ "status": "valid" }),
+                    Some(&account),
+                )
+            }
+            "/order" => {
+                assert_eq!(protected["kid"], format!("{}/account/1", self.url));
+                let domains: Vec<String> = payload["identifiers"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|id| id["value"].as_str().unwrap().to_string())
+                    .collect();
+                *self.order.lock().unwrap() = Some(Order {
+                    checked: vec![false; domains.len()],
+                    valid: vec![false; domains.len()],
+                    domains,
+                    ..Default::default()
+                });
+                let order = format!("{}/order/1", self.url);
+                reply(StatusCode::CREATED, self.order_json(), Some(&order))
+            }
+            "/order/1" => reply(StatusCode::OK, self.order_json(), None),
+            "/finalize" => {
+                let der = URL_SAFE_NO_PAD
+                    .decode(payload["csr"].as_str().unwrap())
+                    .unwrap();
+                let mut csr =
+                    rcgen::CertificateSigningRequestParams::from_der(&der.into()).unwrap();
+                let now = std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .unwrap();
+                // Let's Encrypt certificates last 90 days
+                csr.params.not_after = rcgen::date_time_ymd(1970, 1, 1)
+                    + now
+                    + std::time::Duration::from_secs(90 * 86_400);
+                let cert = csr.signed_by(&self.cert, &self.key).unwrap();
+                let mut order = self.order.lock().unwrap();
+                order.as_mut().unwrap().chain = Some(cert.pem() + &self.cert.pem());
+                drop(order);
+                reply(StatusCode::OK, self.order_json(), None)
+            }
+            "/cert" => {
+                let chain = self.order.lock().unwrap().as_ref().unwrap().chain.clone();
+                Response::new(Full::new(Bytes::from(chain.unwrap())))
+            }
+            _ => {
+                let (kind, index) = path[1..].split_once('/').unwrap();
+                let index: usize = index.parse().unwrap();
+                if kind == "challenge" {
+                    self.validate(index).await;
+                }
+                reply(StatusCode::OK, self.authorization_json(index), None)
+            }
+        }
+    }
+
+    /// Fetch the challenge from Janus, as a CA would from the domain
+    async fn validate(&self, index: usize) {
+        // Janus may ask before the test knows where it listens
+        let janus = loop {
+            if let Some(janus) = *self.janus.lock().unwrap() {
+                break janus;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        };
+        let token = format!("token-{}", index);
+        let request = Request::get(format!(
+            "http://{}/.well-known/acme-challenge/{}",
+            janus, token
+        ))
+        .header("Host", DOMAIN)
+        .body(Full::new(Bytes::new()))
+        .unwrap();
+        let response = send(request).await;
+
+        let jwk = self.account.lock().unwrap().clone().unwrap();
+        let canonical = format!(
+            r#"{{"crv":"{}","kty":"{}","x":"{}","y":"{}"}}"#,
+            jwk["crv"].as_str().unwrap(),
+            jwk["kty"].as_str().unwrap(),
+            jwk["x"].as_str().unwrap(),
+            jwk["y"].as_str().unwrap()
+        );
+        let thumbprint = URL_SAFE_NO_PAD.encode(ring::digest::digest(
+            &ring::digest::SHA256,
+            canonical.as_bytes(),
+        ));
+        let valid = self.accept
+            && response.status == StatusCode::OK
+            && response.body == format!("{}.{}", token, thumbprint);
+
+        if valid {
+            *self.validated.lock().unwrap() += 1;
+        }
+        let mut order = self.order.lock().unwrap();
+        let order = order.as_mut().unwrap();
+        order.checked[index] = true;
+        order.valid[index] = valid;
+    }
+
+    fn order_json(&self) -> Value {
+        let order = self.order.lock().unwrap();
+        let order = order.as_ref().unwrap();
+        let status = if order.chain.is_some() {
+            "valid"
+        } else if order.valid.iter().all(|v| *v) {
+            "ready"
+        } else {
+            "pending"
+        };
+        json!({
+            "status": status,
+            "identifiers": order.domains.iter().map(|d| json!({ "type": "dns", "value": d })).collect::<Vec<_>>(),
+            "authorizations": (0..order.domains.len())
+                .map(|i| format!("{}/authz/{}", self.url, i))
+                .collect::<Vec<_>>(),
+            "finalize": format!("{}/finalize", self.url),
+            "certificate": format!("{}/cert", self.url),
+        })
+    }
+
+    fn authorization_json(&self, index: usize) -> Value {
+        let order = self.order.lock().unwrap();
+        let order = order.as_ref().unwrap();
+        let status = match (order.checked[index], order.valid[index]) {
+            (_, true) => "valid",
+            (true, false) => "invalid",
+            (false, false) => "pending",
+        };
+        let mut challenge = json!({
+            "type": "http-01",
+            "url": format!("{}/challenge/{}", self.url, index),
+            "token": format!("token-{}", index),
+            "status": status,
+        });
+        if status == "invalid" {
+            challenge["error"] = json!({ "detail": "wrong key authorization" });
+        }
+        json!({
+            "status": status,
+            "identifier": { "type": "dns", "value": order.domains[index] },
+            "challenges": [challenge],
+        })
+    }
+}
+
+/// Response carrying a fresh nonce, as every ACME response does
+fn reply(status: StatusCode, body: Value, location: Option<&str>) -> Response<Full<Bytes>> {
+    let mut builder = Response::builder()
+        .status(status)
+        .header("Replay-Nonce", "nonce")
+        .header("Content-Type", "application/json");
+    if let Some(location) = location {
+        builder = builder.header("Location", location);
+    }
+    builder
+        .body(Full::new(Bytes::from(body.to_string())))
+        .unwrap()
+}
+
+fn decode(field: &Value) -> Value {
+    let bytes = URL_SAFE_NO_PAD.decode(field.as_str().unwrap()).unwrap();
+    if bytes.is_empty() {
+        return Value::Null;
+    }
+    serde_json::from_slice(&bytes).unwrap()
+}
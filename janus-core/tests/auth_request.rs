@@ -0,0 +1,191 @@
+//! Routes guarded by auth subrequests
+
+mod common;
+
+use bytes::Bytes;
+use common::{free_port, test_config, upstream, StubBackend, TestServer};
+use http_body_util::Full;
+use hyper::{Method, Response, StatusCode};
+use janus_common::config::{AuthRequestConfig, RouteConfig};
+
+/// Auth service allowing `Bearer good`, forbidding `Bearer banned` and
+/// answering `Bearer broken` with a status it should not use
+async fn auth_service() -> StubBackend {
+    StubBackend::start_with(|req| {
+        assert_eq!(req.uri().path(), "/check");
+        let original = req.headers()["x-original-uri"].to_str().unwrap();
+        assert!(original.starts_with("/api/"));
+
+        let token = req
+            .headers()
+            .get("authorization")
+            .and_then(|v| v.to_str().ok());
+        let response = match token {
+            Some("Bearer good") => Response::builder().header("X-User-Id", "42"),
+            Some("Bearer banned") => Response::builder().status(StatusCode::FORBIDDEN),
+            Some("Bearer broken") => Response::builder().status(StatusCode::IM_A_TEAPOT),
+            _ => Response::builder()
+                .status(StatusCode::UNAUTHORIZED)
+                .header("WWW-Authenticate", "Bearer"),
+        };
+        response
+            .body(Full::new(Bytes::from_static(b"{\"error\":\"session\"}")))
+            .unwrap()
+    })
+    .await
+}
+
+fn guarded_route(auth: AuthRequestConfig) -> RouteConfig {
+    RouteConfig::new("/api/*", "api")
+        .timeout(5)
+        .auth_request(auth)
+}
+
+fn auth_config() -> AuthRequestConfig {
+    AuthRequestConfig {
+        upstream: "auth".to_string(),
+        path: "/check".to_string(),
+        forward_headers: vec!["Authorization".to_string(), "Cookie".to_string()],
+        copy_headers: vec!["X-User-Id".to_string()],
+        forward_body: false,
+        timeout_secs: 5,
+    }
+}
+
+#[tokio::test]
+async fn test_auth_request_guards_route() {
+    let api = StubBackend::start("api").await;
+    let auth = auth_service().await;
+    let mut config = test_config();
+    config
+        .upstreams
+        .insert("api".to_string(), upstream(&[api.addr]));
+    config
+        .upstreams
+        .insert("auth".to_string(), upstream(&[auth.addr]));
+    config.routes.push(guarded_route(auth_config()));
+    let server = TestServer::start(config).await;
+
+    // The auth service's headers replace any the client sent
+    let response = server
+        .request(
+            Method::GET,
+            "/api/me",
+            &[("Authorization", "Bearer good"), ("X-User-Id", "1")],
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    let echo = response.echo();
+    assert_eq!(echo.backend, "api");
+    assert_eq!(echo.header("x-user-id"), Some("42"));
+
+    let response = server.get("/api/me").await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+    assert_eq!(response.header("www-authenticate"), Some("Bearer"));
+    assert!(response.body.contains("401 Unauthorized"));
+
+    let response = server
+        .request(
+            Method::GET,
+            "/api/me",
+            &[("Authorization", "Bearer banned")],
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::FORBIDDEN);
+
+    let response = server
+        .request(
+            Method::GET,
+            "/api/me",
+            &[("Authorization", "Bearer broken")],
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::INTERNAL_SERVER_ERROR);
+
+    let stats = server.janus.stats().await;
+    let auth_stats = &stats.auth_requests["/api/*"];
+    assert_eq!(auth_stats.requests, 4);
+    assert_eq!(auth_stats.denied, 2);
+    assert_eq!(auth_stats.errors, 1);
+    assert!(auth_stats.max_latency_ms >= auth_stats.avg_latency_ms);
+    assert_eq!(stats.route_stats["/api/*"].requests, 4);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_auth_request_failures() {
+    let api = StubBackend::start("api").await;
+    let auth = auth_service().await;
+    let dead = format!("127.0.0.1:{}", free_port()).parse().unwrap();
+    let mut config = test_config();
+    config
+        .upstreams
+        .insert("api".to_string(), upstream(&[api.addr]));
+    config
+        .upstreams
+        .insert("auth".to_string(), upstream(&[auth.addr]));
+    config
+        .upstreams
+        .insert("dead".to_string(), upstream(&[dead]));
+    config.routes.push(RouteConfig {
+        path: "/api/open/*".to_string(),
+        ..guarded_route(AuthRequestConfig {
+            upstream: "dead".to_string(),
+            ..auth_config()
+        })
+    });
+    config.routes.push(guarded_route(AuthRequestConfig {
+        forward_body: true,
+        ..auth_config()
+    }));
+    let server = TestServer::start(config).await;
+
+    // An unreachable auth service never lets requests through
+    let response = server.get("/api/open/x").await;
+    assert_eq!(response.status, StatusCode::BAD_GATEWAY);
+    assert_eq!(server.janus.stats().await.proxy_errors["connect"], 1);
+
+    // The auth service's own body explains the denial
+    let response = server.get("/api/x").await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+    assert_eq!(response.body, "{\"error\":\"session\"}");
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_server_auth_request_checks_every_request() {
+    let api = StubBackend::start("api").await;
+    let auth = auth_service().await;
+    let mut config = test_config();
+    config
+        .upstreams
+        .insert("api".to_string(), upstream(&[api.addr]));
+    config
+        .upstreams
+        .insert("auth".to_string(), upstream(&[auth.addr]));
+    config.server.auth_request = Some(auth_config());
+    config
+        .routes
+        .push(RouteConfig::new("/api/*", "api").timeout(5));
+    let server = TestServer::start(config).await;
+
+    let response = server
+        .request(Method::GET, "/api/me", &[("Authorization", "Bearer good")])
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.echo().header("x-user-id"), Some("42"));
+
+    // Turned away before the route is matched
+    let response = server.get("/api/me").await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+    assert_eq!(response.header("www-authenticate"), Some("Bearer"));
+
+    let stats = server.janus.stats().await;
+    assert_eq!(stats.auth_requests["*"].requests, 2);
+    assert_eq!(stats.auth_requests["*"].denied, 1);
+    assert_eq!(stats.route_stats["/api/*"].requests, 1);
+
+    server.stop().await;
+}
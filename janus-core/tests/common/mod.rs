@@ -0,0 +1,319 @@
+//! In-process test harness: runs Janus on ephemeral ports alongside stub backends
+
+#![allow(dead_code)]
+
+use bytes::Bytes;
+use futures::{SinkExt, StreamExt};
+use http_body_util::{BodyExt, Full};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{HeaderMap, Method, Request, Response, StatusCode};
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use janus_common::config::UpstreamConfig;
+use janus_common::{ClientMessage, JanusConfig, ServerMessage};
+use janus_core::Janus;
+use std::convert::Infallible;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+use tokio_tungstenite::{tungstenite::Message, MaybeTlsStream, WebSocketStream};
+
+/// Bound on every network operation in tests
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Base configuration listening on ephemeral loopback ports
+pub fn test_config() -> JanusConfig {
+    let mut config = JanusConfig::default();
+    config.server.bind_address = "127.0.0.1".to_string();
+    config.server.port = 0;
+    config.server.access_log = false;
+    config.management.address = "127.0.0.1".to_string();
+    config.management.port = 0;
+    config
+}
+
+/// A loopback port that was free a moment ago
+pub fn free_port() -> u16 {
+    std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+/// Upstream pointing at the given backends
+pub fn upstream(addrs: &[SocketAddr]) -> UpstreamConfig {
+    UpstreamConfig::new(addrs.iter().map(ToString::to_string))
+}
+
+/// A running Janus instance
+pub struct TestServer {
+    pub janus: Janus,
+    pub addr: SocketAddr,
+    handle: JoinHandle<anyhow::Result<()>>,
+}
+
+impl TestServer {
+    pub async fn start(config: JanusConfig) -> Self {
+        Self::run(Janus::new(config)).await
+    }
+
+    /// Start with a config file, which is watched and saved to
+    pub async fn start_with_file(config: JanusConfig, path: &Path) -> Self {
+        Self::run(Janus::with_config_file(config, path)).await
+    }
+
+    /// Start with a profile of a config file, which changes are saved to
+    pub async fn start_with_profile(config: JanusConfig, path: &Path, profile: &str) -> Self {
+        Self::run(Janus::with_config_profile(config, path, profile)).await
+    }
+
+    async fn run(janus: Janus) -> Self {
+        let handle = janus.start().await.expect("failed to start Janus");
+        let addr = janus.local_addr().expect("no local address");
+        Self {
+            janus,
+            addr,
+            handle,
+        }
+    }
+
+    /// Send a GET request
+    pub async fn get(&self, path: &str) -> TestResponse {
+        self.request(Method::GET, path, &[]).await
+    }
+
+    /// Send a request with extra headers and an empty body
+    pub async fn request(
+        &self,
+        method: Method,
+        path: &str,
+        headers: &[(&str, &str)],
+    ) -> TestResponse {
+        let mut builder = Request::builder()
+            .method(method)
+            .uri(format!("http://{}{}", self.addr, path));
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        send(builder.body(Full::new(Bytes::new())).unwrap()).await
+    }
+
+    /// Open a management WebSocket connection
+    pub async fn management(&self) -> ManagementConn {
+        let addr = self
+            .janus
+            .management_addr()
+            .expect("management API disabled");
+        ManagementConn::connect(addr).await
+    }
+
+    pub async fn stop(self) {
+        self.janus.shutdown().await;
+        tokio::time::timeout(TIMEOUT, self.handle)
+            .await
+            .expect("server did not stop")
+            .unwrap()
+            .unwrap();
+    }
+}
+
+/// Send a request with a plain HTTP client
+pub async fn send(req: Request<Full<Bytes>>) -> TestResponse {
+    let client = Client::builder(TokioExecutor::new()).build_http();
+    let response = tokio::time::timeout(TIMEOUT, client.request(req))
+        .await
+        .expect("request timed out")
+        .expect("request failed");
+
+    let status = response.status();
+    let headers = response.headers().clone();
+    let body = response.collect().await.unwrap().to_bytes();
+    TestResponse {
+        status,
+        headers,
+        body: String::from_utf8_lossy(&body).into_owned(),
+    }
+}
+
+/// A collected response
+pub struct TestResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: String,
+}
+
+impl TestResponse {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).and_then(|v| v.to_str().ok())
+    }
+
+    /// Parse the body as an echo from a [`StubBackend`]
+    pub fn echo(&self) -> Echo {
+        serde_json::from_str(&self.body).expect("response is not a backend echo")
+    }
+}
+
+/// What a stub backend saw
+#[derive(Debug, serde::Deserialize)]
+pub struct Echo {
+    pub backend: String,
+    pub method: String,
+    pub uri: String,
+    pub headers: Vec<(String, String)>,
+}
+
+impl Echo {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// HTTP server that echoes every request back as JSON
+pub struct StubBackend {
+    pub addr: SocketAddr,
+    handle: JoinHandle<()>,
+}
+
+impl StubBackend {
+    pub async fn start(name: &str) -> Self {
+        Self::start_with_delay(name, Duration::ZERO).await
+    }
+
+    /// Backend that waits before answering each request
+    pub async fn start_with_delay(name: &str, delay: Duration) -> Self {
+        let name = name.to_string();
+        Self::serve(move |req| {
+            let name = name.clone();
+            async move {
+                tokio::time::sleep(delay).await;
+                echo(&name, req)
+            }
+        })
+        .await
+    }
+
+    /// Backend answering every request with a response of its own making
+    /// instead of an echo
+    pub async fn start_with(
+        respond: impl Fn(Request<hyper::body::Incoming>) -> Response<Full<Bytes>>
+            + Send
+            + Sync
+            + 'static,
+    ) -> Self {
+        let respond = Arc::new(respond);
+        Self::serve(move |req| std::future::ready(respond(req))).await
+    }
+
+    /// Backend answering every request with a response it makes
+    /// asynchronously, such as after reading the request body
+    pub async fn start_with_async<F, Fut>(respond: F) -> Self
+    where
+        F: Fn(Request<hyper::body::Incoming>) -> Fut + Clone + Send + 'static,
+        Fut: Future<Output = Response<Full<Bytes>>> + Send,
+    {
+        Self::serve(respond).await
+    }
+
+    async fn serve<F, Fut>(respond: F) -> Self
+    where
+        F: Fn(Request<hyper::body::Incoming>) -> Fut + Clone + Send + 'static,
+        Fut: Future<Output = Response<Full<Bytes>>> + Send,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let handle = tokio::spawn(async move {
+            loop {
+                let Ok((stream, _)) = listener.accept().await else {
+                    break;
+                };
+                let respond = respond.clone();
+                tokio::spawn(async move {
+                    let service = service_fn(move |req: Request<hyper::body::Incoming>| {
+                        let response = respond(req);
+                        async move { Ok::<_, Infallible>(response.await) }
+                    });
+                    let _ = http1::Builder::new()
+                        .serve_connection(TokioIo::new(stream), service)
+                        .await;
+                });
+            }
+        });
+
+        Self { addr, handle }
+    }
+}
+
+impl Drop for StubBackend {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+fn echo(name: &str, req: Request<hyper::body::Incoming>) -> Response<Full<Bytes>> {
+    let headers: Vec<(String, String)> = req
+        .headers()
+        .iter()
+        .map(|(n, v)| (n.to_string(), v.to_str().unwrap_or_default().to_string()))
+        .collect();
+    let body = serde_json::json!({
+        "backend": name,
+        "method": req.method().as_str(),
+        "uri": req.uri().to_string(),
+        "headers": headers,
+    });
+
+    Response::builder()
+        .header("Content-Type", "application/json")
+        .header("X-Backend", name)
+        .body(Full::new(Bytes::from(body.to_string())))
+        .unwrap()
+}
+
+/// Management API connection speaking the JSON protocol
+pub struct ManagementConn {
+    ws: WebSocketStream<MaybeTlsStream<TcpStream>>,
+}
+
+impl ManagementConn {
+    pub async fn connect(addr: SocketAddr) -> Self {
+        let (ws, _) = tokio::time::timeout(
+            TIMEOUT,
+            tokio_tungstenite::connect_async(format!("ws://{}", addr)),
+        )
+        .await
+        .expect("management connect timed out")
+        .expect("management connect failed");
+        Self { ws }
+    }
+
+    /// Send a message and wait for its response
+    pub async fn request(&mut self, msg: ClientMessage) -> ServerMessage {
+        let text = serde_json::to_string(&msg).unwrap();
+        self.ws.send(Message::Text(text)).await.unwrap();
+        self.recv().await
+    }
+
+    /// Wait for the next server message
+    pub async fn recv(&mut self) -> ServerMessage {
+        loop {
+            let msg = tokio::time::timeout(TIMEOUT, self.ws.next())
+                .await
+                .expect("management response timed out")
+                .expect("management connection closed")
+                .unwrap();
+            if let Message::Text(text) = msg {
+                return serde_json::from_str(&text).unwrap();
+            }
+        }
+    }
+}
@@ -0,0 +1,193 @@
+//! Built-in health endpoint tests
+
+mod common;
+
+use bytes::Bytes;
+use common::{send, test_config, upstream, StubBackend, TestServer};
+use http_body_util::Full;
+use hyper::{Method, Request, Response, StatusCode};
+use janus_common::config::{HealthCheckConfig, RouteConfig};
+use janus_common::{ClientMessage, ServerMessage};
+use std::collections::HashMap;
+
+#[tokio::test]
+async fn test_health_reports_ok() {
+    let backend = StubBackend::start("api").await;
+    let mut config = test_config();
+    config
+        .upstreams
+        .insert("api".to_string(), upstream(&[backend.addr]));
+    let server = TestServer::start(config).await;
+
+    let response = server.get("/_janus/health").await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.header("content-type"), Some("application/json"));
+
+    let body: serde_json::Value = serde_json::from_str(&response.body).unwrap();
+    assert_eq!(body["status"], "ok");
+    assert_eq!(body["healthy_upstreams"], 1);
+    assert_eq!(body["total_upstreams"], 1);
+    let state = server.janus.state();
+    assert_eq!(body["config_hash"], state.config.load().config_hash());
+
+    // The hash follows the running configuration
+    let mut tx = state.transaction().await;
+    tx.config.upstreams.remove("api");
+    let changed = tx.config.config_hash();
+    tx.apply().await;
+    let response = server.get("/_janus/health").await;
+    let body: serde_json::Value = serde_json::from_str(&response.body).unwrap();
+    assert_eq!(body["config_hash"], changed);
+
+    // Probes are not counted as traffic
+    assert_eq!(server.janus.stats().await.total_requests, 0);
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_health_bypasses_routes() {
+    let backend = StubBackend::start("catch-all").await;
+    let mut config = test_config();
+    config
+        .upstreams
+        .insert("all".to_string(), upstream(&[backend.addr]));
+    config.routes.push(RouteConfig::new("/*", "all").timeout(5));
+    config.server.health_endpoint.path = "/healthz".to_string();
+    let server = TestServer::start(config).await;
+
+    let response = server.get("/healthz").await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert!(response.header("x-backend").is_none());
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_status_page_needs_its_token() {
+    let backend = StubBackend::start("catch-all").await;
+    let mut config = test_config();
+    config
+        .upstreams
+        .insert("all".to_string(), upstream(&[backend.addr]));
+    config.routes.push(RouteConfig::new("/*", "all").timeout(5));
+    config.server.status_page.enabled = true;
+    config.server.status_page.token = Some("secret".to_string());
+    let server = TestServer::start(config).await;
+    server.get("/counted").await;
+
+    let refused = server.get("/_janus/status").await;
+    assert_eq!(refused.status, StatusCode::UNAUTHORIZED);
+    assert_eq!(refused.header("cache-control"), Some("no-store"));
+    assert!(refused.header("x-backend").is_none());
+
+    let page = server.get("/_janus/status?token=secret").await;
+    assert_eq!(page.status, StatusCode::OK);
+    assert_eq!(page.header("cache-control"), Some("no-store"));
+    assert!(page.body.contains("<h1>Janus status</h1>"));
+    assert!(page.body.contains("<td>all</td><td>1/1</td>"));
+
+    let json = server
+        .request(
+            Method::GET,
+            "/_janus/status?format=json",
+            &[("authorization", "Bearer secret")],
+        )
+        .await;
+    assert_eq!(json.header("content-type"), Some("application/json"));
+    let body: serde_json::Value = serde_json::from_str(&json.body).unwrap();
+    assert_eq!(body["status"]["version"], env!("CARGO_PKG_VERSION"));
+    assert_eq!(body["status"]["route_count"], 1);
+    // Only the proxied request counts as traffic
+    assert_eq!(body["stats"]["total_requests"], 1);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_missing_critical_upstream_is_503() {
+    let mut config = test_config();
+    config.server.health_endpoint.critical_upstreams = vec!["payments".to_string()];
+    let server = TestServer::start(config).await;
+
+    let response = server.get("/_janus/health").await;
+    assert_eq!(response.status, StatusCode::SERVICE_UNAVAILABLE);
+    let body: serde_json::Value = serde_json::from_str(&response.body).unwrap();
+    assert_eq!(body["status"], "unavailable");
+    assert!(body["problems"][0].as_str().unwrap().contains("payments"));
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_health_on_management_port() {
+    let mut config = test_config();
+    config.server.health_endpoint.on_management_port = true;
+    let server = TestServer::start(config).await;
+
+    // No longer answered on the main listener
+    let response = server.get("/_janus/health").await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+
+    let mgmt_addr = server.janus.management_addr().unwrap();
+    let request = Request::get(format!("http://{}/_janus/health", mgmt_addr))
+        .body(Full::new(Bytes::new()))
+        .unwrap();
+    let response = send(request).await;
+    assert_eq!(response.status, StatusCode::OK);
+
+    // WebSocket clients still work on the same port
+    let mut conn = server.management().await;
+    assert!(matches!(
+        conn.request(ClientMessage::GetStatus).await,
+        ServerMessage::Status(_)
+    ));
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_preflight_probes_health_checks_with_custom_headers() {
+    // Healthy only when the key is presented
+    let backend = StubBackend::start_with(|req| {
+        assert_eq!(req.uri().path(), "/ready");
+        let status = match req.headers().get("x-api-key") {
+            Some(key) if key == "probe-key" => StatusCode::OK,
+            _ => StatusCode::UNAUTHORIZED,
+        };
+        Response::builder()
+            .status(status)
+            .body(Full::new(Bytes::new()))
+            .unwrap()
+    })
+    .await;
+
+    std::env::set_var("JANUS_TEST_PROBE_KEY", "probe-key");
+    let check = |key: &str| HealthCheckConfig {
+        interval: 30,
+        timeout: 2,
+        path: "/ready".to_string(),
+        custom_headers: HashMap::from([("X-Api-Key".to_string(), key.to_string())]),
+    };
+    let mut config = test_config();
+    let mut good = upstream(&[backend.addr]);
+    good.health_check = Some(check("${JANUS_TEST_PROBE_KEY}"));
+    config.upstreams.insert("good".to_string(), good);
+    let mut bad = upstream(&[backend.addr]);
+    bad.health_check = Some(check("wrong"));
+    config.upstreams.insert("bad".to_string(), bad);
+    let server = TestServer::start(config).await;
+    let mut conn = server.management().await;
+
+    match conn.request(ClientMessage::GetPreflight).await {
+        ServerMessage::Preflight(report) => assert_eq!(
+            report.warnings,
+            vec![format!(
+                "Upstream 'bad': server '{}' failed its health check: answered 401 Unauthorized",
+                backend.addr
+            )]
+        ),
+        other => panic!("unexpected response: {:?}", other),
+    }
+
+    server.stop().await;
+}
@@ -0,0 +1,1423 @@
+//! Management protocol tests over a real WebSocket connection
+
+mod common;
+
+use common::{free_port, test_config, upstream, ManagementConn, StubBackend, TestServer, TIMEOUT};
+use futures::{SinkExt, StreamExt};
+use janus_common::config::RouteConfig;
+use janus_common::{
+    AuditSource, ClientMessage, JanusConfig, JanusConfigFile, MaintenanceScope, ManagementRole,
+    ManagementToken, ServerMessage, ServerStatus,
+};
+use rustls::pki_types::{PrivatePkcs8KeyDer, ServerName};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use tokio::net::TcpStream;
+use tokio_tungstenite::{tungstenite::Message, WebSocketStream};
+
+#[tokio::test]
+async fn test_status_and_config() {
+    let server = TestServer::start(test_config()).await;
+    let mut conn = server.management().await;
+
+    match conn.request(ClientMessage::GetStatus).await {
+        ServerMessage::Status(status) => {
+            assert!(status.running);
+            assert_eq!(status.route_count, 0);
+        }
+        other => panic!("unexpected response: {:?}", other),
+    }
+
+    match conn.request(ClientMessage::GetConfig).await {
+        ServerMessage::Config(config) => assert!(config.routes.is_empty()),
+        other => panic!("unexpected response: {:?}", other),
+    }
+
+    assert!(matches!(
+        conn.request(ClientMessage::Ping(7)).await,
+        ServerMessage::Pong(7)
+    ));
+
+    server.stop().await;
+}
+
+/// Config hash the server reports, and that of the copy it sends
+async fn config_hashes(conn: &mut ManagementConn) -> (String, String) {
+    let status = match conn.request(ClientMessage::GetStatus).await {
+        ServerMessage::Status(status) => status,
+        other => panic!("unexpected response: {:?}", other),
+    };
+    let config = match conn.request(ClientMessage::GetConfig).await {
+        ServerMessage::Config(config) => config,
+        other => panic!("unexpected response: {:?}", other),
+    };
+    (status.config_hash, config.config_hash())
+}
+
+#[tokio::test]
+async fn test_config_hash_follows_changes() {
+    let server = TestServer::start(test_config()).await;
+    let mut conn = server.management().await;
+
+    let (before, copy) = config_hashes(&mut conn).await;
+    assert_eq!(before, copy);
+
+    assert!(matches!(
+        conn.request(ClientMessage::UpdateServerPort(8081)).await,
+        ServerMessage::Success(_)
+    ));
+    let (after, copy) = config_hashes(&mut conn).await;
+    assert_eq!(after, copy);
+    assert_ne!(after, before);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_shutdown_notifies_clients() {
+    let server = TestServer::start(test_config()).await;
+    let mut conn = server.management().await;
+
+    // Make sure the connection is established before stopping
+    conn.request(ClientMessage::GetStats).await;
+    let stop = tokio::spawn(server.stop());
+
+    assert!(matches!(conn.recv().await, ServerMessage::ShuttingDown));
+    stop.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_shutdown_after_delay() {
+    let server = TestServer::start(test_config()).await;
+    let mut conn = server.management().await;
+
+    let unconfirmed = ClientMessage::Shutdown {
+        confirm: false,
+        delay_secs: 0,
+    };
+    assert!(matches!(
+        conn.request(unconfirmed).await,
+        ServerMessage::Error(_)
+    ));
+
+    let shutdown = ClientMessage::Shutdown {
+        confirm: true,
+        delay_secs: 1,
+    };
+    assert!(matches!(
+        conn.request(shutdown).await,
+        ServerMessage::Success(_)
+    ));
+    assert!(!*server.janus.state().shutdown.borrow());
+
+    // The server stops by itself once the delay is up
+    assert!(matches!(conn.recv().await, ServerMessage::ShuttingDown));
+    assert!(*server.janus.state().shutdown.borrow());
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_config_diff_against_client_copy() {
+    let mut config = test_config();
+    config
+        .static_files
+        .push(janus_common::StaticFileConfig::new(
+            "/assets/",
+            "/srv/assets",
+        ));
+    let server = TestServer::start(config).await;
+    let mut conn = server.management().await;
+
+    let response = conn
+        .request(ClientMessage::GetConfigDiff {
+            before: test_config(),
+        })
+        .await;
+    match response {
+        ServerMessage::ConfigDiff(entries) => {
+            assert_eq!(entries.len(), 1);
+            assert_eq!(entries[0].kind, janus_common::DiffKind::Added);
+            assert_eq!(entries[0].description, "Static directory '/assets/' added");
+        }
+        other => panic!("unexpected response: {:?}", other),
+    }
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_live_requests_feed() {
+    let backend = common::StubBackend::start("api").await;
+    let mut config = test_config();
+    config
+        .upstreams
+        .insert("api".to_string(), upstream(&[backend.addr]));
+    config.server.default_upstream = Some("api".to_string());
+    config.management.live_requests = true;
+    let server = TestServer::start(config).await;
+    let mut conn = server.management().await;
+    // Answered once the session is following the feed
+    conn.request(ClientMessage::Ping(1)).await;
+
+    server.get("/orders/7").await;
+    match conn.recv().await {
+        ServerMessage::RouteHit {
+            path,
+            upstream,
+            status,
+        } => {
+            assert_eq!(path, "/orders/7");
+            assert_eq!(upstream, "api");
+            assert_eq!(status, 200);
+        }
+        other => panic!("unexpected message: {:?}", other),
+    }
+
+    server.stop().await;
+
+    // Off by default
+    let mut config = test_config();
+    config
+        .upstreams
+        .insert("api".to_string(), upstream(&[backend.addr]));
+    config.server.default_upstream = Some("api".to_string());
+    let server = TestServer::start(config).await;
+    let mut conn = server.management().await;
+    conn.request(ClientMessage::Ping(1)).await;
+    server.get("/orders/8").await;
+    assert!(matches!(
+        conn.request(ClientMessage::Ping(2)).await,
+        ServerMessage::Pong(2)
+    ));
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_stats_pushed_without_polling() {
+    let mut config = test_config();
+    config.management.push_stats_interval_ms = Some(50);
+    let server = TestServer::start(config).await;
+    let mut conn = server.management().await;
+
+    // The first arrives straight away, then they keep coming
+    assert!(matches!(conn.recv().await, ServerMessage::Stats(_)));
+    server.get("/missing").await;
+    loop {
+        match conn.recv().await {
+            ServerMessage::Stats(stats) if stats.total_requests == 1 => break,
+            ServerMessage::Stats(_) => {}
+            other => panic!("unexpected message: {:?}", other),
+        }
+    }
+    server.stop().await;
+
+    // Sessions that may not read get none
+    let mut config = test_config();
+    config.management.push_stats_interval_ms = Some(50);
+    config.management.tokens = vec![ManagementToken {
+        token: "ops".to_string(),
+        role: ManagementRole::Admin,
+        name: None,
+    }];
+    let server = TestServer::start(config).await;
+    let mut conn = server.management().await;
+    tokio::time::sleep(Duration::from_millis(150)).await;
+    assert!(matches!(
+        conn.request(ClientMessage::Ping(1)).await,
+        ServerMessage::Error(e) if e == "authentication required"
+    ));
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_update_static_dir_in_place() {
+    let mount = |path: &str, root: &str| {
+        janus_common::StaticFileConfig::new(path.to_string(), root.to_string())
+    };
+    let mut config = test_config();
+    config.static_files.push(mount("/assets/", "/srv/assets"));
+    config.static_files.push(mount("/docs/", "/srv/docs"));
+    let server = TestServer::start(config).await;
+    let mut conn = server.management().await;
+
+    let response = conn
+        .request(ClientMessage::UpdateStaticDir {
+            path: "/assets/".to_string(),
+            config: mount("/assets/", "/srv/assets-v2"),
+        })
+        .await;
+    assert!(
+        matches!(response, ServerMessage::Success(ref m) if m == "Static directory '/assets/' updated")
+    );
+    let static_files = server.janus.config().await.static_files;
+    assert_eq!(static_files[0].root, "/srv/assets-v2");
+    assert_eq!(static_files.len(), 2);
+
+    let response = conn
+        .request(ClientMessage::UpdateStaticDir {
+            path: "/media/".to_string(),
+            config: mount("/media/", "/srv/media"),
+        })
+        .await;
+    assert!(
+        matches!(response, ServerMessage::Error(ref e) if e == "Static directory '/media/' not found")
+    );
+
+    let response = conn
+        .request(ClientMessage::UpdateStaticDir {
+            path: "/assets/".to_string(),
+            config: mount("/docs/", "/srv/assets"),
+        })
+        .await;
+    assert!(
+        matches!(response, ServerMessage::Error(ref e) if e == "Static directory '/docs/' already exists")
+    );
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_read_only_token_cannot_modify() {
+    let mut config = test_config();
+    config.management.tokens = vec![
+        ManagementToken {
+            token: "dashboard".to_string(),
+            role: ManagementRole::Read,
+            name: None,
+        },
+        ManagementToken {
+            token: "ops".to_string(),
+            role: ManagementRole::Admin,
+            name: None,
+        },
+    ];
+    let server = TestServer::start(config).await;
+
+    let mut conn = server.management().await;
+    assert!(matches!(
+        conn.request(ClientMessage::GetStatus).await,
+        ServerMessage::Error(e) if e == "authentication required"
+    ));
+    assert!(matches!(
+        conn.request(ClientMessage::Authenticate {
+            token: "guess".to_string()
+        })
+        .await,
+        ServerMessage::Error(e) if e == "invalid token"
+    ));
+
+    assert!(matches!(
+        conn.request(ClientMessage::Authenticate {
+            token: "dashboard".to_string()
+        })
+        .await,
+        ServerMessage::Authenticated(ManagementRole::Read)
+    ));
+    assert!(matches!(
+        conn.request(ClientMessage::GetStats).await,
+        ServerMessage::Stats(_)
+    ));
+    assert!(matches!(
+        conn.request(ClientMessage::RemoveRoute("/".to_string())).await,
+        ServerMessage::Error(e) if e == "insufficient permissions"
+    ));
+
+    let mut admin = server.management().await;
+    assert!(matches!(
+        admin
+            .request(ClientMessage::Authenticate {
+                token: "ops".to_string()
+            })
+            .await,
+        ServerMessage::Authenticated(ManagementRole::Admin)
+    ));
+    assert!(matches!(
+        admin.request(ClientMessage::ReloadConfig).await,
+        ServerMessage::Error(e) if e != "insufficient permissions"
+    ));
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_changes_are_audited() {
+    let dir = tempfile::tempdir().unwrap();
+    let audit_path = dir.path().join("audit.jsonl");
+    let mut config = test_config();
+    config.management.audit_log = Some(audit_path.to_string_lossy().into_owned());
+    config.management.tokens = vec![
+        ManagementToken {
+            token: "dashboard".to_string(),
+            role: ManagementRole::Read,
+            name: None,
+        },
+        ManagementToken {
+            token: "ops".to_string(),
+            role: ManagementRole::Admin,
+            name: Some("ops team".to_string()),
+        },
+    ];
+    config.upstreams.insert(
+        "backend".to_string(),
+        upstream(&["127.0.0.1:9".parse().unwrap()]),
+    );
+    let server = TestServer::start(config.clone()).await;
+
+    let mut reader = server.management().await;
+    reader
+        .request(ClientMessage::Authenticate {
+            token: "dashboard".to_string(),
+        })
+        .await;
+    reader
+        .request(ClientMessage::RemoveRoute("/".to_string()))
+        .await;
+
+    let mut admin = server.management().await;
+    admin
+        .request(ClientMessage::Authenticate {
+            token: "ops".to_string(),
+        })
+        .await;
+    let route = RouteConfig::new("/api/*", "backend").timeout(5);
+    assert!(matches!(
+        admin.request(ClientMessage::AddRoute(route)).await,
+        ServerMessage::Success(_)
+    ));
+    // Failing validation is recorded as well
+    let mut invalid = config.clone();
+    invalid.server.port = 0;
+    assert!(matches!(
+        admin.request(ClientMessage::UpdateConfig(invalid)).await,
+        ServerMessage::ConfigValidationErrors(_)
+    ));
+    admin.request(ClientMessage::GetStats).await;
+
+    let entries = match reader
+        .request(ClientMessage::GetAuditLog { limit: 10 })
+        .await
+    {
+        ServerMessage::AuditLog(entries) => entries,
+        other => panic!("unexpected response: {:?}", other),
+    };
+    let operations: Vec<_> = entries.iter().map(|e| e.operation.as_str()).collect();
+    assert_eq!(operations, ["UpdateConfig", "AddRoute", "RemoveRoute"]);
+
+    let [update, add, remove] = &entries[..] else {
+        unreachable!()
+    };
+    assert!(!update.success);
+    assert!(update
+        .error
+        .as_deref()
+        .unwrap()
+        .starts_with("server.port: "));
+    assert!(add.success);
+    assert_eq!(add.summary, "/api/* -> backend");
+    assert_eq!(add.token.as_deref(), Some("ops team"));
+    assert_eq!(add.role, Some(ManagementRole::Admin));
+    assert!(add.peer.as_deref().unwrap().starts_with("127.0.0.1:"));
+    assert!(!remove.success);
+    assert_eq!(remove.error.as_deref(), Some("insufficient permissions"));
+    assert_eq!(remove.token, None);
+    assert_eq!(remove.role, Some(ManagementRole::Read));
+
+    // The file holds one JSON object per change
+    let text = std::fs::read_to_string(&audit_path).unwrap();
+    assert_eq!(text.lines().count(), 3);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_file_reloads_are_audited() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("janus.toml");
+    let mut config = test_config();
+    config.server.port = free_port();
+    config.management.port = free_port();
+    config.management.audit_log = Some(
+        dir.path()
+            .join("audit.jsonl")
+            .to_string_lossy()
+            .into_owned(),
+    );
+    config.upstreams.insert(
+        "backend".to_string(),
+        upstream(&["127.0.0.1:9".parse().unwrap()]),
+    );
+    config.save(&path).unwrap();
+    let server = TestServer::start_with_file(config.clone(), &path).await;
+    let mut conn = server.management().await;
+
+    config
+        .routes
+        .push(RouteConfig::new("/api/*", "backend").timeout(5));
+    config.save(&path).unwrap();
+
+    let entries = tokio::time::timeout(TIMEOUT, async {
+        loop {
+            match request_skipping_reloads(&mut conn, ClientMessage::GetAuditLog { limit: 10 })
+                .await
+            {
+                ServerMessage::AuditLog(entries) if !entries.is_empty() => break entries,
+                ServerMessage::AuditLog(_) => {
+                    tokio::time::sleep(std::time::Duration::from_millis(100)).await
+                }
+                other => panic!("unexpected response: {:?}", other),
+            }
+        }
+    })
+    .await
+    .expect("file reload was not audited");
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].source, AuditSource::File);
+    assert_eq!(entries[0].operation, "ReloadConfig");
+    assert_eq!(entries[0].summary, path.display().to_string());
+    assert!(entries[0].success);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_preflight_report() {
+    let mut config = test_config();
+    config
+        .static_files
+        .push(janus_common::StaticFileConfig::new(
+            "/assets/",
+            "/nonexistent/janus-assets",
+        ));
+    let server = TestServer::start(config).await;
+    let mut conn = server.management().await;
+
+    match conn.request(ClientMessage::GetPreflight).await {
+        ServerMessage::Preflight(report) => {
+            assert_eq!(
+            report.warnings,
+            vec!["Static directory '/assets/': root '/nonexistent/janus-assets' is not a directory"]
+        )
+        }
+        other => panic!("unexpected response: {:?}", other),
+    }
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_management_api_follows_config() {
+    let server = TestServer::start(test_config()).await;
+    let mut conn = server.management().await;
+    conn.request(ClientMessage::GetStats).await;
+
+    // Config updates are validated, so replace the ephemeral ports
+    let mut config = server.janus.config().await;
+    config.server.port = server.addr.port();
+    config.management.port = free_port();
+    server.janus.update_config(config.clone()).await.unwrap();
+
+    match conn.recv().await {
+        ServerMessage::Disconnecting(reason) => assert!(reason.contains("moved")),
+        other => panic!("unexpected message: {:?}", other),
+    }
+    let addr = server.janus.management_addr().unwrap();
+    assert_eq!(addr.port(), config.management.port);
+    let mut conn = server.management().await;
+    assert!(matches!(
+        conn.request(ClientMessage::GetStatus).await,
+        ServerMessage::Status(_)
+    ));
+
+    config.management.enabled = false;
+    server.janus.update_config(config.clone()).await.unwrap();
+    assert!(matches!(conn.recv().await, ServerMessage::Disconnecting(_)));
+    assert!(server.janus.management_addr().is_none());
+
+    config.management.enabled = true;
+    server.janus.update_config(config).await.unwrap();
+    // The listener is started in the background
+    let started = async {
+        while server.janus.management_addr().is_none() {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+    };
+    tokio::time::timeout(common::TIMEOUT, started)
+        .await
+        .unwrap();
+    let mut conn = server.management().await;
+    assert!(matches!(
+        conn.request(ClientMessage::GetStatus).await,
+        ServerMessage::Status(_)
+    ));
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_route_stats_detail() {
+    let backend = StubBackend::start("api").await;
+    let mut config = test_config();
+    config
+        .upstreams
+        .insert("api".to_string(), upstream(&[backend.addr]));
+    config
+        .routes
+        .push(RouteConfig::new("/api/*", "api").timeout(5));
+    config
+        .routes
+        .push(RouteConfig::new("/idle/*", "api").timeout(5));
+    let server = TestServer::start(config).await;
+
+    server.get("/api/a").await;
+    server.get("/api/b").await;
+
+    let mut conn = server.management().await;
+    let route_stats = |path: &str| ClientMessage::GetRouteStats {
+        path: path.to_string(),
+    };
+    match conn.request(route_stats("/api/*")).await {
+        ServerMessage::RouteStats(detail) => {
+            assert_eq!(detail.path, "/api/*");
+            assert_eq!(detail.requests, 2);
+            assert_eq!(detail.error_rate, 0.0);
+            assert!(detail.p99_ms > 0.0 && detail.p99_ms >= detail.p50_ms);
+            assert!(detail.bytes_sent > 0);
+            assert!(detail.last_request.is_some());
+        }
+        other => panic!("unexpected response: {:?}", other),
+    }
+    // A route yet to serve anything has empty stats
+    match conn.request(route_stats("/idle/*")).await {
+        ServerMessage::RouteStats(detail) => {
+            assert_eq!(detail.requests, 0);
+            assert_eq!(detail.last_request, None);
+        }
+        other => panic!("unexpected response: {:?}", other),
+    }
+    assert!(matches!(
+        conn.request(route_stats("/nowhere")).await,
+        ServerMessage::Error(_)
+    ));
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_reset_stats_starts_a_new_window() {
+    let server = TestServer::start(test_config()).await;
+    server.get("/missing").await;
+    server.get("/missing").await;
+
+    let mut conn = server.management().await;
+    match conn.request(ClientMessage::GetStatus).await {
+        ServerMessage::Status(status) => assert!(status
+            .capabilities
+            .contains(&janus_common::CAPABILITY_RESET_STATS.to_string())),
+        other => panic!("unexpected response: {:?}", other),
+    }
+    let before = server.janus.stats().await;
+    assert_eq!(before.total_requests, 2);
+
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    assert!(matches!(
+        conn.request(ClientMessage::ResetStats).await,
+        ServerMessage::Success(_)
+    ));
+    let after = server.janus.stats().await;
+    assert_eq!(after.total_requests, 0);
+    assert_eq!(after.status_codes.client_error, 0);
+    assert!(after.route_stats.is_empty());
+    assert!(after.since > before.since);
+    match conn
+        .request(ClientMessage::GetTopClients { limit: 5 })
+        .await
+    {
+        ServerMessage::TopClients(clients) => assert!(clients.is_empty()),
+        other => panic!("unexpected response: {:?}", other),
+    }
+
+    // Counting starts again from the reset
+    server.get("/missing").await;
+    assert_eq!(server.janus.stats().await.total_requests, 1);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_maintenance_switches() {
+    let backend = StubBackend::start("api").await;
+    let dir = tempfile::tempdir().unwrap();
+    let state_file = dir.path().join("maintenance.json");
+    let mut config = test_config();
+    config.server.maintenance.retry_after_secs = 120;
+    config.server.maintenance.state_file = Some(state_file.to_string_lossy().into_owned());
+    config
+        .upstreams
+        .insert("api".to_string(), upstream(&[backend.addr]));
+    config
+        .routes
+        .push(RouteConfig::new("/api/*", "api").timeout(5));
+    config
+        .routes
+        .push(RouteConfig::new("/web/*", "api").timeout(5));
+    let server = TestServer::start(config.clone()).await;
+    let mut conn = server.management().await;
+
+    let set = |scope: MaintenanceScope, enabled: bool, message: Option<&str>| {
+        ClientMessage::SetMaintenance {
+            scope,
+            enabled,
+            message: message.map(str::to_string),
+        }
+    };
+    let api = MaintenanceScope::Route("/api/*".to_string());
+    assert!(matches!(
+        conn.request(set(api.clone(), true, Some("<b>Deploying</b>")))
+            .await,
+        ServerMessage::Success(_)
+    ));
+    let response = server.get("/api/users").await;
+    assert_eq!(response.status, 503);
+    assert_eq!(response.header("retry-after"), Some("120"));
+    assert!(response.body.contains("&lt;b&gt;Deploying&lt;/b&gt;"));
+    assert_eq!(server.get("/web/index").await.status, 200);
+
+    assert!(matches!(
+        conn.request(set(MaintenanceScope::All, true, None)).await,
+        ServerMessage::Success(_)
+    ));
+    let response = server.get("/web/index").await;
+    assert_eq!(response.status, 503);
+    assert!(response.body.contains("Down for maintenance"));
+    match conn.request(ClientMessage::GetStatus).await {
+        ServerMessage::Status(status) => {
+            let scopes: Vec<_> = status.maintenance.into_iter().map(|w| w.scope).collect();
+            assert_eq!(scopes, [MaintenanceScope::All, api.clone()]);
+        }
+        other => panic!("unexpected response: {:?}", other),
+    }
+
+    // Only configured routes can be switched on
+    assert!(matches!(
+        conn.request(set(
+            MaintenanceScope::Route("/nowhere".to_string()),
+            true,
+            None
+        ))
+        .await,
+        ServerMessage::Error(_)
+    ));
+
+    assert!(matches!(
+        conn.request(set(MaintenanceScope::All, false, None)).await,
+        ServerMessage::Success(_)
+    ));
+    assert_eq!(server.get("/web/index").await.status, 200);
+    assert_eq!(server.get("/api/users").await.status, 503);
+    server.stop().await;
+
+    // The switch still on is restored from the state file
+    let server = TestServer::start(config).await;
+    assert_eq!(server.get("/api/users").await.status, 503);
+    assert_eq!(server.get("/web/index").await.status, 200);
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_disabled_routes_and_mounts_are_skipped() {
+    let v1 = StubBackend::start("v1").await;
+    let v2 = StubBackend::start("v2").await;
+    let root = tempfile::tempdir().unwrap();
+    std::fs::write(root.path().join("app.js"), "js").unwrap();
+
+    let mut config = test_config();
+    config
+        .upstreams
+        .insert("v1".to_string(), upstream(&[v1.addr]));
+    config
+        .upstreams
+        .insert("v2".to_string(), upstream(&[v2.addr]));
+    config
+        .routes
+        .push(RouteConfig::new("/api/v2/*", "v2").timeout(5));
+    config
+        .routes
+        .push(RouteConfig::new("/api/*", "v1").timeout(5));
+    config
+        .static_files
+        .push(janus_common::StaticFileConfig::new(
+            "/static/",
+            root.path().to_string_lossy().into_owned(),
+        ));
+    let server = TestServer::start(config).await;
+    let mut conn = server.management().await;
+    assert_eq!(server.get("/api/v2/users").await.echo().backend, "v2");
+    assert_eq!(server.get("/static/app.js").await.body, "js");
+
+    for msg in [
+        ClientMessage::SetRouteEnabled {
+            path: "/api/v2/*".to_string(),
+            enabled: false,
+        },
+        ClientMessage::SetStaticDirEnabled {
+            path: "/static/".to_string(),
+            enabled: false,
+        },
+    ] {
+        assert!(matches!(conn.request(msg).await, ServerMessage::Success(_)));
+    }
+    assert_eq!(server.get("/api/v2/users").await.echo().backend, "v1");
+    assert_eq!(server.get("/static/app.js").await.status, 404);
+
+    // Disabled entries stay in the configuration
+    match conn.request(ClientMessage::GetConfig).await {
+        ServerMessage::Config(config) => {
+            assert!(!config.routes[0].enabled);
+            assert!(!config.static_files[0].enabled);
+        }
+        other => panic!("unexpected response: {:?}", other),
+    }
+
+    assert!(matches!(
+        conn.request(ClientMessage::SetRouteEnabled {
+            path: "/api/v2/*".to_string(),
+            enabled: true,
+        })
+        .await,
+        ServerMessage::Success(_)
+    ));
+    assert_eq!(server.get("/api/v2/users").await.echo().backend, "v2");
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_drained_server_gets_no_new_requests() {
+    let first = StubBackend::start("first").await;
+    let second = StubBackend::start("second").await;
+    let mut config = test_config();
+    config
+        .upstreams
+        .insert("api".to_string(), upstream(&[first.addr, second.addr]));
+    config
+        .routes
+        .push(RouteConfig::new("/api/*", "api").timeout(5));
+    let server = TestServer::start(config).await;
+    let mut conn = server.management().await;
+
+    let drain = |addr: std::net::SocketAddr| ClientMessage::DrainBackendServer {
+        upstream: "api".to_string(),
+        server_address: addr.to_string(),
+    };
+    assert!(matches!(
+        conn.request(drain(second.addr)).await,
+        ServerMessage::Success(_)
+    ));
+    for _ in 0..4 {
+        assert_eq!(server.get("/api/users").await.echo().backend, "first");
+    }
+    let upstream = &server.janus.stats().await.upstream_stats["api"];
+    assert_eq!((upstream.healthy_servers, upstream.total_servers), (1, 2));
+
+    // Still configured, only out of rotation
+    match conn.request(ClientMessage::GetConfig).await {
+        ServerMessage::Config(config) => {
+            let servers = &config.upstreams["api"].servers;
+            assert_eq!(servers.len(), 2);
+            assert!(!servers[0].drain);
+            assert!(servers[1].drain);
+        }
+        other => panic!("unexpected response: {:?}", other),
+    }
+
+    assert!(matches!(
+        conn.request(drain(first.addr)).await,
+        ServerMessage::Success(_)
+    ));
+    assert_eq!(server.get("/api/users").await.status, 503);
+    assert_eq!(
+        server.janus.stats().await.upstream_stats["api"].healthy_servers,
+        0
+    );
+
+    // An upstream with every server draining has nothing available
+    let health = server.get("/_janus/health").await;
+    let body: serde_json::Value = serde_json::from_str(&health.body).unwrap();
+    assert_eq!(body["healthy_upstreams"], 0);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_top_clients_are_anonymized() {
+    let mut config = test_config();
+    config.server.anonymize_client_ips = true;
+    let server = TestServer::start(config).await;
+
+    server.get("/missing").await;
+    server.get("/missing").await;
+
+    let mut conn = server.management().await;
+    match conn
+        .request(ClientMessage::GetTopClients { limit: 5 })
+        .await
+    {
+        ServerMessage::TopClients(clients) => {
+            assert_eq!(clients.len(), 1);
+            assert_eq!(clients[0].address, "127.0.0.0");
+            assert_eq!(clients[0].requests, 2);
+            assert_eq!(clients[0].errors, 2);
+        }
+        other => panic!("unexpected response: {:?}", other),
+    }
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_update_config_reports_each_validation_issue() {
+    let server = TestServer::start(test_config()).await;
+    let mut conn = server.management().await;
+
+    // The ephemeral ports in the test config are themselves invalid
+    match conn
+        .request(ClientMessage::UpdateConfig(test_config()))
+        .await
+    {
+        ServerMessage::ConfigValidationErrors(issues) => {
+            let fields: Vec<_> = issues.iter().map(|i| i.field.as_str()).collect();
+            assert_eq!(fields, ["server.port", "management.port"]);
+        }
+        other => panic!("unexpected response: {:?}", other),
+    }
+
+    server.stop().await;
+}
+
+/// Send a message and wait for its response, skipping reload notices
+/// broadcast on behalf of other clients
+async fn request_skipping_reloads(conn: &mut ManagementConn, msg: ClientMessage) -> ServerMessage {
+    let mut response = conn.request(msg).await;
+    while matches!(
+        response,
+        ServerMessage::ConfigReloaded | ServerMessage::ConfigHash(_)
+    ) {
+        response = conn.recv().await;
+    }
+    response
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_concurrent_mutations_stay_consistent() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("janus.toml");
+
+    // Reloads validate the file, so it needs real ports
+    let mut config = test_config();
+    config.server.port = free_port();
+    config.management.port = free_port();
+    config.upstreams.insert(
+        "backend".to_string(),
+        upstream(&["127.0.0.1:9".parse().unwrap()]),
+    );
+    config.save(&path).unwrap();
+    let server = TestServer::start_with_file(config, &path).await;
+
+    let mut clients = Vec::new();
+    for client in 0..8 {
+        let mut conn = server.management().await;
+        clients.push(tokio::spawn(async move {
+            let mut added = Vec::new();
+            for i in 0..20 {
+                // Every client races for the shared paths
+                let path = if i % 2 == 0 {
+                    format!("/shared/{}", i)
+                } else {
+                    format!("/client{}/{}", client, i)
+                };
+                let route = RouteConfig::new(path.clone(), "backend").timeout(5);
+                match request_skipping_reloads(&mut conn, ClientMessage::AddRoute(route)).await {
+                    ServerMessage::Success(_) => added.push(path),
+                    ServerMessage::Error(e) => assert!(e.contains("already exists"), "{}", e),
+                    other => panic!("unexpected response: {:?}", other),
+                }
+            }
+            added
+        }));
+    }
+
+    // Meanwhile, keep reloading the file as the watcher would
+    let mut reloader = server.management().await;
+    let reloads = tokio::spawn(async move {
+        for _ in 0..50 {
+            assert!(matches!(
+                request_skipping_reloads(&mut reloader, ClientMessage::ReloadConfig).await,
+                ServerMessage::Success(_)
+            ));
+        }
+    });
+
+    let mut added = Vec::new();
+    for client in clients {
+        added.extend(client.await.unwrap());
+    }
+    reloads.await.unwrap();
+
+    // Each shared path was added by exactly one client, and nothing was
+    // lost to a reload
+    added.sort();
+    assert_eq!(added.len(), 10 + 8 * 10);
+    added.dedup();
+    assert_eq!(added.len(), 10 + 8 * 10);
+
+    let running = server.janus.config().await;
+    let mut paths: Vec<_> = running.routes.iter().map(|r| r.path.clone()).collect();
+    paths.sort();
+    assert_eq!(paths, added);
+    assert_eq!(JanusConfig::load(&path).unwrap(), running);
+
+    server.stop().await;
+}
+
+/// Check the response to one of each client message sent in turn by
+/// `test_every_client_message_is_handled`. The match has no catch-all, so a
+/// new message variant does not compile until it is covered here.
+fn check_response(msg: &ClientMessage, response: &ServerMessage) -> bool {
+    match msg {
+        ClientMessage::Authenticate { .. } => matches!(
+            response,
+            ServerMessage::Authenticated(ManagementRole::Admin)
+        ),
+        ClientMessage::Ping(id) => matches!(response, ServerMessage::Pong(pong) if pong == id),
+        ClientMessage::GetStatus => matches!(response, ServerMessage::Status(_)),
+        ClientMessage::GetConfig => matches!(response, ServerMessage::Config(_)),
+        ClientMessage::GetConfigDiff { .. } => matches!(response, ServerMessage::ConfigDiff(_)),
+        ClientMessage::GetStats => matches!(response, ServerMessage::Stats(_)),
+        ClientMessage::GetTopClients { .. } => matches!(response, ServerMessage::TopClients(_)),
+        ClientMessage::GetRouteStats { .. } => matches!(response, ServerMessage::RouteStats(_)),
+        ClientMessage::GetPreflight => matches!(response, ServerMessage::Preflight(_)),
+        ClientMessage::GetErrorLog { .. } => matches!(response, ServerMessage::ErrorLog(_)),
+        ClientMessage::GetAuditLog { .. } => matches!(response, ServerMessage::AuditLog(_)),
+        ClientMessage::UpdateConfig(_)
+        | ClientMessage::UpdateServerPort(_)
+        | ClientMessage::UpdateBindAddress(_)
+        | ClientMessage::AddRoute(_)
+        | ClientMessage::RemoveRoute(_)
+        | ClientMessage::SetRouteEnabled { .. }
+        | ClientMessage::AddUpstream { .. }
+        | ClientMessage::UpdateUpstream { .. }
+        | ClientMessage::RemoveUpstream(_)
+        | ClientMessage::DrainUpstream(_)
+        | ClientMessage::DrainBackendServer { .. }
+        | ClientMessage::AddStaticDir(_)
+        | ClientMessage::UpdateStaticDir { .. }
+        | ClientMessage::RemoveStaticDir(_)
+        | ClientMessage::SetStaticDirEnabled { .. }
+        | ClientMessage::ReloadConfig
+        | ClientMessage::ResetStats
+        | ClientMessage::SetMaintenance { .. }
+        | ClientMessage::Shutdown { .. } => matches!(response, ServerMessage::Success(_)),
+    }
+}
+
+#[tokio::test]
+async fn test_every_client_message_is_handled() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("janus.toml");
+
+    // Reloads validate the file, so it needs real ports
+    let mut config = test_config();
+    config.server.port = free_port();
+    config.management.port = free_port();
+    config.management.tokens = vec![ManagementToken {
+        token: "ops".to_string(),
+        role: ManagementRole::Admin,
+        name: None,
+    }];
+    config.upstreams.insert(
+        "backend".to_string(),
+        upstream(&["127.0.0.1:9".parse().unwrap()]),
+    );
+    config.save(&path).unwrap();
+    let server = TestServer::start_with_file(config.clone(), &path).await;
+    let mut conn = server.management().await;
+
+    let route = RouteConfig::new("/api/*", "backend").timeout(5);
+    let static_dir =
+        janus_common::StaticFileConfig::new("/static/", dir.path().to_string_lossy().into_owned());
+
+    // Ordered so each change has what it needs from the ones before it
+    let messages = vec![
+        ClientMessage::Authenticate {
+            token: "ops".to_string(),
+        },
+        ClientMessage::Ping(3),
+        ClientMessage::GetStatus,
+        ClientMessage::GetConfig,
+        ClientMessage::GetConfigDiff {
+            before: config.clone(),
+        },
+        ClientMessage::UpdateConfig(config.clone()),
+        ClientMessage::UpdateServerPort(config.server.port),
+        ClientMessage::UpdateBindAddress("127.0.0.1".to_string()),
+        ClientMessage::AddUpstream {
+            name: "spare".to_string(),
+            config: upstream(&["127.0.0.1:9".parse().unwrap()]),
+        },
+        ClientMessage::UpdateUpstream {
+            name: "spare".to_string(),
+            config: upstream(&["127.0.0.1:10".parse().unwrap()]),
+        },
+        ClientMessage::AddRoute(route),
+        ClientMessage::GetRouteStats {
+            path: "/api/*".to_string(),
+        },
+        ClientMessage::SetRouteEnabled {
+            path: "/api/*".to_string(),
+            enabled: false,
+        },
+        ClientMessage::RemoveRoute("/api/*".to_string()),
+        ClientMessage::AddStaticDir(static_dir.clone()),
+        ClientMessage::UpdateStaticDir {
+            path: "/static/".to_string(),
+            config: janus_common::StaticFileConfig {
+                directory_listing: true,
+                ..static_dir
+            },
+        },
+        ClientMessage::SetStaticDirEnabled {
+            path: "/static/".to_string(),
+            enabled: false,
+        },
+        ClientMessage::RemoveStaticDir("/static/".to_string()),
+        ClientMessage::DrainBackendServer {
+            upstream: "spare".to_string(),
+            server_address: "127.0.0.1:10".to_string(),
+        },
+        ClientMessage::DrainUpstream("spare".to_string()),
+        ClientMessage::RemoveUpstream("spare".to_string()),
+        ClientMessage::ReloadConfig,
+        ClientMessage::GetStats,
+        ClientMessage::GetTopClients { limit: 5 },
+        ClientMessage::ResetStats,
+        ClientMessage::SetMaintenance {
+            scope: MaintenanceScope::All,
+            enabled: false,
+            message: None,
+        },
+        ClientMessage::GetPreflight,
+        ClientMessage::GetErrorLog { limit: 5 },
+        ClientMessage::GetAuditLog { limit: 5 },
+        ClientMessage::Shutdown {
+            confirm: true,
+            delay_secs: 60,
+        },
+    ];
+
+    // One of each variant
+    let variants: std::collections::HashSet<_> =
+        messages.iter().map(std::mem::discriminant).collect();
+    assert_eq!(variants.len(), messages.len());
+
+    for msg in messages {
+        let response = request_skipping_reloads(&mut conn, msg.clone()).await;
+        assert!(
+            check_response(&msg, &response),
+            "unexpected response to {:?}: {:?}",
+            msg,
+            response
+        );
+    }
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_listener_changes_pending_until_restart() {
+    let server = TestServer::start(test_config()).await;
+    let mut conn = server.management().await;
+
+    let status = |response| match response {
+        ServerMessage::Status(status) => status,
+        other => panic!("unexpected response: {:?}", other),
+    };
+    let before = status(conn.request(ClientMessage::GetStatus).await);
+    assert_eq!(before.listen_address, server.addr.to_string());
+    assert!(before.pending_restart_settings.is_empty());
+
+    for change in [
+        ClientMessage::UpdateServerPort(free_port()),
+        ClientMessage::UpdateBindAddress("127.0.0.2".to_string()),
+    ] {
+        assert!(matches!(
+            conn.request(change).await,
+            ServerMessage::Success(_)
+        ));
+    }
+    let pending = status(conn.request(ClientMessage::GetStatus).await);
+    assert_eq!(pending.listen_address, server.addr.to_string());
+    assert_eq!(
+        pending.pending_restart_settings,
+        ["server.bind_address", "server.port"]
+    );
+
+    // Saving what the listener already uses clears them
+    for change in [
+        ClientMessage::UpdateServerPort(server.addr.port()),
+        ClientMessage::UpdateBindAddress("127.0.0.1".to_string()),
+    ] {
+        conn.request(change).await;
+    }
+    let after = status(conn.request(ClientMessage::GetStatus).await);
+    assert!(after.pending_restart_settings.is_empty());
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_polled_config_file_is_reloaded() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("janus.toml");
+
+    // Reloads validate the file, so it needs real ports
+    let mut config = test_config();
+    config.server.port = free_port();
+    config.management.port = free_port();
+    config.server.config_watch_mode = janus_common::WatchMode::Poll;
+    config.server.poll_interval_ms = 20;
+    config.save(&path).unwrap();
+    let server = TestServer::start_with_file(config.clone(), &path).await;
+    let mut conn = server.management().await;
+    // The connection is up, and the file has been looked at once, before
+    // it changes
+    conn.request(ClientMessage::Ping(1)).await;
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    config.upstreams.insert(
+        "backend".to_string(),
+        upstream(&["127.0.0.1:9".parse().unwrap()]),
+    );
+    config.save(&path).unwrap();
+    assert!(matches!(conn.recv().await, ServerMessage::ConfigReloaded));
+    match conn.recv().await {
+        ServerMessage::ConfigHash(config_hash) => assert_eq!(config_hash, config.config_hash()),
+        other => panic!("unexpected message: {:?}", other),
+    }
+    assert!(server
+        .janus
+        .state()
+        .config
+        .load()
+        .upstreams
+        .contains_key("backend"));
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_changes_are_saved_to_active_profile() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("janus.toml");
+    let mut content = test_config().to_toml().unwrap();
+    content.push_str("\n[profiles.test.server]\naccess_log = true\n");
+    std::fs::write(&path, content).unwrap();
+
+    let config = JanusConfig::load_with_profile(&path, "test").unwrap();
+    assert!(config.server.access_log);
+    let server = TestServer::start_with_profile(config, &path, "test").await;
+    let mut conn = server.management().await;
+
+    match conn.request(ClientMessage::GetStatus).await {
+        ServerMessage::Status(status) => assert_eq!(status.profile.as_deref(), Some("test")),
+        other => panic!("unexpected response: {:?}", other),
+    }
+    assert!(matches!(
+        conn.request(ClientMessage::UpdateServerPort(8081)).await,
+        ServerMessage::Success(_)
+    ));
+
+    // The base is untouched and the profile gains the change
+    let file = JanusConfigFile::load(&path).unwrap();
+    assert_eq!(file.resolve(None).unwrap(), test_config());
+    let profile = file.resolve(Some("test")).unwrap();
+    assert_eq!(profile.server.port, 8081);
+    assert!(profile.server.access_log);
+
+    server.stop().await;
+}
+
+/// Poll the status until the given number of connections is open
+async fn wait_for_active_connections(conn: &mut ManagementConn, active: u64) -> ServerStatus {
+    let poll = async {
+        loop {
+            if let ServerMessage::Status(status) = conn.request(ClientMessage::GetStatus).await {
+                if status.active_connections == active {
+                    return status;
+                }
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+    };
+    tokio::time::timeout(TIMEOUT, poll)
+        .await
+        .expect("connection count did not settle")
+}
+
+#[tokio::test]
+async fn test_status_counts_open_connections() {
+    let server = TestServer::start(test_config()).await;
+    let mut conn = server.management().await;
+
+    // The management connection is not a client connection
+    let open = TcpStream::connect(server.addr).await.unwrap();
+    let status = wait_for_active_connections(&mut conn, 1).await;
+    assert_eq!(status.connections_accepted, 1);
+    assert_eq!(status.connections_rejected, 0);
+
+    drop(open);
+    let status = wait_for_active_connections(&mut conn, 0).await;
+    assert_eq!(status.connections_accepted, 1);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_idle_connections_are_closed() {
+    let mut config = test_config();
+    config.server.idle_connection_timeout_secs = 1;
+    let server = TestServer::start(config).await;
+    let mut conn = server.management().await;
+
+    let mut open = TcpStream::connect(server.addr).await.unwrap();
+    let status = wait_for_active_connections(&mut conn, 1).await;
+    assert_eq!(status.idle_connections, 0);
+
+    let status = wait_for_active_connections(&mut conn, 0).await;
+    assert_eq!(status.connections_accepted, 1);
+    let mut buf = [0; 1];
+    let read = tokio::time::timeout(TIMEOUT, open.read(&mut buf)).await;
+    assert!(matches!(read, Ok(Ok(0)) | Ok(Err(_))), "{:?}", read);
+
+    server.stop().await;
+}
+
+/// Certificates for a management API requiring client certificates
+struct Pki {
+    dir: tempfile::TempDir,
+    ca: rcgen::Certificate,
+    ca_key: rcgen::KeyPair,
+}
+
+impl Pki {
+    fn new() -> Self {
+        let ca_key = rcgen::KeyPair::generate().unwrap();
+        let mut params = rcgen::CertificateParams::new(Vec::new()).unwrap();
+        params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, "Janus test CA");
+        let ca = params.self_signed(&ca_key).unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("ca.pem"), ca.pem()).unwrap();
+        let pki = Self { dir, ca, ca_key };
+        let (cert, key) = pki.issue("localhost");
+        std::fs::write(pki.path("server.pem"), cert.pem()).unwrap();
+        std::fs::write(pki.path("server.key"), key.serialize_pem()).unwrap();
+        pki
+    }
+
+    fn path(&self, name: &str) -> String {
+        self.dir.path().join(name).to_string_lossy().into_owned()
+    }
+
+    fn issue(&self, name: &str) -> (rcgen::Certificate, rcgen::KeyPair) {
+        let key = rcgen::KeyPair::generate().unwrap();
+        let mut params = rcgen::CertificateParams::new(vec![name.to_string()]).unwrap();
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, name);
+        let cert = params.signed_by(&key, &self.ca, &self.ca_key).unwrap();
+        (cert, key)
+    }
+
+    /// Open a WebSocket over TLS, presenting a client certificate for the
+    /// given name, if any
+    async fn connect(
+        &self,
+        addr: std::net::SocketAddr,
+        client: Option<&str>,
+    ) -> WebSocketStream<tokio_rustls::client::TlsStream<TcpStream>> {
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(self.ca.der().clone()).unwrap();
+        let builder = rustls::ClientConfig::builder().with_root_certificates(roots);
+        let config = match client {
+            Some(name) => {
+                let (cert, key) = self.issue(name);
+                let key = PrivatePkcs8KeyDer::from(key.serialize_der()).into();
+                builder
+                    .with_client_auth_cert(vec![cert.der().clone()], key)
+                    .unwrap()
+            }
+            None => builder.with_no_client_auth(),
+        };
+
+        let tcp = TcpStream::connect(addr).await.unwrap();
+        let connector = tokio_rustls::TlsConnector::from(Arc::new(config));
+        let domain = ServerName::try_from("localhost").unwrap();
+        let tls = connector.connect(domain, tcp).await.unwrap();
+        let (ws, _) = tokio_tungstenite::client_async("wss://localhost/", tls)
+            .await
+            .unwrap();
+        ws
+    }
+}
+
+/// Send a message over a raw WebSocket and wait for the response
+async fn tls_request<S>(ws: &mut WebSocketStream<S>, msg: ClientMessage) -> ServerMessage
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+{
+    let text = serde_json::to_string(&msg).unwrap();
+    ws.send(Message::Text(text)).await.unwrap();
+    loop {
+        let msg = tokio::time::timeout(TIMEOUT, ws.next())
+            .await
+            .expect("management response timed out")
+            .expect("management connection closed")
+            .unwrap();
+        if let Message::Text(text) = msg {
+            return serde_json::from_str(&text).unwrap();
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_client_certificate_required() {
+    let pki = Pki::new();
+    let mut config = test_config();
+    config.management.tls_cert = Some(pki.path("server.pem"));
+    config.management.tls_key = Some(pki.path("server.key"));
+    config.management.client_ca_cert = Some(pki.path("ca.pem"));
+    config.management.require_client_cert = true;
+    let server = TestServer::start(config).await;
+    let addr = server.janus.management_addr().unwrap();
+
+    let mut ws = pki.connect(addr, Some("ops")).await;
+    assert!(matches!(
+        tls_request(&mut ws, ClientMessage::GetStatus).await,
+        ServerMessage::Status(_)
+    ));
+
+    // Without a certificate the first response is the rejection
+    let mut ws = pki.connect(addr, None).await;
+    match tls_request(&mut ws, ClientMessage::GetStatus).await {
+        ServerMessage::Error(message) => assert_eq!(message, "Client certificate required"),
+        other => panic!("unexpected response: {:?}", other),
+    }
+
+    server.stop().await;
+}
@@ -0,0 +1,1091 @@
+//! End-to-end reverse proxy tests
+
+mod common;
+
+use bytes::Bytes;
+use common::{free_port, send, test_config, upstream, StubBackend, TestServer};
+use futures::{SinkExt, StreamExt};
+use http_body_util::{BodyExt, Full};
+use hyper::body::Incoming;
+use hyper::{Method, Request, Response, StatusCode};
+use janus_common::config::{BodySpoolConfig, CacheConfig, RouteConfig, UpstreamConfig};
+use janus_common::ClientMessage;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio_tungstenite::tungstenite::Message;
+
+#[tokio::test]
+async fn test_proxy_round_trip_with_headers() {
+    let backend = StubBackend::start("api").await;
+    let mut config = test_config();
+    config
+        .upstreams
+        .insert("api".to_string(), upstream(&[backend.addr]));
+    config.routes.push(
+        RouteConfig::new("/api/*", "api")
+            .header("X-Proxied-By", "janus")
+            .timeout(5),
+    );
+    let server = TestServer::start(config).await;
+
+    let response = server
+        .request(
+            Method::PUT,
+            "/api/items/7",
+            &[("X-Client", "test"), ("X-Forwarded-Proto", "https")],
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    // Upstream response headers are passed back
+    assert_eq!(response.header("x-backend"), Some("api"));
+
+    let echo = response.echo();
+    assert_eq!(echo.method, "PUT");
+    assert_eq!(echo.uri, "/api/items/7");
+    // Client headers are forwarded and route headers added
+    assert_eq!(echo.header("x-client"), Some("test"));
+    assert_eq!(echo.header("x-proxied-by"), Some("janus"));
+    // Host is rewritten to the upstream
+    assert_eq!(echo.header("host"), Some("127.0.0.1"));
+    // The client's scheme claim is replaced with the real one
+    assert_eq!(echo.header("x-forwarded-proto"), Some("http"));
+
+    assert_eq!(server.janus.stats().await.status_codes.success, 1);
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_force_https_redirects_plain_requests() {
+    let backend = StubBackend::start("api").await;
+    let mut config = test_config();
+    config
+        .upstreams
+        .insert("api".to_string(), upstream(&[backend.addr]));
+    config.server.default_upstream = Some("api".to_string());
+    config.server.force_https = true;
+    config.server.https_redirect_code = 307;
+    let server = TestServer::start(config).await;
+
+    let response = server
+        .request(Method::POST, "/submit?x=1", &[("Host", "example.com")])
+        .await;
+    assert_eq!(response.status, StatusCode::TEMPORARY_REDIRECT);
+    assert_eq!(
+        response.header("location"),
+        Some("https://example.com/submit?x=1")
+    );
+    assert_eq!(server.janus.stats().await.status_codes.redirect, 1);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_access_log_sampling_counts_skipped_requests() {
+    let backend = StubBackend::start("api").await;
+    let mut config = test_config();
+    config
+        .upstreams
+        .insert("api".to_string(), upstream(&[backend.addr]));
+    config.routes.push(RouteConfig {
+        path: "/api/*".to_string(),
+        ..proxy_all_to("api")
+    });
+    config.server.access_log = true;
+    config.server.access_log_sample_rate = 0.0;
+    let server = TestServer::start(config).await;
+
+    for _ in 0..3 {
+        assert_eq!(server.get("/api/x").await.status, StatusCode::OK);
+    }
+    // Errors are logged regardless of sampling
+    assert_eq!(server.get("/missing").await.status, StatusCode::NOT_FOUND);
+
+    let stats = server.janus.stats().await;
+    assert_eq!(stats.total_requests, 4);
+    assert_eq!(stats.access_log_sampled_out, 3);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_route_edits_response_headers() {
+    let backend = StubBackend::start("api").await;
+    let mut config = test_config();
+    config
+        .upstreams
+        .insert("api".to_string(), upstream(&[backend.addr]));
+    config.routes.push(RouteConfig {
+        add_response_headers: HashMap::from([
+            ("X-Frame-Options".to_string(), "DENY".to_string()),
+            (
+                "Content-Type".to_string(),
+                "application/vnd.api+json".to_string(),
+            ),
+        ]),
+        remove_response_headers: vec!["x-backend".to_string()],
+        ..proxy_all_to("api")
+    });
+    let server = TestServer::start(config).await;
+
+    let response = server.get("/x").await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.header("x-frame-options"), Some("DENY"));
+    assert_eq!(
+        response.header("content-type"),
+        Some("application/vnd.api+json")
+    );
+    assert_eq!(response.header("x-backend"), None);
+    assert_eq!(response.echo().backend, "api");
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_upstream_headers_merge_with_route_headers() {
+    let backend = StubBackend::start("api").await;
+    let mut config = test_config();
+    let mut api = upstream(&[backend.addr]);
+    api.headers = HashMap::from([
+        ("X-Internal-Service".to_string(), "janus".to_string()),
+        ("X-Env".to_string(), "upstream".to_string()),
+    ]);
+    config.upstreams.insert("api".to_string(), api);
+    config.routes.push(
+        RouteConfig::new("/*", "api")
+            .header("x-env", "route")
+            .timeout(5),
+    );
+    let server = TestServer::start(config).await;
+
+    let echo = server.get("/").await.echo();
+    assert_eq!(echo.header("x-internal-service"), Some("janus"));
+    // The route's value wins, without a duplicate from the upstream
+    let env: Vec<_> = echo
+        .headers
+        .iter()
+        .filter(|(name, _)| name == "x-env")
+        .collect();
+    assert_eq!(env, vec![&("x-env".to_string(), "route".to_string())]);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_repeated_headers_keep_every_value_in_order() {
+    let backend = StubBackend::start("api").await;
+    let mut config = test_config();
+    let mut api = upstream(&[backend.addr]);
+    api.headers = HashMap::from([("X-Trace".to_string(), "+upstream".to_string())]);
+    config.upstreams.insert("api".to_string(), api);
+    config.routes.push(
+        RouteConfig::new("/*", "api")
+            .header("X-Trace", "+route")
+            .header("X-Env", "route")
+            .timeout(5),
+    );
+    let server = TestServer::start(config).await;
+
+    let echo = server
+        .request(
+            Method::GET,
+            "/",
+            &[
+                ("Cookie", "a=1"),
+                ("Accept", "text/html"),
+                ("Cookie", "b=2"),
+                ("Accept", "application/json"),
+                ("X-Trace", "client"),
+                ("X-Env", "client"),
+            ],
+        )
+        .await
+        .echo();
+    let values = |name: &str| -> Vec<&str> {
+        echo.headers
+            .iter()
+            .filter(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+            .collect()
+    };
+    assert_eq!(values("cookie"), ["a=1", "b=2"]);
+    assert_eq!(values("accept"), ["text/html", "application/json"]);
+    // `+` values follow the client's, upstream before route
+    assert_eq!(values("x-trace"), ["client", "upstream", "route"]);
+    // Others replace them
+    assert_eq!(values("x-env"), ["route"]);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_header_name_case_is_forwarded() {
+    // Backend that reports the request head exactly as it arrived
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let backend = listener.local_addr().unwrap();
+    let (head_tx, head_rx) = tokio::sync::oneshot::channel();
+    tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut head = Vec::new();
+        let mut buf = [0u8; 4096];
+        while !head.ends_with(b"\r\n\r\n") {
+            let n = stream.read(&mut buf).await.unwrap();
+            if n == 0 {
+                break;
+            }
+            head.extend_from_slice(&buf[..n]);
+        }
+        let _ = head_tx.send(String::from_utf8_lossy(&head).into_owned());
+        let _ = stream.write_all(b"HTTP/1.1 204 No Content\r\n\r\n").await;
+    });
+
+    let mut config = test_config();
+    config
+        .upstreams
+        .insert("api".to_string(), upstream(&[backend]));
+    config
+        .routes
+        .push(proxy_all_to("api").header("X-Route-Header", "route"));
+    let server = TestServer::start(config).await;
+
+    let mut client = tokio::net::TcpStream::connect(server.addr).await.unwrap();
+    client
+        .write_all(
+            b"GET / HTTP/1.1\r\nHost: example.com\r\nX-SOAP-Action: call\r\n\
+              x-lower: yes\r\nConnection: close\r\n\r\n",
+        )
+        .await
+        .unwrap();
+    let mut response = String::new();
+    client.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 204"), "{}", response);
+
+    let head = head_rx.await.unwrap();
+    assert!(head.contains("\r\nX-SOAP-Action: call\r\n"), "{}", head);
+    assert!(head.contains("\r\nx-lower: yes\r\n"), "{}", head);
+    // Headers Janus adds are sent as configured
+    assert!(head.contains("\r\nx-route-header: route\r\n"), "{}", head);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_unreachable_upstream_is_502() {
+    // Reserve a port, then free it so nothing is listening
+    let addr = {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap()
+    };
+
+    let mut config = test_config();
+    config
+        .upstreams
+        .insert("dead".to_string(), upstream(&[addr]));
+    config
+        .routes
+        .push(RouteConfig::new("/*", "dead").timeout(5));
+    let server = TestServer::start(config).await;
+
+    let response = server.get("/anything").await;
+    assert_eq!(response.status, StatusCode::BAD_GATEWAY);
+    let stats = server.janus.stats().await;
+    assert_eq!(stats.status_codes.server_error, 1);
+    assert_eq!(stats.error_rate, 1.0);
+    assert_eq!(stats.client_error_rate, 0.0);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_no_available_backends_is_503() {
+    let backend = StubBackend::start("backup").await;
+    let mut config = test_config();
+    let mut backups = upstream(&[backend.addr]);
+    backups.servers[0].backup = true;
+    let mut drained = upstream(&[backend.addr]);
+    drained.retry_after_secs = Some(30);
+    for (name, upstream) in [
+        ("backups", backups),
+        ("drained", drained),
+        ("unusable", upstream(&[backend.addr])),
+    ] {
+        config.upstreams.insert(name.to_string(), upstream);
+        config
+            .routes
+            .push(RouteConfig::new(format!("/{}/*", name), name).timeout(5));
+    }
+    let server = TestServer::start(config).await;
+
+    // With only backup servers, the backups take the traffic
+    let response = server.get("/backups/").await;
+    assert_eq!(response.status, StatusCode::OK);
+
+    // Draining leaves no server to send to
+    let state = server.janus.state();
+    state
+        .draining_upstreams
+        .lock()
+        .await
+        .insert("drained".to_string());
+    for _ in 0..2 {
+        let response = server.get("/drained/").await;
+        assert_eq!(response.status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.header("retry-after"), Some("30"));
+    }
+
+    // Every server out of rotation, which validation would not let a
+    // config file do
+    let mut tx = state.transaction().await;
+    tx.config
+        .upstreams
+        .get_mut("unusable")
+        .unwrap()
+        .servers
+        .clear();
+    tx.apply().await;
+    let response = server.get("/unusable/").await;
+    assert_eq!(response.status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(response.header("retry-after"), None);
+
+    let stats = server.janus.stats().await;
+    let upstreams = &stats.upstream_stats;
+    assert_eq!(upstreams["backups"].no_backend_available, 0);
+    assert_eq!(upstreams["backups"].healthy_servers, 1);
+    assert_eq!(upstreams["drained"].no_backend_available, 2);
+    assert_eq!(upstreams["drained"].healthy_servers, 0);
+    assert_eq!(upstreams["drained"].total_servers, 1);
+    assert_eq!(upstreams["unusable"].no_backend_available, 1);
+    assert_eq!(upstreams["unusable"].total_servers, 0);
+    assert_eq!(stats.proxy_errors["no_backend"], 3);
+
+    server.stop().await;
+}
+
+/// Backend naming itself in `X-Backend`, taking half a second over
+/// requests for `/slow`
+async fn slow_on_request(name: &'static str) -> StubBackend {
+    StubBackend::start_with_async(move |req: Request<Incoming>| async move {
+        if req.uri().path() == "/slow" {
+            tokio::time::sleep(Duration::from_millis(500)).await;
+        }
+        Response::builder()
+            .header("X-Backend", name)
+            .body(Full::new(Bytes::new()))
+            .unwrap()
+    })
+    .await
+}
+
+#[tokio::test]
+async fn test_backends_at_capacity_are_skipped() {
+    let first = slow_on_request("first").await;
+    let second = slow_on_request("second").await;
+    let mut config = test_config();
+    let mut limited = upstream(&[first.addr, second.addr]);
+    for server in &mut limited.servers {
+        server.max_concurrent_requests = Some(1);
+    }
+    limited.retry_after_secs = Some(5);
+    config.upstreams.insert("limited".to_string(), limited);
+    config.routes.push(proxy_all_to("limited"));
+    let server = TestServer::start(config).await;
+    let pause = || tokio::time::sleep(Duration::from_millis(100));
+
+    // Round-robin picks first, second, first, second, first
+    let (slow, (fast, skipped, (other_slow, full))) = tokio::join!(server.get("/slow"), async {
+        pause().await;
+        let fast = server.get("/fast").await;
+        // The first server is busy, so the second takes its turn
+        let skipped = server.get("/fast").await;
+        let rest = tokio::join!(server.get("/slow"), async {
+            pause().await;
+            server.get("/fast").await
+        });
+        (fast, skipped, rest)
+    });
+    assert_eq!(slow.header("x-backend"), Some("first"));
+    assert_eq!(fast.header("x-backend"), Some("second"));
+    assert_eq!(skipped.header("x-backend"), Some("second"));
+    assert_eq!(other_slow.header("x-backend"), Some("second"));
+    assert_eq!(full.status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(full.header("retry-after"), Some("5"));
+
+    // Slots are given back once requests finish
+    assert_eq!(server.get("/fast").await.status, StatusCode::OK);
+    let stats = server.janus.stats().await;
+    assert_eq!(stats.upstream_stats["limited"].no_backend_available, 1);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_upstream_connection_limit_queues_requests() {
+    let first = slow_on_request("first").await;
+    let second = slow_on_request("second").await;
+    let mut config = test_config();
+    for (name, queue_timeout_secs) in [("strict", 0), ("queued", 2)] {
+        config.upstreams.insert(
+            name.to_string(),
+            UpstreamConfig {
+                max_connections: Some(1),
+                queue_timeout_secs,
+                ..upstream(&[first.addr, second.addr])
+            },
+        );
+        config.routes.push(RouteConfig {
+            strip_prefix: true,
+            ..RouteConfig::new(format!("/{}/*", name), name)
+        });
+    }
+    let server = TestServer::start(config).await;
+    let pause = || tokio::time::sleep(Duration::from_millis(100));
+
+    // The second backend is free, but the upstream is at its limit
+    let (slow, refused) = tokio::join!(server.get("/strict/slow"), async {
+        pause().await;
+        server.get("/strict/fast").await
+    });
+    assert_eq!(slow.status, StatusCode::OK);
+    assert_eq!(refused.status, StatusCode::SERVICE_UNAVAILABLE);
+
+    let (slow, queued) = tokio::join!(server.get("/queued/slow"), async {
+        pause().await;
+        let started = Instant::now();
+        let response = server.get("/queued/fast").await;
+        (response, started.elapsed())
+    });
+    assert_eq!(slow.status, StatusCode::OK);
+    assert_eq!(queued.0.status, StatusCode::OK);
+    assert!(queued.1 >= Duration::from_millis(300), "{:?}", queued.1);
+
+    let stats = server.janus.stats().await;
+    assert_eq!(stats.upstream_stats["strict"].no_backend_available, 1);
+    let queued = &stats.upstream_stats["queued"];
+    assert_eq!(queued.no_backend_available, 0);
+    assert_eq!(queued.max_connections, Some(1));
+    assert_eq!(queued.peak_connections, 1);
+    assert_eq!(queued.active_connections, 0);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_route_cache_answers_repeat_requests() {
+    // Numbers its responses, and asks for `/private` not to be stored
+    let requests = Arc::new(AtomicUsize::new(0));
+    let counter = requests.clone();
+    let backend = StubBackend::start_with(move |req| {
+        let n = counter.fetch_add(1, Ordering::SeqCst);
+        let mut response = Response::builder();
+        if req.uri().path() == "/private" {
+            response = response.header("cache-control", "no-store");
+        }
+        response
+            .body(Full::new(Bytes::from(n.to_string())))
+            .unwrap()
+    })
+    .await;
+    let mut config = test_config();
+    config
+        .upstreams
+        .insert("products".to_string(), upstream(&[backend.addr]));
+    config.routes.push(RouteConfig {
+        cache: Some(CacheConfig {
+            vary_on: vec!["Accept-Language".to_string()],
+            ..Default::default()
+        }),
+        ..proxy_all_to("products")
+    });
+    let server = TestServer::start(config).await;
+    let client = &server;
+    let get = |path: &'static str, language: &'static str| async move {
+        let headers = [("accept-language", language)];
+        client.request(Method::GET, path, &headers).await
+    };
+
+    let first = get("/products", "en").await;
+    assert_eq!(first.header("x-cache"), Some("MISS"));
+    let hit = get("/products", "en").await;
+    assert_eq!(hit.header("x-cache"), Some("HIT"));
+    assert_eq!(hit.body, first.body);
+    assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+    // Varies on the language, and never keeps what the upstream forbids
+    assert_eq!(get("/products", "de").await.header("x-cache"), Some("MISS"));
+    get("/private", "en").await;
+    assert_eq!(get("/private", "en").await.header("x-cache"), Some("MISS"));
+    let post = server.request(Method::POST, "/products", &[]).await;
+    assert_eq!(post.header("x-cache"), None);
+    assert_eq!(requests.load(Ordering::SeqCst), 5);
+
+    // Hits still count as traffic for the route
+    let stats = server.janus.stats().await;
+    assert_eq!(stats.route_stats["/*"].requests, 6);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_slow_upstream_hits_upstream_timeout() {
+    let backend = StubBackend::start_with_delay("slow", Duration::from_secs(3)).await;
+    let mut config = test_config();
+    config
+        .upstreams
+        .insert("slow".to_string(), upstream(&[backend.addr]));
+    config.routes.push(RouteConfig {
+        upstream_timeout_secs: Some(1),
+        ..RouteConfig::new("/*", "slow").timeout(30)
+    });
+    let server = TestServer::start(config).await;
+
+    let started = Instant::now();
+    let response = server.get("/anything").await;
+    assert_eq!(response.status, StatusCode::GATEWAY_TIMEOUT);
+    assert!(started.elapsed() < Duration::from_secs(3));
+    assert_eq!(server.janus.stats().await.proxy_errors["timeout"], 1);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_timeout_per_try_bounds_each_attempt() {
+    let backend = StubBackend::start_with_delay("slow", Duration::from_secs(3)).await;
+    let mut config = test_config();
+    let mut slow = upstream(&[backend.addr]);
+    slow.timeout_per_try = Some(1);
+    config.upstreams.insert("slow".to_string(), slow);
+    config.routes.push(RouteConfig {
+        timeout: 30,
+        ..proxy_all_to("slow")
+    });
+    let server = TestServer::start(config).await;
+
+    let started = Instant::now();
+    let response = server.get("/anything").await;
+    assert_eq!(response.status, StatusCode::GATEWAY_TIMEOUT);
+    assert!(started.elapsed() < Duration::from_secs(3));
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_retry_codes_trigger_retries() {
+    // Every other request is refused with a 503
+    let requests = Arc::new(AtomicUsize::new(0));
+    let counter = requests.clone();
+    let backend = StubBackend::start_with_async(move |req: Request<Incoming>| {
+        let counter = counter.clone();
+        async move {
+            let body = req.into_body().collect().await.unwrap().to_bytes();
+            let status = if counter.fetch_add(1, Ordering::SeqCst).is_multiple_of(2) {
+                StatusCode::SERVICE_UNAVAILABLE
+            } else {
+                StatusCode::OK
+            };
+            Response::builder()
+                .status(status)
+                .body(Full::new(body))
+                .unwrap()
+        }
+    })
+    .await;
+
+    let mut config = test_config();
+    let mut retrying = upstream(&[backend.addr]);
+    retrying.retries = 1;
+    // The retry reuses the request's connection slot rather than waiting
+    // for another
+    retrying.max_connections = Some(1);
+    retrying.queue_timeout_secs = 0;
+    let mut not_on_503 = upstream(&[backend.addr]);
+    not_on_503.retries = 1;
+    not_on_503.retry_codes = vec![429];
+    for (name, upstream) in [("retrying", retrying), ("not_on_503", not_on_503)] {
+        config.upstreams.insert(name.to_string(), upstream);
+        config
+            .routes
+            .push(RouteConfig::new(format!("/{}/*", name), name).timeout(5));
+    }
+    let server = TestServer::start(config).await;
+
+    // The body is sent again with the retry
+    let response = send(
+        Request::post(format!("http://{}/retrying/", server.addr))
+            .body(Full::new(Bytes::from("payload")))
+            .unwrap(),
+    )
+    .await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.body, "payload");
+    assert_eq!(requests.load(Ordering::SeqCst), 2);
+
+    let response = server.get("/not_on_503/").await;
+    assert_eq!(response.status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(requests.load(Ordering::SeqCst), 3);
+
+    // A retried request counts once, failing only if its last answer did
+    let stats = server.janus.stats().await;
+    let retried = &stats.upstream_stats["retrying"];
+    assert_eq!(
+        (retried.requests, retried.failures, retried.retries),
+        (1, 0, 1)
+    );
+    assert!(retried.avg_response_time_ms > 0.0);
+    let refused = &stats.upstream_stats["not_on_503"];
+    assert_eq!(
+        (refused.requests, refused.failures, refused.retries),
+        (1, 1, 0)
+    );
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_retry_without_a_backend_keeps_upstream_answer() {
+    let requests = Arc::new(AtomicUsize::new(0));
+    let counter = requests.clone();
+    let backend = StubBackend::start_with_async(move |_req: Request<Incoming>| {
+        let counter = counter.clone();
+        async move {
+            counter.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            Response::builder()
+                .status(StatusCode::SERVICE_UNAVAILABLE)
+                .body(Full::new(Bytes::from("upstream busy")))
+                .unwrap()
+        }
+    })
+    .await;
+
+    let mut config = test_config();
+    let mut busy = upstream(&[backend.addr]);
+    busy.servers[0].max_concurrent_requests = Some(1);
+    busy.retries = 1;
+    config.upstreams.insert("busy".to_string(), busy);
+    config.routes.push(proxy_all_to("busy"));
+    let server = TestServer::start(config).await;
+    let mut mgmt = server.management().await;
+
+    // Drained while the first attempt is in flight, so the retry finds
+    // no backend
+    let (response, _) = tokio::join!(server.get("/"), async {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        mgmt.request(ClientMessage::DrainUpstream("busy".to_string()))
+            .await
+    });
+    assert_eq!(response.status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(response.body, "upstream busy");
+    assert_eq!(requests.load(Ordering::SeqCst), 1);
+    let stats = server.janus.stats().await;
+    assert_eq!(stats.upstream_stats["busy"].no_backend_available, 1);
+
+    server.stop().await;
+}
+
+/// Backend answering every connection with the same raw bytes, built from
+/// its own address
+async fn raw_backend(response: impl FnOnce(SocketAddr) -> Vec<u8>) -> SocketAddr {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let response = response(addr);
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let mut buf = [0u8; 4096];
+            let _ = stream.read(&mut buf).await;
+            let _ = stream.write_all(&response).await;
+        }
+    });
+    addr
+}
+
+fn proxy_all_to(upstream_name: &str) -> RouteConfig {
+    RouteConfig::new("/*", upstream_name).timeout(5)
+}
+
+#[tokio::test]
+async fn test_malformed_upstream_responses_are_counted() {
+    let huge = format!(
+        "HTTP/1.1 200 OK\r\nX-Huge: {}\r\nContent-Length: 0\r\n\r\n",
+        "a".repeat(16 * 1024)
+    );
+    let mut big = upstream(&[raw_backend(|_| huge.into_bytes()).await]);
+    big.max_response_header_bytes = 8192;
+    let invalid = raw_backend(|_| b"HTTP/1.1 200 OK\r\nBad Header\r\n\r\n".to_vec()).await;
+    let switching = raw_backend(|_| b"HTTP/1.1 101 Switching Protocols\r\n\r\n".to_vec()).await;
+
+    let mut config = test_config();
+    config.upstreams.insert("big".to_string(), big);
+    config
+        .upstreams
+        .insert("invalid".to_string(), upstream(&[invalid]));
+    config
+        .upstreams
+        .insert("switching".to_string(), upstream(&[switching]));
+    for name in ["big", "invalid", "switching"] {
+        let mut route = proxy_all_to(name);
+        route.path = format!("/{}/*", name);
+        config.routes.push(route);
+    }
+    let server = TestServer::start(config).await;
+
+    for name in ["big", "invalid", "switching"] {
+        let response = server.get(&format!("/{}/", name)).await;
+        assert_eq!(response.status, StatusCode::BAD_GATEWAY, "{}", name);
+    }
+
+    let stats = server.janus.stats().await;
+    for name in ["big", "invalid", "switching"] {
+        assert_eq!(
+            stats.upstream_stats[name].upstream_protocol_errors, 1,
+            "{}",
+            name
+        );
+    }
+    assert_eq!(stats.proxy_errors["protocol"], 3);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_proxy_failures_are_classified() {
+    let dead = format!("127.0.0.1:{}", free_port()).parse().unwrap();
+    let mut config = test_config();
+    config
+        .upstreams
+        .insert("dead".to_string(), upstream(&[dead]));
+    config.upstreams.insert("empty".to_string(), upstream(&[]));
+    for name in ["dead", "empty"] {
+        let mut route = proxy_all_to(name);
+        route.path = format!("/{}/*", name);
+        config.routes.push(route);
+    }
+    let server = TestServer::start(config).await;
+
+    // The page names the request without giving away the cause
+    let response = server
+        .request(Method::GET, "/dead/", &[("X-Request-Id", "req-42")])
+        .await;
+    assert_eq!(response.status, StatusCode::BAD_GATEWAY);
+    assert_eq!(response.header("x-request-id"), Some("req-42"));
+    assert!(response.body.contains("Request ID: req-42"));
+    assert!(!response.body.contains("connect"));
+
+    let response = server.get("/empty/").await;
+    assert_eq!(response.status, StatusCode::SERVICE_UNAVAILABLE);
+    assert!(response.header("x-request-id").is_some());
+
+    let stats = server.janus.stats().await;
+    assert_eq!(stats.proxy_errors["connect"], 1);
+    assert_eq!(stats.proxy_errors["no_backend"], 1);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_informational_responses_are_skipped() {
+    let backend = raw_backend(|_| {
+        b"HTTP/1.1 103 Early Hints\r\nLink: </app.css>\r\n\r\n\
+          HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello"
+            .to_vec()
+    })
+    .await;
+    let mut config = test_config();
+    config
+        .upstreams
+        .insert("hints".to_string(), upstream(&[backend]));
+    config.routes.push(proxy_all_to("hints"));
+    let server = TestServer::start(config).await;
+
+    let response = server.get("/").await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.body, "hello");
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_backend_redirects_are_rewritten() {
+    let redirect = |addr: SocketAddr| {
+        format!(
+            "HTTP/1.1 302 Found\r\nLocation: http://{}/login?next=%2F\r\n\
+             Content-Length: 0\r\n\r\n",
+            addr
+        )
+        .into_bytes()
+    };
+    let rewritten = raw_backend(redirect).await;
+    let untouched = raw_backend(redirect).await;
+
+    let mut config = test_config();
+    config
+        .upstreams
+        .insert("app".to_string(), upstream(&[rewritten]));
+    config
+        .upstreams
+        .insert("legacy".to_string(), upstream(&[untouched]));
+    let mut route = proxy_all_to("legacy");
+    route.path = "/legacy/*".to_string();
+    route.proxy_redirect = false;
+    config.routes.push(route);
+    config.routes.push(proxy_all_to("app"));
+    let server = TestServer::start(config).await;
+
+    let response = server.get("/").await;
+    assert_eq!(response.status, StatusCode::FOUND);
+    assert_eq!(
+        response.header("location"),
+        Some(format!("http://{}/login?next=%2F", server.addr).as_str())
+    );
+
+    let response = server.get("/legacy/").await;
+    assert_eq!(
+        response.header("location"),
+        Some(format!("http://{}/login?next=%2F", untouched).as_str())
+    );
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_repeated_response_headers_are_kept() {
+    let cookies = |_| {
+        b"HTTP/1.1 200 OK\r\nSet-Cookie: session=abc; HttpOnly\r\n\
+          Set-Cookie: theme=dark\r\nSet-Cookie: lang=en\r\nContent-Length: 0\r\n\r\n"
+            .to_vec()
+    };
+    let mut config = test_config();
+    config
+        .upstreams
+        .insert("app".to_string(), upstream(&[raw_backend(cookies).await]));
+    config.routes.push(proxy_all_to("app"));
+    let server = TestServer::start(config).await;
+
+    let response = server.get("/").await;
+    assert_eq!(response.status, StatusCode::OK);
+    let cookies: Vec<_> = response
+        .headers
+        .get_all("set-cookie")
+        .iter()
+        .map(|v| v.to_str().unwrap())
+        .collect();
+    assert_eq!(cookies, ["session=abc; HttpOnly", "theme=dark", "lang=en"]);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_request_body_is_streamed() {
+    // Answers as soon as the first chunk of the body arrives
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let backend = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut received = Vec::new();
+        let mut buf = [0u8; 4096];
+        while !String::from_utf8_lossy(&received).contains("first-chunk") {
+            let n = stream.read(&mut buf).await.unwrap();
+            assert!(n > 0, "connection closed before the first chunk");
+            received.extend_from_slice(&buf[..n]);
+        }
+        let _ = stream
+            .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\nconnection: close\r\n\r\nok")
+            .await;
+    });
+
+    let mut config = test_config();
+    config
+        .upstreams
+        .insert("uploads".to_string(), upstream(&[backend]));
+    let mut route = proxy_all_to("uploads");
+    route.stream_request_body = true;
+    config.routes.push(route);
+    let server = TestServer::start(config).await;
+
+    // Send one chunk and leave the body open
+    let mut client = tokio::net::TcpStream::connect(server.addr).await.unwrap();
+    client
+        .write_all(
+            b"POST /upload HTTP/1.1\r\nhost: janus\r\ntransfer-encoding: chunked\r\n\r\n\
+              b\r\nfirst-chunk\r\n",
+        )
+        .await
+        .unwrap();
+
+    let mut response = [0u8; 12];
+    tokio::time::timeout(Duration::from_secs(5), client.read_exact(&mut response))
+        .await
+        .expect("body was buffered instead of streamed")
+        .unwrap();
+    assert_eq!(&response, b"HTTP/1.1 200");
+
+    drop(client);
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_in_flight_request_keeps_its_config() {
+    let slow = StubBackend::start_with_delay("slow", Duration::from_millis(500)).await;
+    let fast = StubBackend::start("fast").await;
+    let mut config = test_config();
+    config
+        .upstreams
+        .insert("slow".to_string(), upstream(&[slow.addr]));
+    config.routes.push(proxy_all_to("slow"));
+    config.management.enabled = false;
+    let server = TestServer::start(config).await;
+
+    // Repoint the route while the first request is waiting on its backend
+    let mut config = server.janus.config().await;
+    config.server.port = server.addr.port();
+    config
+        .upstreams
+        .insert("fast".to_string(), upstream(&[fast.addr]));
+    config.routes[0] = proxy_all_to("fast");
+    let update = async {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let started = Instant::now();
+        server.janus.update_config(config).await.unwrap();
+        let elapsed = started.elapsed();
+        (elapsed, server.get("/second").await)
+    };
+    let (first, (elapsed, second)) = tokio::join!(server.get("/first"), update);
+
+    assert!(
+        elapsed < Duration::from_millis(250),
+        "update took {elapsed:?}"
+    );
+    assert_eq!(second.echo().backend, "fast");
+    assert_eq!(first.echo().backend, "slow");
+
+    server.stop().await;
+}
+
+/// Backend echoing every WebSocket message it receives
+async fn websocket_echo_backend() -> SocketAddr {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let Ok(mut ws) = tokio_tungstenite::accept_async(stream).await else {
+                    return;
+                };
+                while let Some(Ok(msg)) = ws.next().await {
+                    if msg.is_text() && ws.send(msg).await.is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+    });
+    addr
+}
+
+#[tokio::test]
+async fn test_websocket_session_timeout() {
+    let backend = websocket_echo_backend().await;
+    let mut config = test_config();
+    config
+        .upstreams
+        .insert("ws".to_string(), upstream(&[backend]));
+    // The request timeout does not cut WebSocket sessions short
+    config.routes.push(RouteConfig {
+        path: "/open".to_string(),
+        timeout: 1,
+        ..proxy_all_to("ws")
+    });
+    config.routes.push(RouteConfig {
+        path: "/limited".to_string(),
+        websocket_timeout: Some(1),
+        ..proxy_all_to("ws")
+    });
+    let server = TestServer::start(config).await;
+
+    let connect =
+        |path: &str| tokio_tungstenite::connect_async(format!("ws://{}{}", server.addr, path));
+    let (mut open, response) = connect("/open").await.unwrap();
+    assert_eq!(response.status(), StatusCode::SWITCHING_PROTOCOLS);
+    let (mut limited, _) = connect("/limited").await.unwrap();
+
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+
+    open.send(Message::text("still here")).await.unwrap();
+    assert_eq!(
+        open.next().await.unwrap().unwrap(),
+        Message::text("still here")
+    );
+
+    // The session was closed once its time was up
+    let _ = limited.send(Message::text("too late")).await;
+    assert!(!matches!(limited.next().await, Some(Ok(Message::Text(_)))));
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_large_request_bodies_spool_to_disk() {
+    // Answers with the length and a checksum of the body it received
+    let backend = StubBackend::start_with_async(|req: Request<Incoming>| async move {
+        let body = req.into_body().collect().await.unwrap().to_bytes();
+        let sum: u64 = body.iter().map(|b| u64::from(*b)).sum();
+        Response::new(Full::new(Bytes::from(format!("{} {}", body.len(), sum))))
+    })
+    .await;
+    let spool_dir = tempfile::tempdir().unwrap();
+    let mut config = test_config();
+    config
+        .upstreams
+        .insert("uploads".to_string(), upstream(&[backend.addr]));
+    config.routes.push(RouteConfig {
+        body_spool: Some(BodySpoolConfig {
+            memory_limit_bytes: 1024,
+            directory: Some(spool_dir.path().to_string_lossy().into_owned()),
+        }),
+        ..proxy_all_to("uploads")
+    });
+    config.server.body_spool_max_bytes = 200_000;
+    let server = TestServer::start(config).await;
+
+    let upload = |len: usize| {
+        let body: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+        let sum: u64 = body.iter().map(|b| u64::from(*b)).sum();
+        let request = Request::post(format!("http://{}/upload", server.addr))
+            .body(Full::new(Bytes::from(body)))
+            .unwrap();
+        (send(request), format!("{} {}", len, sum))
+    };
+
+    let (response, expected) = upload(100);
+    assert_eq!(response.await.body, expected);
+    assert_eq!(server.janus.stats().await.spooled_requests, 0);
+
+    let (response, expected) = upload(150_000);
+    assert_eq!(response.await.body, expected);
+    assert_eq!(server.janus.stats().await.spooled_requests, 1);
+
+    // Over the spool's limit
+    let (response, _) = upload(250_000);
+    assert_eq!(response.await.status, StatusCode::SERVICE_UNAVAILABLE);
+    let stats = server.janus.stats().await;
+    assert_eq!(stats.spooled_requests, 2);
+    assert_eq!(stats.proxy_errors.get("spool_full"), Some(&1));
+
+    // Spool files go once their request is done
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while std::fs::read_dir(spool_dir.path())
+            .unwrap()
+            .next()
+            .is_some()
+            || server.janus.stats().await.spooled_bytes > 0
+        {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("spool files left behind");
+
+    server.stop().await;
+}
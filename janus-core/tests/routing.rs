@@ -0,0 +1,427 @@
+//! End-to-end route matching tests
+
+mod common;
+
+use common::{free_port, test_config, upstream, StubBackend, TestServer};
+use hyper::{Method, StatusCode};
+use janus_common::config::{MethodMismatch, RouteConfig};
+use janus_common::RouteStats;
+use std::collections::HashMap;
+use std::time::Duration;
+
+fn route(path: &str, upstream: &str) -> RouteConfig {
+    RouteConfig::new(path, upstream).timeout(5)
+}
+
+#[tokio::test]
+async fn test_exact_route_match() {
+    let backend = StubBackend::start("exact").await;
+    let mut config = test_config();
+    config
+        .upstreams
+        .insert("backend".to_string(), upstream(&[backend.addr]));
+    config.routes.push(route("/health", "backend"));
+    let server = TestServer::start(config).await;
+
+    let response = server.get("/health").await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.echo().uri, "/health");
+
+    let response = server.get("/health/deep").await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_prefix_route_match() {
+    let backend = StubBackend::start("prefix").await;
+    let mut config = test_config();
+    config
+        .upstreams
+        .insert("backend".to_string(), upstream(&[backend.addr]));
+    config.routes.push(route("/api/*", "backend"));
+    let server = TestServer::start(config).await;
+
+    let response = server.get("/api/users/42?expand=true").await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.echo().uri, "/api/users/42?expand=true");
+
+    let response = server.get("/other").await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_first_matching_route_wins() {
+    let first = StubBackend::start("first").await;
+    let second = StubBackend::start("second").await;
+    let mut config = test_config();
+    config
+        .upstreams
+        .insert("first".to_string(), upstream(&[first.addr]));
+    config
+        .upstreams
+        .insert("second".to_string(), upstream(&[second.addr]));
+    config.routes.push(route("/api/*", "first"));
+    config.routes.push(route("/*", "second"));
+    let server = TestServer::start(config).await;
+
+    assert_eq!(server.get("/api/x").await.echo().backend, "first");
+    assert_eq!(server.get("/x").await.echo().backend, "second");
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_round_robin_alternates_backends() {
+    let a = StubBackend::start("a").await;
+    let b = StubBackend::start("b").await;
+    let mut config = test_config();
+    config
+        .upstreams
+        .insert("pool".to_string(), upstream(&[a.addr, b.addr]));
+    config.routes.push(route("/*", "pool"));
+    let server = TestServer::start(config).await;
+
+    let mut backends = Vec::new();
+    for _ in 0..4 {
+        backends.push(server.get("/x").await.echo().backend);
+    }
+    assert_eq!(backends, ["a", "b", "a", "b"]);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_method_filtering() {
+    let posts = StubBackend::start("posts").await;
+    let fallback = StubBackend::start("fallback").await;
+    let mut config = test_config();
+    config
+        .upstreams
+        .insert("posts".to_string(), upstream(&[posts.addr]));
+    config
+        .upstreams
+        .insert("fallback".to_string(), upstream(&[fallback.addr]));
+    let mut post_only = route("/submit", "posts");
+    post_only.methods = vec!["post".to_string()];
+    config.routes.push(post_only);
+    let server = TestServer::start(config.clone()).await;
+
+    // Method names match case-insensitively
+    let response = server.request(Method::POST, "/submit", &[]).await;
+    assert_eq!(response.echo().backend, "posts");
+
+    // A method mismatch with no other route is a 404
+    let response = server.get("/submit").await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+    server.stop().await;
+
+    // A later route picks up mismatched methods
+    config.routes.push(route("/*", "fallback"));
+    let server = TestServer::start(config).await;
+    let response = server.get("/submit").await;
+    assert_eq!(response.echo().backend, "fallback");
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_method_mismatch_rejected_with_405() {
+    let posts = StubBackend::start("posts").await;
+    let fallback = StubBackend::start("fallback").await;
+    let mut config = test_config();
+    config
+        .upstreams
+        .insert("posts".to_string(), upstream(&[posts.addr]));
+    config
+        .upstreams
+        .insert("fallback".to_string(), upstream(&[fallback.addr]));
+    let mut post_only = route("/submit", "posts");
+    post_only.methods = vec!["post".to_string(), "PUT".to_string()];
+    post_only.on_method_mismatch = MethodMismatch::Reject405;
+    config.routes.push(post_only);
+    config.routes.push(route("/*", "fallback"));
+    let server = TestServer::start(config).await;
+
+    // The catch-all is never reached for the rejecting route's path
+    let response = server.get("/submit").await;
+    assert_eq!(response.status, StatusCode::METHOD_NOT_ALLOWED);
+    assert_eq!(response.header("allow"), Some("POST, PUT"));
+    assert!(response.header("x-request-id").is_some());
+
+    // A client-supplied request ID is echoed back
+    let response = server
+        .request(Method::DELETE, "/submit", &[("X-Request-Id", "abc123")])
+        .await;
+    assert_eq!(response.status, StatusCode::METHOD_NOT_ALLOWED);
+    assert_eq!(response.header("x-request-id"), Some("abc123"));
+
+    assert_eq!(
+        server
+            .request(Method::PUT, "/submit", &[])
+            .await
+            .echo()
+            .backend,
+        "posts"
+    );
+    assert_eq!(server.get("/other").await.echo().backend, "fallback");
+
+    let stats = server.janus.stats().await;
+    assert_eq!(stats.status_codes.client_error, 2);
+    assert_eq!(stats.status_codes.success, 2);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_header_matching_selects_route() {
+    let acme = StubBackend::start("acme").await;
+    let shared = StubBackend::start("shared").await;
+    let mut config = test_config();
+    config
+        .upstreams
+        .insert("acme".to_string(), upstream(&[acme.addr]));
+    config
+        .upstreams
+        .insert("shared".to_string(), upstream(&[shared.addr]));
+
+    let mut tenant_route = route("/api/*", "acme");
+    tenant_route.match_headers = HashMap::from([("X-Tenant".to_string(), "acme*".to_string())]);
+    config.routes.push(tenant_route);
+    config.routes.push(route("/api/*", "shared"));
+    let server = TestServer::start(config).await;
+
+    let response = server
+        .request(Method::GET, "/api/x", &[("X-Tenant", "acme")])
+        .await;
+    assert_eq!(response.echo().backend, "acme");
+    let response = server
+        .request(Method::GET, "/api/x", &[("X-Tenant", "acme-eu")])
+        .await;
+    assert_eq!(response.echo().backend, "acme");
+
+    // A different or missing header falls through to the next route
+    let response = server
+        .request(Method::GET, "/api/x", &[("X-Tenant", "globex")])
+        .await;
+    assert_eq!(response.echo().backend, "shared");
+    assert_eq!(server.get("/api/x").await.echo().backend, "shared");
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_query_matching_selects_route() {
+    let v1 = StubBackend::start("v1").await;
+    let v2 = StubBackend::start("v2").await;
+    let mut config = test_config();
+    config
+        .upstreams
+        .insert("v1".to_string(), upstream(&[v1.addr]));
+    config
+        .upstreams
+        .insert("v2".to_string(), upstream(&[v2.addr]));
+
+    // Listed after the plain route, but tried before it
+    config.routes.push(route("/api/*", "v1"));
+    config
+        .routes
+        .push(route("/api/*", "v2").match_query("version", "2"));
+    let server = TestServer::start(config).await;
+
+    let echo = server.get("/api/users?version=2").await.echo();
+    assert_eq!(echo.backend, "v2");
+    assert_eq!(echo.uri, "/api/users?version=2");
+    assert_eq!(
+        server
+            .get("/api/users?page=3&version=2")
+            .await
+            .echo()
+            .backend,
+        "v2"
+    );
+    assert_eq!(
+        server.get("/api/users?version=1").await.echo().backend,
+        "v1"
+    );
+    assert_eq!(server.get("/api/users").await.echo().backend, "v1");
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_rewrite() {
+    let backend = StubBackend::start("rewrite").await;
+    let mut config = test_config();
+    config
+        .upstreams
+        .insert("backend".to_string(), upstream(&[backend.addr]));
+    let mut rewritten = route("/api/*", "backend");
+    rewritten.rewrite = Some("/v1/".to_string());
+    config.routes.push(rewritten);
+    let server = TestServer::start(config).await;
+
+    let response = server.get("/api/users?page=2").await;
+    assert_eq!(response.echo().uri, "/v1/users?page=2");
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_strip_prefix() {
+    let backend = StubBackend::start("strip").await;
+    let mut config = test_config();
+    config
+        .upstreams
+        .insert("backend".to_string(), upstream(&[backend.addr]));
+    let mut stripped = route("/api/*", "backend");
+    stripped.strip_prefix = true;
+    config.routes.push(stripped);
+    let server = TestServer::start(config).await;
+
+    assert_eq!(
+        server.get("/api/users?page=2").await.echo().uri,
+        "/users?page=2"
+    );
+    assert_eq!(server.get("/api").await.echo().uri, "/");
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_unmatched_request_is_404() {
+    let server = TestServer::start(test_config()).await;
+
+    let response = server.get("/nothing/here").await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+    assert!(response.body.contains("404 Not Found"));
+
+    let stats = server.janus.stats().await;
+    assert_eq!(stats.total_requests, 1);
+    assert_eq!(stats.status_codes.client_error, 1);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_oversized_requests_are_rejected() {
+    let mut config = test_config();
+    config.server.max_uri_bytes = 100;
+    config.server.max_request_headers = 10;
+    config.server.max_request_header_bytes = 16 * 1024;
+    let server = TestServer::start(config).await;
+
+    let long_path = format!("/{}", "a".repeat(100));
+    let response = server.get(&long_path).await;
+    assert_eq!(response.status, StatusCode::URI_TOO_LONG);
+
+    let names: Vec<_> = (0..20).map(|i| format!("x-extra-{}", i)).collect();
+    let many: Vec<_> = names.iter().map(|name| (name.as_str(), "1")).collect();
+    let response = server.request(Method::GET, "/", &many).await;
+    assert_eq!(response.status, StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE);
+
+    // The header block limit is checked as the head is read, in steps
+    let cookie = "a".repeat(64 * 1024);
+    let response = server
+        .request(Method::GET, "/", &[("cookie", cookie.as_str())])
+        .await;
+    assert_eq!(response.status, StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE);
+
+    // Requests within the limits are routed as usual
+    let response = server.get(&long_path[..99]).await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+
+    // Header rejections are counted once hyper closes the connection
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let stats = server.janus.stats().await;
+    assert_eq!(stats.uri_too_long, 1);
+    assert_eq!(stats.headers_too_large, 2);
+    assert_eq!(stats.total_requests, 1);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_unmatched_request_goes_to_default_upstream() {
+    let api = StubBackend::start("api").await;
+    let fallback = StubBackend::start("fallback").await;
+    let root = tempfile::tempdir().unwrap();
+    std::fs::write(root.path().join("app.js"), "js").unwrap();
+
+    let mut config = test_config();
+    config
+        .upstreams
+        .insert("api".to_string(), upstream(&[api.addr]));
+    config
+        .upstreams
+        .insert("fallback".to_string(), upstream(&[fallback.addr]));
+    config.routes.push(route("/api/*", "api"));
+    config
+        .static_files
+        .push(janus_common::StaticFileConfig::new(
+            "/static/",
+            root.path().to_string_lossy().into_owned(),
+        ));
+    config.server.default_upstream = Some("fallback".to_string());
+    let server = TestServer::start(config).await;
+
+    // Static mounts and routes still take precedence
+    assert_eq!(server.get("/static/app.js").await.body, "js");
+    assert_eq!(server.get("/api/users").await.echo().backend, "api");
+
+    let echo = server.get("/static/missing.js").await.echo();
+    assert_eq!(echo.backend, "fallback");
+    assert_eq!(echo.uri, "/static/missing.js");
+    assert_eq!(server.get("/elsewhere").await.echo().backend, "fallback");
+
+    let stats = server.janus.stats().await;
+    assert_eq!(stats.route_matches, 1);
+    assert_eq!(stats.default_matches, 2);
+    assert_eq!(stats.route_stats["/api/*"].requests, 1);
+    assert_eq!(stats.route_stats["static:/static/"].requests, 1);
+    assert_eq!(stats.route_stats["default"].requests, 2);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_route_stats_count_errors() {
+    let backend = StubBackend::start("live").await;
+    let mut config = test_config();
+    config
+        .upstreams
+        .insert("live".to_string(), upstream(&[backend.addr]));
+    let dead = format!("127.0.0.1:{}", free_port()).parse().unwrap();
+    config
+        .upstreams
+        .insert("dead".to_string(), upstream(&[dead]));
+    config.routes.push(route("/live/*", "live"));
+    config.routes.push(route("/dead/*", "dead"));
+    let server = TestServer::start(config).await;
+
+    assert_eq!(server.get("/live/a").await.status, StatusCode::OK);
+    assert_eq!(server.get("/dead/a").await.status, StatusCode::BAD_GATEWAY);
+    assert_eq!(server.get("/dead/b").await.status, StatusCode::BAD_GATEWAY);
+    server.get("/unrouted").await;
+
+    let stats = server.janus.stats().await;
+    assert_eq!(
+        stats.route_stats["/live/*"],
+        RouteStats {
+            requests: 1,
+            errors: 0
+        }
+    );
+    assert_eq!(
+        stats.route_stats["/dead/*"],
+        RouteStats {
+            requests: 2,
+            errors: 2
+        }
+    );
+    assert_eq!(stats.route_stats.len(), 2);
+
+    server.stop().await;
+}
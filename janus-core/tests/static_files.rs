@@ -0,0 +1,443 @@
+//! End-to-end static file serving tests
+
+mod common;
+
+use common::{test_config, upstream, StubBackend, TestServer};
+use hyper::{Method, StatusCode};
+use janus_common::config::{MatchOrder, RouteConfig, StaticFileConfig};
+
+fn static_mount(path: &str, root: &std::path::Path, listing: bool) -> StaticFileConfig {
+    StaticFileConfig::new(path.to_string(), root.to_string_lossy().into_owned())
+        .directory_listing(listing)
+}
+
+#[tokio::test]
+async fn test_serves_files_with_content_type() {
+    let root = tempfile::tempdir().unwrap();
+    std::fs::write(root.path().join("app.js"), "console.log(1);").unwrap();
+    std::fs::write(root.path().join("style.css"), "body {}").unwrap();
+
+    let mut config = test_config();
+    config
+        .static_files
+        .push(static_mount("/static/", root.path(), false));
+    let server = TestServer::start(config).await;
+
+    let response = server.get("/static/app.js").await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.body, "console.log(1);");
+    assert_eq!(
+        response.header("content-type"),
+        Some("application/javascript")
+    );
+
+    let response = server.get("/static/style.css").await;
+    assert_eq!(response.header("content-type"), Some("text/css"));
+
+    let response = server.get("/static/missing.txt").await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_static_mounts_serve_reads_only() {
+    let root = tempfile::tempdir().unwrap();
+    std::fs::write(root.path().join("logo.png"), "not really a png").unwrap();
+
+    let mut config = test_config();
+    config
+        .static_files
+        .push(static_mount("/static/", root.path(), true));
+    let server = TestServer::start(config).await;
+
+    let response = server.get("/static/logo.png").await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.header("content-length"), Some("16"));
+
+    let response = server.request(Method::HEAD, "/static/logo.png", &[]).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.header("content-length"), Some("16"));
+    assert_eq!(response.header("content-type"), Some("image/png"));
+    assert_eq!(response.body, "");
+
+    let response = server.request(Method::HEAD, "/static/", &[]).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.body, "");
+
+    for method in [Method::POST, Method::PUT, Method::DELETE] {
+        let response = server.request(method, "/static/logo.png", &[]).await;
+        assert_eq!(response.status, StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(response.header("allow"), Some("GET, HEAD"));
+    }
+
+    let response = server
+        .request(Method::OPTIONS, "/static/logo.png", &[])
+        .await;
+    assert_eq!(response.status, StatusCode::NO_CONTENT);
+    assert_eq!(response.header("allow"), Some("GET, HEAD, OPTIONS"));
+
+    // Paths the mount does not have are not its to reject
+    let response = server.request(Method::POST, "/static/missing", &[]).await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_configured_headers_override_detected_ones() {
+    let root = tempfile::tempdir().unwrap();
+    std::fs::write(root.path().join("openapi.yaml"), "openapi: 3.1.0").unwrap();
+
+    let mut config = test_config();
+    config.static_files.push(
+        static_mount("/docs/", root.path(), true)
+            .header("Cache-Control", "no-store")
+            .header("Content-Type", "application/yaml"),
+    );
+    let server = TestServer::start(config).await;
+
+    let response = server.get("/docs/openapi.yaml").await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.header("cache-control"), Some("no-store"));
+    assert_eq!(response.header("content-type"), Some("application/yaml"));
+
+    let response = server.get("/docs/").await;
+    assert_eq!(response.header("cache-control"), Some("no-store"));
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_index_resolution() {
+    let root = tempfile::tempdir().unwrap();
+    std::fs::write(root.path().join("index.html"), "<h1>home</h1>").unwrap();
+
+    let mut config = test_config();
+    config
+        .static_files
+        .push(static_mount("/", root.path(), false));
+    let server = TestServer::start(config).await;
+
+    let response = server.get("/").await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.body, "<h1>home</h1>");
+    assert_eq!(response.header("content-type"), Some("text/html"));
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_nested_index_candidates() {
+    let root = tempfile::tempdir().unwrap();
+    let guide = root.path().join("docs").join("guide");
+    std::fs::create_dir_all(&guide).unwrap();
+    std::fs::write(guide.join("index.htm"), "<h1>guide</h1>").unwrap();
+    std::fs::write(root.path().join("docs").join("README.html"), "readme").unwrap();
+
+    let mut mount = static_mount("/", root.path(), false);
+    mount.index = vec![
+        "index.html".to_string(),
+        "index.htm".to_string(),
+        "README.html".to_string(),
+    ];
+    let mut config = test_config();
+    config.static_files.push(mount);
+    let server = TestServer::start(config).await;
+
+    let response = server.get("/docs/guide/").await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.body, "<h1>guide</h1>");
+    assert_eq!(server.get("/docs/").await.body, "readme");
+
+    let response = server.get("/docs/guide?lang=en").await;
+    assert_eq!(response.status, StatusCode::MOVED_PERMANENTLY);
+    assert_eq!(response.header("location"), Some("/docs/guide/?lang=en"));
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_directory_listing() {
+    let root = tempfile::tempdir().unwrap();
+    std::fs::create_dir(root.path().join("docs")).unwrap();
+    std::fs::write(root.path().join("docs").join("a.txt"), "a").unwrap();
+    std::fs::create_dir(root.path().join("docs").join("nested")).unwrap();
+
+    let mut config = test_config();
+    config
+        .static_files
+        .push(static_mount("/files/", root.path(), true));
+    let server = TestServer::start(config).await;
+
+    let response = server.get("/files/docs").await;
+    assert_eq!(response.status, StatusCode::MOVED_PERMANENTLY);
+    assert_eq!(response.header("location"), Some("/files/docs/"));
+
+    let response = server.get("/files/docs/").await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert!(response.body.contains("Index of /files/docs/"));
+    assert!(response.body.contains(r#"href="a.txt""#));
+    assert!(response.body.contains(r#"href="nested/""#));
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_directory_listing_escapes_names() {
+    let root = tempfile::tempdir().unwrap();
+    let dir = root.path().join("<i>dir");
+    std::fs::create_dir(&dir).unwrap();
+    std::fs::write(dir.join("<img src=x onerror=alert(1)>.txt"), "x").unwrap();
+    std::fs::write(dir.join("two words.txt"), "spaced").unwrap();
+    let template = root.path().join("listing.html");
+    std::fs::write(&template, "<h1>{path}</h1><ul>{entries}</ul>").unwrap();
+
+    let mut branded = static_mount("/branded/", root.path(), true);
+    branded.directory_listing_template = Some(template.to_string_lossy().into_owned());
+    let mut config = test_config();
+    config
+        .static_files
+        .push(static_mount("/files/", root.path(), true));
+    config.static_files.push(branded);
+    let server = TestServer::start(config).await;
+
+    for mount in ["/files/", "/branded/"] {
+        let response = server.get(&format!("{}%3Ci%3Edir/", mount)).await;
+        assert_eq!(response.status, StatusCode::OK);
+        assert!(!response.body.contains("<img"), "{}", response.body);
+        assert!(!response.body.contains("<i>"), "{}", response.body);
+        assert!(response
+            .body
+            .contains(r#"href="%3Cimg%20src%3Dx%20onerror%3Dalert%281%29%3E.txt""#));
+        assert!(response
+            .body
+            .contains("&lt;img src=x onerror=alert(1)&gt;.txt</a>"));
+    }
+
+    // The links lead to the files they name
+    let response = server.get("/files/%3Ci%3Edir/two%20words.txt").await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.body, "spaced");
+
+    // Nothing outside the root, however it is encoded
+    std::fs::write(root.path().join("secret.txt"), "secret").unwrap();
+    let response = server.get("/files/docs/%2e%2e/%2e%2e/secret.txt").await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_directory_listing_is_truncated() {
+    let root = tempfile::tempdir().unwrap();
+    let dir = root.path().join("many");
+    std::fs::create_dir(&dir).unwrap();
+    for i in 0..10 {
+        std::fs::write(dir.join(format!("{}.txt", i)), "x").unwrap();
+    }
+
+    let mut mount = static_mount("/files/", root.path(), true);
+    mount.listing_limit = 4;
+    let mut config = test_config();
+    config.static_files.push(mount);
+    let server = TestServer::start(config).await;
+
+    let response = server.get("/files/many/").await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.body.matches(r#"class="file""#).count(), 4);
+    assert!(response.body.contains("truncated to the first 4 entries"));
+    assert!(response.body.ends_with("</html>"));
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_directory_listing_template() {
+    let root = tempfile::tempdir().unwrap();
+    std::fs::create_dir(root.path().join("docs")).unwrap();
+    std::fs::write(root.path().join("docs").join("a.txt"), "a").unwrap();
+    let template = root.path().join("listing.html");
+    std::fs::write(
+        &template,
+        "<h1>Acme files: {path}</h1><ul>{entries}</ul><footer>{path}</footer>",
+    )
+    .unwrap();
+
+    let mut branded = static_mount("/files/", root.path(), true);
+    branded.directory_listing_template = Some(template.to_string_lossy().into_owned());
+    // A template that cannot be read falls back to the built-in page
+    let mut missing = static_mount("/plain/", root.path(), true);
+    missing.directory_listing_template = Some("/nonexistent/listing.html".to_string());
+    let mut config = test_config();
+    config.static_files.push(branded);
+    config.static_files.push(missing);
+    let server = TestServer::start(config).await;
+
+    let response = server.get("/files/docs/").await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert!(response
+        .body
+        .starts_with("<h1>Acme files: /files/docs/</h1><ul><li><a href=\"..\">..</a></li>"));
+    assert!(response.body.contains(r#"href="a.txt""#));
+    assert!(response
+        .body
+        .ends_with("</ul><footer>/files/docs/</footer>"));
+
+    let response = server.get("/plain/docs/").await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert!(response.body.contains("Index of /plain/docs/"));
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_directory_without_listing_is_404() {
+    let root = tempfile::tempdir().unwrap();
+    std::fs::create_dir(root.path().join("private")).unwrap();
+
+    let mut config = test_config();
+    config
+        .static_files
+        .push(static_mount("/files/", root.path(), false));
+    let server = TestServer::start(config).await;
+
+    let response = server.get("/files/private").await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+
+    server.stop().await;
+}
+
+/// Which backend answered, or `None` for a response Janus made itself
+fn answered_by(response: &common::TestResponse) -> Option<&str> {
+    response.header("x-backend")
+}
+
+#[tokio::test]
+async fn test_match_order_and_fallthrough() {
+    let root = tempfile::tempdir().unwrap();
+    std::fs::write(root.path().join("app.js"), "console.log(1);").unwrap();
+    std::fs::create_dir(root.path().join("api")).unwrap();
+    std::fs::write(root.path().join("api").join("status"), "static status").unwrap();
+    let api = StubBackend::start("api").await;
+    let ssr = StubBackend::start("ssr").await;
+
+    // Each case: match order, whether the mount at `/` falls through, then
+    // who answers a file the mount has, a path nothing but the default
+    // upstream has, a path both the mount and the `/api/*` route have, and
+    // one only the route has
+    let cases = [
+        (
+            MatchOrder::StaticFirst,
+            true,
+            None,
+            Some("ssr"),
+            None,
+            Some("api"),
+        ),
+        (MatchOrder::StaticFirst, false, None, None, None, None),
+        (
+            MatchOrder::RoutesFirst,
+            true,
+            None,
+            Some("ssr"),
+            Some("api"),
+            Some("api"),
+        ),
+        (
+            MatchOrder::RoutesFirst,
+            false,
+            None,
+            None,
+            Some("api"),
+            Some("api"),
+        ),
+    ];
+    for (order, fallthrough, file, missing, shared, routed) in cases {
+        let mut config = test_config();
+        config.server.match_order = order;
+        config.server.default_upstream = Some("ssr".to_string());
+        config
+            .upstreams
+            .insert("api".to_string(), upstream(&[api.addr]));
+        config
+            .upstreams
+            .insert("ssr".to_string(), upstream(&[ssr.addr]));
+        config
+            .routes
+            .push(RouteConfig::new("/api/*", "api").timeout(5));
+        config.static_files.push(StaticFileConfig {
+            fallthrough,
+            ..static_mount("/", root.path(), false)
+        });
+        let server = TestServer::start(config).await;
+        let case = format!("{:?}, fallthrough {}", order, fallthrough);
+
+        let response = server.get("/app.js").await;
+        assert_eq!(answered_by(&response), file, "{}", case);
+        assert_eq!(response.body, "console.log(1);", "{}", case);
+
+        let response = server.get("/dashboard").await;
+        assert_eq!(answered_by(&response), missing, "{}", case);
+        if missing.is_none() {
+            assert_eq!(response.status, StatusCode::NOT_FOUND, "{}", case);
+        }
+
+        let response = server.get("/api/status").await;
+        assert_eq!(answered_by(&response), shared, "{}", case);
+        if shared.is_none() {
+            assert_eq!(response.body, "static status", "{}", case);
+        }
+
+        let response = server.get("/api/users").await;
+        assert_eq!(answered_by(&response), routed, "{}", case);
+        if routed.is_none() {
+            assert_eq!(response.status, StatusCode::NOT_FOUND, "{}", case);
+        }
+
+        server.stop().await;
+    }
+}
+
+#[tokio::test]
+async fn test_compresses_for_clients_accepting_gzip() {
+    let root = tempfile::tempdir().unwrap();
+    std::fs::write(root.path().join("big.txt"), "janus ".repeat(1000)).unwrap();
+    std::fs::write(root.path().join("small.txt"), "janus").unwrap();
+
+    let mut config = test_config();
+    config.server.compression.enabled = true;
+    config
+        .static_files
+        .push(static_mount("/static/", root.path(), false));
+    let server = TestServer::start(config).await;
+
+    let traffic = server.janus.state().traffic.clone();
+    let bytes_sent = || {
+        let route = traffic.route_detail("static:/static/").unwrap().bytes_sent;
+        assert_eq!(traffic.top_clients(1)[0].bytes, route);
+        route
+    };
+
+    let gzip = [("Accept-Encoding", "gzip, br")];
+    let response = server.request(Method::GET, "/static/big.txt", &gzip).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.header("content-encoding"), Some("gzip"));
+    assert_eq!(response.header("vary"), Some("accept-encoding"));
+    // What was sent, compressed
+    let compressed = bytes_sent();
+    assert!(compressed > 0 && compressed < 6000, "{}", compressed);
+
+    let response = server.get("/static/big.txt").await;
+    assert_eq!(response.header("content-encoding"), None);
+    assert_eq!(response.body, "janus ".repeat(1000));
+
+    let response = server
+        .request(Method::GET, "/static/small.txt", &gzip)
+        .await;
+    assert_eq!(response.header("content-encoding"), None);
+    assert_eq!(response.body, "janus");
+    assert_eq!(bytes_sent(), compressed + 6000 + 5);
+
+    server.stop().await;
+}
@@ -0,0 +1,486 @@
+//! Load testing: `--bench` measures the overhead of proxying through
+//! Janus, and `bench` loads a running server
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use futures::{SinkExt, StreamExt};
+use http_body_util::{BodyExt, Empty};
+use hyper::{Request, StatusCode};
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use janus_common::config::RouteConfig;
+use janus_common::{
+    ClientMessage, JanusConfig, ServerMessage, ServerStats, StatusCodeStats, UpstreamConfig,
+};
+use janus_core::Janus;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio_tungstenite::tungstenite::Message;
+
+/// Concurrent client connections generating load
+const CONNECTIONS: usize = 64;
+
+/// How often progress is reported
+const REPORT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Length of the whole run
+const DURATION: Duration = Duration::from_secs(60);
+
+/// Latencies and response counts gathered since the last report
+#[derive(Default)]
+struct Sample {
+    /// Latencies of requests that got a response
+    latencies_us: Vec<u64>,
+    status_codes: StatusCodeStats,
+
+    /// Requests that failed without a complete response
+    transport_errors: u64,
+}
+
+impl Sample {
+    fn requests(&self) -> u64 {
+        self.latencies_us.len() as u64 + self.transport_errors
+    }
+
+    fn record(&mut self, status: StatusCode, latency: Duration) {
+        self.latencies_us.push(latency.as_micros() as u64);
+        let counter = match status.as_u16() {
+            200..=299 => &mut self.status_codes.success,
+            300..=399 => &mut self.status_codes.redirect,
+            400..=499 => &mut self.status_codes.client_error,
+            _ => &mut self.status_codes.server_error,
+        };
+        *counter += 1;
+    }
+
+    fn absorb(&mut self, mut other: Sample) {
+        self.latencies_us.append(&mut other.latencies_us);
+        let (codes, more) = (&mut self.status_codes, other.status_codes);
+        codes.success += more.success;
+        codes.redirect += more.redirect;
+        codes.client_error += more.client_error;
+        codes.server_error += more.server_error;
+        self.transport_errors += other.transport_errors;
+    }
+
+    /// Latency below which the given fraction of answered requests fell
+    fn percentile_ms(&mut self, fraction: f64) -> f64 {
+        if self.latencies_us.is_empty() {
+            return 0.0;
+        }
+        self.latencies_us.sort_unstable();
+        let index = ((self.latencies_us.len() as f64 * fraction).ceil() as usize)
+            .clamp(1, self.latencies_us.len());
+        self.latencies_us[index - 1] as f64 / 1000.0
+    }
+
+    /// Fraction of requests that failed or got a 4xx or 5xx response
+    fn error_rate(&self) -> f64 {
+        let errors =
+            self.status_codes.client_error + self.status_codes.server_error + self.transport_errors;
+        errors as f64 / self.requests().max(1) as f64
+    }
+}
+
+/// Proxy everything to `upstream_url` through an in-process server, load it
+/// from in-process clients and print throughput, P99 latency and error rate
+pub async fn run(upstream_url: &str) -> Result<()> {
+    let address = upstream_address(upstream_url)
+        .with_context(|| format!("Invalid upstream URL '{}'", upstream_url))?;
+
+    let janus = Janus::new(bench_config(address));
+    let server = janus.start().await?;
+    let addr = janus.local_addr().context("server has no address")?;
+    println!(
+        "Benchmarking http://{} -> {} with {} connections for {}s",
+        addr,
+        upstream_url,
+        CONNECTIONS,
+        DURATION.as_secs()
+    );
+
+    let target = format!("http://{}/", addr).parse()?;
+    let (mut total, elapsed) = generate_load(target, CONNECTIONS, DURATION, true).await;
+    print_line("total", &mut total, elapsed);
+
+    janus.shutdown().await;
+    server.await??;
+    Ok(())
+}
+
+/// Settings of `janus bench`
+pub struct LoadTest {
+    target: hyper::Uri,
+    connections: usize,
+    duration: Duration,
+
+    /// Management API to read server-side statistics from
+    management: Option<String>,
+    token: Option<String>,
+    json: bool,
+}
+
+impl LoadTest {
+    /// Parse the arguments following `bench`
+    pub fn from_args(mut args: impl Iterator<Item = String>) -> Result<Self> {
+        let mut target = None;
+        let mut test = LoadTest {
+            target: hyper::Uri::default(),
+            connections: CONNECTIONS,
+            duration: Duration::from_secs(30),
+            management: None,
+            token: None,
+            json: false,
+        };
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .with_context(|| format!("{} requires a value", arg))
+            };
+            match arg.as_str() {
+                "--target" => target = Some(value()?),
+                "--connections" => test.connections = value()?.parse()?,
+                "--duration" => test.duration = parse_duration(&value()?)?,
+                "--management" => test.management = Some(value()?),
+                "--token" => test.token = Some(value()?),
+                "--json" => test.json = true,
+                other => anyhow::bail!("Unknown bench option '{}'", other),
+            }
+        }
+
+        let target = target.context("bench requires --target <url>")?;
+        test.target = target
+            .parse()
+            .with_context(|| format!("Invalid target URL '{}'", target))?;
+        anyhow::ensure!(
+            test.target.scheme_str() == Some("http"),
+            "Only http:// targets are supported"
+        );
+        anyhow::ensure!(test.connections > 0, "--connections must be at least 1");
+        Ok(test)
+    }
+}
+
+/// Results of `janus bench`, printed as JSON with `--json`
+#[derive(Serialize)]
+struct LoadReport {
+    target: String,
+    connections: usize,
+    duration_secs: f64,
+    requests: u64,
+    requests_per_second: f64,
+    latency_ms: Latency,
+    status_codes: StatusCodeStats,
+    transport_errors: u64,
+    error_rate: f64,
+
+    /// What the server counted over the same period
+    server: Option<ServerDelta>,
+}
+
+#[derive(Serialize)]
+struct Latency {
+    p50: f64,
+    p90: f64,
+    p99: f64,
+    max: f64,
+}
+
+/// Change in the server's statistics during a run
+#[derive(Serialize)]
+struct ServerDelta {
+    requests: u64,
+    status_codes: StatusCodeStats,
+    proxy_errors: HashMap<String, u64>,
+}
+
+impl ServerDelta {
+    fn between(before: &ServerStats, after: &ServerStats) -> Self {
+        let (old, new) = (&before.status_codes, &after.status_codes);
+        let proxy_errors = after
+            .proxy_errors
+            .iter()
+            .map(|(kind, &count)| {
+                let earlier = before.proxy_errors.get(kind).copied().unwrap_or(0);
+                (kind.clone(), count.saturating_sub(earlier))
+            })
+            .filter(|&(_, count)| count > 0)
+            .collect();
+        Self {
+            requests: after.total_requests.saturating_sub(before.total_requests),
+            status_codes: StatusCodeStats {
+                success: new.success.saturating_sub(old.success),
+                redirect: new.redirect.saturating_sub(old.redirect),
+                client_error: new.client_error.saturating_sub(old.client_error),
+                server_error: new.server_error.saturating_sub(old.server_error),
+            },
+            proxy_errors,
+        }
+    }
+}
+
+/// Load a running server and report client-side numbers, alongside the
+/// server's own when its management API is given
+pub async fn load_test(test: LoadTest) -> Result<()> {
+    let management = test.management.as_deref().map(management_url);
+    let before = match management {
+        Some(ref url) => Some(fetch_stats(url, test.token.as_deref()).await?),
+        None => None,
+    };
+
+    if !test.json {
+        println!(
+            "Loading {} with {} connections for {}s",
+            test.target,
+            test.connections,
+            test.duration.as_secs_f64()
+        );
+    }
+    let (mut sample, elapsed) = generate_load(
+        test.target.clone(),
+        test.connections,
+        test.duration,
+        !test.json,
+    )
+    .await;
+
+    let server = match (management, before) {
+        (Some(url), Some(before)) => {
+            let after = fetch_stats(&url, test.token.as_deref()).await?;
+            Some(ServerDelta::between(&before, &after))
+        }
+        _ => None,
+    };
+
+    let report = LoadReport {
+        target: test.target.to_string(),
+        connections: test.connections,
+        duration_secs: elapsed.as_secs_f64(),
+        requests: sample.requests(),
+        requests_per_second: sample.requests() as f64 / elapsed.as_secs_f64(),
+        latency_ms: Latency {
+            p50: sample.percentile_ms(0.50),
+            p90: sample.percentile_ms(0.90),
+            p99: sample.percentile_ms(0.99),
+            max: sample.percentile_ms(1.0),
+        },
+        status_codes: sample.status_codes.clone(),
+        transport_errors: sample.transport_errors,
+        error_rate: sample.error_rate(),
+        server,
+    };
+
+    if test.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print_report(&report);
+    }
+    Ok(())
+}
+
+/// Send requests to `target` from `connections` concurrent clients until
+/// the duration is up, returning everything recorded and the time taken
+async fn generate_load(
+    target: hyper::Uri,
+    connections: usize,
+    duration: Duration,
+    progress: bool,
+) -> (Sample, Duration) {
+    let sample = Arc::new(Mutex::new(Sample::default()));
+    let start = Instant::now();
+    let deadline = start + duration;
+    let client = Client::builder(TokioExecutor::new()).build_http::<Empty<Bytes>>();
+
+    let workers: Vec<_> = (0..connections)
+        .map(|_| {
+            let client = client.clone();
+            let target = target.clone();
+            let sample = sample.clone();
+            tokio::spawn(async move {
+                while Instant::now() < deadline {
+                    let req = Request::get(target.clone()).body(Empty::new()).unwrap();
+                    let sent = Instant::now();
+                    let status = match client.request(req).await {
+                        Ok(response) => {
+                            let status = response.status();
+                            response.into_body().collect().await.ok().map(|_| status)
+                        }
+                        Err(_) => None,
+                    };
+                    let mut sample = sample.lock().unwrap();
+                    match status {
+                        Some(status) => sample.record(status, sent.elapsed()),
+                        None => sample.transport_errors += 1,
+                    }
+                }
+            })
+        })
+        .collect();
+
+    let mut total = Sample::default();
+    let mut ticker = tokio::time::interval_at(
+        tokio::time::Instant::now() + REPORT_INTERVAL,
+        REPORT_INTERVAL,
+    );
+    while Instant::now() + REPORT_INTERVAL <= deadline {
+        ticker.tick().await;
+        let mut interval = std::mem::take(&mut *sample.lock().unwrap());
+        if progress {
+            print_line(
+                &format!("{:>3}s", start.elapsed().as_secs()),
+                &mut interval,
+                REPORT_INTERVAL,
+            );
+        }
+        total.absorb(interval);
+    }
+
+    for worker in workers {
+        let _ = worker.await;
+    }
+    total.absorb(std::mem::take(&mut *sample.lock().unwrap()));
+    (total, start.elapsed())
+}
+
+fn print_line(label: &str, sample: &mut Sample, elapsed: Duration) {
+    println!(
+        "[{}] {:>10.1} req/s  p50 {:>8.2} ms  p99 {:>8.2} ms  errors {:>6.2}%",
+        label,
+        sample.requests() as f64 / elapsed.as_secs_f64(),
+        sample.percentile_ms(0.50),
+        sample.percentile_ms(0.99),
+        sample.error_rate() * 100.0
+    );
+}
+
+fn print_report(report: &LoadReport) {
+    let codes = &report.status_codes;
+    println!(
+        "{} requests in {:.1}s, {:.1} req/s",
+        report.requests, report.duration_secs, report.requests_per_second
+    );
+    let latency = &report.latency_ms;
+    println!(
+        "Latency: p50 {:.2} ms  p90 {:.2} ms  p99 {:.2} ms  max {:.2} ms",
+        latency.p50, latency.p90, latency.p99, latency.max
+    );
+    println!(
+        "Status:  2xx {}  3xx {}  4xx {}  5xx {}  failed {}  ({:.2}% errors)",
+        codes.success,
+        codes.redirect,
+        codes.client_error,
+        codes.server_error,
+        report.transport_errors,
+        report.error_rate * 100.0
+    );
+
+    if let Some(ref server) = report.server {
+        let codes = &server.status_codes;
+        println!(
+            "Server:  {} requests  2xx {}  3xx {}  4xx {}  5xx {}",
+            server.requests, codes.success, codes.redirect, codes.client_error, codes.server_error
+        );
+        let mut errors: Vec<_> = server.proxy_errors.iter().collect();
+        errors.sort();
+        for (kind, count) in errors {
+            println!("         proxy errors ({}): {}", kind, count);
+        }
+    }
+}
+
+/// WebSocket URL of a management API given as `host:port` or a URL
+fn management_url(address: &str) -> String {
+    if address.contains("://") {
+        address.to_string()
+    } else {
+        format!("ws://{}", address)
+    }
+}
+
+/// Read the server's statistics over its management API
+async fn fetch_stats(url: &str, token: Option<&str>) -> Result<ServerStats> {
+    let (mut ws, _) = tokio_tungstenite::connect_async(url)
+        .await
+        .with_context(|| format!("Failed to connect to management API at {}", url))?;
+
+    let mut messages = Vec::new();
+    if let Some(token) = token {
+        messages.push(ClientMessage::Authenticate {
+            token: token.to_string(),
+        });
+    }
+    messages.push(ClientMessage::GetStats);
+    for msg in messages {
+        ws.send(Message::Text(serde_json::to_string(&msg)?)).await?;
+    }
+
+    // Responses arrive in order, possibly among broadcast events
+    while let Some(msg) = ws.next().await {
+        let Message::Text(text) = msg? else {
+            continue;
+        };
+        match serde_json::from_str(&text)? {
+            ServerMessage::Stats(stats) => {
+                let _ = ws.close(None).await;
+                return Ok(stats);
+            }
+            ServerMessage::Error(e) => anyhow::bail!("Management API error: {}", e),
+            _ => {}
+        }
+    }
+    anyhow::bail!("Management API closed the connection")
+}
+
+/// Parse a duration such as `30s`, `2m`, `500ms` or a bare number of
+/// seconds
+fn parse_duration(text: &str) -> Result<Duration> {
+    let split = text
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let number: f64 = number
+        .parse()
+        .with_context(|| format!("Invalid duration '{}'", text))?;
+    let secs = match unit {
+        "" | "s" => number,
+        "ms" => number / 1000.0,
+        "m" => number * 60.0,
+        "h" => number * 3600.0,
+        _ => anyhow::bail!("Invalid duration unit in '{}'", text),
+    };
+    Ok(Duration::from_secs_f64(secs))
+}
+
+/// The `host:port` of an `http://host:port[/...]` URL
+fn upstream_address(url: &str) -> Option<String> {
+    let rest = url.strip_prefix("http://").unwrap_or(url);
+    let address = rest.split('/').next()?;
+    if address.is_empty() {
+        return None;
+    }
+    // Default the port the same way a client would
+    Some(if address.contains(':') {
+        address.to_string()
+    } else {
+        format!("{}:80", address)
+    })
+}
+
+/// A loopback server with one route sending everything to the upstream
+fn bench_config(address: String) -> JanusConfig {
+    let mut config = JanusConfig::default();
+    config.server.bind_address = "127.0.0.1".to_string();
+    config.server.port = 0;
+    config.server.access_log = false;
+    config.management.enabled = false;
+
+    config
+        .upstreams
+        .insert("bench".to_string(), UpstreamConfig::single(address));
+    config
+        .routes
+        .push(RouteConfig::new("/*", "bench").timeout(30));
+    config
+}
@@ -1,47 +1,107 @@
 //! Janus Server - Web server and reverse proxy with live reloading
 
-mod management;
-mod proxy;
-mod reload;
-mod server;
-mod stats;
+mod bench;
+mod service;
 
 use anyhow::Result;
 use janus_common::JanusConfig;
-use std::path::PathBuf;
+use janus_core::{preflight, ErrorLog, Janus, PreflightOptions, PreflightReport};
+use service::{PidFile, Signal, Signals, Supervisor};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use tokio::sync::RwLock;
-use tracing::{error, info};
-
-/// Shared application state
-pub struct AppState {
-    pub config: Arc<RwLock<JanusConfig>>,
-    pub stats: Arc<RwLock<stats::Stats>>,
-    pub start_time: std::time::Instant,
-    pub config_path: PathBuf,
-}
+use tracing::{error, info, warn};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    // Initialize logging
-    tracing_subscriber::fmt()
-        .with_env_filter(
+fn main() -> Result<()> {
+    // Initialize logging, keeping recent warnings and errors for
+    // management clients
+    let error_log = ErrorLog::new();
+    tracing_subscriber::registry()
+        .with(
             tracing_subscriber::EnvFilter::from_default_env().add_directive("janus=info".parse()?),
         )
+        .with(tracing_subscriber::fmt::layer())
+        .with(error_log.layer())
         .init();
 
-    info!("Starting Janus Server v{}", env!("CARGO_PKG_VERSION"));
+    // Parse command line arguments
+    let mut validate_only = false;
+    let mut bench_upstream = None;
+    let mut profile = None;
+    let mut pid_file = None;
+    let mut background = false;
+    let mut config_path = PathBuf::from("janus.toml");
+    let mut args = std::env::args().skip(1).peekable();
+    if args.peek().map(String::as_str) == Some("bench") {
+        args.next();
+        let test = bench::LoadTest::from_args(args)?;
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(worker_threads(0))
+            .thread_name("janus-worker")
+            .enable_all()
+            .build()?;
+        return runtime.block_on(bench::load_test(test));
+    }
+    if args.peek().map(String::as_str) == Some("docs") {
+        args.next();
+        return generate_docs(args);
+    }
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--validate" => validate_only = true,
+            "--bench" => match args.next() {
+                Some(url) => bench_upstream = Some(url),
+                None => anyhow::bail!("--bench requires an upstream URL"),
+            },
+            "--profile" => match args.next() {
+                Some(name) => profile = Some(name),
+                None => anyhow::bail!("--profile requires a profile name"),
+            },
+            "--pid-file" => match args.next() {
+                Some(path) => pid_file = Some(PathBuf::from(path)),
+                None => anyhow::bail!("--pid-file requires a path"),
+            },
+            "--background" => background = true,
+            _ => config_path = PathBuf::from(arg),
+        }
+    }
 
-    // Determine config path
-    let config_path = std::env::args()
-        .nth(1)
-        .map(PathBuf::from)
-        .unwrap_or_else(|| PathBuf::from("janus.toml"));
+    if let Some(url) = bench_upstream {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(worker_threads(0))
+            .thread_name("janus-worker")
+            .enable_all()
+            .build()?;
+        return runtime.block_on(bench::run(&url));
+    }
 
-    // Load or create default configuration
-    let config = if config_path.exists() {
+    let options = PreflightOptions {
+        resolve_upstreams: true,
+        check_bind: true,
+        // Backends are often started alongside the proxy
+        probe_health_checks: false,
+    };
+
+    if validate_only {
+        let config = load_config(&config_path, profile.as_deref())?;
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let report = runtime.block_on(preflight(&config, options));
+        print_report(&report);
+        if !report.is_ok() {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    info!("Starting Janus Server v{}", env!("CARGO_PKG_VERSION"));
+
+    // Load or create default configuration; a profile needs an existing file
+    let config = if config_path.exists() || profile.is_some() {
         info!("Loading configuration from {}", config_path.display());
-        JanusConfig::load(&config_path)?
+        load_config(&config_path, profile.as_deref())?
     } else {
         info!("No configuration file found, using defaults");
         let config = JanusConfig::default();
@@ -52,62 +112,194 @@ async fn main() -> Result<()> {
         config
     };
 
-    // Create shared state
-    let state = Arc::new(AppState {
-        config: Arc::new(RwLock::new(config.clone())),
-        stats: Arc::new(RwLock::new(stats::Stats::default())),
-        start_time: std::time::Instant::now(),
-        config_path: config_path.clone(),
-    });
-
-    // Start file watcher for live reloading
-    let reload_state = state.clone();
-    let reload_handle = tokio::spawn(async move {
-        if let Err(e) = reload::watch_config(reload_state).await {
-            error!("Config watcher error: {}", e);
+    if let Some(ref path) = pid_file {
+        PidFile::check(path)?;
+    }
+
+    // Forking only keeps the calling thread, so this comes before the
+    // runtime starts any
+    let mut supervisor = if background {
+        #[cfg(unix)]
+        {
+            service::daemonize()?
         }
-    });
-
-    // Start management WebSocket server
-    let mgmt_state = state.clone();
-    let mgmt_handle = if config.management.enabled {
-        let addr = format!("{}:{}", config.management.address, config.management.port);
-        info!("Starting management API on ws://{}", addr);
-        Some(tokio::spawn(async move {
-            if let Err(e) = management::run_management_server(mgmt_state).await {
-                error!("Management server error: {}", e);
-            }
-        }))
+        #[cfg(not(unix))]
+        anyhow::bail!("--background is only supported on Unix");
     } else {
-        None
+        Supervisor::default()
+    };
+
+    let server = Server {
+        config,
+        config_path,
+        profile,
+        pid_file,
+        options,
+        error_log,
     };
+    let result = server.run(&mut supervisor);
+    if let Err(ref e) = result {
+        supervisor.failed(e);
+    }
+    result
+}
+
+/// Everything needed to run the server once the command line is parsed
+struct Server {
+    config: JanusConfig,
+    config_path: PathBuf,
+    profile: Option<String>,
+    pid_file: Option<PathBuf>,
+    options: PreflightOptions,
+    error_log: Arc<ErrorLog>,
+}
+
+impl Server {
+    /// Start the runtime and serve until shut down
+    fn run(self, supervisor: &mut Supervisor) -> Result<()> {
+        // The worker count comes from the config, so the runtime is only
+        // built once the config has been loaded
+        let workers = worker_threads(self.config.server.workers);
+        info!("Starting runtime with {} worker thread(s)", workers);
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(workers)
+            .thread_name("janus-worker")
+            .enable_all()
+            .build()?;
+
+        runtime.block_on(self.serve(supervisor))
+    }
 
-    // Start HTTP server
-    let server_state = state.clone();
-    let server_handle = tokio::spawn(async move {
-        if let Err(e) = server::run_server(server_state).await {
-            error!("HTTP server error: {}", e);
+    /// Run the server until Ctrl+C, SIGTERM or a fatal server error,
+    /// reloading the config file on SIGHUP
+    async fn serve(self, supervisor: &mut Supervisor) -> Result<()> {
+        // Catch configuration mistakes before serving traffic
+        let report = preflight(&self.config, self.options).await;
+        for warning in &report.warnings {
+            warn!("Preflight: {}", warning);
+        }
+        for problem in &report.errors {
+            error!("Preflight: {}", problem);
+        }
+        if !report.is_ok() {
+            anyhow::bail!(
+                "Preflight checks failed with {} error(s)",
+                report.errors.len()
+            );
         }
-    });
 
-    // Wait for shutdown signal
-    tokio::select! {
-        _ = tokio::signal::ctrl_c() => {
-            info!("Received shutdown signal");
+        let janus = match self.profile {
+            Some(profile) => Janus::with_config_profile(self.config, self.config_path, profile),
+            None => Janus::with_config_file(self.config, self.config_path),
         }
-        result = server_handle => {
-            if let Err(e) = result {
-                error!("Server task failed: {}", e);
+        .with_error_log(self.error_log);
+        let mut signals = Signals::new()?;
+        let mut server_handle = janus.start().await?;
+        let _pid_file = self.pid_file.as_deref().map(PidFile::write).transpose()?;
+        supervisor.ready();
+
+        loop {
+            tokio::select! {
+                signal = signals.recv() => match signal {
+                    Signal::Shutdown => {
+                        info!("Received shutdown signal");
+                        break;
+                    }
+                    Signal::Reload => {
+                        info!("Received reload signal");
+                        supervisor.reloading();
+                        // Failures are logged, and the running config kept
+                        let _ = janus.reload().await;
+                        supervisor.reloaded();
+                    }
+                },
+                result = &mut server_handle => {
+                    match result {
+                        Ok(Err(e)) => error!("HTTP server error: {}", e),
+                        Err(e) => error!("Server task failed: {}", e),
+                        Ok(Ok(())) => {}
+                    }
+                    break;
+                }
             }
         }
+
+        supervisor.stopping();
+        janus.shutdown().await;
+
+        info!("Janus Server shutdown complete");
+        Ok(())
     }
+}
 
-    // Cleanup
-    reload_handle.abort();
-    if let Some(handle) = mgmt_handle {
-        handle.abort();
+/// Load the config file, applying the profile if one was chosen
+fn load_config(path: &Path, profile: Option<&str>) -> Result<JanusConfig> {
+    let config = match profile {
+        Some(profile) => {
+            info!("Using profile '{}'", profile);
+            JanusConfig::load_with_profile(path, profile)?
+        }
+        None => JanusConfig::load(path)?,
+    };
+    Ok(config)
+}
+
+/// `docs generate [--output PATH]`: write the configuration reference to a
+/// file, or to stdout
+fn generate_docs(mut args: impl Iterator<Item = String>) -> Result<()> {
+    anyhow::ensure!(
+        args.next().as_deref() == Some("generate"),
+        "usage: janus docs generate [--output PATH]"
+    );
+    let mut output = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--output" => match args.next() {
+                Some(path) => output = Some(PathBuf::from(path)),
+                None => anyhow::bail!("--output requires a path"),
+            },
+            other => anyhow::bail!("Unknown docs option '{}'", other),
+        }
     }
 
-    info!("Janus Server shutdown complete");
+    let reference = janus_common::config_reference();
+    match output {
+        Some(path) => {
+            if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+                std::fs::create_dir_all(dir)?;
+            }
+            std::fs::write(&path, reference)?;
+            println!("Wrote {}", path.display());
+        }
+        None => print!("{}", reference),
+    }
     Ok(())
 }
+
+/// Worker threads for the configured count, where 0 means one per CPU
+fn worker_threads(configured: usize) -> usize {
+    match configured {
+        0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+        n => n,
+    }
+}
+
+/// Print preflight findings for `--validate`, grouped by severity
+fn print_report(report: &PreflightReport) {
+    if report.errors.is_empty() && report.warnings.is_empty() {
+        println!("Configuration OK");
+        return;
+    }
+    if !report.errors.is_empty() {
+        println!("Errors:");
+        for problem in &report.errors {
+            println!("  - {}", problem);
+        }
+    }
+    if !report.warnings.is_empty() {
+        println!("Warnings:");
+        for warning in &report.warnings {
+            println!("  - {}", warning);
+        }
+    }
+}
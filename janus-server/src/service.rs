@@ -0,0 +1,256 @@
+//! Running under a service manager: pid file, systemd notifications,
+//! background mode and signals
+
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+/// Reply on the startup pipe when the daemon is serving
+#[cfg(unix)]
+const STARTED: &[u8] = b"ok";
+
+/// Pid file holding the server's process ID, removed when dropped
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    /// Fail if the file names a running process; a stale file is left to be
+    /// overwritten
+    pub fn check(path: &Path) -> Result<()> {
+        let Ok(contents) = std::fs::read_to_string(path) else {
+            return Ok(());
+        };
+        match contents.trim().parse::<u32>() {
+            Ok(pid) if is_running(pid) => anyhow::bail!(
+                "Janus is already running with PID {} (pid file {})",
+                pid,
+                path.display()
+            ),
+            _ => {
+                warn!("Replacing stale pid file {}", path.display());
+                Ok(())
+            }
+        }
+    }
+
+    /// Write this process's ID to `path`
+    pub fn write(path: &Path) -> Result<Self> {
+        std::fs::write(path, format!("{}\n", std::process::id()))
+            .with_context(|| format!("Failed to write pid file {}", path.display()))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            warn!("Failed to remove pid file {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// Whether a process with this ID exists
+#[cfg(unix)]
+fn is_running(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // Signal 0 only checks the process exists; EPERM means it belongs to
+    // someone else
+    let found = unsafe { libc::kill(pid, 0) } == 0;
+    found || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(not(unix))]
+fn is_running(_pid: u32) -> bool {
+    false
+}
+
+/// Reports startup, reloads and shutdown to whatever started the server:
+/// systemd, when built with the `systemd` feature, and the foreground
+/// process of `--background`
+#[derive(Default)]
+pub struct Supervisor {
+    /// Startup pipe to the process waiting in the foreground
+    #[cfg(unix)]
+    starter: Option<std::fs::File>,
+}
+
+impl Supervisor {
+    /// Every listener is bound and serving
+    pub fn ready(&mut self) {
+        notify(State::Ready);
+        #[cfg(unix)]
+        if let Some(mut starter) = self.starter.take() {
+            use std::io::Write;
+            let _ = starter.write_all(STARTED);
+        }
+    }
+
+    /// Startup failed, so the foreground process should report why
+    pub fn failed(&mut self, error: &anyhow::Error) {
+        #[cfg(unix)]
+        if let Some(mut starter) = self.starter.take() {
+            use std::io::Write;
+            let _ = write!(starter, "{:#}", error);
+        }
+        #[cfg(not(unix))]
+        let _ = error;
+    }
+
+    /// The configuration is being reloaded
+    pub fn reloading(&self) {
+        notify(State::Reloading);
+    }
+
+    /// The reload finished, whether or not it succeeded
+    pub fn reloaded(&self) {
+        notify(State::Ready);
+    }
+
+    /// Shutdown has started
+    pub fn stopping(&self) {
+        notify(State::Stopping);
+    }
+}
+
+/// Service state reported to systemd
+#[derive(Debug, Clone, Copy)]
+enum State {
+    Ready,
+    Reloading,
+    Stopping,
+}
+
+#[cfg(feature = "systemd")]
+fn notify(state: State) {
+    use sd_notify::NotifyState;
+    let result = match state {
+        State::Ready => sd_notify::notify(false, &[NotifyState::Ready]),
+        // Type=notify-reload needs the time the reload started
+        State::Reloading => NotifyState::monotonic_usec_now()
+            .and_then(|now| sd_notify::notify(false, &[NotifyState::Reloading, now])),
+        State::Stopping => sd_notify::notify(false, &[NotifyState::Stopping]),
+    };
+    if let Err(e) = result {
+        warn!("Failed to notify systemd of {:?}: {}", state, e);
+    }
+}
+
+#[cfg(not(feature = "systemd"))]
+fn notify(_state: State) {}
+
+/// Detach from the terminal with a double fork, returning in the daemon
+///
+/// The foreground process stays until the daemon reports through the
+/// returned [`Supervisor`], then exits 0 once it is serving, or 1 if it
+/// fails or exits first. The working directory is kept, as relative paths
+/// in the config are resolved against it.
+#[cfg(unix)]
+pub fn daemonize() -> Result<Supervisor> {
+    use std::fs::File;
+    use std::io::Read;
+    use std::os::fd::{AsRawFd, FromRawFd};
+
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        return Err(std::io::Error::last_os_error()).context("Failed to create startup pipe");
+    }
+    let (mut reader, writer) = unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+
+    match unsafe { libc::fork() } {
+        -1 => return Err(std::io::Error::last_os_error()).context("Failed to fork"),
+        0 => {}
+        _ => {
+            // Every copy of the write end closes if the daemon dies, so
+            // this never waits forever
+            drop(writer);
+            let mut reply = Vec::new();
+            let _ = reader.read_to_end(&mut reply);
+            if reply == STARTED {
+                std::process::exit(0);
+            }
+            if reply.is_empty() {
+                eprintln!("Janus exited before it was ready");
+            } else {
+                eprintln!("Error: {}", String::from_utf8_lossy(&reply));
+            }
+            std::process::exit(1);
+        }
+    }
+    drop(reader);
+
+    // A new session without a controlling terminal, whose leader then
+    // exits so the daemon can never acquire one
+    if unsafe { libc::setsid() } == -1 {
+        return Err(std::io::Error::last_os_error()).context("Failed to start a new session");
+    }
+    match unsafe { libc::fork() } {
+        -1 => return Err(std::io::Error::last_os_error()).context("Failed to fork"),
+        0 => {}
+        _ => unsafe { libc::_exit(0) },
+    }
+
+    let null = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/null")
+        .context("Failed to open /dev/null")?;
+    for fd in 0..=2 {
+        if unsafe { libc::dup2(null.as_raw_fd(), fd) } == -1 {
+            return Err(std::io::Error::last_os_error()).context("Failed to detach from terminal");
+        }
+    }
+
+    Ok(Supervisor {
+        starter: Some(writer),
+    })
+}
+
+/// What a signal asks of the server
+pub enum Signal {
+    Shutdown,
+    Reload,
+}
+
+/// Signals the server acts on: Ctrl+C and SIGTERM shut down, SIGHUP
+/// reloads the config file
+pub struct Signals {
+    #[cfg(unix)]
+    terminate: tokio::signal::unix::Signal,
+    #[cfg(unix)]
+    hangup: tokio::signal::unix::Signal,
+}
+
+impl Signals {
+    pub fn new() -> Result<Self> {
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{signal, SignalKind};
+            Ok(Self {
+                terminate: signal(SignalKind::terminate())?,
+                hangup: signal(SignalKind::hangup())?,
+            })
+        }
+        #[cfg(not(unix))]
+        Ok(Self {})
+    }
+
+    /// Wait for the next signal
+    pub async fn recv(&mut self) -> Signal {
+        #[cfg(unix)]
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => Signal::Shutdown,
+            _ = self.terminate.recv() => Signal::Shutdown,
+            _ = self.hangup.recv() => Signal::Reload,
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = tokio::signal::ctrl_c().await;
+            Signal::Shutdown
+        }
+    }
+}
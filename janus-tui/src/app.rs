@@ -1,12 +1,37 @@
 //! Application state and logic
 
-use crate::client::ManagementClient;
 use crossterm::event::{KeyCode, KeyEvent};
-use janus_common::config::{BackendServer, LoadBalancing, RouteConfig, StaticFileConfig, UpstreamConfig};
-use janus_common::{ClientMessage, JanusConfig, ServerMessage, ServerStats, ServerStatus};
-use std::collections::HashMap;
+use janus_common::config::{
+    HealthCheckConfig, LoadBalancing, RouteConfig, StaticFileConfig, UpstreamConfig,
+};
+use janus_common::{
+    check_edit, AuditEntry, ClientMessage, ClientStats, DiffKind, ErrorLogEntry, JanusConfig,
+    MaintenanceScope, ManagementRole, RouteStatsDetail, ServerMessage, ServerStats, ServerStatus,
+    UpstreamStats,
+};
+use janus_tui::client::{ClientEvent, ManagementClient};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::time::{Duration, Instant};
-use tracing::{debug, error};
+use tracing::{debug, error, warn};
+
+/// Clients shown in the Top Clients table
+const TOP_CLIENTS_LIMIT: usize = 10;
+
+/// Warnings and errors shown on the Status tab
+pub const ERROR_LOG_LIMIT: usize = 20;
+
+/// Audit log entries shown on the Config tab
+pub const AUDIT_LOG_LIMIT: usize = 8;
+
+/// Route hits kept for the Live tab
+pub const MAX_LIVE_HITS: usize = 100;
+
+/// Wait before the first reconnection attempt, doubled after each failure
+const INITIAL_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// Longest wait between reconnection attempts
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
 
 /// Application state
 pub struct App {
@@ -19,6 +44,12 @@ pub struct App {
     /// Connection status
     pub connected: bool,
 
+    /// Management token presented on connect
+    pub token: Option<String>,
+
+    /// Access granted by the server
+    pub role: ManagementRole,
+
     /// Current tab
     pub current_tab: Tab,
 
@@ -28,12 +59,55 @@ pub struct App {
     /// Server configuration
     pub config: Option<JanusConfig>,
 
+    /// Hash of `config`, computed when it arrives, to compare with the
+    /// server's
+    pub config_hash: Option<String>,
+
     /// Server statistics
     pub stats: Option<ServerStats>,
 
+    /// Busiest client addresses
+    pub top_clients: Vec<ClientStats>,
+
+    /// Latest warnings and errors the server logged, newest first
+    pub error_log: Vec<ErrorLogEntry>,
+
+    /// Latest management changes and config file reloads, newest first
+    pub audit_log: Vec<AuditEntry>,
+
+    /// Latest proxied requests from the live feed, newest first
+    pub live_hits: VecDeque<RouteHit>,
+
+    /// Requests per minute for each route, measured between stats updates
+    pub route_rates: HashMap<String, f64>,
+
+    /// Route request counts from the previous stats update
+    route_counts: Option<(Instant, HashMap<String, u64>)>,
+
     /// Status messages
     pub messages: Vec<StatusMessage>,
 
+    /// Number of status messages kept
+    pub max_messages: usize,
+
+    /// Number of messages that fit in the Messages panel (set when drawn)
+    pub messages_visible: usize,
+
+    /// How many messages the panel is scrolled back from the newest
+    pub messages_scroll: usize,
+
+    /// Panel receiving scroll keys
+    pub focused_panel: Panel,
+
+    /// Whether the `?` popup with the current tab's shortcuts is open
+    pub show_help_popup: bool,
+
+    /// Route whose detail popup is open, refreshed along with the stats
+    pub route_detail_path: Option<String>,
+
+    /// Latest traffic of the route in the detail popup
+    pub route_detail: Option<RouteStatsDetail>,
+
     /// Selected item in lists
     pub selected_route: usize,
     pub selected_upstream: usize,
@@ -48,6 +122,10 @@ pub struct App {
     /// Input buffer for editing
     pub input_buffer: String,
 
+    /// Edit already warned about as one the server will refuse; submitting
+    /// it unchanged sends it anyway
+    pub warned_edit: Option<(EditMode, String)>,
+
     /// New route being created
     pub new_route: NewRoute,
 
@@ -57,14 +135,53 @@ pub struct App {
     /// New upstream being created
     pub new_upstream: NewUpstream,
 
+    /// Health check being edited
+    pub health_check_edit: HealthCheckEdit,
+
     /// Last refresh time
     pub last_refresh: Instant,
 
     /// Refresh interval
     pub refresh_interval: Duration,
 
+    /// Outstanding ping and when it was sent
+    ping_sent: Option<(u64, Instant)>,
+
+    /// Round-trip time of the last answered ping
+    pub rtt: Option<Duration>,
+
     /// Flag to request config refresh
     needs_config_refresh: bool,
+
+    /// When a confirmed shutdown request takes effect on the server
+    pub shutdown_at: Option<Instant>,
+
+    /// Upstream failure rate (0.0-1.0) shown as a warning
+    pub failure_rate_warn: f64,
+
+    /// Upstream failure rate (0.0-1.0) shown as an error
+    pub failure_rate_alert: f64,
+
+    /// Route P99 latency in milliseconds shown as an error
+    pub slow_route_ms: f64,
+
+    /// Failed connection attempts since the last successful one
+    pub reconnect_attempts: u32,
+
+    /// Wait before the next reconnection attempt after this one fails
+    pub reconnect_delay: Duration,
+
+    /// When the next reconnection attempt is made, while disconnected
+    pub next_reconnect: Option<Instant>,
+
+    /// Paths of the routes marked for a bulk operation
+    pub marked_routes: HashSet<String>,
+
+    /// Row where a range selection on the Routes tab started
+    pub visual_anchor: Option<usize>,
+
+    /// Route changes being sent one after another
+    pub bulk: Option<BulkOperation>,
 }
 
 /// Available tabs
@@ -75,6 +192,7 @@ pub enum Tab {
     Upstreams,
     Config,
     Stats,
+    Live,
     Help,
 }
 
@@ -86,6 +204,7 @@ impl Tab {
             Tab::Upstreams,
             Tab::Config,
             Tab::Stats,
+            Tab::Live,
             Tab::Help,
         ]
     }
@@ -97,11 +216,27 @@ impl Tab {
             Tab::Upstreams => "Upstreams",
             Tab::Config => "Config",
             Tab::Stats => "Stats",
+            Tab::Live => "Live",
             Tab::Help => "Help",
         }
     }
 }
 
+/// A proxied request reported by the live feed
+#[derive(Debug, Clone)]
+pub struct RouteHit {
+    pub path: String,
+    pub upstream: String,
+    pub status: u16,
+}
+
+/// Panels that can take keyboard focus
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Panel {
+    Main,
+    Messages,
+}
+
 /// Edit mode state
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum EditMode {
@@ -118,6 +253,8 @@ pub enum EditMode {
     AddStaticPath,
     /// Adding static directory - step 2: root directory
     AddStaticRoot,
+    /// Editing the root directory of the static directory at this index
+    EditStaticDir(usize),
     /// Adding upstream - step 1: name
     AddUpstreamName,
     /// Adding upstream - step 2: server address
@@ -126,6 +263,75 @@ pub enum EditMode {
     AddUpstreamWeight,
     /// Adding upstream - step 4: load balancing strategy
     AddUpstreamLoadBalancing,
+    /// Editing a health check - step 1: interval, empty to disable
+    EditHealthInterval,
+    /// Editing a health check - step 2: timeout
+    EditHealthTimeout,
+    /// Editing a health check - step 3: path
+    EditHealthPath,
+    /// Confirming a server shutdown and its delay
+    ConfirmShutdown,
+    /// Confirming the removal of the marked routes
+    ConfirmRemoveRoutes,
+    /// Confirming a reset of the server's statistics
+    ConfirmResetStats,
+    /// Switching maintenance on - message shown to clients, empty for the
+    /// server's maintenance page
+    MaintenanceMessage(MaintenanceScope),
+    /// Importing routes - path of the TOML file
+    ImportRoutesPath,
+}
+
+/// Route changes sent one at a time, each once the previous one has been
+/// answered, so every answer can be put down to its route
+#[derive(Debug, Default)]
+pub struct BulkOperation {
+    /// What a successful change did, for the summary ("removed", "added")
+    pub verb: &'static str,
+    /// Changes still to send, with the route each is about
+    pub queue: VecDeque<(String, ClientMessage)>,
+    /// Route whose change is awaiting an answer
+    pub in_flight: Option<String>,
+    pub succeeded: usize,
+    /// Routes whose change the server refused, with its reason
+    pub failed: Vec<(String, String)>,
+    /// Routes left out before sending, with the reason
+    pub skipped: Vec<(String, &'static str)>,
+}
+
+impl BulkOperation {
+    /// One-line outcome, such as "28 removed, 2 failed: /a (not found), ..."
+    pub fn summary(&self) -> String {
+        let mut summary = format!("{} {}", self.succeeded, self.verb);
+        if !self.failed.is_empty() {
+            let failed: Vec<_> = self
+                .failed
+                .iter()
+                .map(|(path, reason)| format!("{} ({})", path, reason))
+                .collect();
+            summary.push_str(&format!(", {} failed: {}", failed.len(), failed.join(", ")));
+        }
+        if !self.skipped.is_empty() {
+            let skipped: Vec<_> = self
+                .skipped
+                .iter()
+                .map(|(path, reason)| format!("{} ({})", path, reason))
+                .collect();
+            summary.push_str(&format!(
+                ", {} skipped: {}",
+                skipped.len(),
+                skipped.join(", ")
+            ));
+        }
+        summary
+    }
+}
+
+/// A TOML file of `[[routes]]` entries to import
+#[derive(Debug, Deserialize)]
+struct RouteFile {
+    #[serde(default)]
+    routes: Vec<RouteConfig>,
 }
 
 /// New route being created
@@ -136,6 +342,13 @@ pub struct NewRoute {
     pub timeout: String,
 }
 
+impl NewRoute {
+    /// Route matching all methods, with the given timeout
+    pub fn to_config(&self, timeout: u64) -> RouteConfig {
+        RouteConfig::new(self.path.clone(), self.upstream.clone()).timeout(timeout)
+    }
+}
+
 /// New static directory being created
 #[derive(Debug, Clone, Default)]
 pub struct NewStaticDir {
@@ -143,6 +356,13 @@ pub struct NewStaticDir {
     pub root: String,
 }
 
+impl NewStaticDir {
+    /// Static directory serving `index.html` with directory listings
+    pub fn to_config(&self) -> StaticFileConfig {
+        StaticFileConfig::new(self.path.clone(), self.root.clone()).directory_listing(true)
+    }
+}
+
 /// New upstream being created
 #[derive(Debug, Clone, Default)]
 pub struct NewUpstream {
@@ -155,8 +375,9 @@ pub struct NewUpstream {
 
 impl NewUpstream {
     /// Available load balancing options
-    pub const LB_OPTIONS: [&'static str; 4] = ["round_robin", "least_connections", "random", "ip_hash"];
-    
+    pub const LB_OPTIONS: [&'static str; 4] =
+        ["round_robin", "least_connections", "random", "ip_hash"];
+
     /// Get the selected load balancing strategy
     pub fn selected_lb(&self) -> LoadBalancing {
         match self.lb_selection {
@@ -169,36 +390,140 @@ impl NewUpstream {
     }
 }
 
+/// Health check settings being edited for an upstream
+#[derive(Debug, Clone, Default)]
+pub struct HealthCheckEdit {
+    pub upstream: String,
+    pub interval: u64,
+    pub timeout: u64,
+    /// Headers of the check being replaced, which are kept
+    pub custom_headers: HashMap<String, String>,
+}
+
+impl HealthCheckEdit {
+    /// Health check with the entered settings and the given path
+    pub fn build(&self, path: &str) -> Result<HealthCheckConfig, String> {
+        if self.interval <= self.timeout {
+            return Err(format!(
+                "Interval ({}s) must exceed timeout ({}s)",
+                self.interval, self.timeout
+            ));
+        }
+        if !path.starts_with('/') {
+            return Err("Health check path must start with '/'".to_string());
+        }
+        Ok(HealthCheckConfig {
+            interval: self.interval,
+            timeout: self.timeout,
+            path: path.to_string(),
+            custom_headers: self.custom_headers.clone(),
+        })
+    }
+}
+
 /// Status message for display
 #[derive(Debug, Clone)]
 pub struct StatusMessage {
     pub text: String,
     pub is_error: bool,
+    /// Shown as a warning rather than an error or success
+    pub is_warning: bool,
+    /// Set for lines describing a config change
+    pub diff: Option<DiffKind>,
+    /// Config setting the message is about, highlighted before the text
+    pub field: Option<String>,
 }
 
 impl App {
-    pub fn new(server_addr: String) -> Self {
+    /// Derive per-route request rates from the change in counts since the
+    /// previous stats update
+    fn update_route_rates(&mut self, stats: &ServerStats) {
+        // Counts from before a reset say nothing about the rates since
+        if self
+            .stats
+            .as_ref()
+            .is_some_and(|old| old.since != stats.since)
+        {
+            self.route_counts = None;
+            self.route_rates.clear();
+        }
+        let now = Instant::now();
+        let counts: HashMap<String, u64> = stats
+            .route_stats
+            .iter()
+            .map(|(route, s)| (route.clone(), s.requests))
+            .collect();
+
+        if let Some((then, ref previous)) = self.route_counts {
+            let minutes = now.duration_since(then).as_secs_f64() / 60.0;
+            if minutes > 0.0 {
+                self.route_rates = counts
+                    .iter()
+                    .map(|(route, &requests)| {
+                        let before = previous.get(route).copied().unwrap_or(0);
+                        (
+                            route.clone(),
+                            requests.saturating_sub(before) as f64 / minutes,
+                        )
+                    })
+                    .collect();
+            }
+        }
+        self.route_counts = Some((now, counts));
+    }
+
+    pub fn new(server_addr: String, token: Option<String>) -> Self {
         Self {
             server_addr,
             client: None,
             connected: false,
+            token,
+            role: ManagementRole::Admin,
             current_tab: Tab::Status,
             status: None,
             config: None,
+            config_hash: None,
             stats: None,
+            top_clients: Vec::new(),
+            error_log: Vec::new(),
+            audit_log: Vec::new(),
+            live_hits: VecDeque::new(),
+            route_rates: HashMap::new(),
+            route_counts: None,
             messages: Vec::new(),
+            max_messages: 50,
+            messages_visible: 3,
+            messages_scroll: 0,
+            focused_panel: Panel::Main,
+            show_help_popup: false,
+            route_detail_path: None,
+            route_detail: None,
             selected_route: 0,
             selected_upstream: 0,
             selected_static_dir: 0,
             selected_upstream_for_route: 0,
             edit_mode: EditMode::None,
+            warned_edit: None,
             input_buffer: String::new(),
             new_route: NewRoute::default(),
             new_static_dir: NewStaticDir::default(),
             new_upstream: NewUpstream::default(),
+            health_check_edit: HealthCheckEdit::default(),
             last_refresh: Instant::now(),
             refresh_interval: Duration::from_secs(2),
+            ping_sent: None,
+            rtt: None,
             needs_config_refresh: false,
+            shutdown_at: None,
+            failure_rate_warn: 0.01,
+            failure_rate_alert: 0.05,
+            slow_route_ms: 500.0,
+            reconnect_attempts: 0,
+            reconnect_delay: INITIAL_RECONNECT_DELAY,
+            next_reconnect: None,
+            marked_routes: HashSet::new(),
+            visual_anchor: None,
+            bulk: None,
         }
     }
 
@@ -209,16 +534,44 @@ impl App {
             Ok(client) => {
                 self.client = Some(client);
                 self.connected = true;
+                self.reconnect_attempts = 0;
+                self.reconnect_delay = INITIAL_RECONNECT_DELAY;
+                self.next_reconnect = None;
                 self.add_message("Connected to server", false);
 
+                // Servers without tokens grant admin access
+                self.role = ManagementRole::Admin;
+                if let Some(token) = self.token.clone() {
+                    self.send_message(ClientMessage::Authenticate { token })
+                        .await;
+                }
+
                 // Request initial data
                 self.send_message(ClientMessage::GetStatus).await;
                 self.send_message(ClientMessage::GetConfig).await;
                 self.send_message(ClientMessage::GetStats).await;
+                self.send_message(ClientMessage::GetTopClients {
+                    limit: TOP_CLIENTS_LIMIT,
+                })
+                .await;
+                self.send_message(ClientMessage::GetErrorLog {
+                    limit: ERROR_LOG_LIMIT,
+                })
+                .await;
+                self.send_message(ClientMessage::GetAuditLog {
+                    limit: AUDIT_LOG_LIMIT,
+                })
+                .await;
             }
             Err(e) => {
                 self.connected = false;
-                self.add_message(&format!("Connection failed: {}", e), true);
+                // Only the first of a run of failures is worth a message
+                if self.reconnect_attempts == 0 {
+                    self.add_message(&format!("Connection failed: {}", e), true);
+                }
+                self.reconnect_attempts += 1;
+                self.schedule_reconnect();
+                self.reconnect_delay = (self.reconnect_delay * 2).min(MAX_RECONNECT_DELAY);
             }
         }
     }
@@ -235,80 +588,376 @@ impl App {
 
     /// Process incoming messages from server
     pub async fn process_messages(&mut self) {
-        // Collect messages first to avoid borrow issues
-        let messages: Vec<ServerMessage> = if let Some(ref mut client) = self.client {
-            let mut msgs = Vec::new();
-            while let Some(msg) = client.try_recv() {
-                msgs.push(msg);
+        // Collect events first to avoid borrow issues
+        let events: Vec<ClientEvent> = if let Some(ref mut client) = self.client {
+            let mut events = Vec::new();
+            while let Some(event) = client.try_recv() {
+                events.push(event);
             }
-            msgs
+            events
         } else {
             Vec::new()
         };
 
-        // Then handle each message
-        for msg in messages {
-            self.handle_server_message(msg);
+        // Then handle each event
+        for event in events {
+            match event {
+                ClientEvent::Connected => debug!("WebSocket connected"),
+                ClientEvent::Message(msg) => self.handle_server_message(msg),
+                ClientEvent::Disconnected { reason } => {
+                    warn!("Disconnected: {}", reason);
+                    // Already handled if the server said it was going away
+                    if self.connected {
+                        self.add_message(&reason, true);
+                        self.disconnected();
+                    }
+                }
+            }
+        }
+
+        self.advance_bulk().await;
+    }
+
+    /// Send the next change of the bulk operation once the previous one is
+    /// answered, or report the outcome when none are left
+    async fn advance_bulk(&mut self) {
+        let Some(bulk) = self.bulk.as_mut() else {
+            return;
+        };
+        if bulk.in_flight.is_some() {
+            return;
+        }
+        match bulk.queue.pop_front() {
+            Some((path, msg)) => {
+                bulk.in_flight = Some(path);
+                self.send_message(msg).await;
+            }
+            None => {
+                let bulk = self.bulk.take().unwrap_or_default();
+                self.add_message(&bulk.summary(), !bulk.failed.is_empty());
+                self.send_message(ClientMessage::GetConfig).await;
+            }
+        }
+    }
+
+    /// Start sending a bulk operation's changes
+    async fn start_bulk(&mut self, bulk: BulkOperation) {
+        self.bulk = Some(bulk);
+        self.advance_bulk().await;
+    }
+
+    /// Whether a route is marked, or inside the range being selected
+    pub fn route_marked(&self, index: usize, path: &str) -> bool {
+        let in_range = self.visual_anchor.is_some_and(|anchor| {
+            (anchor.min(self.selected_route)..=anchor.max(self.selected_route)).contains(&index)
+        });
+        in_range || self.marked_routes.contains(path)
+    }
+
+    /// Mark the routes between the range's start and the selected row
+    fn mark_visual_range(&mut self, anchor: usize) {
+        let Some(ref config) = self.config else {
+            return;
+        };
+        let (start, end) = (
+            anchor.min(self.selected_route),
+            anchor.max(self.selected_route),
+        );
+        for route in config.routes.iter().skip(start).take(end - start + 1) {
+            self.marked_routes.insert(route.path.clone());
+        }
+    }
+
+    /// Read routes from a TOML file and add the ones not configured yet
+    async fn import_routes(&mut self, path: &str) {
+        let routes = match std::fs::read_to_string(path) {
+            Ok(content) => match toml::from_str::<RouteFile>(&content) {
+                Ok(file) => file.routes,
+                Err(e) => {
+                    self.add_message(&format!("Invalid routes file {}: {}", path, e), true);
+                    return;
+                }
+            },
+            Err(e) => {
+                self.add_message(&format!("Failed to read {}: {}", path, e), true);
+                return;
+            }
+        };
+
+        let mut known: HashSet<String> = self
+            .config
+            .as_ref()
+            .map(|c| c.routes.iter().map(|r| r.path.clone()).collect())
+            .unwrap_or_default();
+        let mut bulk = BulkOperation {
+            verb: "added",
+            ..Default::default()
+        };
+        for route in routes {
+            if known.insert(route.path.clone()) {
+                bulk.queue
+                    .push_back((route.path.clone(), ClientMessage::AddRoute(route)));
+            } else {
+                bulk.skipped.push((route.path, "already exists"));
+            }
         }
+        self.start_bulk(bulk).await;
+    }
+
+    /// Mark the connection lost and plan the first attempt to restore it
+    fn disconnected(&mut self) {
+        self.connected = false;
+        self.shutdown_at = None;
+        self.schedule_reconnect();
+    }
+
+    /// Plan the next reconnection attempt after the current delay
+    fn schedule_reconnect(&mut self) {
+        self.next_reconnect = Some(Instant::now() + self.reconnect_delay);
     }
 
     /// Handle a message from the server
     fn handle_server_message(&mut self, msg: ServerMessage) {
+        // Answers to a bulk operation's changes go into its summary
+        if let Some(bulk) = self.bulk.as_mut() {
+            let outcome = match msg {
+                ServerMessage::Success(_) => Some(None),
+                ServerMessage::Error(ref reason) => Some(Some(reason.clone())),
+                ServerMessage::ConfigValidationErrors(ref issues) => Some(Some(
+                    issues
+                        .iter()
+                        .map(|issue| issue.message.as_str())
+                        .collect::<Vec<_>>()
+                        .join("; "),
+                )),
+                _ => None,
+            };
+            if let (Some(outcome), Some(path)) = (outcome, bulk.in_flight.as_ref()) {
+                match outcome {
+                    None => bulk.succeeded += 1,
+                    Some(reason) => bulk.failed.push((path.clone(), reason)),
+                }
+                bulk.in_flight = None;
+                return;
+            }
+        }
+
         debug!("Received: {:?}", msg);
         match msg {
+            ServerMessage::Authenticated(role) => {
+                self.role = role;
+                if role == ManagementRole::Read {
+                    self.add_message("Connected read-only", false);
+                }
+            }
             ServerMessage::Status(status) => {
                 self.status = Some(status);
             }
             ServerMessage::Config(config) => {
+                self.config_hash = Some(config.config_hash());
                 self.config = Some(config);
+                self.clamp_selections();
+            }
+            ServerMessage::ConfigDiff(entries) => {
+                if entries.is_empty() {
+                    self.add_message("No route, upstream or static directory changes", false);
+                }
+                for entry in entries {
+                    self.push_message(StatusMessage {
+                        text: entry.description,
+                        is_error: false,
+                        is_warning: false,
+                        diff: Some(entry.kind),
+                        field: None,
+                    });
+                }
             }
             ServerMessage::Stats(stats) => {
+                self.update_route_rates(&stats);
                 self.stats = Some(stats);
             }
+            ServerMessage::Pong(id) => {
+                if let Some((sent_id, sent)) = self.ping_sent {
+                    if sent_id == id {
+                        self.rtt = Some(sent.elapsed());
+                    }
+                }
+            }
+            ServerMessage::TopClients(clients) => {
+                self.top_clients = clients;
+            }
+            ServerMessage::RouteStats(detail) => {
+                // Ignore answers for a popup that has since closed
+                if self.route_detail_path.as_ref() == Some(&detail.path) {
+                    self.route_detail = Some(detail);
+                }
+            }
+            ServerMessage::ErrorLog(entries) => {
+                self.error_log = entries;
+            }
+            ServerMessage::AuditLog(entries) => {
+                self.audit_log = entries;
+            }
+            ServerMessage::Preflight(report) => {
+                if report.errors.is_empty() && report.warnings.is_empty() {
+                    self.add_message("Preflight checks passed", false);
+                }
+                for error in report.errors {
+                    self.add_message(&format!("Preflight error: {}", error), true);
+                }
+                for warning in report.warnings {
+                    self.push_message(StatusMessage {
+                        text: format!("Preflight warning: {}", warning),
+                        is_error: false,
+                        is_warning: true,
+                        diff: None,
+                        field: None,
+                    });
+                }
+            }
             ServerMessage::Success(msg) => {
                 self.add_message(&msg, false);
             }
             ServerMessage::Error(msg) => {
                 self.add_message(&msg, true);
             }
+            ServerMessage::ConfigValidationErrors(issues) => {
+                self.add_message("Configuration rejected:", true);
+                for issue in issues {
+                    self.push_message(StatusMessage {
+                        text: issue.message,
+                        is_error: true,
+                        is_warning: false,
+                        diff: None,
+                        field: Some(issue.field),
+                    });
+                }
+            }
+            ServerMessage::RouteHit {
+                path,
+                upstream,
+                status,
+            } => {
+                self.live_hits.push_front(RouteHit {
+                    path,
+                    upstream,
+                    status,
+                });
+                self.live_hits.truncate(MAX_LIVE_HITS);
+            }
             ServerMessage::ConfigReloaded => {
                 self.add_message("Configuration reloaded", false);
                 // Set flag to request updated config in next async tick
                 self.needs_config_refresh = true;
             }
+            ServerMessage::ConfigHash(config_hash) => {
+                if let Some(ref mut status) = self.status {
+                    status.config_hash = config_hash;
+                }
+            }
             ServerMessage::ShuttingDown => {
                 self.add_message("Server is shutting down", true);
-                self.connected = false;
+                self.disconnected();
+            }
+            ServerMessage::Disconnecting(reason) => {
+                self.add_message(&reason, true);
+                self.disconnected();
             }
         }
     }
 
     /// Auto-refresh data from server
     pub async fn auto_refresh(&mut self) {
+        if !self.connected && self.next_reconnect.is_some_and(|at| Instant::now() >= at) {
+            self.connect().await;
+            return;
+        }
+
         // Handle pending config refresh request
         if self.needs_config_refresh && self.connected {
+            // Ask what changed relative to what we were showing
+            if let Some(before) = self.config.clone() {
+                self.send_message(ClientMessage::GetConfigDiff { before })
+                    .await;
+            }
             self.send_message(ClientMessage::GetConfig).await;
             self.needs_config_refresh = false;
         }
 
         if self.connected && self.last_refresh.elapsed() >= self.refresh_interval {
+            // Measure round-trip time alongside the regular refresh
+            let id = self.ping_sent.map_or(0, |(id, _)| id.wrapping_add(1));
+            self.ping_sent = Some((id, Instant::now()));
+            self.send_message(ClientMessage::Ping(id)).await;
             self.send_message(ClientMessage::GetStatus).await;
             self.send_message(ClientMessage::GetStats).await;
+            self.send_message(ClientMessage::GetTopClients {
+                limit: TOP_CLIENTS_LIMIT,
+            })
+            .await;
+            self.send_message(ClientMessage::GetErrorLog {
+                limit: ERROR_LOG_LIMIT,
+            })
+            .await;
+            self.send_message(ClientMessage::GetAuditLog {
+                limit: AUDIT_LOG_LIMIT,
+            })
+            .await;
+            if let Some(path) = self.route_detail_path.clone() {
+                self.send_message(ClientMessage::GetRouteStats { path })
+                    .await;
+            }
             self.last_refresh = Instant::now();
         }
     }
 
     /// Add a status message
     pub fn add_message(&mut self, text: &str, is_error: bool) {
-        self.messages.push(StatusMessage {
+        self.push_message(StatusMessage {
             text: text.to_string(),
             is_error,
+            is_warning: false,
+            diff: None,
+            field: None,
         });
+    }
+
+    fn push_message(&mut self, message: StatusMessage) {
+        self.messages.push(message);
 
-        // Keep only last 10 messages
-        if self.messages.len() > 10 {
-            self.messages.remove(0);
+        // Keep the view on the same messages while scrolled back
+        if self.messages_scroll > 0 {
+            self.messages_scroll += 1;
         }
+
+        if self.messages.len() > self.max_messages {
+            let excess = self.messages.len() - self.max_messages;
+            self.messages.drain(..excess);
+        }
+        self.clamp_messages_scroll();
+    }
+
+    /// Furthest the Messages panel can scroll back
+    fn max_messages_scroll(&self) -> usize {
+        self.messages.len().saturating_sub(self.messages_visible)
+    }
+
+    fn clamp_messages_scroll(&mut self) {
+        self.messages_scroll = self.messages_scroll.min(self.max_messages_scroll());
+    }
+
+    /// Keep list selections within the lists of the latest config, which
+    /// may have shrunk through another client's changes
+    fn clamp_selections(&mut self) {
+        let Some(ref config) = self.config else {
+            return;
+        };
+        let last = |len: usize| len.saturating_sub(1);
+        self.selected_route = self.selected_route.min(last(config.routes.len()));
+        self.selected_upstream = self.selected_upstream.min(last(config.upstreams.len()));
+        self.selected_static_dir = self
+            .selected_static_dir
+            .min(last(config.static_files.len()));
     }
 
     /// Check if in editing mode
@@ -318,6 +967,17 @@ impl App {
 
     /// Handle key input
     pub async fn handle_key(&mut self, key: KeyEvent) {
+        // Any key closes the help popup without doing anything else
+        if self.show_help_popup {
+            self.show_help_popup = false;
+            return;
+        }
+        // And the route detail popup
+        if self.route_detail_path.take().is_some() {
+            self.route_detail = None;
+            return;
+        }
+
         // Handle upstream selection mode separately (uses selection, not text input)
         if self.edit_mode == EditMode::AddRouteUpstream {
             match key.code {
@@ -330,13 +990,14 @@ impl App {
                     self.submit_edit().await;
                 }
                 KeyCode::Up | KeyCode::Char('k') => {
-                    if self.selected_upstream_for_route > 0 {
-                        self.selected_upstream_for_route -= 1;
-                    }
+                    self.selected_upstream_for_route =
+                        self.selected_upstream_for_route.saturating_sub(1);
                 }
                 KeyCode::Down | KeyCode::Char('j') => {
                     if let Some(ref config) = self.config {
-                        if self.selected_upstream_for_route < config.upstreams.len().saturating_sub(1) {
+                        if self.selected_upstream_for_route
+                            < config.upstreams.len().saturating_sub(1)
+                        {
                             self.selected_upstream_for_route += 1;
                         }
                     }
@@ -359,20 +1020,18 @@ impl App {
                         self.submit_edit().await;
                     }
                     KeyCode::Up | KeyCode::Char('k') => {
-                        if self.new_upstream.lb_selection > 0 {
-                            self.new_upstream.lb_selection -= 1;
-                        }
+                        self.new_upstream.lb_selection =
+                            self.new_upstream.lb_selection.saturating_sub(1);
                     }
                     KeyCode::Down | KeyCode::Char('j') => {
-                        if self.new_upstream.lb_selection < NewUpstream::LB_OPTIONS.len() - 1 {
-                            self.new_upstream.lb_selection += 1;
-                        }
+                        self.new_upstream.lb_selection = (self.new_upstream.lb_selection + 1)
+                            .min(NewUpstream::LB_OPTIONS.len() - 1);
                     }
                     _ => {}
                 }
                 return;
             }
-            
+
             // Normal text input editing
             match key.code {
                 KeyCode::Esc => {
@@ -395,7 +1054,28 @@ impl App {
 
         // Normal mode key handling
         match key.code {
+            KeyCode::Char('?') => self.show_help_popup = true,
+
             // Tab navigation
+            // Toggle focus between the main view and the Messages panel
+            KeyCode::Char('m') => {
+                self.focused_panel = match self.focused_panel {
+                    Panel::Main => Panel::Messages,
+                    Panel::Messages => Panel::Main,
+                };
+            }
+            KeyCode::Esc if self.focused_panel == Panel::Messages => {
+                self.focused_panel = Panel::Main;
+            }
+
+            // Scroll messages (older/newer)
+            KeyCode::Char('K') if self.focused_panel == Panel::Messages => {
+                self.messages_scroll = (self.messages_scroll + 1).min(self.max_messages_scroll());
+            }
+            KeyCode::Char('J') if self.focused_panel == Panel::Messages => {
+                self.messages_scroll = self.messages_scroll.saturating_sub(1);
+            }
+
             KeyCode::Tab => {
                 let tabs = Tab::all();
                 let current_idx = tabs
@@ -419,48 +1099,89 @@ impl App {
             KeyCode::Char('3') => self.current_tab = Tab::Upstreams,
             KeyCode::Char('4') => self.current_tab = Tab::Config,
             KeyCode::Char('5') => self.current_tab = Tab::Stats,
-            KeyCode::Char('6') => self.current_tab = Tab::Help,
+            KeyCode::Char('6') => self.current_tab = Tab::Live,
+            KeyCode::Char('7') => self.current_tab = Tab::Help,
 
             // Refresh
-            KeyCode::Char('r') => {
-                if self.connected {
-                    self.send_message(ClientMessage::GetStatus).await;
-                    self.send_message(ClientMessage::GetConfig).await;
-                    self.send_message(ClientMessage::GetStats).await;
-                    self.add_message("Refreshing...", false);
-                }
+            KeyCode::Char('r') if self.connected => {
+                self.send_message(ClientMessage::GetStatus).await;
+                self.send_message(ClientMessage::GetConfig).await;
+                self.send_message(ClientMessage::GetStats).await;
+                self.send_message(ClientMessage::GetTopClients {
+                    limit: TOP_CLIENTS_LIMIT,
+                })
+                .await;
+                self.send_message(ClientMessage::GetErrorLog {
+                    limit: ERROR_LOG_LIMIT,
+                })
+                .await;
+                self.send_message(ClientMessage::GetAuditLog {
+                    limit: AUDIT_LOG_LIMIT,
+                })
+                .await;
+                self.add_message("Refreshing...", false);
+            }
+
+            // Run preflight checks on the server
+            KeyCode::Char('P') if self.connected => {
+                self.send_message(ClientMessage::GetPreflight).await;
             }
 
             // Reconnect
-            KeyCode::Char('c') => {
-                if !self.connected {
-                    self.connect().await;
-                }
+            KeyCode::Char('c') if !self.connected => {
+                self.connect().await;
             }
 
             // Reload config
-            KeyCode::Char('R') => {
-                if self.connected {
-                    self.send_message(ClientMessage::ReloadConfig).await;
+            KeyCode::Char('R') if self.can_edit() => {
+                self.send_message(ClientMessage::ReloadConfig).await;
+            }
+
+            // Drain selected upstream
+            KeyCode::Char('D') if self.current_tab == Tab::Upstreams && self.can_edit() => {
+                if let Some(name) = self.selected_upstream_name() {
+                    self.send_message(ClientMessage::DrainUpstream(name)).await;
+                    self.send_message(ClientMessage::GetStatus).await;
+                }
+            }
+
+            // Edit health check of selected upstream
+            KeyCode::Char('h') if self.current_tab == Tab::Upstreams && self.can_edit() => {
+                if let Some(name) = self.selected_upstream_name() {
+                    let check = self
+                        .config
+                        .as_ref()
+                        .and_then(|c| c.upstreams.get(&name))
+                        .and_then(|u| u.health_check.clone());
+                    self.input_buffer = check
+                        .as_ref()
+                        .map_or_else(String::new, |c| c.interval.to_string());
+                    self.health_check_edit = HealthCheckEdit {
+                        upstream: name.clone(),
+                        custom_headers: check.map(|c| c.custom_headers).unwrap_or_default(),
+                        ..Default::default()
+                    };
+                    self.edit_mode = EditMode::EditHealthInterval;
+                    self.add_message(
+                        &format!(
+                            "Enter health check interval for '{}' in seconds, or leave empty to disable",
+                            name
+                        ),
+                        false,
+                    );
                 }
             }
 
             // List navigation
             KeyCode::Up | KeyCode::Char('k') => match self.current_tab {
                 Tab::Routes => {
-                    if self.selected_route > 0 {
-                        self.selected_route -= 1;
-                    }
+                    self.selected_route = self.selected_route.saturating_sub(1);
                 }
                 Tab::Upstreams => {
-                    if self.selected_upstream > 0 {
-                        self.selected_upstream -= 1;
-                    }
+                    self.selected_upstream = self.selected_upstream.saturating_sub(1);
                 }
                 Tab::Config => {
-                    if self.selected_static_dir > 0 {
-                        self.selected_static_dir -= 1;
-                    }
+                    self.selected_static_dir = self.selected_static_dir.saturating_sub(1);
                 }
                 _ => {}
             },
@@ -489,8 +1210,74 @@ impl App {
                 _ => {}
             },
 
+            // Show the selected route's traffic
+            KeyCode::Enter if self.current_tab == Tab::Routes && self.connected => {
+                let path = self
+                    .config
+                    .as_ref()
+                    .and_then(|c| c.routes.get(self.selected_route))
+                    .map(|r| r.path.clone());
+                if let Some(path) = path {
+                    self.route_detail_path = Some(path.clone());
+                    self.route_detail = None;
+                    self.send_message(ClientMessage::GetRouteStats { path })
+                        .await;
+                }
+            }
+
+            // Mark routes for a bulk operation
+            KeyCode::Char(' ') if self.current_tab == Tab::Routes => {
+                let path = self
+                    .config
+                    .as_ref()
+                    .and_then(|c| c.routes.get(self.selected_route))
+                    .map(|r| r.path.clone());
+                if let Some(path) = path {
+                    if !self.marked_routes.remove(&path) {
+                        self.marked_routes.insert(path);
+                    }
+                }
+            }
+            KeyCode::Char('v') if self.current_tab == Tab::Routes => {
+                match self.visual_anchor.take() {
+                    Some(anchor) => self.mark_visual_range(anchor),
+                    None => self.visual_anchor = Some(self.selected_route),
+                }
+            }
+            KeyCode::Esc if self.current_tab == Tab::Routes && self.visual_anchor.is_some() => {
+                self.visual_anchor = None;
+            }
+            KeyCode::Esc if self.current_tab == Tab::Routes => self.marked_routes.clear(),
+
+            // Remove every marked route, after confirmation
+            KeyCode::Char('d') | KeyCode::Delete
+                if self.current_tab == Tab::Routes
+                    && !self.marked_routes.is_empty()
+                    && self.can_edit() =>
+            {
+                self.input_buffer.clear();
+                self.edit_mode = EditMode::ConfirmRemoveRoutes;
+                self.add_message(
+                    &format!(
+                        "Remove {} marked routes? Type y and press Enter to confirm, Esc to cancel",
+                        self.marked_routes.len()
+                    ),
+                    true,
+                );
+            }
+
+            // Import routes from a file
+            KeyCode::Char('i') if self.current_tab == Tab::Routes && self.can_edit() => {
+                self.input_buffer.clear();
+                self.edit_mode = EditMode::ImportRoutesPath;
+                self.add_message(
+                    "Enter the path of a TOML file with [[routes]] entries",
+                    false,
+                );
+            }
+
             // Delete selected item
-            KeyCode::Char('d') | KeyCode::Delete => {
+            KeyCode::Char('d') | KeyCode::Delete if self.can_edit() => {
                 match self.current_tab {
                     Tab::Routes => {
                         if let Some(ref config) = self.config {
@@ -555,73 +1342,322 @@ impl App {
             }
 
             // Add new item
-            KeyCode::Char('a') => {
-                if self.connected {
-                    match self.current_tab {
-                        Tab::Routes => {
-                            // Check if there are any upstreams to route to
-                            if let Some(ref config) = self.config {
-                                if config.upstreams.is_empty() {
-                                    self.add_message(
-                                        "Cannot add route: no upstreams configured",
-                                        true,
-                                    );
-                                } else {
-                                    self.new_route = NewRoute::default();
-                                    self.input_buffer.clear();
-                                    self.edit_mode = EditMode::AddRoutePath;
-                                    self.add_message(
-                                        "Enter route path (e.g., /api/* or /health)",
-                                        false,
-                                    );
-                                }
+            KeyCode::Char('a') if self.can_edit() => {
+                match self.current_tab {
+                    Tab::Routes => {
+                        // Check if there are any upstreams to route to
+                        if let Some(ref config) = self.config {
+                            if config.upstreams.is_empty() {
+                                self.add_message("Cannot add route: no upstreams configured", true);
+                            } else {
+                                self.new_route = NewRoute::default();
+                                self.input_buffer.clear();
+                                self.edit_mode = EditMode::AddRoutePath;
+                                self.add_message(
+                                    "Enter route path (e.g., /api/* or /health)",
+                                    false,
+                                );
                             }
                         }
-                        Tab::Upstreams => {
-                            // Add upstream
-                            self.new_upstream = NewUpstream::default();
-                            self.input_buffer.clear();
-                            self.edit_mode = EditMode::AddUpstreamName;
-                            self.add_message(
-                                "Enter upstream name (e.g., backend, api)",
-                                false,
-                            );
-                        }
-                        Tab::Config => {
-                            // Add static directory
-                            self.new_static_dir = NewStaticDir::default();
-                            self.input_buffer.clear();
-                            self.edit_mode = EditMode::AddStaticPath;
-                            self.add_message(
-                                "Enter URL path for static files (e.g., /static/)",
-                                false,
-                            );
-                        }
-                        _ => {}
                     }
+                    Tab::Upstreams => {
+                        // Add upstream
+                        self.new_upstream = NewUpstream::default();
+                        self.input_buffer.clear();
+                        self.edit_mode = EditMode::AddUpstreamName;
+                        self.add_message("Enter upstream name (e.g., backend, api)", false);
+                    }
+                    Tab::Config => {
+                        // Add static directory
+                        self.new_static_dir = NewStaticDir::default();
+                        self.input_buffer.clear();
+                        self.edit_mode = EditMode::AddStaticPath;
+                        self.add_message("Enter URL path for static files (e.g., /static/)", false);
+                    }
+                    _ => {}
                 }
             }
 
             // Edit port (on Config tab)
-            KeyCode::Char('p') => {
-                if self.current_tab == Tab::Config && self.connected {
-                    if let Some(ref config) = self.config {
-                        self.input_buffer = config.server.port.to_string();
-                        self.edit_mode = EditMode::EditServerPort;
+            KeyCode::Char('p') if self.current_tab == Tab::Config && self.can_edit() => {
+                if let Some(ref config) = self.config {
+                    self.input_buffer = config.server.port.to_string();
+                    self.edit_mode = EditMode::EditServerPort;
+                    self.add_message(
+                        &format!("Enter new server port (current: {})", config.server.port),
+                        false,
+                    );
+                }
+            }
+
+            // Edit selected static directory (on Config tab)
+            KeyCode::Char('e') if self.current_tab == Tab::Config && self.can_edit() => {
+                if let Some(ref config) = self.config {
+                    if let Some(static_dir) = config.static_files.get(self.selected_static_dir) {
+                        self.input_buffer = static_dir.root.clone();
+                        self.edit_mode = EditMode::EditStaticDir(self.selected_static_dir);
                         self.add_message(
-                            &format!("Enter new server port (current: {})", config.server.port),
+                            &format!("Enter new root directory for '{}'", static_dir.path),
                             false,
                         );
                     }
                 }
             }
 
+            // Enable or disable the selected route (on Routes tab) or static
+            // directory (on Config tab)
+            KeyCode::Char('t')
+                if matches!(self.current_tab, Tab::Routes | Tab::Config) && self.can_edit() =>
+            {
+                let Some(ref config) = self.config else {
+                    return;
+                };
+                let msg = if self.current_tab == Tab::Routes {
+                    let Some(route) = config.routes.get(self.selected_route) else {
+                        return;
+                    };
+                    ClientMessage::SetRouteEnabled {
+                        path: route.path.clone(),
+                        enabled: !route.enabled,
+                    }
+                } else {
+                    let Some(static_dir) = config.static_files.get(self.selected_static_dir) else {
+                        return;
+                    };
+                    ClientMessage::SetStaticDirEnabled {
+                        path: static_dir.path.clone(),
+                        enabled: !static_dir.enabled,
+                    }
+                };
+                self.send_message(msg).await;
+                self.send_message(ClientMessage::GetConfig).await;
+            }
+
+            // Reset the server's statistics (on Stats tab), after confirmation
+            KeyCode::Char('x') if self.current_tab == Tab::Stats && self.can_edit() => {
+                if self.server_supports(janus_common::CAPABILITY_RESET_STATS) {
+                    self.input_buffer.clear();
+                    self.edit_mode = EditMode::ConfirmResetStats;
+                    self.add_message(
+                        "Reset all statistics? Type y and press Enter to confirm, Esc to cancel",
+                        true,
+                    );
+                } else {
+                    self.add_message("Server does not support stats reset", true);
+                }
+            }
+
+            // Toggle maintenance for everything (on Status tab) or the
+            // selected route (on Routes tab), asking for a message when
+            // switching it on
+            KeyCode::Char('M')
+                if matches!(self.current_tab, Tab::Status | Tab::Routes) && self.can_edit() =>
+            {
+                let scope = if self.current_tab == Tab::Routes {
+                    let Some(route) = self
+                        .config
+                        .as_ref()
+                        .and_then(|c| c.routes.get(self.selected_route))
+                    else {
+                        return;
+                    };
+                    MaintenanceScope::Route(route.path.clone())
+                } else {
+                    MaintenanceScope::All
+                };
+
+                if !self.server_supports(janus_common::CAPABILITY_MAINTENANCE) {
+                    self.add_message("Server does not support maintenance mode", true);
+                } else if self.in_maintenance(&scope) {
+                    self.send_message(ClientMessage::SetMaintenance {
+                        scope,
+                        enabled: false,
+                        message: None,
+                    })
+                    .await;
+                    self.send_message(ClientMessage::GetStatus).await;
+                } else {
+                    self.add_message(
+                        &format!(
+                            "Enter a message for clients during maintenance of {}, or leave empty for the maintenance page (Esc to cancel)",
+                            scope
+                        ),
+                        true,
+                    );
+                    self.input_buffer.clear();
+                    self.edit_mode = EditMode::MaintenanceMessage(scope);
+                }
+            }
+
+            // Shut down the server (on Status tab), after confirmation
+            KeyCode::Char('S') if self.current_tab == Tab::Status && self.can_edit() => {
+                self.input_buffer = "0".to_string();
+                self.edit_mode = EditMode::ConfirmShutdown;
+                self.add_message(
+                    "Enter a delay in seconds and press Enter to shut down the server, Esc to cancel",
+                    true,
+                );
+            }
+
             _ => {}
         }
     }
 
+    /// Upstreams in the latest statistics, busiest first
+    pub fn upstream_stats(&self) -> Vec<(&str, &UpstreamStats)> {
+        let Some(ref stats) = self.stats else {
+            return Vec::new();
+        };
+        let mut upstreams: Vec<_> = stats
+            .upstream_stats
+            .iter()
+            .map(|(name, upstream)| (name.as_str(), upstream))
+            .collect();
+        upstreams.sort_by(|a, b| b.1.requests.cmp(&a.1.requests).then(a.0.cmp(b.0)));
+        upstreams
+    }
+
+    /// Whether editing operations are available
+    pub fn can_edit(&self) -> bool {
+        self.connected && self.role == ManagementRole::Admin
+    }
+
+    /// Whether the server reported an optional feature of the management
+    /// API in its status
+    fn server_supports(&self, capability: &str) -> bool {
+        self.status
+            .as_ref()
+            .is_some_and(|s| s.capabilities.iter().any(|c| c == capability))
+    }
+
+    /// Whether the server reported maintenance on for exactly this scope
+    pub fn in_maintenance(&self, scope: &MaintenanceScope) -> bool {
+        self.status
+            .as_ref()
+            .is_some_and(|s| s.maintenance.iter().any(|w| w.scope == *scope))
+    }
+
+    /// Name of the upstream selected on the Upstreams tab
+    fn selected_upstream_name(&self) -> Option<String> {
+        self.config
+            .as_ref()?
+            .upstreams
+            .keys()
+            .nth(self.selected_upstream)
+            .cloned()
+    }
+
+    /// Health check of the upstream being edited, as currently configured
+    fn current_health_check(&self) -> Option<HealthCheckConfig> {
+        self.config
+            .as_ref()?
+            .upstreams
+            .get(&self.health_check_edit.upstream)?
+            .health_check
+            .clone()
+    }
+
+    /// Send the upstream being edited with a new health check, or none
+    async fn update_health_check(&mut self, health_check: Option<HealthCheckConfig>) {
+        let name = std::mem::take(&mut self.health_check_edit).upstream;
+        let upstream = self
+            .config
+            .as_ref()
+            .and_then(|c| c.upstreams.get(&name))
+            .cloned();
+        if let Some(upstream) = upstream {
+            self.send_message(ClientMessage::UpdateUpstream {
+                name,
+                config: UpstreamConfig {
+                    health_check,
+                    ..upstream
+                },
+            })
+            .await;
+            self.send_message(ClientMessage::GetConfig).await;
+        }
+
+        // Reset state
+        self.edit_mode = EditMode::None;
+        self.input_buffer.clear();
+    }
+
+    /// Message the current edit will send with the input typed so far,
+    /// taking the defaults of the steps not reached yet
+    fn pending_edit(&self) -> Option<ClientMessage> {
+        let input = self.input_buffer.trim();
+        if input.is_empty() {
+            return None;
+        }
+        let config = self.config.as_ref()?;
+
+        match self.edit_mode {
+            EditMode::AddRoutePath => {
+                // The upstream selector starts on the first upstream
+                let route = NewRoute {
+                    path: self.input_buffer.clone(),
+                    upstream: config.upstreams.keys().next()?.clone(),
+                    ..Default::default()
+                };
+                Some(ClientMessage::AddRoute(route.to_config(30)))
+            }
+            EditMode::AddRouteTimeout => Some(ClientMessage::AddRoute(
+                self.new_route.to_config(input.parse().unwrap_or(30)),
+            )),
+            EditMode::EditServerPort => input.parse().ok().map(ClientMessage::UpdateServerPort),
+            EditMode::AddStaticPath => {
+                let static_dir = NewStaticDir {
+                    path: self.input_buffer.clone(),
+                    ..Default::default()
+                };
+                Some(ClientMessage::AddStaticDir(static_dir.to_config()))
+            }
+            EditMode::AddStaticRoot => {
+                let static_dir = NewStaticDir {
+                    root: self.input_buffer.clone(),
+                    ..self.new_static_dir.clone()
+                };
+                Some(ClientMessage::AddStaticDir(static_dir.to_config()))
+            }
+            EditMode::EditStaticDir(index) => {
+                let existing = config.static_files.get(index)?;
+                Some(ClientMessage::UpdateStaticDir {
+                    path: existing.path.clone(),
+                    config: StaticFileConfig {
+                        root: self.input_buffer.clone(),
+                        ..existing.clone()
+                    },
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// Why the server will refuse the current edit, checked against the
+    /// cached config with the same rules the server applies
+    pub fn edit_feedback(&self) -> Option<String> {
+        let msg = self.pending_edit()?;
+        check_edit(self.config.as_ref()?, &msg)
+            .err()
+            .map(|e| e.to_string())
+    }
+
     /// Submit the current edit
     async fn submit_edit(&mut self) {
+        // Edits the server will refuse are held back once, with the reason
+        if let Some(problem) = self.edit_feedback() {
+            let edit = (self.edit_mode.clone(), self.input_buffer.clone());
+            if self.warned_edit.as_ref() != Some(&edit) {
+                self.add_message(
+                    &format!("{} (press Enter again to send anyway)", problem),
+                    true,
+                );
+                self.warned_edit = Some(edit);
+                return;
+            }
+        }
+        self.warned_edit = None;
+
         match self.edit_mode {
             EditMode::AddRoutePath => {
                 if self.input_buffer.is_empty() {
@@ -642,7 +1678,11 @@ impl App {
             EditMode::AddRouteUpstream => {
                 // Get the selected upstream name from the list
                 if let Some(ref config) = self.config {
-                    if let Some(name) = config.upstreams.keys().nth(self.selected_upstream_for_route) {
+                    if let Some(name) = config
+                        .upstreams
+                        .keys()
+                        .nth(self.selected_upstream_for_route)
+                    {
                         self.new_route.upstream = name.clone();
                         self.input_buffer = "30".to_string(); // Default timeout
                         self.edit_mode = EditMode::AddRouteTimeout;
@@ -659,14 +1699,7 @@ impl App {
                 self.new_route.timeout = timeout.to_string();
 
                 // Create and send the route
-                let route = RouteConfig {
-                    path: self.new_route.path.clone(),
-                    methods: vec![], // All methods
-                    upstream: self.new_route.upstream.clone(),
-                    rewrite: None,
-                    headers: HashMap::new(),
-                    timeout,
-                };
+                let route = self.new_route.to_config(timeout);
 
                 self.send_message(ClientMessage::AddRoute(route)).await;
                 self.send_message(ClientMessage::GetConfig).await;
@@ -692,6 +1725,143 @@ impl App {
                 self.send_message(ClientMessage::UpdateServerPort(port))
                     .await;
                 self.send_message(ClientMessage::GetConfig).await;
+                self.send_message(ClientMessage::GetStatus).await;
+
+                // Reset state
+                self.edit_mode = EditMode::None;
+                self.input_buffer.clear();
+            }
+            EditMode::EditHealthInterval => {
+                if self.input_buffer.trim().is_empty() {
+                    self.update_health_check(None).await;
+                    return;
+                }
+                self.health_check_edit.interval = match self.input_buffer.trim().parse() {
+                    Ok(i) if i > 0 => i,
+                    _ => {
+                        self.add_message("Invalid interval", true);
+                        return;
+                    }
+                };
+                self.input_buffer = self
+                    .current_health_check()
+                    .map_or(5, |c| c.timeout)
+                    .to_string();
+                self.edit_mode = EditMode::EditHealthTimeout;
+                self.add_message("Enter health check timeout in seconds", false);
+            }
+            EditMode::EditHealthTimeout => {
+                self.health_check_edit.timeout = match self.input_buffer.trim().parse() {
+                    Ok(t) if t > 0 => t,
+                    _ => {
+                        self.add_message("Invalid timeout", true);
+                        return;
+                    }
+                };
+                if let Err(e) = self.health_check_edit.build("/") {
+                    self.add_message(&e, true);
+                    return;
+                }
+                self.input_buffer = self
+                    .current_health_check()
+                    .map_or_else(|| "/health".to_string(), |c| c.path);
+                self.edit_mode = EditMode::EditHealthPath;
+                self.add_message("Enter health check path (e.g., /health)", false);
+            }
+            EditMode::EditHealthPath => {
+                match self.health_check_edit.build(self.input_buffer.trim()) {
+                    Ok(check) => self.update_health_check(Some(check)).await,
+                    Err(e) => self.add_message(&e, true),
+                }
+            }
+            EditMode::ConfirmRemoveRoutes => {
+                if self.input_buffer.trim().eq_ignore_ascii_case("y") {
+                    let configured: Vec<String> = self
+                        .config
+                        .as_ref()
+                        .map(|c| c.routes.iter().map(|r| r.path.clone()).collect())
+                        .unwrap_or_default();
+                    let mut bulk = BulkOperation {
+                        verb: "removed",
+                        ..Default::default()
+                    };
+                    // In the order they are listed
+                    for path in &configured {
+                        if self.marked_routes.contains(path) {
+                            bulk.queue.push_back((
+                                path.clone(),
+                                ClientMessage::RemoveRoute(path.clone()),
+                            ));
+                        }
+                    }
+                    for path in self.marked_routes.drain() {
+                        if !configured.contains(&path) {
+                            bulk.skipped.push((path, "no longer configured"));
+                        }
+                    }
+                    self.start_bulk(bulk).await;
+                } else {
+                    self.add_message("Route removal cancelled", false);
+                }
+
+                self.edit_mode = EditMode::None;
+                self.input_buffer.clear();
+            }
+            EditMode::ConfirmResetStats => {
+                if self.input_buffer.trim().eq_ignore_ascii_case("y") {
+                    self.send_message(ClientMessage::ResetStats).await;
+                    self.send_message(ClientMessage::GetStats).await;
+                    self.send_message(ClientMessage::GetTopClients {
+                        limit: TOP_CLIENTS_LIMIT,
+                    })
+                    .await;
+                } else {
+                    self.add_message("Stats reset cancelled", false);
+                }
+
+                self.edit_mode = EditMode::None;
+                self.input_buffer.clear();
+            }
+            EditMode::MaintenanceMessage(ref scope) => {
+                let scope = scope.clone();
+                let message = self.input_buffer.trim();
+                let message = (!message.is_empty()).then(|| message.to_string());
+                self.send_message(ClientMessage::SetMaintenance {
+                    scope,
+                    enabled: true,
+                    message,
+                })
+                .await;
+                self.send_message(ClientMessage::GetStatus).await;
+
+                self.edit_mode = EditMode::None;
+                self.input_buffer.clear();
+            }
+            EditMode::ImportRoutesPath => {
+                let path = self.input_buffer.trim().to_string();
+                if path.is_empty() {
+                    self.add_message("Path cannot be empty", true);
+                    return;
+                }
+                self.edit_mode = EditMode::None;
+                self.input_buffer.clear();
+                self.import_routes(&path).await;
+            }
+            EditMode::ConfirmShutdown => {
+                let delay_secs: u64 = match self.input_buffer.trim().parse() {
+                    Ok(d) => d,
+                    _ => {
+                        self.add_message("Invalid delay", true);
+                        return;
+                    }
+                };
+
+                self.send_message(ClientMessage::Shutdown {
+                    confirm: true,
+                    delay_secs,
+                })
+                .await;
+                self.shutdown_at = Some(Instant::now() + Duration::from_secs(delay_secs));
 
                 // Reset state
                 self.edit_mode = EditMode::None;
@@ -715,12 +1885,7 @@ impl App {
                 self.new_static_dir.root = self.input_buffer.clone();
 
                 // Create and send the static config
-                let static_config = StaticFileConfig {
-                    path: self.new_static_dir.path.clone(),
-                    root: self.new_static_dir.root.clone(),
-                    index: "index.html".to_string(),
-                    directory_listing: true,
-                };
+                let static_config = self.new_static_dir.to_config();
 
                 self.send_message(ClientMessage::AddStaticDir(static_config))
                     .await;
@@ -738,6 +1903,33 @@ impl App {
                 self.input_buffer.clear();
                 self.new_static_dir = NewStaticDir::default();
             }
+            EditMode::EditStaticDir(index) => {
+                if self.input_buffer.is_empty() {
+                    self.add_message("Root directory cannot be empty", true);
+                    return;
+                }
+                let existing = self
+                    .config
+                    .as_ref()
+                    .and_then(|c| c.static_files.get(index))
+                    .cloned();
+                if let Some(existing) = existing {
+                    let static_config = StaticFileConfig {
+                        root: self.input_buffer.clone(),
+                        ..existing.clone()
+                    };
+                    self.send_message(ClientMessage::UpdateStaticDir {
+                        path: existing.path,
+                        config: static_config,
+                    })
+                    .await;
+                    self.send_message(ClientMessage::GetConfig).await;
+                }
+
+                // Reset state
+                self.edit_mode = EditMode::None;
+                self.input_buffer.clear();
+            }
             EditMode::AddUpstreamName => {
                 if self.input_buffer.is_empty() {
                     self.add_message("Upstream name cannot be empty", true);
@@ -791,17 +1983,12 @@ impl App {
                 let load_balancing = self.new_upstream.selected_lb();
 
                 // Create and send the upstream config
-                let upstream_config = UpstreamConfig {
-                    servers: vec![BackendServer {
-                        address: self.new_upstream.server_address.clone(),
-                        weight: self.new_upstream.server_weight,
-                        backup: false,
-                    }],
-                    load_balancing,
-                    health_check: None,
-                };
+                let mut upstream_config =
+                    UpstreamConfig::single(self.new_upstream.server_address.clone());
+                upstream_config.servers[0].weight = self.new_upstream.server_weight;
+                upstream_config.load_balancing = load_balancing;
 
-                self.send_message(ClientMessage::UpdateUpstream {
+                self.send_message(ClientMessage::AddUpstream {
                     name: self.new_upstream.name.clone(),
                     config: upstream_config,
                 })
@@ -830,11 +2017,19 @@ impl App {
             EditMode::AddRouteTimeout => "Timeout (seconds): ",
             EditMode::EditServerPort => "Server port: ",
             EditMode::AddStaticPath => "URL path: ",
-            EditMode::AddStaticRoot => "Root directory: ",
+            EditMode::AddStaticRoot | EditMode::EditStaticDir(_) => "Root directory: ",
             EditMode::AddUpstreamName => "Upstream name: ",
             EditMode::AddUpstreamServer => "Server address: ",
             EditMode::AddUpstreamWeight => "Server weight: ",
             EditMode::AddUpstreamLoadBalancing => "Load balancing: ",
+            EditMode::EditHealthInterval => "Health check interval (seconds, empty = off): ",
+            EditMode::EditHealthTimeout => "Health check timeout (seconds): ",
+            EditMode::EditHealthPath => "Health check path: ",
+            EditMode::ConfirmShutdown => "Shut down server in (seconds): ",
+            EditMode::ConfirmRemoveRoutes => "Remove marked routes? (y/n): ",
+            EditMode::ConfirmResetStats => "Reset statistics? (y/n): ",
+            EditMode::MaintenanceMessage(_) => "Maintenance message (empty = maintenance page): ",
+            EditMode::ImportRoutesPath => "Import routes from file: ",
         }
     }
 
@@ -847,3 +2042,331 @@ impl App {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_reconnect_backoff() {
+        // Nothing listens on a port that was just released
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let mut app = App::new(format!("127.0.0.1:{}", port), None);
+
+        for attempt in 1..=8 {
+            app.connect().await;
+            assert!(!app.connected);
+            assert_eq!(app.reconnect_attempts, attempt);
+        }
+        assert_eq!(app.reconnect_delay, MAX_RECONNECT_DELAY);
+        assert!(app.next_reconnect.is_some());
+
+        // Only the first failure is reported
+        let failures = app
+            .messages
+            .iter()
+            .filter(|m| m.text.starts_with("Connection failed"))
+            .count();
+        assert_eq!(failures, 1);
+    }
+
+    #[tokio::test]
+    async fn test_dropped_connection_schedules_reconnect() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            // Drop the connection as soon as it is up
+            let _ = tokio_tungstenite::accept_async(stream).await;
+        });
+
+        let mut app = App::new(addr.to_string(), None);
+        app.connect().await;
+        assert!(app.connected);
+
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while app.connected {
+                app.process_messages().await;
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("disconnect not noticed");
+        assert!(app.next_reconnect.is_some());
+        assert!(app
+            .messages
+            .iter()
+            .any(|m| m.is_error && m.text.starts_with("Connection lost")));
+    }
+
+    #[test]
+    fn test_health_check_edit_validation() {
+        let edit = HealthCheckEdit {
+            upstream: "api".to_string(),
+            interval: 10,
+            timeout: 2,
+            custom_headers: HashMap::new(),
+        };
+        assert_eq!(
+            edit.build("/ping"),
+            Ok(HealthCheckConfig {
+                interval: 10,
+                timeout: 2,
+                path: "/ping".to_string(),
+                custom_headers: HashMap::new(),
+            })
+        );
+        assert_eq!(
+            edit.build("ping"),
+            Err("Health check path must start with '/'".to_string())
+        );
+
+        let edit = HealthCheckEdit {
+            timeout: 10,
+            ..edit
+        };
+        assert_eq!(
+            edit.build("/ping"),
+            Err("Interval (10s) must exceed timeout (10s)".to_string())
+        );
+    }
+
+    /// A routes file listing the given paths
+    fn routes_file(paths: &[&str]) -> String {
+        paths
+            .iter()
+            .map(|p| format!("[[routes]]\npath = \"{}\"\nupstream = \"api\"\n", p))
+            .collect()
+    }
+
+    fn app_with_routes(paths: &[&str]) -> App {
+        let mut app = App::new("127.0.0.1:9090".to_string(), None);
+        app.config = Some(JanusConfig {
+            routes: toml::from_str::<RouteFile>(&routes_file(paths))
+                .unwrap()
+                .routes,
+            ..Default::default()
+        });
+        app
+    }
+
+    #[tokio::test]
+    async fn test_import_routes_reports_each_outcome() {
+        let mut app = app_with_routes(&["/existing"]);
+        let path =
+            std::env::temp_dir().join(format!("janus-tui-import-{}.toml", std::process::id()));
+        std::fs::write(&path, routes_file(&["/existing", "/a", "/b", "/a"])).unwrap();
+        app.import_routes(path.to_str().unwrap()).await;
+        std::fs::remove_file(&path).unwrap();
+
+        // Sent one at a time, each once the previous one is answered
+        assert_eq!(app.bulk.as_ref().unwrap().in_flight.as_deref(), Some("/a"));
+        app.handle_server_message(ServerMessage::Success("Route added".to_string()));
+        app.advance_bulk().await;
+        assert_eq!(app.bulk.as_ref().unwrap().in_flight.as_deref(), Some("/b"));
+        app.handle_server_message(ServerMessage::Error("Unknown upstream".to_string()));
+        app.advance_bulk().await;
+
+        assert!(app.bulk.is_none());
+        assert_eq!(
+            app.messages.last().unwrap().text,
+            "1 added, 1 failed: /b (Unknown upstream), \
+             2 skipped: /existing (already exists), /a (already exists)"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_edit_feedback_warns_once() {
+        let mut app = app_with_routes(&["/existing"]);
+        if let Some(config) = app.config.as_mut() {
+            let upstream = toml::from_str("servers = [{ address = \"127.0.0.1:3000\" }]").unwrap();
+            config.upstreams.insert("api".to_string(), upstream);
+        }
+        app.edit_mode = EditMode::AddRoutePath;
+
+        app.input_buffer = "/new".to_string();
+        assert_eq!(app.edit_feedback(), None);
+        app.input_buffer = "/existing".to_string();
+        assert_eq!(
+            app.edit_feedback().as_deref(),
+            Some("Route '/existing' already exists")
+        );
+
+        // Held back the first time, sent as typed the second
+        app.submit_edit().await;
+        assert_eq!(app.edit_mode, EditMode::AddRoutePath);
+        app.submit_edit().await;
+        assert_eq!(app.edit_mode, EditMode::AddRouteUpstream);
+    }
+
+    #[tokio::test]
+    async fn test_help_popup_closed_by_any_key() {
+        let mut app = app_with_routes(&["/a", "/b"]);
+        app.current_tab = Tab::Routes;
+
+        app.handle_key(KeyEvent::from(KeyCode::Char('?'))).await;
+        assert!(app.show_help_popup);
+
+        // The key only closes the popup
+        app.handle_key(KeyEvent::from(KeyCode::Char('j'))).await;
+        assert!(!app.show_help_popup);
+        assert_eq!(app.selected_route, 0);
+
+        // Typed as text while editing
+        app.edit_mode = EditMode::AddRoutePath;
+        app.handle_key(KeyEvent::from(KeyCode::Char('?'))).await;
+        assert!(!app.show_help_popup);
+        assert_eq!(app.input_buffer, "?");
+    }
+
+    #[tokio::test]
+    async fn test_stats_reset_needs_server_support() {
+        let mut app = App::new("127.0.0.1:9090".to_string(), None);
+        app.current_tab = Tab::Stats;
+        app.connected = true;
+        app.status = Some(ServerStatus::default());
+
+        app.handle_key(KeyEvent::from(KeyCode::Char('x'))).await;
+        assert_eq!(app.edit_mode, EditMode::None);
+        assert_eq!(
+            app.messages.last().unwrap().text,
+            "Server does not support stats reset"
+        );
+
+        app.status = Some(ServerStatus {
+            capabilities: vec![janus_common::CAPABILITY_RESET_STATS.to_string()],
+            ..Default::default()
+        });
+        app.handle_key(KeyEvent::from(KeyCode::Char('x'))).await;
+        assert_eq!(app.edit_mode, EditMode::ConfirmResetStats);
+        app.handle_key(KeyEvent::from(KeyCode::Char('n'))).await;
+        app.handle_key(KeyEvent::from(KeyCode::Enter)).await;
+        assert_eq!(app.edit_mode, EditMode::None);
+        assert_eq!(app.messages.last().unwrap().text, "Stats reset cancelled");
+    }
+
+    #[tokio::test]
+    async fn test_maintenance_key_prompts_only_to_switch_on() {
+        let mut app = App::new("127.0.0.1:9090".to_string(), None);
+        app.current_tab = Tab::Routes;
+        app.connected = true;
+        let mut config = JanusConfig::default();
+        config.routes.push(RouteConfig::new("/api/*", "api"));
+        app.config = Some(config);
+        app.status = Some(ServerStatus::default());
+
+        app.handle_key(KeyEvent::from(KeyCode::Char('M'))).await;
+        assert_eq!(app.edit_mode, EditMode::None);
+        assert_eq!(
+            app.messages.last().unwrap().text,
+            "Server does not support maintenance mode"
+        );
+
+        let route = MaintenanceScope::Route("/api/*".to_string());
+        app.status = Some(ServerStatus {
+            capabilities: vec![janus_common::CAPABILITY_MAINTENANCE.to_string()],
+            ..Default::default()
+        });
+        app.handle_key(KeyEvent::from(KeyCode::Char('M'))).await;
+        assert_eq!(app.edit_mode, EditMode::MaintenanceMessage(route.clone()));
+        app.handle_key(KeyEvent::from(KeyCode::Esc)).await;
+
+        // Switched off straight away
+        app.status.as_mut().unwrap().maintenance = vec![janus_common::MaintenanceWindow {
+            scope: route,
+            message: None,
+            since: 0,
+        }];
+        app.handle_key(KeyEvent::from(KeyCode::Char('M'))).await;
+        assert_eq!(app.edit_mode, EditMode::None);
+
+        // Everything, from the Status tab
+        app.current_tab = Tab::Status;
+        app.handle_key(KeyEvent::from(KeyCode::Char('M'))).await;
+        assert_eq!(
+            app.edit_mode,
+            EditMode::MaintenanceMessage(MaintenanceScope::All)
+        );
+    }
+
+    #[test]
+    fn test_stats_reset_clears_rate_history() {
+        let mut app = App::new("127.0.0.1:9090".to_string(), None);
+        let stats = |since, requests| ServerStats {
+            since: Some(since),
+            route_stats: HashMap::from([(
+                "/api/*".to_string(),
+                janus_common::RouteStats {
+                    requests,
+                    errors: 0,
+                },
+            )]),
+            ..Default::default()
+        };
+        app.handle_server_message(ServerMessage::Stats(stats(100, 500)));
+        app.route_rates.insert("/api/*".to_string(), 42.0);
+
+        // Without the reset the drop to 3 would read as no traffic
+        app.handle_server_message(ServerMessage::Stats(stats(200, 3)));
+        assert!(app.route_rates.is_empty());
+        assert_eq!(
+            app.route_counts
+                .as_ref()
+                .map(|(_, counts)| counts["/api/*"]),
+            Some(3)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_route_detail_popup() {
+        let mut app = app_with_routes(&["/a", "/b"]);
+        app.current_tab = Tab::Routes;
+        app.connected = true;
+
+        app.handle_key(KeyEvent::from(KeyCode::Char('j'))).await;
+        app.handle_key(KeyEvent::from(KeyCode::Enter)).await;
+        assert_eq!(app.route_detail_path.as_deref(), Some("/b"));
+
+        // Only the open route's answer is kept
+        let detail = |path: &str| RouteStatsDetail {
+            path: path.to_string(),
+            requests: 3,
+            ..Default::default()
+        };
+        app.handle_server_message(ServerMessage::RouteStats(detail("/a")));
+        assert_eq!(app.route_detail, None);
+        app.handle_server_message(ServerMessage::RouteStats(detail("/b")));
+        assert_eq!(app.route_detail, Some(detail("/b")));
+
+        // Any key closes it without doing anything else
+        app.handle_key(KeyEvent::from(KeyCode::Char('k'))).await;
+        assert_eq!(app.route_detail_path, None);
+        assert_eq!(app.route_detail, None);
+        assert_eq!(app.selected_route, 1);
+    }
+
+    #[test]
+    fn test_marks_survive_refresh() {
+        let mut app = app_with_routes(&["/a", "/b", "/c", "/d"]);
+        app.visual_anchor = Some(1);
+        app.selected_route = 2;
+        assert!(app.route_marked(2, "/c"));
+        app.mark_visual_range(1);
+        app.visual_anchor = None;
+        assert_eq!(
+            app.marked_routes,
+            HashSet::from(["/b".to_string(), "/c".to_string()])
+        );
+
+        // A refreshed config listing the routes in another order
+        if let Some(config) = app.config.as_mut() {
+            config.routes.reverse();
+        }
+        assert!(app.route_marked(1, "/c") && app.route_marked(2, "/b"));
+        assert!(!app.route_marked(0, "/d"));
+    }
+}
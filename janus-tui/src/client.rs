@@ -3,7 +3,8 @@
 use anyhow::Result;
 use futures::{stream::SplitSink, SinkExt, StreamExt};
 use janus_common::{ClientMessage, ServerMessage};
-use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use tokio::net::TcpStream;
 use tokio::sync::mpsc;
 use tokio_tungstenite::{connect_async, tungstenite::Message, MaybeTlsStream, WebSocketStream};
@@ -12,16 +13,30 @@ use tracing::{debug, error};
 type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
 type WsSink = SplitSink<WsStream, Message>;
 
+/// What happened on the connection
+#[derive(Debug)]
+#[allow(clippy::large_enum_variant)]
+pub enum ClientEvent {
+    /// The connection is up and messages can be sent
+    Connected,
+
+    /// A message from the server
+    Message(ServerMessage),
+
+    /// The connection ended; no events follow
+    Disconnected { reason: String },
+}
+
 /// Management API client
 pub struct ManagementClient {
     /// Channel to send messages to the WebSocket task
     tx: mpsc::Sender<ClientMessage>,
 
-    /// Buffer of received messages
-    received: VecDeque<ServerMessage>,
+    /// Channel to receive events from the WebSocket task
+    rx: mpsc::Receiver<ClientEvent>,
 
-    /// Channel to receive messages from the WebSocket task
-    rx: mpsc::Receiver<ServerMessage>,
+    /// Cleared by the WebSocket task when the connection ends
+    alive: Arc<AtomicBool>,
 }
 
 impl ManagementClient {
@@ -32,47 +47,55 @@ impl ManagementClient {
 
         // Create channels
         let (tx, mut cmd_rx) = mpsc::channel::<ClientMessage>(100);
-        let (msg_tx, rx) = mpsc::channel::<ServerMessage>(100);
+        let (event_tx, rx) = mpsc::channel::<ClientEvent>(100);
+        let alive = Arc::new(AtomicBool::new(true));
 
         // Spawn task to handle WebSocket communication
+        let task_alive = alive.clone();
         tokio::spawn(async move {
-            if let Err(e) = run_client(write, read, &mut cmd_rx, &msg_tx).await {
-                error!("WebSocket client error: {}", e);
-            }
+            let _ = event_tx.send(ClientEvent::Connected).await;
+            let reason = match run_client(write, read, &mut cmd_rx, &event_tx).await {
+                Ok(reason) => reason,
+                Err(e) => {
+                    error!("WebSocket client error: {}", e);
+                    format!("Connection lost: {}", e)
+                }
+            };
+            task_alive.store(false, Ordering::Release);
+            let _ = event_tx.send(ClientEvent::Disconnected { reason }).await;
         });
 
-        Ok(Self {
-            tx,
-            received: VecDeque::new(),
-            rx,
-        })
+        Ok(Self { tx, rx, alive })
     }
 
     /// Send a message to the server
     pub async fn send(&mut self, msg: ClientMessage) -> Result<()> {
+        if !self.is_alive() {
+            anyhow::bail!("not connected");
+        }
         self.tx.send(msg).await?;
         Ok(())
     }
 
-    /// Try to receive a message (non-blocking)
-    pub fn try_recv(&mut self) -> Option<ServerMessage> {
-        // First check buffered messages
-        if let Some(msg) = self.received.pop_front() {
-            return Some(msg);
-        }
-
-        // Try to receive from channel
+    /// Try to receive an event (non-blocking)
+    pub fn try_recv(&mut self) -> Option<ClientEvent> {
         self.rx.try_recv().ok()
     }
+
+    /// Whether the connection is still up; its `Disconnected` event may not
+    /// have been received yet
+    pub fn is_alive(&self) -> bool {
+        self.alive.load(Ordering::Acquire)
+    }
 }
 
-/// Run the WebSocket client
+/// Run the WebSocket client, returning why the connection ended
 async fn run_client(
     mut write: WsSink,
     mut read: futures::stream::SplitStream<WsStream>,
     cmd_rx: &mut mpsc::Receiver<ClientMessage>,
-    msg_tx: &mpsc::Sender<ServerMessage>,
-) -> Result<()> {
+    event_tx: &mpsc::Sender<ClientEvent>,
+) -> Result<String> {
     loop {
         tokio::select! {
             // Handle outgoing messages
@@ -83,14 +106,14 @@ async fn run_client(
             }
 
             // Handle incoming messages
-            Some(msg) = read.next() => {
+            msg = read.next() => {
                 match msg {
-                    Ok(Message::Text(text)) => {
+                    Some(Ok(Message::Text(text))) => {
                         debug!("Received: {}", text);
                         match serde_json::from_str::<ServerMessage>(&text) {
                             Ok(server_msg) => {
-                                if msg_tx.send(server_msg).await.is_err() {
-                                    break;
+                                if event_tx.send(ClientEvent::Message(server_msg)).await.is_err() {
+                                    return Ok("Client closed".to_string());
                                 }
                             }
                             Err(e) => {
@@ -98,24 +121,107 @@ async fn run_client(
                             }
                         }
                     }
-                    Ok(Message::Close(_)) => {
+                    Some(Ok(Message::Close(_))) => {
                         debug!("Server closed connection");
-                        break;
+                        return Ok("Connection closed by server".to_string());
                     }
-                    Ok(Message::Ping(data)) => {
+                    Some(Ok(Message::Ping(data))) => {
                         write.send(Message::Pong(data)).await?;
                     }
-                    Ok(_) => {}
-                    Err(e) => {
+                    Some(Ok(_)) => {}
+                    Some(Err(e)) => {
                         error!("WebSocket error: {}", e);
-                        break;
+                        return Ok(format!("Connection lost: {}", e));
                     }
+                    None => return Ok("Connection lost".to_string()),
                 }
             }
-
-            else => break,
         }
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::net::TcpListener;
+
+    /// Accept one WebSocket connection, send `messages`, then hand the
+    /// stream to `end`
+    async fn mock_server<F>(messages: Vec<ServerMessage>, end: F) -> String
+    where
+        F: FnOnce(WebSocketStream<TcpStream>) + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+            for msg in messages {
+                let text = serde_json::to_string(&msg).unwrap();
+                ws.send(Message::Text(text)).await.unwrap();
+            }
+            end(ws);
+        });
+        format!("ws://{}", addr)
+    }
+
+    /// Every event up to and including `Disconnected`
+    async fn events_until_disconnected(client: &mut ManagementClient) -> Vec<ClientEvent> {
+        let mut events = Vec::new();
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while let Some(event) = client.rx.recv().await {
+                let done = matches!(event, ClientEvent::Disconnected { .. });
+                events.push(event);
+                if done {
+                    break;
+                }
+            }
+        })
+        .await
+        .expect("no Disconnected event");
+        events
+    }
 
-    Ok(())
+    #[tokio::test]
+    async fn test_dropped_connection_reports_disconnected() {
+        let url = mock_server(vec![ServerMessage::Pong(7)], drop).await;
+        let mut client = ManagementClient::connect(&url).await.unwrap();
+
+        let events = events_until_disconnected(&mut client).await;
+        assert!(matches!(events[0], ClientEvent::Connected));
+        assert!(matches!(
+            events[1],
+            ClientEvent::Message(ServerMessage::Pong(7))
+        ));
+        let ClientEvent::Disconnected { ref reason } = events[2] else {
+            panic!("expected Disconnected, got {:?}", events[2]);
+        };
+        assert!(reason.starts_with("Connection lost"), "{}", reason);
+
+        assert!(!client.is_alive());
+        assert!(client.try_recv().is_none());
+        assert!(client.send(ClientMessage::GetStatus).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_close_frame_reports_disconnected() {
+        let url = mock_server(vec![], |mut ws| {
+            tokio::spawn(async move {
+                ws.close(None).await.unwrap();
+                // Hold the socket until the client has seen the close
+                while ws.next().await.is_some() {}
+            });
+        })
+        .await;
+        let mut client = ManagementClient::connect(&url).await.unwrap();
+        assert!(client.is_alive());
+
+        let events = events_until_disconnected(&mut client).await;
+        let Some(ClientEvent::Disconnected { reason }) = events.last() else {
+            panic!("expected Disconnected, got {:?}", events);
+        };
+        assert_eq!(reason, "Connection closed by server");
+        assert!(!client.is_alive());
+    }
 }
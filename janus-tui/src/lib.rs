@@ -0,0 +1,3 @@
+//! Janus TUI library - the management API client, for use outside the binary
+
+pub mod client;
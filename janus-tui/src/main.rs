@@ -1,7 +1,6 @@
 //! Janus TUI - Terminal User Interface for managing Janus server
 
 mod app;
-mod client;
 mod ui;
 
 use anyhow::Result;
@@ -27,6 +26,10 @@ fn print_help() {
     println!();
     println!("OPTIONS:");
     println!("    -d, --debug      Enable debug logging to janus-tui.log");
+    println!("    -t, --token      Management access token [env: JANUS_TOKEN]");
+    println!("    --failure-warn <PERCENT>   Upstream failure rate shown in yellow [default: 1]");
+    println!("    --failure-alert <PERCENT>  Upstream failure rate shown in red [default: 5]");
+    println!("    --slow-route <MS>          Route P99 latency shown in red [default: 500]");
     println!("    -h, --help       Print help information");
 }
 
@@ -36,8 +39,13 @@ async fn main() -> Result<()> {
     let args: Vec<String> = std::env::args().skip(1).collect();
     let mut debug_mode = false;
     let mut server_addr = "127.0.0.1:9090".to_string();
+    let mut token = std::env::var("JANUS_TOKEN").ok();
+    let mut failure_rate_warn = 1.0;
+    let mut failure_rate_alert = 5.0;
+    let mut slow_route_ms = 500.0;
 
-    for arg in &args {
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
         match arg.as_str() {
             "-h" | "--help" => {
                 print_help();
@@ -46,6 +54,31 @@ async fn main() -> Result<()> {
             "-d" | "--debug" => {
                 debug_mode = true;
             }
+            "-t" | "--token" => match args.next() {
+                Some(value) => token = Some(value.clone()),
+                None => {
+                    eprintln!("error: {} requires a value", arg);
+                    std::process::exit(1);
+                }
+            },
+            "--failure-warn" | "--failure-alert" => {
+                let Some(percent) = args.next().and_then(|v| v.parse::<f64>().ok()) else {
+                    eprintln!("error: {} requires a percentage", arg);
+                    std::process::exit(1);
+                };
+                if arg == "--failure-warn" {
+                    failure_rate_warn = percent;
+                } else {
+                    failure_rate_alert = percent;
+                }
+            }
+            "--slow-route" => {
+                let Some(ms) = args.next().and_then(|v| v.parse::<f64>().ok()) else {
+                    eprintln!("error: {} requires milliseconds", arg);
+                    std::process::exit(1);
+                };
+                slow_route_ms = ms;
+            }
             _ if arg.starts_with('-') => {
                 eprintln!("error: unknown option: {}", arg);
                 eprintln!();
@@ -84,7 +117,10 @@ async fn main() -> Result<()> {
     let mut terminal = Terminal::new(backend)?;
 
     // Create app and run
-    let mut app = App::new(server_addr);
+    let mut app = App::new(server_addr, token);
+    app.failure_rate_warn = failure_rate_warn / 100.0;
+    app.failure_rate_alert = failure_rate_alert / 100.0;
+    app.slow_route_ms = slow_route_ms;
     let res = run_app(&mut terminal, &mut app).await;
 
     // Restore terminal
@@ -119,7 +155,12 @@ async fn run_app<B: ratatui::backend::Backend>(
         if event::poll(std::time::Duration::from_millis(100))? {
             if let Event::Key(key) = event::read()? {
                 // Global quit handler
-                if key.code == KeyCode::Char('q') && key.modifiers.is_empty() && !app.is_editing() {
+                // While the help popup is open, 'q' only closes it
+                if key.code == KeyCode::Char('q')
+                    && key.modifiers.is_empty()
+                    && !app.is_editing()
+                    && !app.show_help_popup
+                {
                     return Ok(());
                 }
                 if key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL) {
@@ -1,6 +1,7 @@
 //! TUI rendering
 
-use crate::app::{App, EditMode, Tab};
+use crate::app::{App, EditMode, Panel, Tab, AUDIT_LOG_LIMIT, ERROR_LOG_LIMIT, MAX_LIVE_HITS};
+use janus_common::{AcmeStatus, AuditSource, DiffKind, LogLevel, MaintenanceScope, ManagementRole};
 use ratatui::{
     layout::{Constraint, Direction, Layout, Rect},
     style::{Color, Modifier, Style},
@@ -10,7 +11,7 @@ use ratatui::{
 };
 
 /// Main draw function
-pub fn draw(f: &mut Frame, app: &App) {
+pub fn draw(f: &mut Frame, app: &mut App) {
     let chunks = Layout::default()
         .direction(Direction::Vertical)
         .margin(1)
@@ -22,6 +23,9 @@ pub fn draw(f: &mut Frame, app: &App) {
         ])
         .split(f.size());
 
+    // Size the message history to the panel, less its borders
+    app.messages_visible = chunks[2].height.saturating_sub(2) as usize;
+
     draw_tabs(f, app, chunks[0]);
     draw_main_content(f, app, chunks[1]);
     draw_messages(f, app, chunks[2]);
@@ -31,6 +35,14 @@ pub fn draw(f: &mut Frame, app: &App) {
     if app.edit_mode == EditMode::AddRouteUpstream {
         draw_upstream_selector(f, app);
     }
+
+    if app.route_detail_path.is_some() {
+        draw_route_detail_popup(f, app);
+    }
+
+    if app.show_help_popup {
+        draw_help_popup(f, app);
+    }
 }
 
 /// Draw tab bar
@@ -66,16 +78,82 @@ fn draw_tabs(f: &mut Frame, app: &App, area: Rect) {
 
 /// Draw main content based on current tab
 fn draw_main_content(f: &mut Frame, app: &App, area: Rect) {
+    // Maintenance is shown on every tab, as it turns clients away
+    let mut banners: Vec<_> = maintenance_banner(app).into_iter().collect();
+    if matches!(app.current_tab, Tab::Status | Tab::Config) {
+        banners.extend(restart_banner(app));
+    }
+    let mut constraints = vec![Constraint::Length(1); banners.len()];
+    constraints.push(Constraint::Min(0));
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints(constraints)
+        .split(area);
+    for (banner, &chunk) in banners.into_iter().zip(chunks.iter()) {
+        f.render_widget(banner, chunk);
+    }
+    let area = chunks[chunks.len() - 1];
+
     match app.current_tab {
         Tab::Status => draw_status(f, app, area),
         Tab::Routes => draw_routes(f, app, area),
         Tab::Upstreams => draw_upstreams(f, app, area),
         Tab::Config => draw_config(f, app, area),
         Tab::Stats => draw_stats(f, app, area),
+        Tab::Live => draw_live(f, app, area),
         Tab::Help => draw_help(f, area),
     }
 }
 
+/// Reconnection attempts so far and the wait until the next one, while
+/// disconnected
+fn reconnect_progress(app: &App) -> Option<String> {
+    let next = app.next_reconnect.filter(|_| !app.connected)?;
+    let wait = next.saturating_duration_since(std::time::Instant::now());
+    Some(format!(
+        "Reconnection attempt {}/∞ (next in {}s)",
+        app.reconnect_attempts + 1,
+        wait.as_secs_f64().ceil() as u64
+    ))
+}
+
+/// Banner naming saved listener settings the running server is not using
+/// yet, as reported by the server
+fn restart_banner(app: &App) -> Option<Paragraph<'static>> {
+    let status = app.status.as_ref().filter(|_| app.connected)?;
+    if status.pending_restart_settings.is_empty() {
+        return None;
+    }
+    let text = format!(
+        " Restart pending: {} saved, still listening on {} ",
+        status.pending_restart_settings.join(", "),
+        status.listen_address
+    );
+    Some(Paragraph::new(text).style(Style::default().fg(Color::Black).bg(Color::Yellow)))
+}
+
+/// Banner naming what the server is answering with 503 for maintenance
+fn maintenance_banner(app: &App) -> Option<Paragraph<'static>> {
+    let status = app.status.as_ref().filter(|_| app.connected)?;
+    if status.maintenance.is_empty() {
+        return None;
+    }
+    let scopes: Vec<_> = status
+        .maintenance
+        .iter()
+        .map(|window| window.scope.to_string())
+        .collect();
+    let text = format!(" MAINTENANCE: answering 503 for {} ", scopes.join(", "));
+    Some(
+        Paragraph::new(text).style(
+            Style::default()
+                .fg(Color::White)
+                .bg(Color::Red)
+                .add_modifier(Modifier::BOLD),
+        ),
+    )
+}
+
 /// Draw status tab
 fn draw_status(f: &mut Frame, app: &App, area: Rect) {
     let connection_status = if app.connected {
@@ -84,14 +162,34 @@ fn draw_status(f: &mut Frame, app: &App, area: Rect) {
         Span::styled("● Disconnected", Style::default().fg(Color::Red))
     };
 
+    let mut connection = vec![Span::raw("Connection: "), connection_status];
+    if app.connected && app.role == ManagementRole::Read {
+        connection.push(Span::styled(
+            " (read-only)",
+            Style::default().fg(Color::Yellow),
+        ));
+    }
+    if let Some(retry) = reconnect_progress(app) {
+        connection.push(Span::styled(
+            format!(" - {}", retry),
+            Style::default().fg(Color::Yellow),
+        ));
+    }
+
     let mut lines = vec![
-        Line::from(vec![Span::raw("Connection: "), connection_status]),
+        Line::from(connection),
         Line::from(vec![
             Span::raw("Server: "),
             Span::styled(&app.server_addr, Style::default().fg(Color::Cyan)),
         ]),
-        Line::raw(""),
     ];
+    if let Some(countdown) = shutdown_countdown(app) {
+        lines.push(Line::styled(
+            countdown,
+            Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+        ));
+    }
+    lines.push(Line::raw(""));
 
     if let Some(ref status) = app.status {
         lines.extend(vec![
@@ -103,6 +201,15 @@ fn draw_status(f: &mut Frame, app: &App, area: Rect) {
                 Span::raw("Listen Address: "),
                 Span::styled(&status.listen_address, Style::default().fg(Color::Cyan)),
             ]),
+            Line::from(vec![
+                Span::raw("Profile: "),
+                match status.profile {
+                    Some(ref profile) => {
+                        Span::styled(profile.as_str(), Style::default().fg(Color::Yellow))
+                    }
+                    None => Span::styled("(base)", Style::default().fg(Color::DarkGray)),
+                },
+            ]),
             Line::from(vec![
                 Span::raw("Uptime: "),
                 Span::styled(
@@ -116,6 +223,20 @@ fn draw_status(f: &mut Frame, app: &App, area: Rect) {
                     status.active_connections.to_string(),
                     Style::default().fg(Color::Magenta),
                 ),
+                Span::styled(
+                    format!(" ({} idle)", status.idle_connections),
+                    Style::default().fg(Color::DarkGray),
+                ),
+            ]),
+            Line::from(vec![
+                Span::raw("Connections Accepted / Rejected: "),
+                Span::styled(
+                    format!(
+                        "{} / {}",
+                        status.connections_accepted, status.connections_rejected
+                    ),
+                    Style::default().fg(Color::Magenta),
+                ),
             ]),
             Line::from(vec![
                 Span::raw("Routes: "),
@@ -132,6 +253,24 @@ fn draw_status(f: &mut Frame, app: &App, area: Rect) {
                 ),
             ]),
         ]);
+
+        if !status.config_hash.is_empty() {
+            lines.push(config_hash_line(app, &status.config_hash));
+        }
+
+        if status.draining_upstreams > 0 {
+            lines.push(Line::from(vec![
+                Span::raw("Draining Upstreams: "),
+                Span::styled(
+                    status.draining_upstreams.to_string(),
+                    Style::default().fg(Color::Yellow),
+                ),
+            ]));
+        }
+
+        if let Some(ref acme) = status.acme {
+            lines.extend(acme_lines(acme));
+        }
     } else {
         lines.push(Line::styled(
             "No status data available",
@@ -139,20 +278,78 @@ fn draw_status(f: &mut Frame, app: &App, area: Rect) {
         ));
     }
 
+    // Borders and one line per entry, up to the limit
+    let error_rows = app.error_log.len().clamp(1, ERROR_LOG_LIMIT) as u16;
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Min(12), Constraint::Length(error_rows + 2)])
+        .split(area);
+
     let paragraph = Paragraph::new(lines)
-        .block(
-            Block::default()
-                .borders(Borders::ALL)
-                .title("Server Status"),
-        )
+        .block(Block::default().borders(Borders::ALL).title(block_title(
+            app,
+            "Server Status",
+            "S: shut down, M: maintenance",
+            "",
+        )))
         .wrap(Wrap { trim: true });
 
-    f.render_widget(paragraph, area);
+    f.render_widget(paragraph, chunks[0]);
+    draw_error_log(f, app, chunks[1]);
+}
+
+/// Latest warnings and errors the server logged, newest first
+fn draw_error_log(f: &mut Frame, app: &App, area: Rect) {
+    let items: Vec<ListItem> = if app.error_log.is_empty() {
+        vec![ListItem::new(Line::styled(
+            "No warnings or errors logged",
+            Style::default().fg(Color::DarkGray),
+        ))]
+    } else {
+        app.error_log
+            .iter()
+            .map(|entry| {
+                let (label, color) = match entry.level {
+                    LogLevel::Error => ("ERROR", Color::Red),
+                    LogLevel::Warn => ("WARN ", Color::Yellow),
+                };
+                let seconds = entry.timestamp % 86_400;
+                ListItem::new(Line::from(vec![
+                    Span::styled(
+                        format!(
+                            "{:02}:{:02}:{:02} ",
+                            seconds / 3600,
+                            seconds / 60 % 60,
+                            seconds % 60
+                        ),
+                        Style::default().fg(Color::DarkGray),
+                    ),
+                    Span::styled(format!("{} ", label), Style::default().fg(color)),
+                    Span::styled(
+                        format!("{}: ", entry.target),
+                        Style::default().fg(Color::DarkGray),
+                    ),
+                    Span::styled(entry.message.as_str(), Style::default().fg(color)),
+                ]))
+            })
+            .collect()
+    };
+
+    let list = List::new(items).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title("Recent Warnings and Errors (UTC, newest first)"),
+    );
+    f.render_widget(list, area);
 }
 
 /// Draw routes tab
 fn draw_routes(f: &mut Frame, app: &App, area: Rect) {
-    let header_cells = ["Path", "Methods", "Upstream", "Timeout"].iter().map(|h| {
+    let header_cells = [
+        "Path", "Methods", "Upstream", "Timeout", "Req/min", "Errors",
+    ]
+    .iter()
+    .map(|h| {
         Cell::from(*h).style(
             Style::default()
                 .fg(Color::Yellow)
@@ -173,17 +370,51 @@ fn draw_routes(f: &mut Frame, app: &App, area: Rect) {
                     route.methods.join(", ")
                 };
 
-                let style = if i == app.selected_route {
-                    Style::default().bg(Color::DarkGray)
+                let marked = app.route_marked(i, &route.path);
+                let selected = i == app.selected_route;
+                let style = match (selected, marked) {
+                    (true, _) => Style::default().bg(Color::DarkGray),
+                    (false, true) => Style::default().fg(Color::Cyan),
+                    (false, false) => Style::default(),
+                };
+                let style = dim_if_disabled(style, route.enabled, selected);
+
+                // Mark routes that also match on request headers or the
+                // query
+                let mut path = route.path.clone();
+                if !route.match_headers.is_empty() {
+                    path.push_str(" [H]");
+                }
+                if !route.query_match.is_empty() {
+                    path.push_str(" [Q]");
+                }
+                if app.in_maintenance(&MaintenanceScope::Route(route.path.clone())) {
+                    path.push_str(" [M]");
+                }
+                let path = if marked { format!("* {}", path) } else { path };
+
+                let rate = app
+                    .route_rates
+                    .get(&route.path)
+                    .map_or_else(|| "-".to_string(), |rate| format!("{:.1}", rate));
+                let errors = app
+                    .stats
+                    .as_ref()
+                    .and_then(|stats| stats.route_stats.get(&route.path))
+                    .map_or(0, |stats| stats.errors);
+                let errors = if errors > 0 {
+                    Cell::from(errors.to_string()).style(Style::default().fg(Color::Red))
                 } else {
-                    Style::default()
+                    Cell::from("0")
                 };
 
                 Row::new(vec![
-                    Cell::from(route.path.clone()),
+                    Cell::from(path),
                     Cell::from(methods),
                     Cell::from(route.upstream.clone()),
                     Cell::from(format!("{}s", route.timeout)),
+                    Cell::from(rate),
+                    errors,
                 ])
                 .style(style)
             })
@@ -195,20 +426,205 @@ fn draw_routes(f: &mut Frame, app: &App, area: Rect) {
     let table = Table::new(
         rows,
         [
-            Constraint::Percentage(30),
+            Constraint::Percentage(25),
             Constraint::Percentage(20),
-            Constraint::Percentage(30),
             Constraint::Percentage(20),
+            Constraint::Percentage(10),
+            Constraint::Percentage(15),
+            Constraint::Percentage(10),
         ],
     )
     .header(header)
-    .block(
-        Block::default()
-            .borders(Borders::ALL)
-            .title("Routes (a: add, d: delete, j/k: navigate)"),
-    );
+    .block(Block::default().borders(Borders::ALL).title(block_title(
+        app,
+        "Routes",
+        "a: add, d: delete, i: import, t: toggle, M: maintenance, ",
+        "space/v: mark, j/k: navigate",
+    )));
 
     f.render_widget(table, area);
+
+    if !app.is_editing() {
+        draw_route_tooltip(f, app, area);
+    }
+}
+
+/// Overlay the full details of the selected route at the bottom of the
+/// routes table, since long values are clipped in the cells
+fn draw_route_tooltip(f: &mut Frame, app: &App, area: Rect) {
+    let Some(route) = app
+        .config
+        .as_ref()
+        .and_then(|config| config.routes.get(app.selected_route))
+    else {
+        return;
+    };
+
+    let label = Style::default().fg(Color::Yellow);
+    let mut lines = vec![
+        Line::from(vec![Span::styled("path: ", label), Span::raw(&route.path)]),
+        Line::from(vec![
+            Span::styled("upstream: ", label),
+            Span::raw(&route.upstream),
+        ]),
+    ];
+    if let Some(ref rewrite) = route.rewrite {
+        lines.push(Line::from(vec![
+            Span::styled("rewrite: ", label),
+            Span::raw(rewrite),
+        ]));
+    }
+    let mut match_headers: Vec<_> = route.match_headers.iter().collect();
+    match_headers.sort();
+    for (name, pattern) in match_headers {
+        lines.push(Line::from(vec![
+            Span::styled(format!("match {}: ", name), label),
+            Span::raw(pattern),
+        ]));
+    }
+    let mut query_match: Vec<_> = route.query_match.iter().collect();
+    query_match.sort();
+    for (name, pattern) in query_match {
+        lines.push(Line::from(vec![
+            Span::styled(format!("match ?{}: ", name), label),
+            Span::raw(pattern),
+        ]));
+    }
+    let mut headers: Vec<_> = route.headers.iter().collect();
+    headers.sort();
+    for (name, value) in headers {
+        lines.push(Line::from(vec![
+            Span::styled(format!("{}: ", name), label),
+            Span::raw(value),
+        ]));
+    }
+
+    // Keep the table header and selected row visible above the tooltip
+    let height = lines.len() as u16 + 2;
+    let visible_rows = area.height.saturating_sub(3);
+    if visible_rows < height + app.selected_route as u16 + 1 {
+        return;
+    }
+
+    let tooltip_area = Rect::new(
+        area.x + 1,
+        area.y + area.height - 1 - height,
+        area.width.saturating_sub(2),
+        height,
+    );
+    f.render_widget(Clear, tooltip_area);
+    f.render_widget(
+        Paragraph::new(lines).wrap(Wrap { trim: false }).block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title("Route Details")
+                .border_style(Style::default().fg(Color::DarkGray)),
+        ),
+        tooltip_area,
+    );
+}
+
+/// Time left before a requested shutdown takes effect
+fn shutdown_countdown(app: &App) -> Option<String> {
+    let at = app.shutdown_at?;
+    let left = at.saturating_duration_since(std::time::Instant::now());
+    Some(format!(
+        "Shutting down in {}s",
+        left.as_secs_f64().ceil() as u64
+    ))
+}
+
+/// Certificate from the ACME CA: its domains, expiry and last renewal
+fn acme_lines(acme: &AcmeStatus) -> Vec<Line<'_>> {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+
+    let expiry = match acme.expires_at {
+        Some(at) if at > now => Span::styled(
+            format!("in {}", format_duration(at - now)),
+            Style::default().fg(Color::Green),
+        ),
+        Some(_) => Span::styled("expired", Style::default().fg(Color::Red)),
+        None => Span::styled("no certificate yet", Style::default().fg(Color::Yellow)),
+    };
+    let mut lines = vec![
+        Line::raw(""),
+        Line::from(vec![
+            Span::raw("Certificate: "),
+            Span::styled(acme.domains.join(", "), Style::default().fg(Color::Cyan)),
+        ]),
+        Line::from(vec![Span::raw("Certificate Expires: "), expiry]),
+    ];
+    if let Some(at) = acme.last_attempt {
+        lines.push(Line::from(vec![
+            Span::raw("Last Renewal Attempt: "),
+            Span::styled(
+                format!("{} ago", format_duration(now.saturating_sub(at))),
+                Style::default().fg(Color::Blue),
+            ),
+        ]));
+    }
+    if let Some(ref error) = acme.last_error {
+        lines.push(Line::from(vec![
+            Span::raw("Last Renewal Error: "),
+            Span::styled(error.as_str(), Style::default().fg(Color::Red)),
+        ]));
+    }
+    lines
+}
+
+/// Dim the row of a disabled route or static directory, keeping it
+/// readable on the selection highlight
+fn dim_if_disabled(style: Style, enabled: bool, selected: bool) -> Style {
+    match (enabled, selected) {
+        (true, _) => style,
+        (false, true) => style.add_modifier(Modifier::DIM),
+        (false, false) => style.fg(Color::DarkGray),
+    }
+}
+
+/// Block title listing key hints, leaving out editing keys when read-only
+fn block_title(app: &App, name: &str, edit_keys: &str, keys: &str) -> String {
+    let edit_keys = if app.can_edit() { edit_keys } else { "" };
+    if edit_keys.is_empty() && keys.is_empty() {
+        name.to_string()
+    } else {
+        format!("{} ({}{})", name, edit_keys, keys)
+    }
+}
+
+/// Draw live tab: the latest proxied requests, colored by status class
+fn draw_live(f: &mut Frame, app: &App, area: Rect) {
+    let items: Vec<ListItem> = app
+        .live_hits
+        .iter()
+        .map(|hit| {
+            let color = match hit.status {
+                200..=299 => Color::Green,
+                300..=399 => Color::Cyan,
+                400..=499 => Color::Yellow,
+                _ => Color::Red,
+            };
+            ListItem::new(Line::from(vec![
+                Span::styled(format!("{} ", hit.status), Style::default().fg(color)),
+                Span::raw(format!("{} -> {}", hit.path, hit.upstream)),
+            ]))
+        })
+        .collect();
+
+    let enabled = app
+        .config
+        .as_ref()
+        .is_some_and(|c| c.management.live_requests);
+    let title = if enabled {
+        format!("Live Requests (last {}, newest first)", MAX_LIVE_HITS)
+    } else {
+        "Live Requests (off: set management.live_requests = true)".to_string()
+    };
+
+    let list = List::new(items).block(Block::default().borders(Borders::ALL).title(title));
+    f.render_widget(list, area);
 }
 
 /// Draw upstreams tab
@@ -219,12 +635,23 @@ fn draw_upstreams(f: &mut Frame, app: &App, area: Rect) {
             .iter()
             .enumerate()
             .map(|(i, (name, upstream))| {
-                let servers = upstream
-                    .servers
-                    .iter()
-                    .map(|s| format!("{} (weight: {})", s.address, s.weight))
-                    .collect::<Vec<_>>()
-                    .join(", ");
+                // Draining servers stand out, as they take no new requests
+                let mut servers = vec![Span::raw("  Servers: ")];
+                for (j, s) in upstream.servers.iter().enumerate() {
+                    if j > 0 {
+                        servers.push(Span::raw(", "));
+                    }
+                    servers.push(Span::styled(
+                        format!("{} (weight: {})", s.address, s.weight),
+                        Style::default().fg(Color::White),
+                    ));
+                    if s.drain {
+                        servers.push(Span::styled(
+                            " [draining]",
+                            Style::default().fg(Color::Yellow),
+                        ));
+                    }
+                }
 
                 let style = if i == app.selected_upstream {
                     Style::default().bg(Color::DarkGray)
@@ -239,10 +666,7 @@ fn draw_upstreams(f: &mut Frame, app: &App, area: Rect) {
                             .fg(Color::Cyan)
                             .add_modifier(Modifier::BOLD),
                     )]),
-                    Line::from(vec![
-                        Span::raw("  Servers: "),
-                        Span::styled(servers, Style::default().fg(Color::White)),
-                    ]),
+                    Line::from(servers),
                     Line::from(vec![
                         Span::raw("  Load Balancing: "),
                         Span::styled(
@@ -250,6 +674,19 @@ fn draw_upstreams(f: &mut Frame, app: &App, area: Rect) {
                             Style::default().fg(Color::Yellow),
                         ),
                     ]),
+                    Line::from(vec![
+                        Span::raw("  Health Check: "),
+                        match upstream.health_check {
+                            Some(ref check) => Span::styled(
+                                format!(
+                                    "{} every {}s, timeout {}s",
+                                    check.path, check.interval, check.timeout
+                                ),
+                                Style::default().fg(Color::Green),
+                            ),
+                            None => Span::styled("off", Style::default().fg(Color::DarkGray)),
+                        },
+                    ]),
                 ])
                 .style(style)
             })
@@ -258,11 +695,12 @@ fn draw_upstreams(f: &mut Frame, app: &App, area: Rect) {
         vec![]
     };
 
-    let list = List::new(items).block(
-        Block::default()
-            .borders(Borders::ALL)
-            .title("Upstreams (a: add, d: delete, j/k: navigate)"),
-    );
+    let list = List::new(items).block(Block::default().borders(Borders::ALL).title(block_title(
+        app,
+        "Upstreams",
+        "a: add, d: delete, D: drain, h: health check, ",
+        "j/k: navigate",
+    )));
 
     f.render_widget(list, area);
 }
@@ -321,12 +759,14 @@ fn draw_upstream_selector(f: &mut Frame, app: &App) {
 
 /// Draw config tab
 fn draw_config(f: &mut Frame, app: &App, area: Rect) {
-    // Split into two sections: server settings and static directories
+    // Server settings, static directories and recent changes
+    let audit_rows = app.audit_log.len().clamp(1, AUDIT_LOG_LIMIT) as u16;
     let chunks = Layout::default()
         .direction(Direction::Vertical)
         .constraints([
-            Constraint::Length(8), // Server settings
-            Constraint::Min(5),    // Static directories
+            Constraint::Length(8),              // Server settings
+            Constraint::Min(5),                 // Static directories
+            Constraint::Length(audit_rows + 2), // Recent changes
         ])
         .split(area);
 
@@ -364,10 +804,15 @@ fn draw_config(f: &mut Frame, app: &App, area: Rect) {
             Line::from(vec![
                 Span::raw("  Access Log: "),
                 Span::styled(
-                    if config.server.access_log {
-                        "enabled"
+                    if !config.server.access_log {
+                        "disabled".to_string()
+                    } else if config.server.access_log_sample_rate < 1.0 {
+                        format!(
+                            "sampled {:.1}%",
+                            config.server.access_log_sample_rate * 100.0
+                        )
                     } else {
-                        "disabled"
+                        "enabled".to_string()
                     },
                     Style::default().fg(Color::Green),
                 ),
@@ -380,11 +825,10 @@ fn draw_config(f: &mut Frame, app: &App, area: Rect) {
         ));
     }
 
-    let server_para = Paragraph::new(server_lines).block(
-        Block::default()
-            .borders(Borders::ALL)
-            .title("Server (p: edit port, R: reload config)"),
-    );
+    let server_para =
+        Paragraph::new(server_lines).block(Block::default().borders(Borders::ALL).title(
+            block_title(app, "Server", "p: edit port, R: reload config", ""),
+        ));
     f.render_widget(server_para, chunks[0]);
 
     // Static directories
@@ -405,16 +849,18 @@ fn draw_config(f: &mut Frame, app: &App, area: Rect) {
             .iter()
             .enumerate()
             .map(|(i, sf)| {
-                let style = if i == app.selected_static_dir {
+                let selected = i == app.selected_static_dir;
+                let style = if selected {
                     Style::default().bg(Color::DarkGray)
                 } else {
                     Style::default()
                 };
+                let style = dim_if_disabled(style, sf.enabled, selected);
 
                 Row::new(vec![
                     Cell::from(sf.path.clone()),
                     Cell::from(sf.root.clone()),
-                    Cell::from(sf.index.clone()),
+                    Cell::from(sf.index.join(", ")),
                     Cell::from(if sf.directory_listing { "Yes" } else { "No" }),
                 ])
                 .style(style)
@@ -434,13 +880,90 @@ fn draw_config(f: &mut Frame, app: &App, area: Rect) {
         ],
     )
     .header(header)
-    .block(
+    .block(Block::default().borders(Borders::ALL).title(block_title(
+        app,
+        "Static Directories",
+        "a: add, e: edit, d: delete, t: toggle, ",
+        "j/k: navigate",
+    )));
+
+    f.render_widget(table, chunks[1]);
+    draw_audit_log(f, app, chunks[2]);
+}
+
+/// Latest entries of the server's audit log, newest first
+fn draw_audit_log(f: &mut Frame, app: &App, area: Rect) {
+    let audited = app
+        .config
+        .as_ref()
+        .is_some_and(|config| config.management.audit_log.is_some());
+    let items: Vec<ListItem> = if !audited {
+        vec![ListItem::new(Line::styled(
+            "Off: set management.audit_log to record changes",
+            Style::default().fg(Color::DarkGray),
+        ))]
+    } else if app.audit_log.is_empty() {
+        vec![ListItem::new(Line::styled(
+            "No changes recorded",
+            Style::default().fg(Color::DarkGray),
+        ))]
+    } else {
+        app.audit_log
+            .iter()
+            .map(|entry| {
+                let seconds = entry.timestamp % 86_400;
+                let who = match entry.source {
+                    AuditSource::File => "config file".to_string(),
+                    AuditSource::Management => {
+                        let name = entry.token.as_deref().unwrap_or(match entry.role {
+                            Some(ManagementRole::Admin) => "admin",
+                            Some(ManagementRole::Read) => "read",
+                            None => "anonymous",
+                        });
+                        match entry.peer {
+                            Some(ref peer) => format!("{} ({})", name, peer),
+                            None => name.to_string(),
+                        }
+                    }
+                };
+                let mut spans = vec![
+                    Span::styled(
+                        format!(
+                            "{:02}:{:02}:{:02} ",
+                            seconds / 3600,
+                            seconds / 60 % 60,
+                            seconds % 60
+                        ),
+                        Style::default().fg(Color::DarkGray),
+                    ),
+                    Span::styled(
+                        format!("{} ", entry.operation),
+                        Style::default().fg(if entry.success {
+                            Color::Green
+                        } else {
+                            Color::Red
+                        }),
+                    ),
+                    Span::raw(format!("{} ", entry.summary)),
+                    Span::styled(format!("by {}", who), Style::default().fg(Color::DarkGray)),
+                ];
+                if let Some(ref error) = entry.error {
+                    spans.push(Span::styled(
+                        format!(": {}", error),
+                        Style::default().fg(Color::Red),
+                    ));
+                }
+                ListItem::new(Line::from(spans))
+            })
+            .collect()
+    };
+
+    let list = List::new(items).block(
         Block::default()
             .borders(Borders::ALL)
-            .title("Static Directories (a: add, d: delete, j/k: navigate)"),
+            .title("Recent Changes"),
     );
-
-    f.render_widget(table, chunks[1]);
+    f.render_widget(list, area);
 }
 
 /// Draw stats tab
@@ -448,6 +971,21 @@ fn draw_stats(f: &mut Frame, app: &App, area: Rect) {
     let mut lines = vec![];
 
     if let Some(ref stats) = app.stats {
+        // Rates and totals count from here, not from server startup
+        if let Some(since) = stats
+            .since
+            .and_then(|secs| chrono::DateTime::from_timestamp(secs as i64, 0))
+        {
+            lines.push(Line::styled(
+                format!(
+                    "Stats since {}",
+                    since
+                        .with_timezone(&chrono::Local)
+                        .format("%Y-%m-%d %H:%M:%S")
+                ),
+                Style::default().fg(Color::DarkGray),
+            ));
+        }
         lines.extend(vec![
             Line::from(vec![
                 Span::raw("Total Requests: "),
@@ -510,7 +1048,105 @@ fn draw_stats(f: &mut Frame, app: &App, area: Rect) {
                     Style::default().fg(Color::Red),
                 ),
             ]),
+            Line::from(vec![
+                Span::raw("Total Connections: "),
+                Span::styled(
+                    stats.total_connections.to_string(),
+                    Style::default().fg(Color::Green),
+                ),
+            ]),
+            Line::from(vec![
+                Span::raw("Unlogged (Sampled Out): "),
+                Span::styled(
+                    stats.access_log_sampled_out.to_string(),
+                    Style::default().fg(Color::DarkGray),
+                ),
+            ]),
+            Line::from(vec![
+                Span::raw("Matched Route / Default: "),
+                Span::styled(
+                    format!("{} / {}", stats.route_matches, stats.default_matches),
+                    Style::default().fg(Color::Cyan),
+                ),
+            ]),
+            Line::raw(""),
+            Line::from(vec![
+                Span::raw("Error Rate (5xx): "),
+                error_rate_span(stats.error_rate),
+            ]),
+            Line::from(vec![
+                Span::raw("Client Error Rate (4xx): "),
+                error_rate_span(stats.client_error_rate),
+            ]),
         ]);
+
+        if stats.spooled_requests > 0 {
+            lines.push(Line::from(vec![
+                Span::raw("Spooled Bodies: "),
+                Span::styled(
+                    format!(
+                        "{} ({} on disk)",
+                        stats.spooled_requests,
+                        format_bytes(stats.spooled_bytes)
+                    ),
+                    Style::default().fg(Color::Cyan),
+                ),
+            ]));
+        }
+
+        // Usually scanners, so only shown once there are some
+        if stats.uri_too_long > 0 || stats.headers_too_large > 0 {
+            lines.push(Line::from(vec![
+                Span::raw("Oversized (URI / Headers): "),
+                Span::styled(
+                    format!("{} / {}", stats.uri_too_long, stats.headers_too_large),
+                    Style::default().fg(Color::Yellow),
+                ),
+            ]));
+        }
+
+        // Busiest failure classes first
+        let mut proxy_errors: Vec<_> = stats.proxy_errors.iter().collect();
+        proxy_errors.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        if !proxy_errors.is_empty() {
+            lines.push(Line::raw(""));
+            lines.push(Line::styled(
+                "Proxy Errors:",
+                Style::default().add_modifier(Modifier::BOLD),
+            ));
+            for (kind, count) in proxy_errors {
+                lines.push(Line::from(vec![
+                    Span::raw(format!("  {}: ", kind)),
+                    Span::styled(count.to_string(), Style::default().fg(Color::Red)),
+                ]));
+            }
+        }
+
+        let mut auth_requests: Vec<_> = stats.auth_requests.iter().collect();
+        auth_requests.sort_by(|a, b| a.0.cmp(b.0));
+        if !auth_requests.is_empty() {
+            lines.push(Line::raw(""));
+            lines.push(Line::styled(
+                "Auth Subrequests:",
+                Style::default().add_modifier(Modifier::BOLD),
+            ));
+            for (route, auth) in auth_requests {
+                lines.push(Line::from(vec![
+                    Span::raw(format!("  {}: ", route)),
+                    Span::styled(
+                        format!(
+                            "{} ({} denied, {} failed), avg {:.1} ms, max {:.1} ms",
+                            auth.requests,
+                            auth.denied,
+                            auth.errors,
+                            auth.avg_latency_ms,
+                            auth.max_latency_ms
+                        ),
+                        Style::default().fg(Color::Cyan),
+                    ),
+                ]));
+            }
+        }
     } else {
         lines.push(Line::styled(
             "No statistics available",
@@ -518,49 +1154,509 @@ fn draw_stats(f: &mut Frame, app: &App, area: Rect) {
         ));
     }
 
+    // Header, borders and one line per upstream, up to eight
+    let upstream_rows = app.upstream_stats().len().clamp(1, 8) as u16;
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Min(10),
+            Constraint::Length(upstream_rows + 3),
+            Constraint::Length(14),
+        ])
+        .split(area);
+
     let paragraph = Paragraph::new(lines)
         .block(Block::default().borders(Borders::ALL).title("Statistics"))
         .wrap(Wrap { trim: true });
 
-    f.render_widget(paragraph, area);
+    let top = Layout::horizontal([Constraint::Percentage(60), Constraint::Percentage(40)])
+        .split(chunks[0]);
+    f.render_widget(paragraph, top[0]);
+    draw_top_clients(f, app, top[1]);
+    draw_upstream_stats(f, app, chunks[1]);
+
+    let bottom = Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)])
+        .split(chunks[2]);
+    draw_slowest_routes(f, app, bottom[0]);
+    draw_busiest_routes(f, app, bottom[1]);
 }
 
-/// Draw help tab
-fn draw_help(f: &mut Frame, area: Rect) {
-    let help_text = vec![
-        Line::styled("Navigation", Style::default().add_modifier(Modifier::BOLD)),
-        Line::raw("  Tab / Shift+Tab - Switch between tabs"),
-        Line::raw("  1-6            - Jump to specific tab"),
-        Line::raw("  j/k or ↑/↓     - Navigate lists"),
-        Line::raw(""),
-        Line::styled(
-            "Global Actions",
-            Style::default().add_modifier(Modifier::BOLD),
-        ),
-        Line::raw("  r              - Refresh data from server"),
-        Line::raw("  R              - Reload server configuration from file"),
-        Line::raw("  c              - Reconnect to server"),
-        Line::raw("  q              - Quit"),
-        Line::raw(""),
-        Line::styled("Routes Tab", Style::default().add_modifier(Modifier::BOLD)),
-        Line::raw("  a              - Add new route (select upstream from list)"),
-        Line::raw("  d / Delete     - Delete selected route"),
-        Line::raw(""),
-        Line::styled("Upstreams Tab", Style::default().add_modifier(Modifier::BOLD)),
-        Line::raw("  a              - Add new upstream"),
-        Line::raw("  d / Delete     - Delete selected upstream"),
-        Line::raw(""),
-        Line::styled("Config Tab", Style::default().add_modifier(Modifier::BOLD)),
-        Line::raw("  p              - Edit server port"),
-        Line::raw("  a              - Add static directory"),
-        Line::raw("  d / Delete     - Delete selected static directory"),
-        Line::raw(""),
-        Line::styled("Editing", Style::default().add_modifier(Modifier::BOLD)),
-        Line::raw("  Enter          - Confirm input/selection"),
-        Line::raw("  Esc            - Cancel editing"),
-        Line::raw(""),
-        Line::styled("Notes", Style::default().add_modifier(Modifier::BOLD)),
-        Line::raw("  Port changes require server restart to take effect."),
+/// Table of the routes with the most requests
+fn draw_busiest_routes(f: &mut Frame, app: &App, area: Rect) {
+    let header_cells = ["Route", "Requests"].iter().map(|h| {
+        Cell::from(*h).style(
+            Style::default()
+                .fg(Color::Yellow)
+                .add_modifier(Modifier::BOLD),
+        )
+    });
+    let header = Row::new(header_cells).height(1);
+
+    let rows: Vec<Row> = app
+        .stats
+        .iter()
+        .flat_map(|stats| &stats.top_routes_by_traffic)
+        .map(|(route, requests)| {
+            Row::new(vec![
+                Cell::from(route.clone()),
+                Cell::from(requests.to_string()),
+            ])
+        })
+        .collect();
+
+    let table = Table::new(
+        rows,
+        [Constraint::Percentage(75), Constraint::Percentage(25)],
+    )
+    .header(header)
+    .block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title("Busiest Routes"),
+    );
+
+    f.render_widget(table, area);
+}
+
+/// Table of the routes with the highest P99 latency
+fn draw_slowest_routes(f: &mut Frame, app: &App, area: Rect) {
+    let header_cells = ["Route", "P99 ms"].iter().map(|h| {
+        Cell::from(*h).style(
+            Style::default()
+                .fg(Color::Yellow)
+                .add_modifier(Modifier::BOLD),
+        )
+    });
+    let header = Row::new(header_cells).height(1);
+
+    let rows: Vec<Row> = app
+        .stats
+        .iter()
+        .flat_map(|stats| &stats.slowest_routes)
+        .map(|(route, p99_ms)| {
+            let latency = if *p99_ms > app.slow_route_ms {
+                Style::default().fg(Color::Red)
+            } else {
+                Style::default()
+            };
+            Row::new(vec![
+                Cell::from(route.clone()),
+                Cell::from(format!("{:.1}", p99_ms)).style(latency),
+            ])
+        })
+        .collect();
+
+    let table = Table::new(
+        rows,
+        [Constraint::Percentage(75), Constraint::Percentage(25)],
+    )
+    .header(header)
+    .block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title("Slowest Routes (P99)"),
+    );
+
+    f.render_widget(table, area);
+}
+
+/// Table of traffic per upstream, busiest first
+fn draw_upstream_stats(f: &mut Frame, app: &App, area: Rect) {
+    let header_cells = [
+        "Upstream",
+        "Requests",
+        "Failures",
+        "Failure %",
+        "Retries",
+        "Avg ms",
+        "Healthy",
+        "Protocol Errors",
+        "Connections",
+    ]
+    .iter()
+    .map(|h| {
+        Cell::from(*h).style(
+            Style::default()
+                .fg(Color::Yellow)
+                .add_modifier(Modifier::BOLD),
+        )
+    });
+    let header = Row::new(header_cells).height(1);
+
+    let rows: Vec<Row> = app
+        .upstream_stats()
+        .into_iter()
+        .map(|(name, upstream)| {
+            let failure_rate = if upstream.requests > 0 {
+                upstream.failures as f64 / upstream.requests as f64
+            } else {
+                0.0
+            };
+            let failure_color = if failure_rate > app.failure_rate_alert {
+                Color::Red
+            } else if failure_rate > app.failure_rate_warn {
+                Color::Yellow
+            } else {
+                Color::Green
+            };
+            // Requests to an upstream with no available backends are
+            // refused outright
+            let unavailable = upstream.healthy_servers == 0;
+            let healthy = if unavailable {
+                Style::default().fg(Color::Red).add_modifier(Modifier::BOLD)
+            } else if upstream.healthy_servers < upstream.total_servers {
+                Style::default().fg(Color::Yellow)
+            } else {
+                Style::default()
+            };
+            // At the limit, further requests queue
+            let connections = match upstream.max_connections {
+                Some(max) => Cell::from(format!(
+                    "{}/{} (peak {})",
+                    upstream.active_connections, max, upstream.peak_connections
+                ))
+                .style(if upstream.active_connections >= max {
+                    Style::default().fg(Color::Yellow)
+                } else {
+                    Style::default()
+                }),
+                None => Cell::from("-"),
+            };
+            Row::new(vec![
+                Cell::from(name.to_string()).style(if unavailable {
+                    Style::default().fg(Color::Red)
+                } else {
+                    Style::default()
+                }),
+                Cell::from(upstream.requests.to_string()),
+                Cell::from(upstream.failures.to_string()),
+                Cell::from(format!("{:.2}%", failure_rate * 100.0))
+                    .style(Style::default().fg(failure_color)),
+                Cell::from(upstream.retries.to_string()),
+                Cell::from(format!("{:.1}", upstream.avg_response_time_ms)),
+                Cell::from(format!(
+                    "{}/{}",
+                    upstream.healthy_servers, upstream.total_servers
+                ))
+                .style(healthy),
+                Cell::from(upstream.upstream_protocol_errors.to_string()),
+                connections,
+            ])
+        })
+        .collect();
+
+    let table = Table::new(
+        rows,
+        [
+            Constraint::Percentage(16),
+            Constraint::Percentage(10),
+            Constraint::Percentage(9),
+            Constraint::Percentage(10),
+            Constraint::Percentage(8),
+            Constraint::Percentage(9),
+            Constraint::Percentage(8),
+            Constraint::Percentage(14),
+            Constraint::Percentage(16),
+        ],
+    )
+    .header(header)
+    .block(Block::default().borders(Borders::ALL).title("Upstreams"));
+
+    f.render_widget(table, area);
+}
+
+/// Table of the busiest client addresses
+fn draw_top_clients(f: &mut Frame, app: &App, area: Rect) {
+    let header_cells = ["Client", "Requests", "Bytes", "Errors"].iter().map(|h| {
+        Cell::from(*h).style(
+            Style::default()
+                .fg(Color::Yellow)
+                .add_modifier(Modifier::BOLD),
+        )
+    });
+    let header = Row::new(header_cells).height(1);
+
+    let rows: Vec<Row> = app
+        .top_clients
+        .iter()
+        .map(|client| {
+            let errors = if client.errors > 0 {
+                Style::default().fg(Color::Red)
+            } else {
+                Style::default()
+            };
+            Row::new(vec![
+                Cell::from(client.address.clone()),
+                Cell::from(client.requests.to_string()),
+                Cell::from(format_bytes(client.bytes)),
+                Cell::from(client.errors.to_string()).style(errors),
+            ])
+        })
+        .collect();
+
+    let table = Table::new(
+        rows,
+        [
+            Constraint::Percentage(40),
+            Constraint::Percentage(20),
+            Constraint::Percentage(20),
+            Constraint::Percentage(20),
+        ],
+    )
+    .header(header)
+    .block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title("Top Clients (recent traffic)"),
+    );
+
+    f.render_widget(table, area);
+}
+
+/// Format an error rate as a percentage, colored by severity
+fn error_rate_span(rate: f64) -> Span<'static> {
+    let color = if rate < 0.01 {
+        Color::Green
+    } else if rate <= 0.05 {
+        Color::Yellow
+    } else {
+        Color::Red
+    };
+    Span::styled(format!("{:.2}%", rate * 100.0), Style::default().fg(color))
+}
+
+/// Shortcuts listed in the `?` popup of each tab
+fn tab_shortcuts(tab: Tab) -> &'static [(&'static str, &'static str)] {
+    match tab {
+        Tab::Status => &[
+            ("S", "Shut down the server"),
+            ("M", "Toggle maintenance for every request"),
+            ("P", "Run preflight checks"),
+            ("R", "Reload configuration from file"),
+            ("r", "Refresh"),
+        ],
+        Tab::Routes => &[
+            ("j/k", "Select route"),
+            ("a", "Add route"),
+            ("d / Delete", "Delete selected or marked routes"),
+            ("Space", "Mark or unmark selected route"),
+            ("v", "Start a range, press again to mark it"),
+            ("Esc", "Cancel the range, or clear marks"),
+            ("i", "Import routes from a TOML file"),
+            ("t", "Enable or disable selected route"),
+            ("M", "Toggle maintenance for selected route"),
+            ("Enter", "Show traffic of selected route"),
+        ],
+        Tab::Upstreams => &[
+            ("j/k", "Select upstream"),
+            ("a", "Add upstream"),
+            ("d / Delete", "Delete selected upstream"),
+            ("D", "Drain selected upstream, then remove it"),
+            ("h", "Edit health check"),
+        ],
+        Tab::Config => &[
+            ("j/k", "Select static directory"),
+            ("p", "Edit server port"),
+            ("a", "Add static directory"),
+            ("e", "Edit root of selected static directory"),
+            ("d / Delete", "Delete selected static directory"),
+            ("t", "Enable or disable selected static directory"),
+        ],
+        Tab::Stats => &[("r", "Refresh statistics"), ("x", "Reset statistics")],
+        Tab::Live => &[
+            ("Tab", "Leave the feed for the next tab"),
+            ("m", "Focus the Messages panel"),
+        ],
+        Tab::Help => &[
+            ("Tab / Shift+Tab", "Switch between tabs"),
+            ("1-7", "Jump to a tab"),
+            ("m", "Focus the Messages panel"),
+            ("q", "Quit"),
+        ],
+    }
+}
+
+/// Draw the current tab's shortcuts over the rest of the screen
+fn draw_help_popup(f: &mut Frame, app: &App) {
+    let shortcuts = tab_shortcuts(app.current_tab);
+    let key_width = shortcuts
+        .iter()
+        .map(|(key, _)| key.len())
+        .max()
+        .unwrap_or(0);
+    let mut lines: Vec<Line> = shortcuts
+        .iter()
+        .map(|(key, action)| {
+            Line::from(vec![
+                Span::styled(
+                    format!(" {:width$}  ", key, width = key_width),
+                    Style::default().fg(Color::Yellow),
+                ),
+                Span::raw(*action),
+            ])
+        })
+        .collect();
+    lines.push(Line::raw(""));
+    lines.push(Line::styled(
+        " Press any key to close",
+        Style::default().fg(Color::DarkGray),
+    ));
+
+    let area = f.size();
+    let width = 60.min(area.width.saturating_sub(4));
+    let height = (lines.len() as u16 + 2).min(area.height.saturating_sub(4));
+    let popup_area = Rect::new(
+        area.width.saturating_sub(width) / 2,
+        area.height.saturating_sub(height) / 2,
+        width,
+        height,
+    );
+
+    f.render_widget(Clear, popup_area);
+    let paragraph = Paragraph::new(lines).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title(format!("{} Shortcuts", app.current_tab.name()))
+            .border_style(Style::default().fg(Color::Yellow)),
+    );
+    f.render_widget(paragraph, popup_area);
+}
+
+/// Draw the selected route's latency and traffic over the rest of the
+/// screen
+fn draw_route_detail_popup(f: &mut Frame, app: &App) {
+    let path = app.route_detail_path.as_deref().unwrap_or_default();
+    let field = |name: &str, value: String, color: Color| {
+        Line::from(vec![
+            Span::raw(format!(" {:<16}", name)),
+            Span::styled(value, Style::default().fg(color)),
+        ])
+    };
+    let mut lines = match app.route_detail {
+        Some(ref detail) => {
+            let p99_color = if detail.p99_ms > app.slow_route_ms {
+                Color::Red
+            } else {
+                Color::Green
+            };
+            vec![
+                field("Requests", detail.requests.to_string(), Color::Green),
+                Line::from(vec![
+                    Span::raw(format!(" {:<16}", "Error Rate")),
+                    error_rate_span(detail.error_rate),
+                ]),
+                Line::raw(""),
+                field("P50", format!("{:.1} ms", detail.p50_ms), Color::Cyan),
+                field("P95", format!("{:.1} ms", detail.p95_ms), Color::Cyan),
+                field("P99", format!("{:.1} ms", detail.p99_ms), p99_color),
+                field("Max", format!("{:.1} ms", detail.max_ms), Color::Cyan),
+                Line::raw(""),
+                field(
+                    "Bytes Received",
+                    format_bytes(detail.bytes_received),
+                    Color::Cyan,
+                ),
+                field("Bytes Sent", format_bytes(detail.bytes_sent), Color::Cyan),
+                field(
+                    "Last Request",
+                    detail
+                        .last_request
+                        .clone()
+                        .unwrap_or_else(|| "never".to_string()),
+                    Color::Yellow,
+                ),
+            ]
+        }
+        None => vec![Line::styled(
+            " Loading...",
+            Style::default().fg(Color::DarkGray),
+        )],
+    };
+    lines.push(Line::raw(""));
+    lines.push(Line::styled(
+        " Press any key to close",
+        Style::default().fg(Color::DarkGray),
+    ));
+
+    let area = f.size();
+    let width = 50.min(area.width.saturating_sub(4));
+    let height = (lines.len() as u16 + 2).min(area.height.saturating_sub(4));
+    let popup_area = Rect::new(
+        area.width.saturating_sub(width) / 2,
+        area.height.saturating_sub(height) / 2,
+        width,
+        height,
+    );
+
+    f.render_widget(Clear, popup_area);
+    let paragraph = Paragraph::new(lines).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title(format!("Route {}", path))
+            .border_style(Style::default().fg(Color::Yellow)),
+    );
+    f.render_widget(paragraph, popup_area);
+}
+
+/// Draw help tab
+fn draw_help(f: &mut Frame, area: Rect) {
+    let help_text = vec![
+        Line::styled("Navigation", Style::default().add_modifier(Modifier::BOLD)),
+        Line::raw("  Tab / Shift+Tab - Switch between tabs"),
+        Line::raw("  1-7            - Jump to specific tab"),
+        Line::raw("  j/k or ↑/↓     - Navigate lists"),
+        Line::raw("  ?              - Show the current tab's shortcuts"),
+        Line::raw(""),
+        Line::styled(
+            "Global Actions",
+            Style::default().add_modifier(Modifier::BOLD),
+        ),
+        Line::raw("  r              - Refresh data from server"),
+        Line::raw("  R              - Reload server configuration from file"),
+        Line::raw("  P              - Run preflight checks on the server"),
+        Line::raw("  c              - Reconnect now (retried automatically while disconnected)"),
+        Line::raw("  S              - Shut down the server (Status tab, asks for a delay)"),
+        Line::raw("  x              - Reset statistics (Stats tab, asks to confirm)"),
+        Line::raw("  M              - Toggle maintenance for every request (Status tab)"),
+        Line::raw("  q              - Quit"),
+        Line::raw(""),
+        Line::styled("Messages", Style::default().add_modifier(Modifier::BOLD)),
+        Line::raw("  m              - Focus/unfocus the Messages panel"),
+        Line::raw("  K / J          - Scroll to older/newer messages when focused"),
+        Line::raw(""),
+        Line::styled("Routes Tab", Style::default().add_modifier(Modifier::BOLD)),
+        Line::raw("  a              - Add new route (select upstream from list)"),
+        Line::raw("  d / Delete     - Delete selected route, or all marked routes"),
+        Line::raw("  Space          - Mark or unmark selected route"),
+        Line::raw("  v              - Start a range, press again to mark it"),
+        Line::raw("  Esc            - Cancel the range, or clear marks"),
+        Line::raw("  i              - Import routes from a TOML file"),
+        Line::raw("  t              - Enable or disable selected route (disabled ones are dimmed)"),
+        Line::raw("  M              - Toggle maintenance for selected route"),
+        Line::raw("  Enter          - Show traffic of selected route"),
+        Line::raw(""),
+        Line::styled(
+            "Upstreams Tab",
+            Style::default().add_modifier(Modifier::BOLD),
+        ),
+        Line::raw("  a              - Add new upstream"),
+        Line::raw("  d / Delete     - Delete selected upstream"),
+        Line::raw("  D              - Drain selected upstream, then remove it"),
+        Line::raw("  h              - Edit health check of selected upstream"),
+        Line::raw(""),
+        Line::styled("Config Tab", Style::default().add_modifier(Modifier::BOLD)),
+        Line::raw("  p              - Edit server port"),
+        Line::raw("  a              - Add static directory"),
+        Line::raw("  e              - Edit root of selected static directory"),
+        Line::raw("  d / Delete     - Delete selected static directory"),
+        Line::raw("  t              - Enable or disable selected static directory"),
+        Line::raw(""),
+        Line::styled("Editing", Style::default().add_modifier(Modifier::BOLD)),
+        Line::raw("  Enter          - Confirm input/selection"),
+        Line::raw("  Esc            - Cancel editing"),
+        Line::raw(""),
+        Line::styled("Notes", Style::default().add_modifier(Modifier::BOLD)),
+        Line::raw("  Port changes require server restart to take effect."),
         Line::raw("  Route, upstream, and static directory changes apply immediately."),
     ];
 
@@ -573,22 +1669,66 @@ fn draw_help(f: &mut Frame, area: Rect) {
 
 /// Draw status messages
 fn draw_messages(f: &mut Frame, app: &App, area: Rect) {
+    let scroll = app
+        .messages_scroll
+        .min(app.messages.len().saturating_sub(app.messages_visible));
     let items: Vec<ListItem> = app
         .messages
         .iter()
         .rev()
-        .take(3)
+        .skip(scroll)
+        .take(app.messages_visible)
         .map(|msg| {
-            let style = if msg.is_error {
-                Style::default().fg(Color::Red)
-            } else {
-                Style::default().fg(Color::Green)
+            let color = match msg.diff {
+                Some(DiffKind::Added) => Color::Green,
+                Some(DiffKind::Removed) => Color::Red,
+                Some(DiffKind::Changed) => Color::Yellow,
+                None if msg.is_error => Color::Red,
+                None if msg.is_warning => Color::Yellow,
+                None => Color::Green,
             };
-            ListItem::new(Line::styled(&msg.text, style))
+            let style = Style::default().fg(color);
+            let mut spans = Vec::new();
+            if let Some(ref field) = msg.field {
+                spans.push(Span::styled(
+                    format!("{}: ", field),
+                    Style::default()
+                        .fg(Color::Yellow)
+                        .add_modifier(Modifier::BOLD),
+                ));
+            }
+            spans.push(Span::styled(msg.text.as_str(), style));
+            ListItem::new(Line::from(spans))
         })
         .collect();
 
-    let list = List::new(items).block(Block::default().borders(Borders::ALL).title("Messages"));
+    // Newest messages are at the top; show where the view sits when
+    // there is more history than fits
+    let total = app.messages.len();
+    let title = if total > app.messages_visible {
+        let last = (scroll + app.messages_visible).min(total);
+        let more = match (scroll > 0, last < total) {
+            (true, true) => " ↑↓",
+            (true, false) => " ↑",
+            (false, true) => " ↓",
+            (false, false) => "",
+        };
+        format!("Messages ({}-{} of {}){}", scroll + 1, last, total, more)
+    } else {
+        "Messages".to_string()
+    };
+    let border_style = if app.focused_panel == Panel::Messages {
+        Style::default().fg(Color::Yellow)
+    } else {
+        Style::default()
+    };
+
+    let list = List::new(items).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title(title)
+            .border_style(border_style),
+    );
 
     f.render_widget(list, area);
 }
@@ -611,38 +1751,131 @@ fn draw_footer(f: &mut Frame, app: &App, area: Rect) {
                     } else {
                         Style::default().fg(Color::White)
                     };
-                    vec![
-                        Span::styled(format!(" {} ", opt), style),
-                        Span::raw(" "),
-                    ]
+                    vec![Span::styled(format!(" {} ", opt), style), Span::raw(" ")]
                 })
                 .collect();
-            
+
             Paragraph::new(Line::from(
-                std::iter::once(Span::styled(app.get_edit_prompt(), Style::default().fg(Color::Yellow)))
-                    .chain(options_display)
-                    .collect::<Vec<_>>()
+                std::iter::once(Span::styled(
+                    app.get_edit_prompt(),
+                    Style::default().fg(Color::Yellow),
+                ))
+                .chain(options_display)
+                .collect::<Vec<_>>(),
             ))
         } else {
-            Paragraph::new(format!("{}{}_", app.get_edit_prompt(), app.input_buffer))
-                .style(Style::default().fg(Color::Yellow))
+            let mut spans = vec![Span::raw(format!(
+                "{}{}_",
+                app.get_edit_prompt(),
+                app.input_buffer
+            ))];
+            if let Some(problem) = app.edit_feedback() {
+                spans.push(Span::styled(
+                    format!("  {}", problem),
+                    Style::default().fg(Color::Red),
+                ));
+            }
+            Paragraph::new(Line::from(spans)).style(Style::default().fg(Color::Yellow))
         }
     } else {
         // Context-sensitive footer message
         let context_hint = match app.current_tab {
+            _ if app.role == ManagementRole::Read => "read-only",
             Tab::Routes => "'a' add route",
             Tab::Upstreams => "'a' add upstream",
             Tab::Config => "'a' add static dir",
+            Tab::Status => "'S' shut down server",
             _ => "'a' add item",
         };
         Paragraph::new(format!(
-            "Press 'q' to quit | Tab to switch views | 'r' to refresh | {}",
+            "Press 'q' to quit | '?' for help | Tab to switch views | 'r' to refresh | {}",
             context_hint
         ))
         .style(Style::default().fg(Color::DarkGray))
     };
 
-    f.render_widget(footer, area);
+    let status = status_bar(app);
+    let chunks = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([
+            Constraint::Min(0),
+            Constraint::Length(status.width() as u16),
+        ])
+        .split(area);
+
+    f.render_widget(footer, chunks[0]);
+    f.render_widget(Paragraph::new(status), chunks[1]);
+}
+
+/// Connection state, server address, round-trip time and local time
+fn status_bar(app: &App) -> Line<'static> {
+    let separator = || Span::styled(" | ", Style::default().fg(Color::DarkGray));
+    let (state, state_color) = if app.connected {
+        ("Connected", Color::Green)
+    } else {
+        ("Disconnected", Color::Red)
+    };
+    let rtt = match app.rtt.filter(|_| app.connected) {
+        Some(rtt) => {
+            let ms = rtt.as_millis();
+            let color = if ms < 50 {
+                Color::Green
+            } else if ms <= 200 {
+                Color::Yellow
+            } else {
+                Color::Red
+            };
+            Span::styled(format!("rtt: {}ms", ms), Style::default().fg(color))
+        }
+        None => Span::styled("rtt: -", Style::default().fg(Color::DarkGray)),
+    };
+
+    let mut spans = vec![
+        Span::raw("["),
+        Span::styled(state, Style::default().fg(state_color)),
+        separator(),
+    ];
+    if let Some(countdown) = shutdown_countdown(app) {
+        spans.push(Span::styled(countdown, Style::default().fg(Color::Red)));
+        spans.push(separator());
+    }
+    if let Some(retry) = reconnect_progress(app) {
+        spans.push(Span::styled(retry, Style::default().fg(Color::Yellow)));
+        spans.push(separator());
+    }
+    spans.extend([
+        Span::raw(app.server_addr.clone()),
+        separator(),
+        rtt,
+        separator(),
+        Span::raw(chrono::Local::now().format("%H:%M:%S").to_string()),
+        Span::raw("]"),
+    ]);
+    Line::from(spans)
+}
+
+/// The server's config hash, with a warning when this TUI's copy of the
+/// configuration hashes differently
+fn config_hash_line<'a>(app: &App, server_hash: &'a str) -> Line<'a> {
+    let mut spans = vec![
+        Span::raw("Config Hash: "),
+        Span::styled(server_hash, Style::default().fg(Color::Cyan)),
+    ];
+    match app.config_hash {
+        Some(ref local) if local != server_hash => spans.push(Span::styled(
+            format!(
+                " (differs from this TUI's copy {}, press r to refresh)",
+                local
+            ),
+            Style::default().fg(Color::Red),
+        )),
+        Some(_) => spans.push(Span::styled(
+            " (in sync)",
+            Style::default().fg(Color::DarkGray),
+        )),
+        None => {}
+    }
+    Line::from(spans)
 }
 
 /// Format duration in human-readable form
@@ -679,3 +1912,507 @@ fn format_bytes(bytes: u64) -> String {
         format!("{} B", bytes)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use janus_common::{JanusConfig, ServerStats, ServerStatus, UpstreamStats};
+    use ratatui::{backend::TestBackend, buffer::Buffer, Terminal};
+
+    fn upstream(requests: u64, failures: u64) -> UpstreamStats {
+        UpstreamStats {
+            requests,
+            failures,
+            avg_response_time_ms: 12.5,
+            healthy_servers: 1,
+            total_servers: 2,
+            ..Default::default()
+        }
+    }
+
+    fn render(app: &App, draw: fn(&mut Frame, &App, Rect)) -> Buffer {
+        let mut terminal = Terminal::new(TestBackend::new(120, 60)).unwrap();
+        terminal.draw(|f| draw(f, app, f.size())).unwrap();
+        terminal.backend().buffer().clone()
+    }
+
+    /// Position of the first occurrence of `text` in the rendered buffer
+    fn find(buffer: &Buffer, text: &str) -> (u16, u16) {
+        for y in 0..buffer.area.height {
+            let line: String = (0..buffer.area.width)
+                .map(|x| buffer.get(x, y).symbol())
+                .collect();
+            if let Some(x) = line.find(text) {
+                // Column of the match, as borders take several bytes
+                return (line[..x].chars().count() as u16, y);
+            }
+        }
+        panic!("{:?} not rendered", text);
+    }
+
+    #[test]
+    fn test_upstream_table_sorted_and_colored() {
+        let mut app = App::new("127.0.0.1:9090".to_string(), None);
+        let mut stats = ServerStats::default();
+        stats.upstream_stats.insert(
+            "legacy".to_string(),
+            UpstreamStats {
+                retries: 4321,
+                ..upstream(50, 10)
+            },
+        );
+        stats
+            .upstream_stats
+            .insert("api".to_string(), upstream(1000, 2));
+        stats
+            .upstream_stats
+            .insert("auth".to_string(), upstream(100, 3));
+        stats.upstream_stats.insert(
+            "spare".to_string(),
+            UpstreamStats {
+                healthy_servers: 0,
+                ..upstream(0, 0)
+            },
+        );
+        app.stats = Some(stats);
+
+        let buffer = render(&app, draw_stats);
+
+        // Busiest first
+        let (_, api) = find(&buffer, "api ");
+        let (_, auth) = find(&buffer, "auth ");
+        let (_, legacy) = find(&buffer, "legacy ");
+        assert!(api < auth && auth < legacy);
+        assert_eq!(find(&buffer, "4321").1, legacy);
+
+        for (rate, color) in [
+            ("0.20%", Color::Green),
+            ("3.00%", Color::Yellow),
+            ("20.00%", Color::Red),
+        ] {
+            let (x, y) = find(&buffer, rate);
+            assert_eq!(buffer.get(x, y).fg, color, "color of {}", rate);
+        }
+        let (x, y) = find(&buffer, "1/2");
+        assert_eq!(buffer.get(x, y).fg, Color::Yellow);
+
+        // Upstreams with no available backends stand out
+        let (x, y) = find(&buffer, "spare ");
+        assert_eq!(buffer.get(x, y).fg, Color::Red);
+        let (x, y) = find(&buffer, "0/2");
+        assert_eq!(buffer.get(x, y).fg, Color::Red);
+
+        // Thresholds come from the TUI's settings
+        app.failure_rate_warn = 0.25;
+        app.failure_rate_alert = 0.5;
+        let buffer = render(&app, draw_stats);
+        let (x, y) = find(&buffer, "20.00%");
+        assert_eq!(buffer.get(x, y).fg, Color::Green);
+    }
+
+    #[test]
+    fn test_upstream_connections_against_limit() {
+        let mut app = App::new("127.0.0.1:9090".to_string(), None);
+        let mut stats = ServerStats::default();
+        stats.upstream_stats.insert(
+            "api".to_string(),
+            UpstreamStats {
+                active_connections: 8,
+                peak_connections: 8,
+                max_connections: Some(8),
+                ..upstream(1000, 0)
+            },
+        );
+        stats.upstream_stats.insert(
+            "auth".to_string(),
+            UpstreamStats {
+                active_connections: 2,
+                peak_connections: 5,
+                max_connections: Some(8),
+                ..upstream(100, 0)
+            },
+        );
+        app.stats = Some(stats);
+
+        let buffer = render(&app, draw_stats);
+        let (x, y) = find(&buffer, "8/8 (peak 8)");
+        assert_eq!(buffer.get(x, y).fg, Color::Yellow);
+        let (x, y) = find(&buffer, "2/8 (peak 5)");
+        assert_eq!(buffer.get(x, y).fg, Color::Reset);
+    }
+
+    #[test]
+    fn test_slowest_routes_over_threshold_in_red() {
+        let mut app = App::new("127.0.0.1:9090".to_string(), None);
+        app.stats = Some(ServerStats {
+            slowest_routes: vec![
+                ("/reports/*".to_string(), 812.4),
+                ("/api/*".to_string(), 35.2),
+            ],
+            ..Default::default()
+        });
+
+        let buffer = render(&app, draw_stats);
+        let (x, y) = find(&buffer, "812.4");
+        assert_eq!(buffer.get(x, y).fg, Color::Red);
+        let (x, y) = find(&buffer, "35.2");
+        assert_eq!(buffer.get(x, y).fg, Color::Reset);
+        assert!(find(&buffer, "/reports/*").1 < find(&buffer, "/api/*").1);
+
+        app.slow_route_ms = 1000.0;
+        let buffer = render(&app, draw_stats);
+        let (x, y) = find(&buffer, "812.4");
+        assert_eq!(buffer.get(x, y).fg, Color::Reset);
+    }
+
+    #[test]
+    fn test_busiest_routes_beside_slowest() {
+        let mut app = App::new("127.0.0.1:9090".to_string(), None);
+        app.stats = Some(ServerStats {
+            slowest_routes: vec![("/reports/*".to_string(), 812.4)],
+            top_routes_by_traffic: vec![
+                ("/api/*".to_string(), 9120),
+                ("/static/*".to_string(), 431),
+            ],
+            ..Default::default()
+        });
+
+        let buffer = render(&app, draw_stats);
+        let slowest = find(&buffer, "Slowest Routes");
+        let busiest = find(&buffer, "Busiest Routes");
+        assert_eq!(slowest.1, busiest.1);
+        assert!(slowest.0 < busiest.0);
+        assert_eq!(find(&buffer, "9120").1, find(&buffer, "/api/*").1);
+        assert!(find(&buffer, "/api/*").1 < find(&buffer, "/static/*").1);
+    }
+
+    #[test]
+    fn test_route_detail_popup() {
+        let mut app = App::new("127.0.0.1:9090".to_string(), None);
+        app.route_detail_path = Some("/api/*".to_string());
+
+        let mut terminal = Terminal::new(TestBackend::new(120, 40)).unwrap();
+        terminal.draw(|f| draw(f, &mut app)).unwrap();
+        find(terminal.backend().buffer(), "Loading...");
+
+        app.route_detail = Some(janus_common::RouteStatsDetail {
+            path: "/api/*".to_string(),
+            requests: 1200,
+            p50_ms: 12.0,
+            p95_ms: 240.5,
+            p99_ms: 731.2,
+            max_ms: 1503.0,
+            bytes_sent: 2048,
+            last_request: Some("2026-10-16T09:30:00Z".to_string()),
+            ..Default::default()
+        });
+        terminal.draw(|f| draw(f, &mut app)).unwrap();
+        let buffer = terminal.backend().buffer().clone();
+        find(&buffer, "Route /api/*");
+        find(&buffer, "1200");
+        find(&buffer, "2026-10-16T09:30:00Z");
+        // P99 over the slow route threshold
+        let (x, y) = find(&buffer, "731.2 ms");
+        assert_eq!(buffer.get(x, y).fg, Color::Red);
+        let (x, y) = find(&buffer, "240.5 ms");
+        assert_eq!(buffer.get(x, y).fg, Color::Cyan);
+    }
+
+    #[test]
+    fn test_help_popup_shows_current_tab_shortcuts() {
+        let mut app = App::new("127.0.0.1:9090".to_string(), None);
+        app.current_tab = Tab::Upstreams;
+        app.show_help_popup = true;
+
+        let mut terminal = Terminal::new(TestBackend::new(120, 40)).unwrap();
+        terminal.draw(|f| draw(f, &mut app)).unwrap();
+        let buffer = terminal.backend().buffer().clone();
+        find(&buffer, "Upstreams Shortcuts");
+        find(&buffer, "Drain selected upstream, then remove it");
+        find(&buffer, "Press any key to close");
+    }
+
+    #[test]
+    fn test_live_hits_colored_by_status_class() {
+        let mut app = App::new("127.0.0.1:9090".to_string(), None);
+        for (path, status) in [
+            ("/ok", 200),
+            ("/moved", 301),
+            ("/missing", 404),
+            ("/broken", 502),
+        ] {
+            app.live_hits.push_front(crate::app::RouteHit {
+                path: path.to_string(),
+                upstream: "api".to_string(),
+                status,
+            });
+        }
+
+        let buffer = render(&app, draw_live);
+        let (_, newest) = find(&buffer, "/broken -> api");
+        let (_, oldest) = find(&buffer, "/ok -> api");
+        assert!(newest < oldest);
+        for (status, color) in [
+            ("200", Color::Green),
+            ("301", Color::Cyan),
+            ("404", Color::Yellow),
+            ("502", Color::Red),
+        ] {
+            let (x, y) = find(&buffer, status);
+            assert_eq!(buffer.get(x, y).fg, color, "color of {}", status);
+        }
+        find(&buffer, "off: set management.live_requests = true");
+    }
+
+    #[test]
+    fn test_error_log_on_status_tab() {
+        let mut app = App::new("127.0.0.1:9090".to_string(), None);
+        let buffer = render(&app, draw_status);
+        find(&buffer, "No warnings or errors logged");
+
+        for (level, message) in [
+            (LogLevel::Error, "Config reload failed"),
+            (LogLevel::Warn, "Backend refused"),
+        ] {
+            app.error_log.push(janus_common::ErrorLogEntry {
+                timestamp: 86_400 + 3_723,
+                level,
+                target: "janus_core::reload".to_string(),
+                message: message.to_string(),
+            });
+        }
+
+        let buffer = render(&app, draw_status);
+        let (x, y) = find(&buffer, "Config reload failed");
+        assert_eq!(buffer.get(x, y).fg, Color::Red);
+        let (x, y) = find(&buffer, "Backend refused");
+        assert_eq!(buffer.get(x, y).fg, Color::Yellow);
+        find(&buffer, "01:02:03 ERROR janus_core::reload: ");
+    }
+
+    #[test]
+    fn test_audit_log_on_config_tab() {
+        let mut app = App::new("127.0.0.1:9090".to_string(), None);
+        let mut config = JanusConfig::default();
+        app.config = Some(config.clone());
+        let buffer = render(&app, draw_config);
+        find(&buffer, "Off: set management.audit_log");
+
+        config.management.audit_log = Some("/var/log/janus/audit.jsonl".to_string());
+        app.config = Some(config);
+        app.audit_log = vec![
+            janus_common::AuditEntry {
+                timestamp: 86_400 + 3_723,
+                source: AuditSource::Management,
+                peer: Some("10.0.0.5:51000".to_string()),
+                certificate: None,
+                token: Some("ops".to_string()),
+                role: Some(ManagementRole::Admin),
+                operation: "RemoveUpstream".to_string(),
+                summary: "api".to_string(),
+                success: false,
+                error: Some("Upstream 'api' is used by routes".to_string()),
+            },
+            janus_common::AuditEntry {
+                timestamp: 86_400 + 3_600,
+                source: AuditSource::File,
+                peer: None,
+                certificate: None,
+                token: None,
+                role: None,
+                operation: "ReloadConfig".to_string(),
+                summary: "janus.toml".to_string(),
+                success: true,
+                error: None,
+            },
+        ];
+
+        let buffer = render(&app, draw_config);
+        let (x, y) = find(
+            &buffer,
+            "01:02:03 RemoveUpstream api by ops (10.0.0.5:51000)",
+        );
+        assert_eq!(buffer.get(x + 9, y).fg, Color::Red);
+        find(&buffer, ": Upstream 'api' is used by routes");
+        let (x, y) = find(&buffer, "01:00:00 ReloadConfig janus.toml by config file");
+        assert_eq!(buffer.get(x + 9, y).fg, Color::Green);
+    }
+
+    #[test]
+    fn test_status_shows_acme_certificate() {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let mut app = App::new("127.0.0.1:9090".to_string(), None);
+        app.current_tab = Tab::Status;
+        app.status = Some(ServerStatus {
+            acme: Some(AcmeStatus {
+                domains: vec!["example.com".to_string(), "www.example.com".to_string()],
+                expires_at: Some(now + 10 * 86_400 - 1800),
+                last_attempt: Some(now - 120),
+                last_error: Some("urn:ietf:params:acme:error:rateLimited".to_string()),
+            }),
+            ..Default::default()
+        });
+
+        let buffer = render(&app, draw_main_content);
+        find(&buffer, "Certificate: example.com, www.example.com");
+        let (x, y) = find(&buffer, "in 9d 23h");
+        assert_eq!(buffer.get(x, y).fg, Color::Green);
+        find(&buffer, "Last Renewal Attempt: 2m");
+        let (x, y) = find(&buffer, "urn:ietf:params:acme:error:rateLimited");
+        assert_eq!(buffer.get(x, y).fg, Color::Red);
+
+        app.status.as_mut().unwrap().acme = Some(AcmeStatus {
+            expires_at: Some(now - 1),
+            ..Default::default()
+        });
+        let buffer = render(&app, draw_main_content);
+        let (x, y) = find(&buffer, "Certificate Expires: expired");
+        assert_eq!(buffer.get(x + 21, y).fg, Color::Red);
+    }
+
+    #[test]
+    fn test_restart_banner_follows_server_status() {
+        let mut app = App::new("127.0.0.1:9090".to_string(), None);
+        app.connected = true;
+        app.status = Some(ServerStatus {
+            listen_address: "0.0.0.0:8080".to_string(),
+            pending_restart_settings: vec!["server.port".to_string()],
+            ..Default::default()
+        });
+
+        for tab in [Tab::Status, Tab::Config] {
+            app.current_tab = tab;
+            let buffer = render(&app, draw_main_content);
+            let (x, y) = find(&buffer, "Restart pending: server.port saved");
+            assert_eq!(y, 0);
+            assert_eq!(buffer.get(x, y).bg, Color::Yellow);
+            find(&buffer, "still listening on 0.0.0.0:8080");
+        }
+
+        // Cleared once the live listener matches the saved config
+        app.current_tab = Tab::Status;
+        app.status
+            .as_mut()
+            .unwrap()
+            .pending_restart_settings
+            .clear();
+        let buffer = render(&app, draw_main_content);
+        let top: String = (0..buffer.area.width)
+            .map(|x| buffer.get(x, 0).symbol())
+            .collect();
+        assert!(!top.contains("Restart pending"));
+    }
+
+    #[test]
+    fn test_maintenance_banner_on_every_tab() {
+        let mut app = App::new("127.0.0.1:9090".to_string(), None);
+        app.connected = true;
+        let mut config = JanusConfig::default();
+        config
+            .routes
+            .push(janus_common::RouteConfig::new("/api/*", "api"));
+        app.config = Some(config);
+        app.status = Some(ServerStatus {
+            pending_restart_settings: vec!["server.port".to_string()],
+            maintenance: vec![janus_common::MaintenanceWindow {
+                scope: MaintenanceScope::Route("/api/*".to_string()),
+                message: None,
+                since: 0,
+            }],
+            ..Default::default()
+        });
+
+        for &tab in Tab::all() {
+            app.current_tab = tab;
+            let buffer = render(&app, draw_main_content);
+            let (x, y) = find(&buffer, "MAINTENANCE: answering 503 for route /api/*");
+            assert_eq!(y, 0);
+            assert_eq!(buffer.get(x, y).bg, Color::Red);
+        }
+
+        // Above the restart banner, and the route is marked in the table
+        app.current_tab = Tab::Status;
+        assert_eq!(
+            find(&render(&app, draw_main_content), "Restart pending").1,
+            1
+        );
+        app.current_tab = Tab::Routes;
+        find(&render(&app, draw_main_content), "/api/* [M]");
+    }
+
+    #[test]
+    fn test_disabled_rows_dimmed() {
+        let mut app = App::new("127.0.0.1:9090".to_string(), None);
+        let mut config = JanusConfig::default();
+        config
+            .routes
+            .push(janus_common::RouteConfig::new("/api/*", "api"));
+        config
+            .routes
+            .push(janus_common::RouteConfig::new("/web/*", "web"));
+        config.routes[1].enabled = false;
+        config.static_files.push(janus_common::StaticFileConfig {
+            enabled: false,
+            ..janus_common::StaticFileConfig::new("/assets/", "/srv/assets")
+        });
+        app.config = Some(config);
+
+        let buffer = render(&app, draw_routes);
+        let (x, y) = find(&buffer, "/web/*");
+        assert_eq!(buffer.get(x, y).fg, Color::DarkGray);
+        let (x, y) = find(&buffer, "/api/*");
+        assert_ne!(buffer.get(x, y).fg, Color::DarkGray);
+
+        // Still readable when selected
+        app.selected_route = 1;
+        let buffer = render(&app, draw_routes);
+        let (x, y) = find(&buffer, "/web/*");
+        assert_eq!(buffer.get(x, y).bg, Color::DarkGray);
+        assert!(buffer.get(x, y).modifier.contains(Modifier::DIM));
+
+        app.selected_static_dir = 1;
+        let buffer = render(&app, draw_config);
+        let (x, y) = find(&buffer, "/srv/assets");
+        assert_eq!(buffer.get(x, y).fg, Color::DarkGray);
+    }
+
+    #[test]
+    fn test_draining_servers_marked() {
+        let mut app = App::new("127.0.0.1:9090".to_string(), None);
+        let mut config = JanusConfig::default();
+        let mut upstream = janus_common::UpstreamConfig::new([
+            "10.0.0.1:80".to_string(),
+            "10.0.0.2:80".to_string(),
+        ]);
+        upstream.servers[1].drain = true;
+        config.upstreams.insert("api".to_string(), upstream);
+        app.config = Some(config);
+
+        let buffer = render(&app, draw_upstreams);
+        let (x, y) = find(&buffer, "10.0.0.2:80 (weight: 1) [draining]");
+        let (marker, _) = find(&buffer, "[draining]");
+        assert_eq!(buffer.get(x, y).fg, Color::White);
+        assert_eq!(buffer.get(marker, y).fg, Color::Yellow);
+        assert_eq!(find(&buffer, "10.0.0.1:80 (weight: 1), 10.0.0.2").1, y);
+    }
+
+    #[test]
+    fn test_config_hash_mismatch_warned() {
+        let mut app = App::new("127.0.0.1:9090".to_string(), None);
+        app.status = Some(ServerStatus {
+            config_hash: "00000000000000aa".to_string(),
+            ..Default::default()
+        });
+        app.config_hash = Some("00000000000000aa".to_string());
+        let buffer = render(&app, draw_status);
+        find(&buffer, "Config Hash: 00000000000000aa (in sync)");
+
+        app.config_hash = Some("00000000000000bb".to_string());
+        let buffer = render(&app, draw_status);
+        let (x, y) = find(&buffer, "(differs from this TUI's copy 00000000000000bb");
+        assert_eq!(buffer.get(x, y).fg, Color::Red);
+    }
+}
@@ -0,0 +1,222 @@
+//! Management protocol round trips between the TUI's client and a real server
+
+use janus_common::config::{RouteConfig, UpstreamConfig};
+use janus_common::{
+    ClientMessage, JanusConfig, MaintenanceScope, ManagementRole, ManagementToken, ServerMessage,
+    StaticFileConfig,
+};
+use janus_core::Janus;
+use janus_tui::client::{ClientEvent, ManagementClient};
+use std::time::Duration;
+
+/// Bound on every operation
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A loopback port that was free a moment ago
+fn free_port() -> u16 {
+    std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+/// Wait for the client's next event
+async fn next_event(client: &mut ManagementClient) -> ClientEvent {
+    tokio::time::timeout(TIMEOUT, async {
+        loop {
+            if let Some(event) = client.try_recv() {
+                return event;
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+    })
+    .await
+    .expect("no event from the client")
+}
+
+/// Send a message and wait for its response, skipping the reload
+/// notifications configuration changes broadcast
+async fn request(client: &mut ManagementClient, msg: ClientMessage) -> ServerMessage {
+    tokio::time::timeout(TIMEOUT, client.send(msg))
+        .await
+        .expect("send timed out")
+        .expect("send failed");
+    loop {
+        match next_event(client).await {
+            ClientEvent::Message(ServerMessage::ConfigReloaded | ServerMessage::ConfigHash(_)) => {}
+            ClientEvent::Message(response) => return response,
+            other => panic!("unexpected event: {:?}", other),
+        }
+    }
+}
+
+/// Whether a response is the one the message asks for
+fn check_response(msg: &ClientMessage, response: &ServerMessage) -> bool {
+    match msg {
+        ClientMessage::Authenticate { .. } => matches!(
+            response,
+            ServerMessage::Authenticated(ManagementRole::Admin)
+        ),
+        ClientMessage::Ping(id) => matches!(response, ServerMessage::Pong(pong) if pong == id),
+        ClientMessage::GetStatus => matches!(response, ServerMessage::Status(_)),
+        ClientMessage::GetConfig => matches!(response, ServerMessage::Config(_)),
+        ClientMessage::GetConfigDiff { .. } => matches!(response, ServerMessage::ConfigDiff(_)),
+        ClientMessage::GetStats => matches!(response, ServerMessage::Stats(_)),
+        ClientMessage::GetTopClients { .. } => matches!(response, ServerMessage::TopClients(_)),
+        ClientMessage::GetRouteStats { .. } => matches!(response, ServerMessage::RouteStats(_)),
+        ClientMessage::GetPreflight => matches!(response, ServerMessage::Preflight(_)),
+        ClientMessage::GetErrorLog { .. } => matches!(response, ServerMessage::ErrorLog(_)),
+        ClientMessage::GetAuditLog { .. } => matches!(response, ServerMessage::AuditLog(_)),
+        ClientMessage::UpdateConfig(_)
+        | ClientMessage::UpdateServerPort(_)
+        | ClientMessage::UpdateBindAddress(_)
+        | ClientMessage::AddRoute(_)
+        | ClientMessage::RemoveRoute(_)
+        | ClientMessage::SetRouteEnabled { .. }
+        | ClientMessage::AddUpstream { .. }
+        | ClientMessage::UpdateUpstream { .. }
+        | ClientMessage::RemoveUpstream(_)
+        | ClientMessage::DrainUpstream(_)
+        | ClientMessage::DrainBackendServer { .. }
+        | ClientMessage::AddStaticDir(_)
+        | ClientMessage::UpdateStaticDir { .. }
+        | ClientMessage::RemoveStaticDir(_)
+        | ClientMessage::SetStaticDirEnabled { .. }
+        | ClientMessage::ReloadConfig
+        | ClientMessage::ResetStats
+        | ClientMessage::SetMaintenance { .. }
+        | ClientMessage::Shutdown { .. } => matches!(response, ServerMessage::Success(_)),
+    }
+}
+
+#[tokio::test]
+async fn test_every_client_message_round_trips() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("janus.toml");
+
+    // Reloads validate the file, so it needs real ports
+    let mut config = JanusConfig::default();
+    config.server.bind_address = "127.0.0.1".to_string();
+    config.server.port = free_port();
+    config.server.access_log = false;
+    config.management.address = "127.0.0.1".to_string();
+    config.management.port = free_port();
+    config.management.tokens = vec![ManagementToken {
+        token: "ops".to_string(),
+        role: ManagementRole::Admin,
+        name: None,
+    }];
+    let backend = || UpstreamConfig::new(["127.0.0.1:9".to_string()]);
+    config.upstreams.insert("backend".to_string(), backend());
+    config.save(&path).unwrap();
+
+    let janus = Janus::with_config_file(config.clone(), &path);
+    let handle = janus.start().await.expect("failed to start Janus");
+    let addr = janus.management_addr().expect("management API disabled");
+
+    let mut client = tokio::time::timeout(
+        TIMEOUT,
+        ManagementClient::connect(&format!("ws://{}", addr)),
+    )
+    .await
+    .expect("connect timed out")
+    .expect("connect failed");
+    assert!(matches!(
+        next_event(&mut client).await,
+        ClientEvent::Connected
+    ));
+
+    let route = RouteConfig::new("/api/*", "backend").timeout(5);
+    let static_dir = StaticFileConfig::new("/static/", dir.path().to_string_lossy().into_owned());
+
+    // Ordered so each change has what it needs from the ones before it
+    let messages = vec![
+        ClientMessage::Authenticate {
+            token: "ops".to_string(),
+        },
+        ClientMessage::Ping(3),
+        ClientMessage::GetStatus,
+        ClientMessage::GetConfig,
+        ClientMessage::GetConfigDiff {
+            before: config.clone(),
+        },
+        ClientMessage::UpdateConfig(config.clone()),
+        ClientMessage::UpdateServerPort(config.server.port),
+        ClientMessage::UpdateBindAddress("127.0.0.1".to_string()),
+        ClientMessage::AddUpstream {
+            name: "spare".to_string(),
+            config: backend(),
+        },
+        ClientMessage::UpdateUpstream {
+            name: "spare".to_string(),
+            config: UpstreamConfig::new(["127.0.0.1:10".to_string()]),
+        },
+        ClientMessage::AddRoute(route),
+        ClientMessage::GetRouteStats {
+            path: "/api/*".to_string(),
+        },
+        ClientMessage::SetRouteEnabled {
+            path: "/api/*".to_string(),
+            enabled: false,
+        },
+        ClientMessage::RemoveRoute("/api/*".to_string()),
+        ClientMessage::AddStaticDir(static_dir.clone()),
+        ClientMessage::UpdateStaticDir {
+            path: "/static/".to_string(),
+            config: StaticFileConfig {
+                directory_listing: true,
+                ..static_dir
+            },
+        },
+        ClientMessage::SetStaticDirEnabled {
+            path: "/static/".to_string(),
+            enabled: false,
+        },
+        ClientMessage::RemoveStaticDir("/static/".to_string()),
+        ClientMessage::DrainBackendServer {
+            upstream: "spare".to_string(),
+            server_address: "127.0.0.1:10".to_string(),
+        },
+        ClientMessage::DrainUpstream("spare".to_string()),
+        ClientMessage::RemoveUpstream("spare".to_string()),
+        ClientMessage::ReloadConfig,
+        ClientMessage::GetStats,
+        ClientMessage::GetTopClients { limit: 5 },
+        ClientMessage::ResetStats,
+        ClientMessage::SetMaintenance {
+            scope: MaintenanceScope::All,
+            enabled: false,
+            message: None,
+        },
+        ClientMessage::GetPreflight,
+        ClientMessage::GetErrorLog { limit: 5 },
+        ClientMessage::GetAuditLog { limit: 5 },
+        ClientMessage::Shutdown {
+            confirm: true,
+            delay_secs: 60,
+        },
+    ];
+
+    // One of each variant
+    let variants: std::collections::HashSet<_> =
+        messages.iter().map(std::mem::discriminant).collect();
+    assert_eq!(variants.len(), messages.len());
+
+    for msg in messages {
+        let response = request(&mut client, msg.clone()).await;
+        assert!(
+            check_response(&msg, &response),
+            "unexpected response to {:?}: {:?}",
+            msg,
+            response
+        );
+    }
+
+    janus.shutdown().await;
+    tokio::time::timeout(TIMEOUT, handle)
+        .await
+        .expect("server did not stop")
+        .unwrap()
+        .unwrap();
+}